@@ -0,0 +1,160 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Benchmarks for the publish planner. Run with `cargo bench --features bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashSet;
+use subpub::ordering::{self, DependencyGraph};
+
+const GRAPH_SIZES: [usize; 3] = [250, 1000, 2500];
+
+/// Generate a workspace-like graph: a handful of "primitives" crates which
+/// most crates depend on, plus dependencies on crates close to each other,
+/// like crates of the same family in Substrate.
+fn synthetic_graph(size: usize) -> DependencyGraph {
+    // xorshift, so that every run benchmarks the same graph
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+
+    (0..size)
+        .map(|i| {
+            let mut deps = HashSet::new();
+            if i > 0 {
+                for _ in 0..next(6) {
+                    let dep = if next(4) == 0 {
+                        next(i.min(20))
+                    } else {
+                        i - 1 - next(i.min(50))
+                    };
+                    deps.insert(crate_name(dep));
+                }
+            }
+            (crate_name(i), deps)
+        })
+        .collect()
+}
+
+/// Generate a graph where every crate depends on the previous one, so that
+/// every crate is on a level of its own.
+fn chain_graph(size: usize) -> DependencyGraph {
+    (0..size)
+        .map(|i| {
+            let deps = if i > 0 {
+                HashSet::from([crate_name(i - 1)])
+            } else {
+                HashSet::new()
+            };
+            (crate_name(i), deps)
+        })
+        .collect()
+}
+
+fn crate_name(i: usize) -> String {
+    format!("crate-{i:05}")
+}
+
+fn bench_publish_levels(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_levels");
+    for size in GRAPH_SIZES {
+        let graph = synthetic_graph(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &graph, |b, graph| {
+            b.iter(|| ordering::publish_levels(black_box(graph)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_publish_levels_chain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("publish_levels_chain");
+    for size in GRAPH_SIZES {
+        let graph = chain_graph(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &graph, |b, graph| {
+            b.iter(|| ordering::publish_levels(black_box(graph)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_selection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_selection");
+    for size in GRAPH_SIZES {
+        let publish_order = ordering::publish_levels(&synthetic_graph(size)).concat();
+        // Select everything but every tenth crate, as a long exclude list would
+        let selected = publish_order
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 10 != 0)
+            .map(|(_, krate)| krate.clone())
+            .collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &(publish_order, selected),
+            |b, (publish_order, selected)| {
+                b.iter(|| ordering::order_selection(black_box(publish_order), black_box(selected)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_dependency_closure(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dependency_closure");
+    for size in GRAPH_SIZES {
+        let graph = synthetic_graph(size);
+        let publish_order = ordering::publish_levels(&graph).concat();
+        // The last crate in the order pulls in the most dependencies
+        let krate = publish_order.last().unwrap().clone();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &(graph, publish_order),
+            |b, (graph, publish_order)| {
+                b.iter(|| ordering::dependency_closure(graph, black_box(&krate), publish_order))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_dependents(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dependents");
+    for size in GRAPH_SIZES {
+        let graph = synthetic_graph(size);
+        // The first crates are the ones most others depend on
+        let krates = vec![crate_name(0)];
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &(graph, krates),
+            |b, (graph, krates)| b.iter(|| ordering::dependents(graph, black_box(krates), None)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_publish_levels,
+    bench_publish_levels_chain,
+    bench_selection,
+    bench_dependency_closure,
+    bench_dependents
+);
+criterion_main!(benches);
@@ -0,0 +1,141 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The two-person rule: a plan has to be approved by enough distinct people
+//! before it's executed. An approval is an SSH signature of the plan file,
+//! made with `ssh-keygen -Y sign -n subpub`, by a key listed in the allowed
+//! signers file of the workspace.
+
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::info;
+
+/// The namespace approvals are signed in, so that signatures made for other
+/// purposes can't be passed off as approvals.
+const SIGNATURE_NAMESPACE: &str = "subpub";
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ApprovalPolicy {
+    /// How many distinct people have to approve a plan.
+    pub required: usize,
+    /// The `ssh-keygen` allowed signers file listing who can approve plans,
+    /// relative to the workspace root.
+    pub allowed_signers: PathBuf,
+}
+
+/// The directory approvals of a plan are read from: `<plan>.approvals`.
+pub fn approvals_dir(plan_path: &Path) -> PathBuf {
+    let mut dir = plan_path.as_os_str().to_owned();
+    dir.push(".approvals");
+    PathBuf::from(dir)
+}
+
+/// Check that the plan was approved by as many distinct signers as the policy
+/// requires. Every `*.sig` file in the approvals directory of the plan has to
+/// be a valid signature of it.
+pub fn verify_approvals(
+    root: &Path,
+    policy: &ApprovalPolicy,
+    plan_path: &Path,
+) -> anyhow::Result<()> {
+    let allowed_signers = root.join(&policy.allowed_signers);
+    let dir = approvals_dir(plan_path);
+    let mut signatures = if dir.is_dir() {
+        std::fs::read_dir(&dir)
+            .with_context(|| format!("Cannot read the approvals in {:?}", dir.as_os_str()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        vec![]
+    };
+    signatures.retain(|path| path.extension().map(|ext| ext == "sig").unwrap_or(false));
+    signatures.sort();
+
+    let mut approvers = BTreeSet::new();
+    for signature in &signatures {
+        let signer = verify_signature(&allowed_signers, plan_path, signature)?;
+        info!("The plan was approved by {signer}");
+        approvers.insert(signer);
+    }
+
+    if approvers.len() < policy.required {
+        anyhow::bail!(
+            "The plan needs approvals from {} distinct people, but it was approved by {} ({}). Approvals are signatures of the plan, made with `ssh-keygen -Y sign -n {SIGNATURE_NAMESPACE} -f <key> {}`, and placed as *.sig files in {:?}.",
+            policy.required,
+            approvers.len(),
+            approvers.into_iter().collect::<Vec<_>>().join(", "),
+            plan_path.display(),
+            dir.as_os_str()
+        );
+    }
+    Ok(())
+}
+
+/// Verify a signature of the plan, returning who made it.
+fn verify_signature(
+    allowed_signers: &Path,
+    plan_path: &Path,
+    signature: &Path,
+) -> anyhow::Result<String> {
+    let output = Command::new("ssh-keygen")
+        .arg("-Y")
+        .arg("find-principals")
+        .arg("-f")
+        .arg(allowed_signers)
+        .arg("-s")
+        .arg(signature)
+        .output()
+        .context("Cannot run ssh-keygen")?;
+    let principals = String::from_utf8_lossy(&output.stdout[..]);
+    let signer = match principals.lines().next() {
+        Some(signer) if output.status.success() => signer.trim().to_owned(),
+        _ => anyhow::bail!(
+            "The approval {:?} was not made by any of the allowed signers in {:?}",
+            signature.as_os_str(),
+            allowed_signers.as_os_str()
+        ),
+    };
+
+    let plan = std::fs::File::open(plan_path)
+        .with_context(|| format!("Cannot read the plan at {:?}", plan_path.as_os_str()))?;
+    let verified = Command::new("ssh-keygen")
+        .arg("-Y")
+        .arg("verify")
+        .arg("-f")
+        .arg(allowed_signers)
+        .arg("-I")
+        .arg(&signer)
+        .arg("-n")
+        .arg(SIGNATURE_NAMESPACE)
+        .arg("-s")
+        .arg(signature)
+        .stdin(Stdio::from(plan))
+        .stdout(Stdio::null())
+        .status()
+        .context("Cannot run ssh-keygen")?
+        .success();
+    if !verified {
+        anyhow::bail!(
+            "The approval {:?} of {signer} is not a valid signature of the plan {:?}, which might have changed since it was approved",
+            signature.as_os_str(),
+            plan_path.as_os_str()
+        );
+    }
+    Ok(signer)
+}
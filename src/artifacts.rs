@@ -0,0 +1,180 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Files which have to be built before a crate can be packaged, e.g. the wasm
+//! blob a runtime crate embeds. They are built by a command configured for the
+//! crate and cached in the git directory, keyed by the crate's sources, so
+//! that they're only rebuilt when the sources change.
+
+use crate::context::Context;
+use crate::crate_details::CrateDetails;
+use crate::events;
+use anyhow::Context as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::info;
+
+const CACHE_DIR: &str = "subpub/artifacts";
+
+/// A command which builds files the package of a crate needs.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PrePackageHook {
+    /// The command, run with `sh -c` in the directory of the crate.
+    pub command: String,
+    /// The files the command builds, relative to the directory of the crate.
+    /// They have to be part of the package, e.g. through `package.include`.
+    #[serde(default)]
+    pub artifacts: Vec<PathBuf>,
+}
+
+/// Make sure the artifacts of the crate exist, restoring them from the cache
+/// or running the hook's command to build them.
+pub fn prepare<P: AsRef<Path>>(
+    ctx: &Context,
+    root: P,
+    details: &CrateDetails,
+    hook: &PrePackageHook,
+) -> anyhow::Result<()> {
+    let root = root.as_ref();
+    let crate_dir = details
+        .toml_path
+        .parent()
+        .with_context(|| format!("{:?} has no parent directory", details.toml_path))?;
+    let cache_dir = ctx
+        .git
+        .path(root, CACHE_DIR)?
+        .join(&details.name)
+        .join(cache_key(ctx, root, crate_dir, hook)?);
+
+    if !hook.artifacts.is_empty()
+        && hook
+            .artifacts
+            .iter()
+            .all(|artifact| cache_dir.join(artifact).exists())
+    {
+        info!("Using the cached artifacts of {}", details.name);
+        return copy_artifacts(&hook.artifacts, &cache_dir, crate_dir);
+    }
+
+    info!("Running the pre-package command of {}", details.name);
+    let mut cmd = Command::new("sh");
+    if !cmd
+        .current_dir(crate_dir)
+        .arg("-c")
+        .arg(&hook.command)
+        .stdout(events::child_stdout())
+        .status()?
+        .success()
+    {
+        anyhow::bail!("The pre-package command of {} failed", details.name);
+    }
+    for artifact in &hook.artifacts {
+        if !crate_dir.join(artifact).exists() {
+            anyhow::bail!(
+                "The pre-package command of {} did not build {:?}",
+                details.name,
+                artifact.as_os_str()
+            );
+        }
+    }
+    copy_artifacts(&hook.artifacts, crate_dir, &cache_dir)
+}
+
+/// Check that the artifacts of the crate end up in its package.
+pub fn verify_packaged(
+    ctx: &Context,
+    details: &CrateDetails,
+    hook: &PrePackageHook,
+) -> anyhow::Result<()> {
+    let crate_dir = details
+        .toml_path
+        .parent()
+        .with_context(|| format!("{:?} has no parent directory", details.toml_path))?;
+    let mut cmd = ctx.cargo.command();
+    let output = cmd
+        .current_dir(crate_dir)
+        .arg("package")
+        .arg("--list")
+        .arg("--allow-dirty")
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to list the files of the package of {}: {}",
+            details.name,
+            String::from_utf8_lossy(&output.stderr[..])
+        );
+    }
+    let packaged = String::from_utf8_lossy(&output.stdout[..]);
+    let missing = hook
+        .artifacts
+        .iter()
+        .map(|artifact| artifact.to_string_lossy().replace('\\', "/"))
+        .filter(|artifact| !packaged.lines().any(|file| file == artifact))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "The artifacts {} of {} would not be part of its package. Add them to package.include in its Cargo.toml.",
+            missing.join(", "),
+            details.name
+        );
+    }
+    Ok(())
+}
+
+/// Identify the sources the artifacts are built from: the files of the crate
+/// other than its manifest, whose version changes while publishing, along
+/// with the command. The key names a directory of the cache, so it's hashed
+/// with git, which hashes the same way across versions of Rust and subpub.
+fn cache_key(
+    ctx: &Context,
+    root: &Path,
+    crate_dir: &Path,
+    hook: &PrePackageHook,
+) -> anyhow::Result<String> {
+    let crate_dir = crate_dir.strip_prefix(root).unwrap_or(crate_dir);
+    let manifest = crate_dir.join("Cargo.toml");
+    let mut sources = String::new();
+    for line in ctx.git.ls_tree(root, crate_dir)?.lines() {
+        let is_manifest = line
+            .split_once('\t')
+            .map(|(_, path)| Path::new(path) == manifest)
+            .unwrap_or(false);
+        if !is_manifest {
+            sources.push_str(line);
+            sources.push('\n');
+        }
+    }
+    sources.push_str(&hook.command);
+    ctx.git.hash_contents(root, &sources)
+}
+
+fn copy_artifacts(artifacts: &[PathBuf], from: &Path, to: &Path) -> anyhow::Result<()> {
+    for artifact in artifacts {
+        let destination = to.join(artifact);
+        if let Some(dir) = destination.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::copy(from.join(artifact), &destination).with_context(|| {
+            format!(
+                "Cannot copy {:?} to {:?}",
+                from.join(artifact).as_os_str(),
+                destination.as_os_str()
+            )
+        })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,309 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Changelog entries generated from the commits which touched a crate since
+//! its latest published version, for `publish --generate-changelog`.
+
+use crate::config::ChangelogSettings;
+use crate::context::Context;
+use crate::crate_details::CrateDetails;
+use crate::freeze::Date;
+use crate::history;
+use crate::package;
+use crate::tags;
+use anyhow::Context as _;
+use semver::Version;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// The sections of an entry, by the type of the conventional commits listed
+/// in them. Commits of other types, or which don't follow the convention, are
+/// listed under "Other".
+const SECTIONS: [(&str, &str); 4] = [
+    ("feat", "Features"),
+    ("fix", "Fixes"),
+    ("perf", "Performance"),
+    ("docs", "Documentation"),
+];
+
+/// The changelog of a crate.
+pub fn path(details: &CrateDetails, settings: &ChangelogSettings) -> anyhow::Result<PathBuf> {
+    Ok(details.crate_dir()?.join(
+        settings
+            .path
+            .as_deref()
+            .unwrap_or("CHANGELOG.md")
+            .replace("{crate}", &details.name),
+    ))
+}
+
+/// Whether `changelog` has an entry for `version` of `krate`: a line starting
+/// with the `pattern` of the settings, or a heading mentioning the version.
+pub fn has_entry(
+    changelog: &str,
+    settings: &ChangelogSettings,
+    krate: &str,
+    version: &Version,
+) -> bool {
+    let version = version.to_string();
+    match &settings.pattern {
+        Some(pattern) => {
+            let entry = pattern
+                .replace("{version}", &version)
+                .replace("{crate}", krate);
+            changelog
+                .lines()
+                .any(|line| line.trim_start().starts_with(&entry))
+        }
+        None => changelog.lines().any(|line| {
+            line.starts_with('#')
+                && line
+                    .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')))
+                    .any(|word| word.strip_prefix('v').unwrap_or(word) == version)
+        }),
+    }
+}
+
+/// A commit, read as a conventional commit if it is one, e.g.
+/// `feat(parser)!: accept tabs`.
+#[derive(Debug, Clone)]
+struct Change {
+    /// The abbreviated id of the commit.
+    commit: String,
+    kind: Option<String>,
+    scope: Option<String>,
+    breaking: bool,
+    description: String,
+}
+
+fn parse_commit(commit: &str, message: &str) -> Change {
+    let mut lines = message.lines();
+    let subject = lines.next().unwrap_or_default().trim();
+    let breaking_footer = lines
+        .any(|line| line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:"));
+    let other = Change {
+        commit: commit.to_owned(),
+        kind: None,
+        scope: None,
+        breaking: breaking_footer,
+        description: subject.to_owned(),
+    };
+
+    let (prefix, description) = match subject.split_once(": ") {
+        Some((prefix, description)) if !description.trim().is_empty() => {
+            (prefix, description.trim())
+        }
+        _ => return other,
+    };
+    let (prefix, breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, breaking_footer),
+    };
+    let (kind, scope) = match prefix.split_once('(') {
+        Some((kind, scope)) => match scope.strip_suffix(')') {
+            Some(scope) if !scope.is_empty() => (kind, Some(scope.to_owned())),
+            _ => return other,
+        },
+        None => (prefix, None),
+    };
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return other;
+    }
+    Change {
+        commit: commit.to_owned(),
+        kind: Some(kind.to_ascii_lowercase()),
+        scope,
+        breaking,
+        description: description.to_owned(),
+    }
+}
+
+/// The entry listing `changes` under `heading`, grouped into sections.
+fn entry(heading: &str, changes: &[Change]) -> String {
+    let mut entry = format!("{heading}\n");
+    let mut section = |title: &str, changes: Vec<&Change>| {
+        if changes.is_empty() {
+            return;
+        }
+        entry.push_str(&format!("\n### {title}\n\n"));
+        for change in changes {
+            match &change.scope {
+                Some(scope) => entry.push_str(&format!(
+                    "- {scope}: {} ({})\n",
+                    change.description, change.commit
+                )),
+                None => entry.push_str(&format!("- {} ({})\n", change.description, change.commit)),
+            }
+        }
+    };
+
+    section(
+        "Breaking changes",
+        changes.iter().filter(|change| change.breaking).collect(),
+    );
+    for (kind, title) in SECTIONS {
+        section(
+            title,
+            changes
+                .iter()
+                .filter(|change| !change.breaking && change.kind.as_deref() == Some(kind))
+                .collect(),
+        );
+    }
+    section(
+        "Other",
+        changes
+            .iter()
+            .filter(|change| {
+                !change.breaking
+                    && !SECTIONS
+                        .iter()
+                        .any(|(kind, _)| change.kind.as_deref() == Some(kind))
+            })
+            .collect(),
+    );
+    if changes.is_empty() {
+        entry.push_str("\n- Updated dependencies.\n");
+    }
+    entry
+}
+
+/// The commit `version` of the crate was published from: the one of its tag,
+/// the one the history of the workspace recorded, or the one Cargo recorded
+/// in the package, if the repository has it.
+fn published_commit(
+    ctx: &Context,
+    root: &Path,
+    details: &CrateDetails,
+    version: &Version,
+    tag_format: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let tag = tags::tag_name(tag_format, &details.name, version);
+    if ctx.git.tags(root)?.contains(&tag) {
+        return Ok(Some(tag));
+    }
+    let recorded = history::load(&ctx.git, root, Some(&details.name))?
+        .into_iter()
+        .rev()
+        .find(|release| &release.version == version)
+        .map(|release| release.commit);
+    let commit = match recorded {
+        Some(commit) => Some(commit),
+        None => match ctx.registry.try_download_crate(&details.name, version)? {
+            Some(published) => {
+                let dir = tempfile::tempdir()?;
+                let unpacked = package::unpack(&published, dir.path())?;
+                std::fs::read_to_string(unpacked.join(".cargo_vcs_info.json"))
+                    .ok()
+                    .and_then(|vcs_info| serde_json::from_str::<serde_json::Value>(&vcs_info).ok())
+                    .and_then(|vcs_info| vcs_info["git"]["sha1"].as_str().map(String::from))
+            }
+            None => None,
+        },
+    };
+    match commit {
+        Some(commit) if ctx.git.has_commit(root, &commit)? => Ok(Some(commit)),
+        _ => Ok(None),
+    }
+}
+
+/// Add an entry for `version` of the crate to its changelog, listing the
+/// commits which touched the crate since `latest_version` was published, or
+/// all of them if it wasn't. Commits made by subpub, and the ones with
+/// `release_commit_message`, are left out. A changelog which has an entry for
+/// the version already is left alone. Returns whether the changelog changed.
+#[allow(clippy::too_many_arguments)]
+pub fn update_changelog(
+    ctx: &Context,
+    root: &Path,
+    details: &CrateDetails,
+    settings: &ChangelogSettings,
+    version: &Version,
+    latest_version: Option<&Version>,
+    tag_format: Option<&str>,
+    release_commit_message: &str,
+) -> anyhow::Result<bool> {
+    let path = path(details, settings)?;
+    let changelog = match std::fs::read_to_string(&path) {
+        Ok(changelog) => changelog,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("Cannot read {path:?}")),
+    };
+    if has_entry(&changelog, settings, &details.name, version) {
+        info!(
+            "The changelog of {} has an entry for {version} already",
+            details.name
+        );
+        return Ok(false);
+    }
+
+    let since = match latest_version {
+        Some(latest_version) => {
+            match published_commit(ctx, root, details, latest_version, tag_format)? {
+                Some(commit) => Some(commit),
+                None => {
+                    warn!(
+                        "Cannot tell which commit {} {latest_version} was published from, so no changelog entry is generated for {version}",
+                        details.name
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+        None => None,
+    };
+    let changes = ctx
+        .git
+        .log(root, since.as_deref(), details.crate_dir()?)?
+        .into_iter()
+        .filter(|(_, message)| {
+            !message.starts_with("[subpub]") && message.trim() != release_commit_message
+        })
+        .map(|(commit, message)| parse_commit(&commit, &message))
+        .collect::<Vec<_>>();
+
+    let heading = match &settings.pattern {
+        Some(pattern) => pattern
+            .replace("{version}", &version.to_string())
+            .replace("{crate}", &details.name),
+        None => format!("## {version}"),
+    };
+    let entry = entry(&format!("{heading} - {}", Date::today()), &changes);
+
+    // The entry goes above the latest one, below the title and any section
+    // of unreleased changes.
+    let mut lines = changelog.lines().collect::<Vec<_>>();
+    let position = lines.iter().position(|line| {
+        line.starts_with("##")
+            && !line.starts_with("###")
+            && !line.to_ascii_lowercase().contains("unreleased")
+    });
+    let changelog = match position {
+        Some(position) => {
+            lines.insert(position, &entry);
+            lines.join("\n") + "\n"
+        }
+        None if changelog.trim().is_empty() => format!("# Changelog\n\n{entry}"),
+        None => format!("{}\n\n{entry}", changelog.trim_end()),
+    };
+    std::fs::write(&path, changelog).with_context(|| format!("Cannot write {path:?}"))?;
+    info!(
+        "Added an entry for {version} with {} commits to the changelog of {} at {path:?}",
+        changes.len(),
+        details.name
+    );
+    Ok(true)
+}
@@ -1,12 +1,33 @@
-use crate::git::{git_checkpoint, GCKP};
+use crate::git::{Git, GCKP};
 use std::path::Path;
 
 pub fn with_save_checkpoint<T, P: AsRef<Path>, F: FnOnce() -> T>(
+    git: &Git,
     root: P,
     func: F,
 ) -> anyhow::Result<T> {
-    git_checkpoint(&root, GCKP::Save)?;
+    git.checkpoint(&root, GCKP::Save)?;
     let result = func();
-    git_checkpoint(&root, GCKP::Save)?;
+    git.checkpoint(&root, GCKP::Save)?;
+    Ok(result)
+}
+
+/// Run `func`, then undo everything it changed in the repository at `root`,
+/// including the commits it made. Changes which weren't committed before are
+/// committed for `func` to start from, and are left uncommitted again
+/// afterwards.
+pub fn with_changes_undone<T, P: AsRef<Path>, F: FnOnce() -> T>(
+    git: &Git,
+    root: P,
+    func: F,
+) -> anyhow::Result<T> {
+    let head = git.head(&root)?;
+    git.checkpoint(&root, GCKP::Save)?;
+    let start = git.head(&root)?;
+    let result = func();
+    git.reset_hard(&root, &start)?;
+    if start != head {
+        git.reset_mixed(&root, &head)?;
+    }
     Ok(result)
 }
@@ -0,0 +1,324 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Checks of the manifests of crates which are about to be published, so that
+//! problems come up before the release starts rather than halfway through it.
+
+use crate::changelog;
+use crate::config::{ChangelogSettings, NativeCheck, NativeChecks, RegistryProfile};
+use crate::crate_details::CrateDetails;
+use anyhow::Context;
+use semver::Version;
+use std::collections::BTreeMap;
+
+/// The Cargo version which stabilized namespaced (`dep:`) and weak (`pkg?/feat`)
+/// dependency features. Older versions can't parse manifests using them.
+const NAMESPACED_AND_WEAK_FEATURES_CARGO_VERSION: Version = Version::new(1, 60, 0);
+
+/// Check that the features of a crate only use syntax which the registry
+/// profile's minimum Cargo version understands. Returns a description of
+/// every offending feature entry.
+pub fn check_feature_syntax(
+    details: &CrateDetails,
+    profile: &RegistryProfile,
+) -> anyhow::Result<Vec<String>> {
+    let min_cargo_version = match &profile.min_cargo_version {
+        Some(min_cargo_version) => min_cargo_version,
+        None => return Ok(vec![]),
+    };
+    if *min_cargo_version >= NAMESPACED_AND_WEAK_FEATURES_CARGO_VERSION {
+        return Ok(vec![]);
+    }
+
+    let mut problems = vec![];
+    for (feature, enables) in details.features()? {
+        for enable in enables {
+            let syntax = if enable.starts_with("dep:") {
+                "namespaced dependency"
+            } else if enable.contains("?/") {
+                "weak dependency"
+            } else {
+                continue;
+            };
+            problems.push(format!(
+                "{}: feature \"{feature}\" enables \"{enable}\", which uses {syntax} syntax, requiring Cargo {NAMESPACED_AND_WEAK_FEATURES_CARGO_VERSION}, but the registry has to support Cargo {min_cargo_version}",
+                details.name
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// The Cargo versions which stabilized each edition.
+const EDITION_CARGO_VERSIONS: [(&str, Version); 4] = [
+    ("2015", Version::new(1, 0, 0)),
+    ("2018", Version::new(1, 31, 0)),
+    ("2021", Version::new(1, 56, 0)),
+    ("2024", Version::new(1, 85, 0)),
+];
+
+/// The Cargo versions which stabilized each feature resolver.
+const RESOLVER_CARGO_VERSIONS: [(&str, Version); 3] = [
+    ("1", Version::new(1, 0, 0)),
+    ("2", Version::new(1, 51, 0)),
+    ("3", Version::new(1, 84, 0)),
+];
+
+/// The Cargo version which introduced the `rust-version` field.
+const RUST_VERSION_CARGO_VERSION: Version = Version::new(1, 56, 0);
+
+/// Check the `edition`, `resolver` and `rust-version` of a crate against the
+/// limits of the registry profile. Returns a description of every marker the
+/// registry, or its minimum Cargo version, wouldn't accept.
+pub fn check_manifest_markers(
+    details: &CrateDetails,
+    profile: &RegistryProfile,
+) -> anyhow::Result<Vec<String>> {
+    let mut problems = vec![];
+
+    let markers = [
+        ("edition", &EDITION_CARGO_VERSIONS[..], &profile.max_edition),
+        (
+            "resolver",
+            &RESOLVER_CARGO_VERSIONS[..],
+            &profile.max_resolver,
+        ),
+    ];
+    for (key, cargo_versions, max) in markers {
+        let value = match details.package_field(key)? {
+            Some(value) => value,
+            None => continue,
+        };
+        let position = match cargo_versions.iter().position(|(known, _)| *known == value) {
+            Some(position) => position,
+            None => {
+                problems.push(format!(
+                    "{}: {key} \"{value}\" is not known, expected one of {}",
+                    details.name,
+                    cargo_versions
+                        .iter()
+                        .map(|(known, _)| *known)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+                continue;
+            }
+        };
+        if let Some(max) = max {
+            let max_position = cargo_versions.iter().position(|(known, _)| known == max);
+            if max_position
+                .map(|max_position| position > max_position)
+                .unwrap_or(true)
+            {
+                problems.push(format!(
+                    "{}: {key} \"{value}\" is newer than {max}, the newest {key} the registry accepts",
+                    details.name
+                ));
+            }
+        }
+        let required_cargo_version = &cargo_versions[position].1;
+        if let Some(min_cargo_version) = &profile.min_cargo_version {
+            if min_cargo_version < required_cargo_version {
+                problems.push(format!(
+                    "{}: {key} \"{value}\" requires Cargo {required_cargo_version}, but the registry has to support Cargo {min_cargo_version}",
+                    details.name
+                ));
+            }
+        }
+    }
+
+    if let (Some(rust_version), Some(min_cargo_version)) = (
+        details.package_field("rust-version")?,
+        &profile.min_cargo_version,
+    ) {
+        if *min_cargo_version < RUST_VERSION_CARGO_VERSION {
+            problems.push(format!(
+                "{}: rust-version \"{rust_version}\" requires Cargo {RUST_VERSION_CARGO_VERSION}, but the registry has to support Cargo {min_cargo_version}",
+                details.name
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Check that a crate tells docs.rs which features and targets to document,
+/// since docs.rs only documents the default features otherwise.
+pub fn check_docs_rs(details: &CrateDetails) -> anyhow::Result<Vec<String>> {
+    let docs_rs = match details.docs_rs_metadata()? {
+        Some(docs_rs) => docs_rs,
+        None => {
+            return Ok(vec![format!(
+                "{}: [package.metadata.docs.rs] is missing",
+                details.name
+            )])
+        }
+    };
+    let mut problems = vec![];
+    if !docs_rs.contains_key("all-features") && !docs_rs.contains_key("features") {
+        problems.push(format!(
+            "{}: [package.metadata.docs.rs] sets neither all-features nor features",
+            details.name
+        ));
+    }
+    if !docs_rs.contains_key("targets") {
+        problems.push(format!(
+            "{}: [package.metadata.docs.rs] does not set targets",
+            details.name
+        ));
+    }
+    Ok(problems)
+}
+
+/// Check that a crate whose build script links native libraries declares
+/// what it needs, following the checklist of the config. Returns the items of
+/// the checklist the crate doesn't satisfy.
+pub fn check_native_requirements(
+    details: &CrateDetails,
+    native: &NativeChecks,
+) -> anyhow::Result<Vec<String>> {
+    if native.checklist.is_empty() || !details.links_native_libraries(&native.build_dependencies)? {
+        return Ok(vec![]);
+    }
+
+    let mut problems = vec![];
+    for check in &native.checklist {
+        let problem = match check {
+            NativeCheck::Links => details
+                .package_field("links")?
+                .is_none()
+                .then(|| "package.links is not set".to_owned()),
+            NativeCheck::SystemDepsMetadata => (!details.has_system_deps_metadata()?)
+                .then(|| "[package.metadata.system-deps] is missing".to_owned()),
+            NativeCheck::Readme => {
+                let heading = native.readme_heading.to_lowercase();
+                let documented = match details.readme_path()? {
+                    Some(readme) => std::fs::read_to_string(&readme)?.lines().any(|line| {
+                        line.starts_with('#') && line.to_lowercase().contains(&heading)
+                    }),
+                    None => false,
+                };
+                (!documented)
+                    .then(|| format!("the README has no \"{}\" section", native.readme_heading))
+            }
+        };
+        if let Some(problem) = problem {
+            problems.push(format!(
+                "{}: links native libraries, but {problem} ({check})",
+                details.name
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// Check that a crate isn't about to be published at a version which looks
+/// like a leftover of the template its manifest was created from: 0.0.0,
+/// 0.0.1 for the first release of the crate, or a `-dev` pre-release outside
+/// of snapshot releases.
+pub fn check_placeholder_version(
+    krate: &str,
+    version: &Version,
+    first_release: bool,
+    allow_placeholder: bool,
+    snapshot: bool,
+) -> Option<String> {
+    let placeholder =
+        *version == Version::new(0, 0, 0) || (*version == Version::new(0, 0, 1) && first_release);
+    if placeholder && !allow_placeholder {
+        Some(format!(
+            "{krate} would be published at the placeholder version {version}. Pass --allow-placeholder-version {krate} if that's intended."
+        ))
+    } else if version.pre.as_str().starts_with("dev") && !snapshot {
+        Some(format!(
+            "{krate} would be published at the development version {version}. Pass --snapshot if this is a snapshot release."
+        ))
+    } else {
+        None
+    }
+}
+
+/// Check that the changelog of a crate has an entry for the version the crate
+/// is about to be published at.
+pub fn check_changelog(
+    details: &CrateDetails,
+    settings: &ChangelogSettings,
+    version: &Version,
+) -> anyhow::Result<Option<String>> {
+    let path = changelog::path(details, settings)?;
+    let changelog = match std::fs::read_to_string(&path) {
+        Ok(changelog) => changelog,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Some(format!(
+                "{} has no changelog at {path:?}",
+                details.name
+            )))
+        }
+        Err(err) => return Err(err).with_context(|| format!("Cannot read {path:?}")),
+    };
+
+    let has_entry = changelog::has_entry(&changelog, settings, &details.name, version);
+    Ok((!has_entry).then(|| {
+        format!(
+            "The changelog of {} at {path:?} has no entry for {version}",
+            details.name
+        )
+    }))
+}
+
+/// Find crates which share the same description or keywords, which could be
+/// generated from a template instead. Returns a warning for every group of
+/// such crates.
+pub fn check_duplicated_metadata(crates: &[&CrateDetails]) -> anyhow::Result<Vec<String>> {
+    let mut descriptions: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    let mut keywords: BTreeMap<Vec<String>, Vec<&str>> = BTreeMap::new();
+    for details in crates {
+        if let Some(description) = details.package_field("description")? {
+            descriptions
+                .entry(description)
+                .or_default()
+                .push(&details.name);
+        }
+        if let Some(crate_keywords) = details.package_list("keywords")? {
+            if !crate_keywords.is_empty() {
+                keywords
+                    .entry(crate_keywords)
+                    .or_default()
+                    .push(&details.name);
+            }
+        }
+    }
+
+    let mut warnings = vec![];
+    for (description, krates) in descriptions {
+        if krates.len() > 1 {
+            warnings.push(format!(
+                "{} have the same description \"{description}\"",
+                krates.join(", ")
+            ));
+        }
+    }
+    for (keywords, krates) in keywords {
+        if krates.len() > 1 {
+            warnings.push(format!(
+                "{} have the same keywords [{}]",
+                krates.join(", "),
+                keywords.join(", ")
+            ));
+        }
+    }
+    Ok(warnings)
+}
@@ -0,0 +1,1279 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::{self, Config};
+use crate::context::Context;
+use crate::crate_details::CrateDetails;
+use crate::crates::Crates;
+use crate::external::cargo::Cargo;
+use crate::graph::{GraphFormat, Highlights};
+use crate::plan::{Plan, PlanFormat};
+use crate::publish::{self, PublishOpts};
+use crate::version::Bump;
+use crate::{
+    events, external, git, graph, history, impact, import, interrupt, lockfile, multi_root,
+    ordering, owners, rollback, schema, scratch, secrets, set_version, status, sync_state, yank,
+};
+use anyhow::anyhow;
+use anyhow::Context as _;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    #[clap(about = "Publish crates in order from least to most dependees")]
+    Publish(PublishOpts),
+    #[clap(
+        about = "Work out what publish would do with the same options, without publishing anything, and print it as JSON. The output can be given to publish --from-plan."
+    )]
+    Plan(PlanOpts),
+    #[clap(
+        about = "Compare the crates of the workspace against the registry: their local and published versions, and whether they need publishing"
+    )]
+    Status(StatusOpts),
+    #[clap(
+        about = "Show which crates would need new releases if a crate's version was bumped, without changing anything"
+    )]
+    Impact(ImpactOpts),
+    #[clap(
+        about = "Print the dependency graph of the workspace in DOT or Mermaid format, e.g. to debug the publish order"
+    )]
+    Graph(GraphOpts),
+    #[clap(
+        about = "Print the order crates would be published in, without touching git or the registry"
+    )]
+    Order(OrderOpts),
+    #[clap(
+        about = "Import the settings of cargo-release and release-plz into subpub.toml, printing the result unless --write is given"
+    )]
+    Import(ImportOpts),
+    #[clap(
+        about = "Yank the versions the last publish run published, e.g. to roll back a release which failed half way"
+    )]
+    Yank(YankOpts),
+    #[clap(
+        about = "Undo the last publish run according to its journal: reset the workspace to where the run started, delete the tags it created and optionally yank what it published"
+    )]
+    Rollback(RollbackOpts),
+    #[clap(
+        about = "Show the releases publish made from the workspace, oldest first, with when and by whom they were made"
+    )]
+    History(HistoryOpts),
+    #[clap(
+        about = "Update the crates of the workspace in its lock file, e.g. after they were published by something else than subpub"
+    )]
+    UpdateLockfile(UpdateLockfileOpts),
+    #[clap(
+        about = "Reconcile what subpub knows about the crates of the workspace with the registry, after some of them were published by something else than subpub"
+    )]
+    SyncState(SyncStateOpts),
+    #[clap(
+        about = "Set the version of a crate, or of every crate with --all, and update the requirements of the workspace on it"
+    )]
+    SetVersion(SetVersionOpts),
+    #[clap(
+        about = "Check a registry token, read from stdin, and store it for publishing to that registry"
+    )]
+    Login(LoginOpts),
+    #[clap(
+        about = "Add owners to the published crates of the workspace, or remove them, e.g. a team after new crates were published"
+    )]
+    Owners(OwnersOpts),
+    #[clap(
+        about = "Print the JSON schema of a document subpub writes, e.g. plans, for tools which read them"
+    )]
+    Schema(SchemaOpts),
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct PlanOpts {
+    #[clap(flatten)]
+    publish: PublishOpts,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "json",
+        help = "How to print the plan. Only the JSON output can be given to publish --from-plan; the other formats imitate the dry runs of cargo-workspaces and cargo-smart-release."
+    )]
+    plan_format: PlanFormat,
+
+    #[clap(
+        long,
+        help = "Only look at the workspace, for checking every pull request in seconds: the registry isn't queried, versions are looked up among those cached by previous runs, and crates aren't packaged, so a crate whose version is published is assumed not to have changed. Fails if something would block the release, e.g. a dependency on a crate with publish = false or a dependency cycle."
+    )]
+    fast_plan: bool,
+
+    #[clap(
+        long,
+        help = "Never run git in the workspace, e.g. in pre-commit hooks or on read-only checkouts. The workspace, with its uncommitted changes, is copied to a temporary directory and examined there instead, and packages are compared by their files, as with --compare-contents."
+    )]
+    no_git: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct StatusOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(long, help = "Print the status as line-delimited JSON")]
+    json: bool,
+
+    #[clap(
+        long,
+        help = "Compare the files of the packages rather than the packages byte for byte, as with publish --compare-contents"
+    )]
+    compare_contents: bool,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "Read the published versions from this sparse index rather than from the registry API, as with publish --index-url"
+    )]
+    index_url: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "Query the crates.io-compatible API at this URL rather than the one in SPUB_CRATES_API, as with publish --api-url"
+    )]
+    api_url: Option<String>,
+
+    #[clap(
+        long,
+        help = "Never run git in the workspace, e.g. in pre-commit hooks or on read-only checkouts. The workspace, with its uncommitted changes, is copied to a temporary directory and examined there instead, and packages are compared by their files, as with --compare-contents."
+    )]
+    no_git: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct ImpactOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(help = "The crate whose version would be bumped")]
+    krate: String,
+
+    #[clap(long, arg_enum, help = "How the crate's version would be bumped")]
+    bump: Bump,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct GraphOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(long, arg_enum, default_value = "dot", help = "The output format")]
+    format: GraphFormat,
+
+    #[clap(
+        short = 'e',
+        long = "exclude",
+        help = "Crates to be excluded, as with publish --exclude."
+    )]
+    exclude: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Highlight the crates which publish would exclude, either because of --exclude or because of the exclude-kinds and skip-bin-only settings."
+    )]
+    highlight_excluded: bool,
+
+    #[clap(
+        long,
+        value_name = "PLAN",
+        help = "Highlight the crates which need publishing according to a plan, as printed by the plan subcommand."
+    )]
+    highlight_plan: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Fail rather than run git in the workspace. This subcommand doesn't need git, so this only makes sure it stays that way, e.g. in pre-commit hooks."
+    )]
+    no_git: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct OrderOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only print these crates and the crates they depend on, which publish --crate would process."
+    )]
+    crates: Vec<String>,
+
+    #[clap(
+        short = 'e',
+        long = "exclude",
+        help = "Crates to be excluded, as with publish --exclude."
+    )]
+    exclude: Vec<String>,
+
+    #[clap(long, arg_enum, default_value = "text", help = "The output format")]
+    format: OrderFormat,
+
+    #[clap(
+        long,
+        help = "Fail rather than run git in the workspace. This subcommand doesn't need git, so this only makes sure it stays that way, e.g. in pre-commit hooks."
+    )]
+    no_git: bool,
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy)]
+enum OrderFormat {
+    /// One crate per line.
+    Text,
+    /// A JSON array of the crates.
+    Json,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct ImportOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(long, help = "Update subpub.toml instead of printing it")]
+    write: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct YankOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only yank these crates. Crates the last run didn't publish are yanked at --version."
+    )]
+    crates: Vec<String>,
+
+    #[clap(long, help = "Only yank this version")]
+    version: Option<semver::Version>,
+
+    #[clap(
+        long,
+        help = "Read what the last run published from its journal rather than from its state, so that crates it published right before it died, without saving its state, are yanked as well"
+    )]
+    last_run: bool,
+
+    #[clap(long, help = "Show what would be yanked, without yanking anything")]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        value_name = "REGISTRY",
+        help = "The registry to yank from, as with publish --registry"
+    )]
+    registry: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct RollbackOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(long, help = "Yank the versions the last run published as well")]
+    yank: bool,
+
+    #[clap(
+        long,
+        help = "Show what would be undone, without changing the workspace, the tags or the registry"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        value_name = "REGISTRY",
+        help = "The registry to yank from, as with publish --registry"
+    )]
+    registry: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct OwnersOpts {
+    #[clap(subcommand)]
+    action: Option<OwnersAction>,
+
+    #[clap(long, required = true, help = "Path to the workspace root")]
+    root: Option<PathBuf>,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only update these crates, rather than every published crate of the workspace"
+    )]
+    crates: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "LOGIN",
+        help = "Make sure this user or team, e.g. github:org:team, owns the crates. Users are invited, and only become owners once they accept. Can be given more than once."
+    )]
+    add: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "LOGIN",
+        help = "Make sure this user or team doesn't own the crates. Can be given more than once."
+    )]
+    remove: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Show which crates would be updated, without updating them"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        value_name = "REGISTRY",
+        help = "The registry of the crates, as with publish --registry"
+    )]
+    registry: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum OwnersAction {
+    #[clap(
+        about = "Hand every published crate of the workspace over to a team or user, e.g. when a maintainer leaves"
+    )]
+    Transfer(TransferOpts),
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct TransferOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only transfer these crates, rather than every published crate of the workspace"
+    )]
+    crates: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "LOGIN",
+        help = "The team, e.g. github:org:team, or user the crates are handed over to. Users are invited, and only become owners once they accept."
+    )]
+    to: String,
+
+    #[clap(
+        long,
+        value_name = "LOGIN",
+        help = "The departing owner, removed from every crate once --to owns it. Crates a user was invited to keep it until the invitation is accepted; run the transfer again after that to complete it."
+    )]
+    from: Option<String>,
+
+    #[clap(
+        long,
+        help = "Show which crates would be updated, without updating them"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        value_name = "REGISTRY",
+        help = "The registry of the crates, as with publish --registry"
+    )]
+    registry: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct HistoryOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(help = "Only show the releases of this crate")]
+    krate: Option<String>,
+
+    #[clap(long, help = "Print the releases as line-delimited JSON")]
+    json: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct UpdateLockfileOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only update these crates, rather than every crate of the workspace"
+    )]
+    crates: Vec<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct SetVersionOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        value_name = "[CRATE] VERSION",
+        min_values = 1,
+        max_values = 2,
+        required = true,
+        help = "The crate and the version to set it to, or only the version with --all"
+    )]
+    args: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Set the version of every crate of the workspace, and the one of [workspace.package], for a uniform workspace version"
+    )]
+    all: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct SyncStateOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Only reconcile these crates, rather than every crate of the workspace which can be published"
+    )]
+    crates: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Show what would be reconciled, without changing the workspace or the run state"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        value_name = "REGISTRY",
+        help = "The registry of the crates, as with publish --registry"
+    )]
+    registry: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct LoginOpts {
+    #[clap(
+        long,
+        default_value = external::cargo::CRATES_IO,
+        help = "The registry to log into, as named in the Cargo configuration"
+    )]
+    registry: String,
+
+    #[clap(
+        long,
+        help = "The API of the registry, e.g. https://crates.io/api/v1, to check the token against. Defaults to SPUB_CRATES_API."
+    )]
+    api: Option<String>,
+}
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+struct SchemaOpts {
+    #[clap(arg_enum, help = "The document to print the schema of")]
+    document: schema::Document,
+}
+
+/// The command line interface, where publish and plan accept `--root` more
+/// than once.
+fn command() -> clap::Command<'static> {
+    let mut command = Args::command();
+    for name in ["publish", "plan"] {
+        if let Some(subcommand) = command.find_subcommand_mut(name) {
+            *subcommand = subcommand
+                .clone()
+                .mut_arg("root", |arg| arg.multiple_occurrences(true));
+        }
+    }
+    command
+}
+
+/// Parse the arguments, where publish and plan take several `--root`s, e.g.
+/// `--root a --crate x --root b --exclude y`: `--crate` and `--exclude` apply
+/// to the root they follow, and every other option to all of them. With
+/// several roots, the first one's options are returned along with the
+/// commands for the other roots.
+fn parse_args() -> anyhow::Result<(Args, Vec<Command>)> {
+    let matches = command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    let scopes = match matches.subcommand() {
+        Some(("publish" | "plan", matches)) => root_scopes(matches)?,
+        _ => vec![],
+    };
+    if scopes.len() < 2 {
+        return Ok((args, vec![]));
+    }
+
+    let mut other_roots = vec![];
+    for scope in &scopes[1..] {
+        let mut command = args.command.clone();
+        scope.apply(&mut command);
+        other_roots.push(command);
+    }
+    scopes[0].apply(&mut args.command);
+    Ok((args, other_roots))
+}
+
+/// A `--root` along with the `--crate` and `--exclude` given after it.
+struct RootScope {
+    root: PathBuf,
+    crates: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl RootScope {
+    fn apply(&self, command: &mut Command) {
+        let opts = match command {
+            Command::Publish(opts) => opts,
+            Command::Plan(opts) => &mut opts.publish,
+            _ => return,
+        };
+        opts.root = self.root.clone();
+        opts.crates = self.crates.clone();
+        opts.exclude = self.exclude.clone();
+    }
+}
+
+/// The roots given to publish or plan, each with the crates selected for it,
+/// using where clap found the arguments to tell which root they follow.
+fn root_scopes(matches: &clap::ArgMatches) -> anyhow::Result<Vec<RootScope>> {
+    let mut scopes = matches
+        .values_of("root")
+        .into_iter()
+        .flatten()
+        .zip(matches.indices_of("root").into_iter().flatten())
+        .map(|(root, index)| {
+            let scope = RootScope {
+                root: PathBuf::from(root),
+                crates: vec![],
+                exclude: vec![],
+            };
+            (index, scope)
+        })
+        .collect::<Vec<_>>();
+    if scopes.len() < 2 {
+        return Ok(vec![]);
+    }
+
+    for (id, flag) in [("crates", "--crate"), ("exclude", "--exclude")] {
+        let values = matches
+            .values_of(id)
+            .into_iter()
+            .flatten()
+            .zip(matches.indices_of(id).into_iter().flatten());
+        for (value, index) in values {
+            let scope = match scopes.iter_mut().rev().find(|(root, _)| *root < index) {
+                Some((_, scope)) => scope,
+                None => anyhow::bail!(
+                    "{flag} {value} is given before the first --root, so it's not clear which root it applies to. Give it after that --root."
+                ),
+            };
+            if id == "crates" {
+                scope.crates.push(value.to_string());
+            } else {
+                scope.exclude.push(value.to_string());
+            }
+        }
+    }
+    Ok(scopes.into_iter().map(|(_, scope)| scope).collect())
+}
+
+/// Run the `subpub` command line interface with the arguments of the process.
+pub fn run_cli() -> anyhow::Result<()> {
+    // With several roots, the first one's arguments drive the options which
+    // aren't about the crates, e.g. the output format.
+    let (args, other_roots) = parse_args()?;
+
+    let porcelain = matches!(&args.command, Command::Publish(opts) if opts.porcelain);
+    if porcelain {
+        events::enable();
+    }
+    // Logs go to stderr when stdout is meant to be parsed.
+    let logs_to_stderr = porcelain
+        || matches!(
+            &args.command,
+            Command::Plan(_)
+                | Command::Graph(_)
+                | Command::Order(_)
+                | Command::Status(_)
+                | Command::Import(_)
+                | Command::History(_)
+        );
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .with(
+            tracing_subscriber::fmt::layer()
+                .without_time()
+                .with_writer(secrets::Redacting(if logs_to_stderr {
+                    BoxMakeWriter::new(std::io::stderr)
+                } else {
+                    BoxMakeWriter::new(std::io::stdout)
+                }))
+                .with_target(false),
+        )
+        // Errors are printed to stderr as well, unless all logs go there.
+        .with((!logs_to_stderr).then(|| {
+            tracing_subscriber::fmt::layer()
+                .without_time()
+                .with_writer(secrets::Redacting(std::io::stderr))
+                .with_target(false)
+                .with_filter(tracing_subscriber::filter::LevelFilter::ERROR)
+        }))
+        .init();
+
+    secrets::register_env_vars();
+    if matches!(args.command, Command::Publish(_)) {
+        interrupt::install();
+    }
+
+    let result = match args.command {
+        Command::Publish(opts) if !other_roots.is_empty() => multi_root::publish_roots(
+            std::iter::once(opts)
+                .chain(other_roots.into_iter().filter_map(|command| match command {
+                    Command::Publish(opts) => Some(opts),
+                    _ => None,
+                }))
+                .collect(),
+        )
+        .map(|_| ()),
+        Command::Publish(opts) => publish::publish(opts).map(|_| ()),
+        Command::Plan(opts) if !other_roots.is_empty() => plan_roots(
+            std::iter::once(opts)
+                .chain(other_roots.into_iter().filter_map(|command| match command {
+                    Command::Plan(opts) => Some(opts),
+                    _ => None,
+                }))
+                .collect(),
+        ),
+        Command::Plan(opts) => plan(opts),
+        Command::Status(opts) => status(opts),
+        Command::Impact(opts) => impact(opts),
+        Command::Graph(opts) => graph(opts),
+        Command::Order(opts) => order(opts),
+        Command::Import(opts) => import(opts),
+        Command::Yank(opts) => yank(opts),
+        Command::Rollback(opts) => rollback(opts),
+        Command::History(opts) => history(opts),
+        Command::UpdateLockfile(opts) => update_lockfile(opts),
+        Command::SyncState(opts) => sync_state(opts),
+        Command::SetVersion(opts) => set_version(opts),
+        Command::Login(opts) => login(opts),
+        Command::Owners(opts) => owners(opts),
+        Command::Schema(opts) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema::schema(opts.document))?
+            );
+            Ok(())
+        }
+    };
+    events::emit_result(&result);
+
+    result.map_err(|err| anyhow!(secrets::redact(&format!("{err:?}"))))
+}
+
+fn plan(opts: PlanOpts) -> anyhow::Result<()> {
+    if opts.fast_plan {
+        let fast_plan = publish::fast_plan(opts.publish)?;
+        print!("{}", fast_plan.plan.render(opts.plan_format)?);
+        if !fast_plan.blockers.is_empty() {
+            for blocker in &fast_plan.blockers {
+                error!("{blocker}");
+            }
+            anyhow::bail!("The release would be blocked, see the errors above");
+        }
+        return Ok(());
+    }
+    let plan = if opts.no_git {
+        let mut ctx = publish::context(&opts.publish)?;
+        ctx.git.forbid_git_in(&opts.publish.root)?;
+        let scratch = scratch::scratch_copy(&opts.publish.root)?;
+        publish::plan_with(
+            &ctx,
+            PublishOpts {
+                root: scratch.root.clone(),
+                compare_contents: true,
+                ..opts.publish
+            },
+        )?
+    } else {
+        publish::plan(opts.publish)?
+    };
+    print!("{}", plan.render(opts.plan_format)?);
+    Ok(())
+}
+
+fn plan_roots(opts: Vec<PlanOpts>) -> anyhow::Result<()> {
+    if opts.iter().any(|opts| opts.fast_plan || opts.no_git) {
+        anyhow::bail!("--fast-plan and --no-git cannot be used with several roots");
+    }
+    let plan_format = opts[0].plan_format;
+    let plan = multi_root::plan_roots(opts.into_iter().map(|opts| opts.publish).collect())?;
+    print!("{}", plan.render(plan_format)?);
+    Ok(())
+}
+
+fn update_lockfile(opts: UpdateLockfileOpts) -> anyhow::Result<()> {
+    let ctx = local_context(&opts.root)?;
+    let krates = if opts.crates.is_empty() {
+        let crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+        let mut krates = crates.details.into_keys().collect::<Vec<_>>();
+        krates.sort();
+        krates
+    } else {
+        opts.crates
+    };
+    lockfile::update_lockfile(&ctx, &opts.root, &krates)
+}
+
+fn sync_state(opts: SyncStateOpts) -> anyhow::Result<()> {
+    let ctx = registry_context(&opts.root, opts.registry)?;
+    let mut crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+    let sync = sync_state::sync_state(&ctx, &opts.root, &mut crates, &opts.crates, opts.dry_run)?;
+    if sync.behind.is_empty() {
+        info!("The versions of the workspace are up to date with the registry");
+    }
+    if !sync.processed.is_empty() {
+        if opts.dry_run {
+            info!(
+                "Would record {} as processed by the unfinished run",
+                sync.processed.join(", ")
+            );
+        } else if sync.finished_run {
+            info!("Every crate of the unfinished run is published now, so the run is finished");
+        } else {
+            info!(
+                "Recorded {} as processed by the unfinished run",
+                sync.processed.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn set_version(opts: SetVersionOpts) -> anyhow::Result<()> {
+    let (krate, version) = match (opts.all, &opts.args[..]) {
+        (true, [version]) => (None, version),
+        (false, [krate, version]) => (Some(krate.as_str()), version),
+        (true, _) => {
+            anyhow::bail!("--all sets the version of every crate, so only the version is given")
+        }
+        (false, _) => anyhow::bail!(
+            "Both the crate and the version have to be given, or --all and the version"
+        ),
+    };
+    let version = semver::Version::parse(version)
+        .with_context(|| format!("Cannot parse the version {version:?}"))?;
+
+    let ctx = local_context(&opts.root)?;
+    let mut crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+    let changed = set_version::set_version(&opts.root, &mut crates, krate, &version)?;
+    if changed.is_empty() {
+        info!("Every crate is at {version} already");
+    }
+    Ok(())
+}
+
+fn login(opts: LoginOpts) -> anyhow::Result<()> {
+    let api = match opts.api {
+        Some(api) => api,
+        None => std::env::var("SPUB_CRATES_API")
+            .context("Either --api or SPUB_CRATES_API has to be set")?,
+    };
+
+    eprintln!("Paste the token for {} below", opts.registry);
+    let mut token = String::new();
+    std::io::stdin().read_line(&mut token)?;
+    let token = token.trim();
+    if token.is_empty() {
+        anyhow::bail!("No token was given");
+    }
+    secrets::register(token);
+
+    let owner = external::crates_io::token_owner(&api, token)?;
+    Cargo::default().login(&opts.registry, token)?;
+    info!("Stored the token of {owner} for {}", opts.registry);
+
+    Ok(())
+}
+
+fn status(opts: StatusOpts) -> anyhow::Result<()> {
+    let config = Config::load(&opts.root)?;
+    let mut ctx = Context::new(
+        &opts.root,
+        &config,
+        Cargo::new(None, config.publish.registry.first().map(String::as_str)),
+        opts.index_url.as_deref(),
+        opts.api_url.as_deref(),
+    )?;
+    let statuses = if opts.no_git {
+        ctx.git.forbid_git_in(&opts.root)?;
+        let scratch = scratch::scratch_copy(&opts.root)?;
+        status::status(&ctx, &scratch.root, true)?
+    } else {
+        status::status(&ctx, &opts.root, opts.compare_contents)?
+    };
+    if opts.json {
+        for status in &statuses {
+            println!("{}", serde_json::to_string(status)?);
+        }
+        return Ok(());
+    }
+
+    let width = statuses
+        .iter()
+        .map(|status| status.krate.len())
+        .max()
+        .unwrap_or(0);
+    for status in &statuses {
+        let published_version = status
+            .published_version
+            .as_ref()
+            .map(|version| version.to_string())
+            .unwrap_or_else(|| "-".into());
+        let state = if !status.needs_publishing {
+            "up to date"
+        } else if status.published_version.is_none() {
+            "never published"
+        } else {
+            "needs publishing"
+        };
+        println!(
+            "{:width$}  {:>12}  {:>12}  {state}",
+            status.krate, status.version, published_version
+        );
+    }
+    Ok(())
+}
+
+fn impact(opts: ImpactOpts) -> anyhow::Result<()> {
+    let crates = Crates::load(opts.root.clone())?;
+    let impact = impact::impact(&crates, &opts.krate, opts.bump)?;
+
+    println!("Requirement updates:");
+    if impact.requirement_updates.is_empty() {
+        println!("  none");
+    }
+    for update in &impact.requirement_updates {
+        println!(
+            "  {}: {} {} -> {}",
+            update.krate,
+            update.dependency,
+            update
+                .version_req
+                .as_ref()
+                .map(|version_req| version_req.to_string())
+                .unwrap_or_else(|| "(no version)".into()),
+            update.new_version
+        );
+    }
+
+    println!("New releases:");
+    for release in &impact.releases {
+        print!(
+            "  {} {} -> {}",
+            release.krate, release.version, release.new_version
+        );
+        match &release.cause {
+            Some(cause) => println!(" (because of {cause})"),
+            None => println!(" ({} bump)", opts.bump),
+        }
+    }
+
+    println!("Resulting versions:");
+    let mut releases = impact.releases.iter().collect::<Vec<_>>();
+    releases.sort_by(|a, b| a.krate.cmp(&b.krate));
+    for release in releases {
+        println!("  {} {}", release.krate, release.new_version);
+    }
+
+    Ok(())
+}
+
+fn graph(opts: GraphOpts) -> anyhow::Result<()> {
+    let config = Config::load(&opts.root)?;
+    let mut ctx = Context::local(&config);
+    if opts.no_git {
+        ctx.git.forbid_git_in(&opts.root)?;
+    }
+    let crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+
+    let mut graph = crates.dependency_graph(CrateDetails::deps_to_publish);
+    // Edges which break cycles might be build dependencies, which aren't part
+    // of this graph.
+    let broken_edges = config
+        .break_cycle
+        .iter()
+        .filter(|edge| {
+            graph
+                .get_mut(&edge.krate)
+                .map(|deps| deps.remove(&edge.dependency))
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut highlights = Highlights::default();
+    if opts.highlight_excluded {
+        let mut krates = crates.details.keys().cloned().collect::<Vec<_>>();
+        krates.sort();
+        highlights.excluded = publish::excluded_crates(&crates, &config, &krates, &opts.exclude)?
+            .into_iter()
+            .collect();
+    }
+    if let Some(path) = &opts.highlight_plan {
+        let plan = Plan::load(path)?;
+        highlights.needs_publishing = if plan.releases.is_empty() {
+            plan.crates.into_iter().collect()
+        } else {
+            plan.releases
+                .into_iter()
+                .map(|release| release.krate)
+                .collect()
+        };
+    }
+
+    print!(
+        "{}",
+        graph::render(&graph, &broken_edges, &highlights, opts.format)
+    );
+
+    Ok(())
+}
+
+fn order(opts: OrderOpts) -> anyhow::Result<()> {
+    let config = Config::load(&opts.root)?;
+    let mut ctx = Context::local(&config);
+    if opts.no_git {
+        ctx.git.forbid_git_in(&opts.root)?;
+    }
+    let crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+
+    let publish_order = publish::publish_levels(&crates, &config)?.concat();
+    let order = if opts.crates.is_empty() {
+        let exclude = publish::excluded_crates(&crates, &config, &publish_order, &opts.exclude)?;
+        publish_order
+            .iter()
+            .filter(|krate| !exclude.contains(krate))
+            .filter(|krate| crates.details[*krate].should_be_published)
+            .cloned()
+            .collect::<Vec<_>>()
+    } else {
+        let mut selected = vec![];
+        for krate in &opts.crates {
+            selected.extend(crates.what_needs_publishing(krate, &publish_order)?);
+        }
+        ordering::order_selection(&publish_order, &selected)
+            .into_iter()
+            .filter(|krate| !opts.exclude.contains(krate))
+            .cloned()
+            .collect()
+    };
+
+    match opts.format {
+        OrderFormat::Text => {
+            for krate in &order {
+                println!("{krate}");
+            }
+        }
+        OrderFormat::Json => println!("{}", serde_json::to_string(&order)?),
+    }
+
+    Ok(())
+}
+
+fn import(opts: ImportOpts) -> anyhow::Result<()> {
+    let crates = Crates::load(opts.root.clone())?;
+    let import = import::import(&opts.root, &crates)?;
+
+    for krate in &import.exclude {
+        info!("Excluding {krate}, which is not published by the existing configuration");
+    }
+    if let Some(registry) = &import.registry {
+        info!("Publishing to {registry}, like the existing configuration");
+    }
+    for setting in &import.unsupported {
+        warn!("Cannot import {setting}");
+    }
+
+    let config = import::merge_into_config(&opts.root, &import)?;
+    if opts.write {
+        let path = opts.root.join(config::CONFIG_FILE);
+        std::fs::write(&path, config.to_string())
+            .with_context(|| format!("Cannot write the config at {:?}", path.as_os_str()))?;
+        info!("Updated {:?}", path.as_os_str());
+    } else {
+        print!("{config}");
+    }
+
+    Ok(())
+}
+
+fn yank(opts: YankOpts) -> anyhow::Result<()> {
+    let ctx = registry_context(&opts.root, opts.registry)?;
+    let yanked = yank::yank(
+        &ctx,
+        &opts.root,
+        &opts.crates,
+        opts.version.as_ref(),
+        opts.last_run,
+        opts.dry_run,
+    )?;
+    if !opts.dry_run {
+        info!(
+            "Yanked {}",
+            yanked
+                .iter()
+                .map(|(krate, version)| format!("{krate} {version}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn rollback(opts: RollbackOpts) -> anyhow::Result<()> {
+    let ctx = if opts.yank {
+        registry_context(&opts.root, opts.registry)?
+    } else {
+        local_context(&opts.root)?
+    };
+    let rollback = rollback::rollback(&ctx, &opts.root, opts.yank, opts.dry_run)?;
+    if rollback.reset_to.is_none()
+        && rollback.deleted_branch.is_none()
+        && rollback.deleted_tags.is_empty()
+        && rollback.deleted_remote_tags.is_empty()
+        && rollback.yanked.is_empty()
+    {
+        info!("The last run left nothing behind to undo");
+    } else if !opts.dry_run {
+        info!("The last run was rolled back");
+    }
+    Ok(())
+}
+
+/// The context of a command on the registry the crates are published to: the
+/// one given, or the first one of the configuration, along with the index and
+/// the API the configuration gives.
+fn registry_context(root: &Path, registry: Option<String>) -> anyhow::Result<Context> {
+    let config = Config::load(root)?;
+    let registry = registry.or_else(|| config.publish.registry.first().cloned());
+    let cargo = Cargo::new(None, registry.as_deref());
+    external::cargo::check_registry_configured(root, &cargo.target_registry())?;
+    Context::new(root, &config, cargo, None, None)
+}
+
+/// The context of a command which doesn't publish, which writes requirements
+/// in the styles the config gives, since commands other than `publish` have no
+/// `--requirement-style`.
+fn local_context(root: &Path) -> anyhow::Result<Context> {
+    Ok(Context::local(&Config::load(root)?))
+}
+
+fn owners(opts: OwnersOpts) -> anyhow::Result<()> {
+    if let Some(OwnersAction::Transfer(opts)) = opts.action {
+        return transfer_owners(opts);
+    }
+    if opts.add.is_empty() && opts.remove.is_empty() {
+        anyhow::bail!(
+            "Nothing to do, give the owners to add with --add or to remove with --remove"
+        );
+    }
+    let root = opts
+        .root
+        .context("Path to the workspace root is required")?;
+    let ctx = registry_context(&root, opts.registry)?;
+
+    let crates = Crates::load_crates_in_workspace(root, &ctx)?;
+    let updated = owners::sync_owners(
+        &crates,
+        &opts.crates,
+        &owners::OwnerChanges {
+            add: opts.add,
+            remove: opts.remove,
+        },
+        opts.dry_run,
+    )?;
+    if updated.is_empty() {
+        info!("The owners of every crate are up to date");
+    } else if opts.dry_run {
+        info!("Would update {} crates", updated.len());
+    } else {
+        info!(
+            "Updated {} crates: {}",
+            updated.len(),
+            updated
+                .iter()
+                .map(|(krate, _)| krate.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn transfer_owners(opts: TransferOpts) -> anyhow::Result<()> {
+    if opts.from.as_deref() == Some(opts.to.as_str()) {
+        anyhow::bail!("--to and --from are the same owner, {}", opts.to);
+    }
+    let ctx = registry_context(&opts.root, opts.registry)?;
+
+    let crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+    let transfer = owners::transfer_owners(
+        &crates,
+        &opts.crates,
+        &opts.to,
+        opts.from.as_deref(),
+        opts.dry_run,
+    )?;
+    if transfer.updated.is_empty() && transfer.failed.is_empty() {
+        info!("Every crate is already owned by {}", opts.to);
+    } else if opts.dry_run {
+        info!("Would update {} crates", transfer.updated.len());
+    } else {
+        info!("Updated {} crates", transfer.updated.len());
+    }
+    if !transfer.pending.is_empty() {
+        if let Some(from) = &opts.from {
+            info!(
+                "{from} still owns {} until {} accepts the invitations; run the transfer again after that to remove it",
+                transfer.pending.join(", "),
+                opts.to
+            );
+        }
+    }
+    if !transfer.failed.is_empty() {
+        anyhow::bail!(
+            "Failed to update the owners of {}, run the transfer again to retry them",
+            transfer
+                .failed
+                .iter()
+                .map(|(krate, _)| krate.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn history(opts: HistoryOpts) -> anyhow::Result<()> {
+    let releases = history::load(&git::Git::default(), &opts.root, opts.krate.as_deref())?;
+    if releases.is_empty() {
+        info!("No releases were recorded");
+    }
+    for release in &releases {
+        if opts.json {
+            println!("{}", serde_json::to_string(release)?);
+        } else {
+            println!(
+                "{} {} {} to {} from {} by {}",
+                release.date,
+                release.krate,
+                release.version,
+                release.registry,
+                &release.commit[..release.commit.len().min(12)],
+                release.operator.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(args: &[&str]) -> anyhow::Result<Vec<RootScope>> {
+        let matches = command().try_get_matches_from(args)?;
+        let (_, matches) = matches.subcommand().unwrap();
+        root_scopes(matches)
+    }
+
+    #[test]
+    fn crates_apply_to_the_root_they_follow() {
+        let scopes = scopes(&[
+            "subpub",
+            "publish",
+            "--root",
+            "a",
+            "-c",
+            "x",
+            "--crate",
+            "y",
+            "--root",
+            "b",
+            "--exclude",
+            "z",
+            "--dry-run",
+        ])
+        .unwrap();
+        assert_eq!(scopes.len(), 2);
+        assert_eq!(scopes[0].root, PathBuf::from("a"));
+        assert_eq!(scopes[0].crates, ["x", "y"]);
+        assert!(scopes[0].exclude.is_empty());
+        assert_eq!(scopes[1].root, PathBuf::from("b"));
+        assert!(scopes[1].crates.is_empty());
+        assert_eq!(scopes[1].exclude, ["z"]);
+    }
+
+    #[test]
+    fn a_single_root_has_no_scopes() {
+        assert!(scopes(&["subpub", "plan", "--root", "a", "-c", "x"])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn crates_before_the_first_root_are_rejected() {
+        assert!(scopes(&["subpub", "publish", "-c", "x", "--root", "a", "--root", "b"]).is_err());
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::approvals::ApprovalPolicy;
+use crate::artifacts::PrePackageHook;
+use crate::consumers::Consumer;
+use crate::crate_details::CrateKind;
+use crate::freeze::Freeze;
+use crate::groups::Group;
+use crate::ordering::Edge;
+use crate::post_check::PostCheckScope;
+use crate::publish::{DependencyKind, DocsRsMode, Propagation};
+use crate::template::MetadataTemplate;
+use crate::toml::toml_read;
+use crate::train::Train;
+use crate::version::{BumpOverride, RequirementStyle};
+use anyhow::Context;
+use semver::Version;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "subpub.toml";
+
+/// Settings read from the `subpub.toml` file at the root of the workspace, or
+/// from `[workspace.metadata.subpub]` in the root manifest.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Dependency edges, in the form `"crate -> dependency"`, which are
+    /// ignored when computing the publish order so that cycles can be broken.
+    #[serde(default)]
+    pub break_cycle: Vec<Edge>,
+
+    /// Crates which are excluded from publishing unless they are explicitly
+    /// selected.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Kinds of helper crates, e.g. fuzzers, which are excluded from
+    /// publishing unless they are explicitly selected.
+    #[serde(default)]
+    pub exclude_kinds: Vec<CrateKind>,
+
+    /// Whether crates which only have binary targets are excluded from
+    /// publishing unless they are explicitly selected.
+    #[serde(default)]
+    pub skip_bin_only: bool,
+
+    /// Named release trains, each pinning the versions of a set of crates
+    /// which are released together.
+    #[serde(default)]
+    pub trains: HashMap<String, Train>,
+
+    /// Settings for the registries crates are published to, by registry name.
+    /// crates.io is called "crates-io".
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryProfile>,
+
+    /// Crates which can't be published during a period, or at all, unless
+    /// the freeze is overridden.
+    #[serde(default)]
+    pub freeze: Vec<Freeze>,
+
+    /// If set, crates can only be published from a plan which was approved by
+    /// enough people.
+    pub approvals: Option<ApprovalPolicy>,
+
+    /// The docs.rs settings which `publish --docs-rs inject` adds to crates
+    /// which don't have them.
+    #[serde(default)]
+    pub docs_rs: DocsRsDefaults,
+
+    /// What crates whose build scripts link native libraries are expected to
+    /// declare.
+    #[serde(default)]
+    pub native: NativeChecks,
+
+    /// Templates for the metadata of families of crates, by name. Crates use
+    /// one with `package.metadata.subpub.template`.
+    #[serde(default)]
+    pub templates: HashMap<String, MetadataTemplate>,
+
+    /// Defaults for the options of `publish`, which the command line
+    /// overrides.
+    #[serde(default)]
+    pub publish: PublishDefaults,
+
+    /// Settings of individual crates, by crate name.
+    #[serde(default)]
+    pub crates: HashMap<String, CrateSettings>,
+
+    /// Groups of crates, e.g. by directory, which plans and summaries are
+    /// split into.
+    #[serde(default)]
+    pub groups: Vec<Group>,
+
+    /// Projects depending on the crates, which are checked against the new
+    /// versions after publishing.
+    #[serde(default)]
+    pub consumers: Vec<Consumer>,
+
+    /// Where the changelogs of the crates are, for `--require-changelog` and
+    /// `--generate-changelog`.
+    #[serde(default)]
+    pub changelog: ChangelogSettings,
+}
+
+/// Where the changelog of each crate is and what its entries look like.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ChangelogSettings {
+    /// The changelog of each crate, relative to the directory of the crate,
+    /// where `{crate}` is replaced by the name of the crate. Defaults to
+    /// "CHANGELOG.md".
+    pub path: Option<String>,
+    /// What the line starting the entry of a version looks like, e.g.
+    /// "## [{version}]", where `{version}` and `{crate}` are replaced. By
+    /// default, any Markdown heading mentioning the version starts an entry.
+    pub pattern: Option<String>,
+}
+
+/// The options of `publish` which can be set in the config file, named like
+/// the command line flags. Flags which are switched on in the config file
+/// can't be switched off on the command line, and lists given on the command
+/// line replace those of the config file.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PublishDefaults {
+    #[serde(default)]
+    pub crates: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub include_crates_dependents: bool,
+    pub dependents_depth: Option<usize>,
+    #[serde(default)]
+    pub dependents_kind: Vec<DependencyKind>,
+    #[serde(default)]
+    pub check_registry_metadata: bool,
+    pub publish_timeout: Option<u64>,
+    pub publish_attempts: Option<u32>,
+    #[serde(default)]
+    pub continue_on_error: bool,
+    pub docs_rs: Option<DocsRsMode>,
+    #[serde(default)]
+    pub provenance: bool,
+    #[serde(default)]
+    pub allow_placeholder_version: Vec<String>,
+    #[serde(default)]
+    pub allow_dirty: bool,
+    #[serde(default)]
+    pub snapshot: bool,
+    #[serde(default)]
+    pub post_check: bool,
+    pub post_check_scope: Option<PostCheckScope>,
+    #[serde(default)]
+    pub post_check_command: Vec<String>,
+    #[serde(default)]
+    pub post_check_features: Vec<String>,
+    #[serde(default)]
+    pub post_check_all_features: bool,
+    #[serde(default)]
+    pub skip_consumers: bool,
+    #[serde(default)]
+    pub skip_preflight: bool,
+    #[serde(default)]
+    pub require_changelog: bool,
+    #[serde(default)]
+    pub generate_changelog: bool,
+    #[serde(default)]
+    pub tag: bool,
+    pub tag_format: Option<String>,
+    pub push_tags: Option<String>,
+    #[serde(default)]
+    pub sign: bool,
+    #[serde(default)]
+    pub bump_doc_examples: bool,
+    #[serde(default)]
+    pub semver_checks: bool,
+    #[serde(default)]
+    pub bump: Vec<BumpOverride>,
+    pub requirement_style: Option<RequirementStyle>,
+    pub propagate: Option<Propagation>,
+    #[serde(default)]
+    pub worktree: bool,
+    pub worktree_branch: Option<String>,
+    #[serde(default)]
+    pub check_tags: bool,
+    #[serde(default)]
+    pub squash_checkpoints: bool,
+    pub release_commit_message: Option<String>,
+    pub toolchain: Option<String>,
+    pub index_url: Option<String>,
+    pub api_url: Option<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub registry: Vec<String>,
+    #[serde(default)]
+    pub verify_target: Vec<String>,
+    #[serde(default)]
+    pub notarize: bool,
+    #[serde(default)]
+    pub self_check: bool,
+    #[serde(default)]
+    pub compare_contents: bool,
+    #[serde(default)]
+    pub yank_superseded_prereleases: bool,
+    #[serde(default)]
+    pub yank_prerelease_kind: Vec<String>,
+}
+
+/// Settings which only apply to one crate, overriding those of `publish`.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CrateSettings {
+    /// Exclude the crate from publishing unless it's explicitly selected,
+    /// like the `exclude` setting.
+    #[serde(default)]
+    pub exclude: bool,
+    /// Allow the crate to be published at a placeholder version.
+    #[serde(default)]
+    pub allow_placeholder_version: bool,
+    /// Let Cargo publish the crate with uncommitted changes, e.g. files
+    /// generated by its build.
+    #[serde(default)]
+    pub allow_dirty: bool,
+    /// Check or inject the docs.rs metadata of the crate, whatever the
+    /// `--docs-rs` option is.
+    pub docs_rs: Option<DocsRsMode>,
+    /// Build files the package of the crate needs before it's packaged.
+    pub pre_package: Option<PrePackageHook>,
+    /// The targets to verify the package of the crate for, instead of those
+    /// given by `--verify-target`.
+    pub verify_targets: Option<Vec<String>>,
+    /// How dependents require new versions of the crate, whatever the
+    /// `--requirement-style` option is.
+    pub requirement_style: Option<RequirementStyle>,
+}
+
+/// What a registry, and the Cargo versions used with it, can cope with.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RegistryProfile {
+    /// The oldest Cargo version which has to be able to use the crates
+    /// published to the registry.
+    pub min_cargo_version: Option<Version>,
+
+    /// The newest edition the registry accepts, e.g. "2018".
+    pub max_edition: Option<String>,
+
+    /// The newest feature resolver the registry accepts, e.g. "1".
+    pub max_resolver: Option<String>,
+
+    /// How many crates can be published to the registry per minute. Unlimited
+    /// if it isn't set.
+    pub publish_rate: Option<f64>,
+
+    /// How many crates can be published at once before `publish-rate`
+    /// applies. Defaults to 1.
+    pub publish_burst: Option<u32>,
+
+    /// The crates.io-compatible API of the registry, e.g.
+    /// "https://kellnr.example.com/api/v1", to query instead of SPUB_CRATES_API
+    /// when crates are published to it.
+    pub api: Option<String>,
+
+    /// The sparse index of the registry. When the registry is a mirror, each
+    /// crate published to it is waited for in this index, so that the crates
+    /// depending on it can be published to the mirror next.
+    pub index: Option<String>,
+
+    /// How many seconds to wait after publishing a crate to the registry when
+    /// it's a mirror, e.g. for a registry without a sparse index.
+    pub delay: Option<u64>,
+
+    /// The users or teams, e.g. "github:org:team", which are added as owners
+    /// of every crate published to the registry for the first time, so that
+    /// new crates aren't only owned by the account of the token.
+    #[serde(default)]
+    pub owners: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DocsRsDefaults {
+    #[serde(default = "default_all_features")]
+    pub all_features: bool,
+    #[serde(default = "default_docs_rs_targets")]
+    pub targets: Vec<String>,
+}
+
+impl Default for DocsRsDefaults {
+    fn default() -> DocsRsDefaults {
+        DocsRsDefaults {
+            all_features: default_all_features(),
+            targets: default_docs_rs_targets(),
+        }
+    }
+}
+
+/// Accept either a single string or a list of strings, e.g. for `registry`.
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+fn default_all_features() -> bool {
+    true
+}
+
+fn default_docs_rs_targets() -> Vec<String> {
+    vec!["x86_64-unknown-linux-gnu".into()]
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NativeChecks {
+    /// Build dependencies which give away that a build script builds or links
+    /// native code.
+    #[serde(default = "default_native_build_dependencies")]
+    pub build_dependencies: Vec<String>,
+    #[serde(default = "default_native_checklist")]
+    pub checklist: Vec<NativeCheck>,
+    /// The heading of the README section listing the required system
+    /// dependencies, for [`NativeCheck::Readme`].
+    #[serde(default = "default_readme_heading")]
+    pub readme_heading: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum NativeCheck {
+    /// The crate declares the native library it links with `package.links`.
+    Links,
+    /// The crate lists its system dependencies under
+    /// `[package.metadata.system-deps]`.
+    SystemDepsMetadata,
+    /// The README of the crate has a section about its system dependencies.
+    Readme,
+}
+
+impl Default for NativeChecks {
+    fn default() -> NativeChecks {
+        NativeChecks {
+            build_dependencies: default_native_build_dependencies(),
+            checklist: default_native_checklist(),
+            readme_heading: default_readme_heading(),
+        }
+    }
+}
+
+fn default_native_build_dependencies() -> Vec<String> {
+    [
+        "cc",
+        "cmake",
+        "pkg-config",
+        "bindgen",
+        "vcpkg",
+        "system-deps",
+        "autotools",
+    ]
+    .iter()
+    .map(|dep| dep.to_string())
+    .collect()
+}
+
+fn default_native_checklist() -> Vec<NativeCheck> {
+    vec![NativeCheck::Links]
+}
+
+fn default_readme_heading() -> String {
+    "System requirements".into()
+}
+
+impl Config {
+    /// Load the configuration from the workspace root, falling back to
+    /// `[workspace.metadata.subpub]` in the root manifest and then to the
+    /// defaults.
+    pub fn load<P: AsRef<Path>>(root: P) -> anyhow::Result<Config> {
+        let path = root.as_ref().join(CONFIG_FILE);
+        let manifest_path = root.as_ref().join("Cargo.toml");
+        let metadata = if manifest_path.exists() {
+            toml_read(&manifest_path)?
+                .get("workspace")
+                .and_then(|workspace| workspace.get("metadata"))
+                .and_then(|metadata| metadata.get("subpub"))
+                .cloned()
+        } else {
+            None
+        };
+
+        match (path.exists(), metadata) {
+            (true, Some(_)) => anyhow::bail!(
+                "Settings are given both in {:?} and under [workspace.metadata.subpub] in {:?}, keep only one of them",
+                path.as_os_str(),
+                manifest_path.as_os_str()
+            ),
+            (true, None) => {
+                let contents = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Cannot read the config at {:?}", path.as_os_str()))?;
+                toml_edit::easy::from_str(&contents)
+                    .with_context(|| format!("Cannot parse the config at {:?}", path.as_os_str()))
+            }
+            (false, Some(metadata)) => toml_edit::de::from_item(metadata).with_context(|| {
+                format!(
+                    "Cannot parse [workspace.metadata.subpub] in {:?}",
+                    manifest_path.as_os_str()
+                )
+            }),
+            (false, None) => Ok(Config::default()),
+        }
+    }
+
+    /// The settings of a crate, which are the defaults unless the crate is
+    /// listed under `crates`.
+    pub fn crate_settings(&self, krate: &str) -> CrateSettings {
+        self.crates.get(krate).cloned().unwrap_or_default()
+    }
+
+    /// The crates which have their own requirement style.
+    pub fn requirement_styles(&self) -> BTreeMap<String, RequirementStyle> {
+        self.crates
+            .iter()
+            .filter_map(|(krate, settings)| Some((krate.clone(), settings.requirement_style?)))
+            .collect()
+    }
+
+    /// The profile of any registry, e.g. a mirror.
+    pub fn registry_profile_of(&self, registry: &str) -> RegistryProfile {
+        self.registries.get(registry).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Downstream projects which are built against the crates of a release right
+//! after it's published, to catch breakage in the ecosystem early.
+
+use crate::context::Context;
+use crate::crates::write_dependency_version;
+use crate::events;
+use crate::git::git_clone;
+use crate::plan::PlannedRelease;
+use anyhow::Context as _;
+use std::path::Path;
+use tracing::{error, info};
+
+/// A project depending on crates of the workspace, given either by a git
+/// repository or by the dependencies of a project made up on the spot.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Consumer {
+    pub name: String,
+    /// The repository of the project.
+    pub git: Option<String>,
+    /// The branch of `git` to check, instead of the default one.
+    pub branch: Option<String>,
+    /// The `[dependencies]` of a minimal project, e.g.
+    /// `{ sp-core = { version = "21", features = ["full_crypto"] } }`.
+    pub dependencies: Option<toml_edit::easy::value::Table>,
+}
+
+/// Check each consumer with the dependencies on the released crates bumped to
+/// their new versions, failing if any of them doesn't build.
+pub fn check_consumers(
+    ctx: &Context,
+    consumers: &[Consumer],
+    releases: &[PlannedRelease],
+) -> anyhow::Result<()> {
+    let mut broken = vec![];
+    for consumer in consumers {
+        info!("Checking consumer {}", consumer.name);
+        if let Err(err) = check_consumer(ctx, consumer, releases) {
+            error!(
+                "Consumer {} is broken by the release: {err:?}",
+                consumer.name
+            );
+            broken.push(consumer.name.as_str());
+        }
+    }
+    if !broken.is_empty() {
+        anyhow::bail!(
+            "The crates were published, but the following consumers don't build with them: {}",
+            broken.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn check_consumer(
+    ctx: &Context,
+    consumer: &Consumer,
+    releases: &[PlannedRelease],
+) -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().join(&consumer.name);
+    match (&consumer.git, &consumer.dependencies) {
+        (Some(url), None) => git_clone(url, consumer.branch.as_deref(), &root)?,
+        (None, Some(dependencies)) => create_project(&root, dependencies)?,
+        _ => anyhow::bail!(
+            "Consumer {} needs either git or dependencies, but not both",
+            consumer.name
+        ),
+    }
+    let root = root.canonicalize()?;
+
+    let mut manifests = vec![root.join("Cargo.toml")];
+    for package in ctx.cargo.metadata(&root)?.packages {
+        if !manifests.contains(&package.manifest_path) {
+            manifests.push(package.manifest_path);
+        }
+    }
+    for manifest in &manifests {
+        for release in releases {
+            write_dependency_version(
+                &ctx.requirement_styles,
+                manifest,
+                &release.krate,
+                &release.next_version,
+            )?;
+        }
+    }
+
+    // Resolve the dependencies again, as a new user of the crates would.
+    for args in [&["update"][..], &["check", "--workspace", "--all-targets"]] {
+        let mut cmd = ctx.cargo.command();
+        cmd.current_dir(&root)
+            .args(args)
+            .stdout(events::child_stdout());
+        if !cmd.status()?.success() {
+            anyhow::bail!("Command failed: {cmd:?}");
+        }
+    }
+    Ok(())
+}
+
+fn create_project(root: &Path, dependencies: &toml_edit::easy::value::Table) -> anyhow::Result<()> {
+    std::fs::create_dir_all(root.join("src"))?;
+    std::fs::write(root.join("src").join("lib.rs"), "")?;
+    let manifest = format!(
+        "[package]\nname = \"subpub-consumer\"\nversion = \"0.0.0\"\nedition = \"2021\"\npublish = false\n\n[dependencies]\n{}",
+        toml_edit::easy::to_string(dependencies).context("Cannot write the dependencies")?
+    );
+    std::fs::write(root.join("Cargo.toml"), manifest)?;
+    Ok(())
+}
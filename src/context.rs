@@ -0,0 +1,94 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::config::Config;
+use crate::crates::RequirementStyles;
+use crate::external::cargo::{self, Cargo};
+use crate::external::crates_io::Registry;
+use crate::git::Git;
+use crate::secrets;
+use std::path::Path;
+
+/// The settings a run works with: how Cargo and git are run, the registry the
+/// crates are published to, and the styles requirements are written in. They
+/// are handed to whatever needs them, so that runs on different workspaces,
+/// e.g. the roots of a multi-root plan, don't step on each other.
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub cargo: Cargo,
+    pub registry: Registry,
+    pub git: Git,
+    pub requirement_styles: RequirementStyles,
+}
+
+impl Context {
+    /// The context of a run on the workspace at `root`, configured by
+    /// `config`, which runs `cargo` and publishes to its target registry. The
+    /// versions of crates are read from the index at `index_url` and the API
+    /// at `api_url` if they are given, otherwise from those of the
+    /// configuration.
+    pub fn new(
+        root: &Path,
+        config: &Config,
+        cargo: Cargo,
+        index_url: Option<&str>,
+        api_url: Option<&str>,
+    ) -> anyhow::Result<Context> {
+        let target_registry = cargo.target_registry();
+        let mut registry = Registry::new(&target_registry);
+        if let Some(index_url) = index_url.or(config.publish.index_url.as_deref()) {
+            registry.read_versions_from_index(index_url);
+        }
+        registry.use_registry_index(cargo::registry_index(root, &target_registry)?.as_deref());
+        let api_url = api_url
+            .or(config.publish.api_url.as_deref())
+            .map(String::from)
+            .or_else(|| config.registry_profile_of(&target_registry).api);
+        if let Some(api_url) = api_url {
+            let token = match std::env::var("SPUB_CRATES_API_TOKEN") {
+                Ok(token) => {
+                    secrets::register(token.as_str());
+                    Some(token)
+                }
+                Err(_) => cargo.registry_token(&target_registry)?,
+            };
+            registry.use_api(&api_url, token);
+        }
+
+        Ok(Context {
+            cargo,
+            registry,
+            ..Context::local(config)
+        })
+    }
+
+    /// The context of a command which doesn't publish, on a workspace
+    /// configured by `config`: Cargo is run as it is, with its default
+    /// registry, and requirements are written in the styles of the
+    /// configuration.
+    pub fn local(config: &Config) -> Context {
+        let cargo = Cargo::default();
+        Context {
+            registry: Registry::new(&cargo.target_registry()),
+            cargo,
+            git: Git::default(),
+            requirement_styles: RequirementStyles {
+                style: config.publish.requirement_style,
+                per_dependency: config.requirement_styles(),
+            },
+        }
+    }
+}
@@ -14,120 +14,193 @@
 // You should have received a copy of the GNU General Public License
 // along with subpub.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::artifacts::{self, PrePackageHook};
+use crate::config::DocsRsDefaults;
+use crate::context::Context;
 use crate::crates::{
-    edit_all_dependency_sections, write_dependency_version, CrateDependencyKey,
-    CRATE_DEPENDENCY_KEYS,
+    dependency_package, edit_all_dependency_sections, is_inherited, set_dependencies_registry,
+    write_dependency_version, CRATE_DEPENDENCY_KEYS,
 };
-use crate::toml::{toml_read, toml_write};
-use crate::version::maybe_bump_for_breaking_change;
-use crate::{external, git::*};
-use anyhow::{anyhow, Context};
-use semver::Version;
-
-use std::collections::HashSet;
+use crate::external::crates_io::{VersionDependency, VersionMetadata};
+use crate::provenance::Provenance;
+use crate::template::{self, MetadataTemplate};
+use crate::toml::{set_value, toml_read, toml_write};
+use crate::{events, external, git::*, package};
+use anyhow::Context as _;
+use semver::{Version, VersionReq};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use tracing::{info, span, Level};
 
+/// Kinds of helper crates which usually aren't meant to be released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum CrateKind {
+    Fuzz,
+    Example,
+    TestUtil,
+    Bench,
+}
+
+/// Dependencies which give away a fuzzing crate.
+const FUZZ_DEPENDENCIES: [&str; 3] = ["libfuzzer-sys", "honggfuzz", "afl"];
+
 #[derive(Debug, Clone)]
 pub struct CrateDetails {
     pub name: String,
     pub version: Version,
+    /// The workspace crates this crate depends on, including those only
+    /// depended on for some targets, e.g. under
+    /// `[target.'cfg(windows)'.dependencies]`.
     pub deps: HashSet<String>,
     pub build_deps: HashSet<String>,
     pub dev_deps: HashSet<String>,
+    /// The version requirements on workspace dependencies, for those which
+    /// specify one.
+    pub dep_version_reqs: HashMap<String, VersionReq>,
     pub should_be_published: bool,
     pub toml_path: PathBuf,
     pub readme: Option<String>,
+    pub kind: Option<CrateKind>,
+    pub has_lib: bool,
+    pub has_bin: bool,
+    /// The `[package]` fields which are filled in from the crate's template
+    /// when it's published. See [`CrateDetails::render_template`].
+    pub template_metadata: MetadataTemplate,
+    /// How to build the files the package of the crate needs, if any.
+    pub pre_package: Option<PrePackageHook>,
+    /// How the package of the crate is built when it's verified.
+    pub verify: VerifyHints,
 }
 
-impl CrateDetails {
-    /// Read a Cargo.toml file, pulling out the information we care about.
-    pub fn load(toml_path: PathBuf) -> anyhow::Result<CrateDetails> {
-        let toml: toml_edit::Document = toml_read(&toml_path)?;
-
-        let name = toml
-            .get("package")
-            .ok_or_else(|| anyhow!("Cannot read [package] section from toml file."))?
-            .get("name")
-            .ok_or_else(|| anyhow!("Cannot read package.name from toml file."))?
-            .as_str()
-            .ok_or_else(|| anyhow!("package.name is not a string, but should be."))?
-            .to_owned();
-
-        let readme = if let Some(readme) = toml
-            .get("package")
-            .ok_or_else(|| anyhow!("Cannot read [package] section from toml file."))?
-            .get("readme")
-        {
-            if let Some(readme) = readme.as_str() {
-                Some(readme.to_owned())
-            } else {
-                anyhow::bail!("package.readme is not a string, but should be.");
-            }
-        } else {
-            None
-        };
+/// How the package of a crate has to be built to be verified, read from
+/// `[package.metadata.subpub.verify]`, e.g. for no-std crates which only build
+/// without their default features on some targets.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VerifyHints {
+    /// The features to build the package with.
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Whether to build the package without its default features.
+    #[serde(default)]
+    pub no_default_features: bool,
+    /// The targets to verify the package for, instead of those given by
+    /// `--verify-target`.
+    pub targets: Option<Vec<String>>,
+}
 
-        let version = toml
-            .get("package")
-            .ok_or_else(|| anyhow!("Cannot read [package] section from {name}."))?
-            .get("version")
-            .ok_or_else(|| anyhow!("Cannot read package.version from {name}."))?
-            .as_str()
-            .ok_or_else(|| anyhow!("Cannot read package.version from {name}."))?
-            .to_owned();
+impl VerifyHints {
+    pub fn features(&self) -> external::cargo::Features {
+        external::cargo::Features {
+            features: self.features.clone(),
+            no_default_features: self.no_default_features,
+        }
+    }
+}
 
-        let version = Version::parse(&version)
-            .with_context(|| format!("Cannot parse SemVer compatible version from {name}"))?;
+impl CrateDetails {
+    /// Read a Cargo.toml file of the workspace at `root`, pulling out the
+    /// information we care about.
+    pub fn load(
+        package: &external::cargo::Package,
+        toml_path: PathBuf,
+        root: &Path,
+    ) -> anyhow::Result<CrateDetails> {
+        let name = package.name.clone();
 
         let mut build_deps = HashSet::new();
         let mut dev_deps = HashSet::new();
         let mut deps = HashSet::new();
-
-        for key in CRATE_DEPENDENCY_KEYS {
-            match key {
-                CrateDependencyKey::BuildDependencies => {
-                    for item in get_all_dependency_sections(&toml, &key.to_string()) {
-                        build_deps.extend(filter_workspace_dependencies(item)?)
-                    }
-                }
-                CrateDependencyKey::Dependencies => {
-                    for item in get_all_dependency_sections(&toml, &key.to_string()) {
-                        deps.extend(filter_workspace_dependencies(item)?)
-                    }
-                }
-                CrateDependencyKey::DevDependencies => {
-                    for item in get_all_dependency_sections(&toml, &key.to_string()) {
-                        dev_deps.extend(filter_workspace_dependencies(item)?)
-                    }
-                }
+        let mut dep_version_reqs = HashMap::new();
+
+        // Only dependencies given by a path can be on crates of the workspace.
+        for dep in package.dependencies.iter().filter(|dep| dep.path.is_some()) {
+            let crate_deps = match dep.kind.as_deref() {
+                Some("build") => &mut build_deps,
+                Some("dev") => &mut dev_deps,
+                _ => &mut deps,
+            };
+            // Cargo reports dependencies without a version requirement as "*".
+            if dep.req != VersionReq::STAR {
+                dep_version_reqs
+                    .entry(dep.name.clone())
+                    .or_insert_with(|| dep.req.clone());
             }
+            crate_deps.insert(dep.name.clone());
         }
 
-        let should_be_published = toml
-            .get("package")
-            .ok_or_else(|| anyhow!("Cannot read [package] section from toml file."))?
-            .get("publish")
-            .and_then(|value| value.as_bool())
+        let should_be_published = package
+            .publish
+            .as_ref()
+            .map(|registries| !registries.is_empty())
             .unwrap_or(true);
 
+        let kind = detect_crate_kind(&name, toml_path.strip_prefix(root)?, package);
+
+        let verify = match package
+            .metadata
+            .get("subpub")
+            .and_then(|subpub| subpub.get("verify"))
+        {
+            Some(verify) => serde_json::from_value(verify.clone())
+                .with_context(|| format!("Cannot read package.metadata.subpub.verify of {name}"))?,
+            None => VerifyHints::default(),
+        };
+
+        let has_target = |kinds: &[&str]| {
+            package.targets.iter().any(|target| {
+                target
+                    .kind
+                    .iter()
+                    .any(|kind| kinds.contains(&kind.as_str()))
+            })
+        };
+        let has_lib = has_target(&["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"]);
+        let has_bin = has_target(&["bin"]);
+
         Ok(CrateDetails {
             name,
-            version,
+            version: package.version.clone(),
             deps,
             dev_deps,
             build_deps,
+            dep_version_reqs,
             toml_path,
             should_be_published,
-            readme,
+            readme: package.readme.clone(),
+            kind,
+            has_lib,
+            has_bin,
+            template_metadata: MetadataTemplate::new(),
+            pre_package: None,
+            verify,
         })
     }
 
     pub fn write_own_version(&mut self, new_version: Version) -> anyhow::Result<()> {
         // Load TOML file and update the version in that.
         let mut toml = self.read_toml()?;
-        toml["package"]["version"] = toml_edit::value(new_version.to_string());
+        // Bumping the version in [workspace.package] would bump every crate
+        // inheriting it, whether it needs a release or not, so the crate gets
+        // a version of its own instead.
+        let inherited = is_inherited(&toml["package"]["version"]);
+        set_value(&mut toml["package"]["version"], new_version.to_string());
+        if inherited {
+            info!(
+                "{} no longer inherits its version from the workspace",
+                self.name
+            );
+            // The key was written as `version.workspace`, without spacing.
+            if let Some(decor) = toml["package"]
+                .as_table_mut()
+                .and_then(|package| package.key_decor_mut("version"))
+            {
+                *decor = toml_edit::Decor::new("", " ");
+            }
+        }
         self.write_toml(&toml)?;
 
         // If that worked, save the in-memory version too
@@ -136,6 +209,18 @@ impl CrateDetails {
         Ok(())
     }
 
+    /// Whether the crate gets its version from `[workspace.package]`, as
+    /// `version.workspace = true`.
+    pub fn inherits_version(&self) -> anyhow::Result<bool> {
+        Ok(is_inherited(&self.read_toml()?["package"]["version"]))
+    }
+
+    /// Crates which only have binary targets can't be depended upon by other
+    /// crates.
+    pub fn is_bin_only(&self) -> bool {
+        self.has_bin && !self.has_lib
+    }
+
     pub fn all_deps(&self) -> impl Iterator<Item = &String> {
         self.deps
             .iter()
@@ -143,6 +228,10 @@ impl CrateDetails {
             .chain(self.build_deps.iter())
     }
 
+    /// The dependencies which have to be published before this crate. Dev
+    /// dependencies are stripped before the crate is packaged, so they can
+    /// form cycles, e.g. A dev-depending on B which depends on A, without
+    /// affecting the order crates are published in.
     pub fn deps_relevant_during_publish(&self) -> impl Iterator<Item = &String> {
         self.deps.iter().chain(self.build_deps.iter())
     }
@@ -156,29 +245,10 @@ impl CrateDetails {
 
         let mut toml = self.read_toml()?;
 
-        fn do_set(item: &mut toml_edit::Item, registry: &str) -> anyhow::Result<()> {
-            let table = match item.as_table_like_mut() {
-                Some(table) => table,
-                None => return Ok(()),
-            };
-
-            for (_, item) in table.iter_mut() {
-                if let Some(version) = item.as_str() {
-                    let mut tbl = toml_edit::InlineTable::new();
-                    tbl.insert("version", version.into());
-                    tbl.insert("registry", registry.into());
-                    *item = toml_edit::Item::Value(toml_edit::Value::InlineTable(tbl));
-                } else {
-                    item["registry"] = toml_edit::value(registry.to_string());
-                }
-            }
-
-            Ok(())
-        }
-
         for key in CRATE_DEPENDENCY_KEYS {
             edit_all_dependency_sections(&mut toml, &key.to_string(), |item| {
-                do_set(item, registry)
+                set_dependencies_registry(item, registry);
+                Ok(())
             })?;
         }
 
@@ -190,17 +260,23 @@ impl CrateDetails {
     /// Set any references to the dependency provided to the version given.
     pub fn write_dependency_version(
         &self,
+        ctx: &Context,
         dependency: &str,
         version: &Version,
     ) -> anyhow::Result<()> {
         if self.all_deps().any(|dep| dep == dependency) {
-            write_dependency_version(&self.toml_path, dependency, version)?;
+            write_dependency_version(
+                &ctx.requirement_styles,
+                &self.toml_path,
+                dependency,
+                version,
+            )?;
         }
         Ok(())
     }
 
     /// Strip dev dependencies.
-    pub fn strip_dev_deps<P>(&self, root: P) -> anyhow::Result<()>
+    pub fn strip_dev_deps<P>(&self, ctx: &Context, root: P) -> anyhow::Result<()>
     where
         P: AsRef<Path>,
     {
@@ -215,44 +291,300 @@ impl CrateDetails {
             .into_iter()
             .flat_map(|table| table.iter_mut())
             .flat_map(|(_, item)| item.as_table_like_mut())
-            .fold(false, |is_removed, t| {
-                t.remove("dev-dependencies").is_some() || is_removed
-            });
+            .map(|t| t.remove("dev-dependencies").is_some())
+            .filter(|is_removed| *is_removed)
+            .count()
+            > 0;
 
         // Only write the toml file back if we did remove something.
         if removed_top_level || removed_target_deps {
-            git_checkpoint(&root, GCKP::Save)?;
+            ctx.git.checkpoint(&root, GCKP::Save)?;
             self.write_toml(&toml)?;
-            git_checkpoint(&root, GCKP::RevertLater)?;
+            ctx.git.checkpoint(&root, GCKP::RevertLater)?;
         }
 
         Ok(())
     }
 
-    /// Publish the current code for this crate as-is. You may want to run
-    /// [`CrateDetails::strip_dev_deps()`] first.
-    pub fn publish(&self) -> anyhow::Result<()> {
+    pub fn crate_dir(&self) -> anyhow::Result<&Path> {
+        self.toml_path
+            .parent()
+            .with_context(|| format!("{:?} has no parent directory", self.toml_path))
+    }
+
+    /// The build script of the crate, if it has one: either the one given by
+    /// `package.build`, or `build.rs` beside the manifest.
+    pub fn build_script(&self) -> anyhow::Result<Option<PathBuf>> {
+        let toml = self.read_toml()?;
+        let build = toml.get("package").and_then(|package| package.get("build"));
+        let path = match build.and_then(|build| build.as_value()) {
+            Some(toml_edit::Value::String(path)) => self.crate_dir()?.join(path.value()),
+            Some(toml_edit::Value::Boolean(enabled)) if !*enabled.value() => return Ok(None),
+            _ => self.crate_dir()?.join("build.rs"),
+        };
+        Ok(path.exists().then_some(path))
+    }
+
+    /// Whether the build script of the crate links native libraries, judging
+    /// by what it tells Cargo and by its build dependencies.
+    pub fn links_native_libraries(&self, native_build_deps: &[String]) -> anyhow::Result<bool> {
+        let build_script = match self.build_script()? {
+            Some(build_script) => build_script,
+            None => return Ok(false),
+        };
+        let source = std::fs::read_to_string(&build_script)
+            .with_context(|| format!("Cannot read {:?}", build_script.as_os_str()))?;
+        if source.contains("rustc-link-lib") {
+            return Ok(true);
+        }
+        let toml = self.read_toml()?;
+        let has_native_build_dep = get_all_dependency_sections(&toml, "build-dependencies")
+            .filter_map(|item| item.as_table_like())
+            .flat_map(|deps| deps.iter())
+            .any(|(name, dep)| {
+                let package = dependency_package(name, dep);
+                native_build_deps.iter().any(|native| native == package)
+            });
+        Ok(has_native_build_dep)
+    }
+
+    pub fn has_system_deps_metadata(&self) -> anyhow::Result<bool> {
+        Ok(self
+            .read_toml()?
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("system-deps"))
+            .is_some())
+    }
+
+    /// The README of the crate, if it has one.
+    pub fn readme_path(&self) -> anyhow::Result<Option<PathBuf>> {
+        let path = self
+            .crate_dir()?
+            .join(self.readme.as_deref().unwrap_or("README.md"));
+        Ok(path.exists().then_some(path))
+    }
+
+    /// The `[package.metadata.docs.rs]` table of the manifest, if there's one.
+    pub fn docs_rs_metadata(&self) -> anyhow::Result<Option<toml_edit::Table>> {
+        Ok(self
+            .read_toml()?
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("docs"))
+            .and_then(|docs| docs.get("rs"))
+            .and_then(|docs_rs| docs_rs.as_table_like())
+            .map(|docs_rs| {
+                let mut table = toml_edit::Table::new();
+                for (key, item) in docs_rs.iter() {
+                    table.insert(key, item.clone());
+                }
+                table
+            }))
+    }
+
+    /// Add the docs.rs settings which are missing from the manifest, leaving
+    /// the ones which are there alone. Returns whether anything was added.
+    pub fn write_docs_rs_defaults(&self, defaults: &DocsRsDefaults) -> anyhow::Result<bool> {
+        let mut toml = self.read_toml()?;
+        let package = toml
+            .get_mut("package")
+            .and_then(|package| package.as_table_mut())
+            .with_context(|| format!("Cannot read [package] section from {}.", self.name))?;
+        let docs_rs = ["metadata", "docs", "rs"]
+            .iter()
+            .try_fold(package, |table, key| child_table(table, key))
+            .with_context(|| format!("package.metadata.docs.rs of {} is not a table", self.name))?;
+        docs_rs.set_implicit(false);
+
+        let mut changed = false;
+        if !docs_rs.contains_key("all-features") && !docs_rs.contains_key("features") {
+            docs_rs.insert("all-features", toml_edit::value(defaults.all_features));
+            changed = true;
+        }
+        if !docs_rs.contains_key("targets") && !defaults.targets.is_empty() {
+            docs_rs.insert(
+                "targets",
+                toml_edit::value(toml_edit::Array::from_iter(defaults.targets.iter())),
+            );
+            changed = true;
+        }
+        if changed {
+            self.write_toml(&toml)?;
+        }
+        Ok(changed)
+    }
+
+    /// Render the template named by `package.metadata.subpub.template.name`,
+    /// if any, with the other keys of that table and `{crate}` as variables.
+    /// Only the fields which the crate doesn't set itself are kept.
+    pub fn render_template(
+        &mut self,
+        templates: &HashMap<String, MetadataTemplate>,
+    ) -> anyhow::Result<()> {
+        let toml = self.read_toml()?;
+        let package = toml.get("package");
+        let table = match package
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("subpub"))
+            .and_then(|subpub| subpub.get("template"))
+            .and_then(|template| template.as_table_like())
+        {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+
+        let mut variables = table
+            .iter()
+            .filter_map(|(key, value)| Some((key.to_owned(), value.as_str()?.to_owned())))
+            .collect::<HashMap<_, _>>();
+        let name = variables.remove("name").with_context(|| {
+            format!(
+                "package.metadata.subpub.template of {} does not name a template",
+                self.name
+            )
+        })?;
+        let template = templates
+            .get(&name)
+            .with_context(|| format!("Template {name} of {} is not defined", self.name))?;
+        variables.insert("crate".into(), self.name.clone());
+
+        self.template_metadata = template::render(template, &variables)
+            .with_context(|| format!("Cannot render template {name} for {}", self.name))?
+            .into_iter()
+            .filter(|(field, _)| package.and_then(|package| package.get(field)).is_none())
+            .collect();
+        Ok(())
+    }
+
+    /// Fill in the fields rendered from the crate's template. Like stripping
+    /// dev dependencies, this is reverted once the crate is packaged.
+    pub fn write_template_metadata<P: AsRef<Path>>(
+        &self,
+        ctx: &Context,
+        root: P,
+    ) -> anyhow::Result<()> {
+        if self.template_metadata.is_empty() {
+            return Ok(());
+        }
+
+        let mut toml = self.read_toml()?;
+        let package = toml
+            .get_mut("package")
+            .and_then(|package| package.as_table_mut())
+            .with_context(|| format!("Cannot read [package] section from {}.", self.name))?;
+        for (field, value) in &self.template_metadata {
+            package.insert(field, value.to_item());
+        }
+
+        ctx.git.checkpoint(&root, GCKP::Save)?;
+        self.write_toml(&toml)?;
+        ctx.git.checkpoint(&root, GCKP::RevertLater)?;
+
+        Ok(())
+    }
+
+    /// Record where the crate comes from under `[package.metadata.release]`.
+    /// Like stripping dev dependencies, this is reverted once the crate is
+    /// published.
+    pub fn write_provenance<P: AsRef<Path>>(
+        &self,
+        ctx: &Context,
+        root: P,
+        provenance: &Provenance,
+    ) -> anyhow::Result<()> {
+        let mut toml = self.read_toml()?;
+        let package = toml
+            .get_mut("package")
+            .and_then(|package| package.as_table_mut())
+            .with_context(|| format!("Cannot read [package] section from {}.", self.name))?;
+        child_table(package, "metadata")
+            .with_context(|| format!("package.metadata of {} is not a table", self.name))?
+            .insert("release", toml_edit::Item::Table(provenance.to_table()));
+
+        ctx.git.checkpoint(&root, GCKP::Save)?;
+        self.write_toml(&toml)?;
+        ctx.git.checkpoint(&root, GCKP::RevertLater)?;
+
+        Ok(())
+    }
+
+    /// Build the files the package of the crate needs, if it has a
+    /// pre-package hook.
+    pub fn run_pre_package_hook<P: AsRef<Path>>(
+        &self,
+        ctx: &Context,
+        root: P,
+    ) -> anyhow::Result<()> {
+        if let Some(hook) = &self.pre_package {
+            artifacts::prepare(ctx, root, self, hook)?;
+        }
+        Ok(())
+    }
+
+    /// Publish the current code for this crate as-is to `registry`. You may
+    /// want to run [`CrateDetails::strip_dev_deps()`] first. Unless
+    /// `allow_dirty` is set, Cargo refuses to publish files with uncommitted
+    /// changes.
+    pub fn publish(
+        &self,
+        ctx: &Context,
+        registry: &str,
+        allow_dirty: bool,
+        attempts: u32,
+    ) -> anyhow::Result<()> {
         let parent = self
             .toml_path
             .parent()
             .with_context(|| format!("{:?} has no parent directory", self.toml_path))?;
-        external::cargo::publish_crate(parent, &self.name)
+        ctx.cargo.publish_crate(
+            parent,
+            &self.name,
+            &self.version,
+            registry,
+            &self.verify.features(),
+            allow_dirty,
+            attempts,
+        )
+    }
+
+    /// Check that the package of the crate builds for each of `targets`.
+    pub fn verify(
+        &self,
+        ctx: &Context,
+        targets: &[String],
+        allow_dirty: bool,
+    ) -> anyhow::Result<()> {
+        let parent = self.crate_dir()?;
+        for target in targets {
+            info!("Verifying {} for {target}", self.name);
+            ctx.cargo.verify_crate(
+                parent,
+                &self.name,
+                target,
+                &self.verify.features(),
+                allow_dirty,
+            )?;
+        }
+        Ok(())
     }
 
     /// This checks whether we actually need to publish a new version of the crate. It'll return `false`
-    /// only if, as far as we can see, the current version is published to crates.io, and there have been
+    /// only if, as far as we can see, the current version is published to the registry, and there have been
     /// no changes to it since.
+    ///
+    /// With `compare_contents`, the packages are compared by the files they
+    /// contain rather than byte for byte, see `package::differences`.
     pub fn needs_publishing<P: AsRef<Path>>(
         &self,
+        ctx: &Context,
         root: P,
         prev_versions: &[semver::Version],
+        compare_contents: bool,
     ) -> anyhow::Result<bool> {
-        if prev_versions
-            .iter()
-            .any(|prev_version| *prev_version == self.version)
-        {
-            let result = self.needs_publishing_inner(&root, &self.version);
-            git_checkpoint_revert(&root)?;
+        if prev_versions.contains(&self.version) {
+            let result = self.needs_publishing_inner(ctx, &root, &self.version, compare_contents);
+            ctx.git.checkpoint_revert(&root)?;
             result
         } else {
             Ok(true)
@@ -261,20 +593,25 @@ impl CrateDetails {
 
     pub fn needs_publishing_inner<P: AsRef<Path>>(
         &self,
+        ctx: &Context,
         root: P,
         version: &semver::Version,
+        compare_contents: bool,
     ) -> anyhow::Result<bool> {
         let name = &self.name;
 
         let span = span!(Level::INFO, "__", crate = self.name);
         let _enter = span.enter();
 
+        let registry = ctx.cargo.target_registry();
         info!(
-            "Comparing crate {} against crates.io to see if it needs to be published",
+            "Comparing crate {} against {registry} to see if it needs to be published",
             self.name
         );
 
-        self.strip_dev_deps(&root)?;
+        self.run_pre_package_hook(ctx, &root)?;
+        self.write_template_metadata(ctx, &root)?;
+        self.strip_dev_deps(ctx, &root)?;
 
         let crate_dir = self
             .toml_path
@@ -289,7 +626,7 @@ impl CrateDetails {
         };
 
         info!("Generating .crate file");
-        let mut cmd = Command::new("cargo");
+        let mut cmd = ctx.cargo.command();
         if !cmd
             .current_dir(crate_dir)
             .arg("package")
@@ -297,6 +634,7 @@ impl CrateDetails {
             .arg("--allow-dirty")
             .arg("--target-dir")
             .arg(&target_dir)
+            .stdout(events::child_stdout())
             .status()?
             .success()
         {
@@ -307,40 +645,183 @@ impl CrateDetails {
             .join(format!("{name}-{}.crate", version));
         let pkg_bytes = std::fs::read(&pkg_path)?;
 
-        info!("Checking generated .crate file against crates.io");
-        let crates_io_bytes = if let Some(bytes) =
-            external::crates_io::try_download_crate(&self.name, &self.version)?
-        {
-            bytes
-        } else {
-            return Ok(true);
-        };
+        info!("Checking generated .crate file against {registry}");
+        let crates_io_bytes =
+            if let Some(bytes) = ctx.registry.try_download_crate(&self.name, &self.version)? {
+                bytes
+            } else {
+                return Ok(true);
+            };
 
-        if crates_io_bytes != pkg_bytes {
+        if compare_contents {
+            let differences = package::differences(&pkg_path, &crates_io_bytes)?;
+            if !differences.is_empty() {
+                info!(
+                    "The contents of the crate differ from the published version: {}",
+                    differences.join(", ")
+                );
+                return Ok(true);
+            }
+        } else if crates_io_bytes != pkg_bytes {
             info!("The file at {pkg_path:?} is different from the published version");
             return Ok(true);
         }
 
-        info!("The crate is identical to the version from crates.io");
+        info!("The crate is identical to the version from {registry}");
         Ok(false)
     }
 
-    pub fn maybe_bump_version(
-        &mut self,
-        prev_versions: Vec<semver::Version>,
+    /// Whether the crate only changed in its dependencies and its version
+    /// since `version` was published, see `package::only_dependencies_changed`.
+    pub fn only_dependencies_changed(
+        &self,
+        ctx: &Context,
+        version: &semver::Version,
     ) -> anyhow::Result<bool> {
-        let new_version = maybe_bump_for_breaking_change(prev_versions, self.version.clone());
-        let bumped = if let Some(new_version) = new_version {
-            info!(
-                "Bumping crate {} from {} to {}",
-                self.name, self.version, new_version
-            );
-            self.write_own_version(new_version)?;
-            true
-        } else {
-            false
-        };
-        Ok(bumped)
+        let crate_dir = self
+            .toml_path
+            .parent()
+            .with_context(|| format!("{:?} has no parent directory", self.toml_path))?;
+        match ctx.registry.try_download_crate(&self.name, version)? {
+            Some(published) => package::only_dependencies_changed(crate_dir, &published),
+            None => Ok(false),
+        }
+    }
+
+    /// Bump the version of the crate to `new_version`.
+    pub fn bump_version(&mut self, new_version: semver::Version) -> anyhow::Result<()> {
+        info!(
+            "Bumping crate {} from {} to {}",
+            self.name, self.version, new_version
+        );
+        self.write_own_version(new_version)
+    }
+
+    /// The dependencies given by a path in the manifest as it is on disk, i.e.
+    /// re-read rather than loaded, as the packages they are on and their
+    /// version requirements, if any. Dev dependencies are left out since they
+    /// are stripped before publishing.
+    pub fn path_dependencies(&self) -> anyhow::Result<Vec<(String, Option<String>)>> {
+        let toml = self.read_toml()?;
+        let mut dependencies = vec![];
+        for label in ["dependencies", "build-dependencies"] {
+            for section in get_all_dependency_sections(&toml, label) {
+                let section = match section.as_table_like() {
+                    Some(section) => section,
+                    None => continue,
+                };
+                for (key, dep) in section.iter() {
+                    if dep.get("path").is_none() {
+                        continue;
+                    }
+                    let version_req = dep
+                        .get("version")
+                        .and_then(|version| version.as_str())
+                        .map(String::from);
+                    dependencies.push((dependency_package(key, dep).to_owned(), version_req));
+                }
+            }
+        }
+        Ok(dependencies)
+    }
+
+    /// The metadata the registry should report once this crate is published,
+    /// as far as it follows from the manifest. Dev dependencies are left out
+    /// since they are stripped before publishing.
+    pub fn expected_metadata(&self) -> anyhow::Result<VersionMetadata> {
+        let toml = self.read_toml()?;
+
+        let features = read_features(&toml);
+
+        let links = toml
+            .get("package")
+            .and_then(|package| package.get("links"))
+            .and_then(|links| links.as_str())
+            .map(|links| links.to_owned());
+
+        let mut dependencies = vec![];
+        for (label, kind) in [("dependencies", "normal"), ("build-dependencies", "build")] {
+            let targets = toml
+                .get("target")
+                .and_then(|targets| targets.as_table_like())
+                .into_iter()
+                .flat_map(|targets| targets.iter())
+                .filter_map(|(target, item)| Some((Some(target.to_owned()), item.get(label)?)));
+            for (target, section) in toml
+                .get(label)
+                .map(|item| (None, item))
+                .into_iter()
+                .chain(targets)
+            {
+                let section = match section.as_table_like() {
+                    Some(section) => section,
+                    None => continue,
+                };
+                for (key, dep) in section.iter() {
+                    let (version_req, package, optional) = match dep.as_table_like() {
+                        Some(props) => (
+                            props.get("version").and_then(|version| version.as_str()),
+                            props.get("package").and_then(|package| package.as_str()),
+                            props
+                                .get("optional")
+                                .and_then(|optional| optional.as_bool())
+                                .unwrap_or(false),
+                        ),
+                        None => (dep.as_str(), None, false),
+                    };
+                    // Dependencies without a version can't be published anyway
+                    let version_req = match version_req {
+                        Some(version_req) => version_req,
+                        None => continue,
+                    };
+                    dependencies.push(VersionDependency {
+                        crate_id: package.unwrap_or(key).to_owned(),
+                        req: version_req.to_owned(),
+                        kind: kind.to_owned(),
+                        optional,
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+        dependencies.sort();
+
+        Ok(VersionMetadata {
+            features,
+            links,
+            dependencies,
+        })
+    }
+
+    /// The features declared in the manifest, in the form `feature => enables`.
+    pub fn features(&self) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+        Ok(read_features(&self.read_toml()?))
+    }
+
+    /// A string field of the `[package]` section of the manifest, if it's set
+    /// to a plain string rather than e.g. inherited from the workspace.
+    pub fn package_field(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .read_toml()?
+            .get("package")
+            .and_then(|package| package.get(key))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_owned()))
+    }
+
+    pub fn package_list(&self, key: &str) -> anyhow::Result<Option<Vec<String>>> {
+        Ok(self
+            .read_toml()?
+            .get("package")
+            .and_then(|package| package.get(key))
+            .and_then(|value| value.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str())
+                    .map(|value| value.to_owned())
+                    .collect()
+            }))
     }
 
     fn read_toml(&self) -> anyhow::Result<toml_edit::Document> {
@@ -352,9 +833,87 @@ impl CrateDetails {
     }
 }
 
+/// Get the table at `key`, creating it if it doesn't exist. Returns `None` if
+/// there's something else than a table at `key`.
+fn child_table<'a>(table: &'a mut toml_edit::Table, key: &str) -> Option<&'a mut toml_edit::Table> {
+    if !table.contains_key(key) {
+        let mut child = toml_edit::Table::new();
+        child.set_implicit(true);
+        table.insert(key, toml_edit::Item::Table(child));
+    }
+    table[key].as_table_mut()
+}
+
+fn read_features(toml: &toml_edit::Document) -> BTreeMap<String, Vec<String>> {
+    toml.get("features")
+        .and_then(|features| features.as_table_like())
+        .into_iter()
+        .flat_map(|features| features.iter())
+        .map(|(feature, enables)| {
+            let enables = enables
+                .as_array()
+                .into_iter()
+                .flat_map(|enables| enables.iter())
+                .filter_map(|enable| enable.as_str().map(|enable| enable.to_owned()))
+                .collect();
+            (feature.to_owned(), enables)
+        })
+        .collect()
+}
+
+/// Guess whether a crate is one of the [`CrateKind`]s from the words in its
+/// name, the directories it's in (relative to the workspace root), and some
+/// tell-tale manifest entries.
+fn detect_crate_kind(
+    name: &str,
+    toml_path: &Path,
+    package: &external::cargo::Package,
+) -> Option<CrateKind> {
+    let name_parts = name.split(['-', '_']).collect::<Vec<_>>();
+    let has_name_part = |parts: &[&str]| name_parts.iter().any(|part| parts.contains(part));
+    let has_name_parts = |a: &str, b: &str| name_parts.windows(2).any(|w| w[0] == a && w[1] == b);
+    let dirs = toml_path
+        .parent()
+        .into_iter()
+        .flat_map(|dir| dir.components())
+        .filter_map(|component| component.as_os_str().to_str())
+        .collect::<Vec<_>>();
+    let has_dir = |names: &[&str]| dirs.iter().any(|dir| names.contains(dir));
+
+    let is_cargo_fuzz = package
+        .metadata
+        .get("cargo-fuzz")
+        .and_then(|cargo_fuzz| cargo_fuzz.as_bool())
+        .unwrap_or(false);
+    let has_fuzz_dependency = package
+        .dependencies
+        .iter()
+        .any(|dep| dep.kind.is_none() && FUZZ_DEPENDENCIES.contains(&dep.name.as_str()));
+
+    if is_cargo_fuzz
+        || has_fuzz_dependency
+        || has_name_part(&["fuzz", "fuzzer", "fuzzing"])
+        || has_dir(&["fuzz"])
+    {
+        Some(CrateKind::Fuzz)
+    } else if has_name_part(&["bench", "benches"]) || has_dir(&["bench", "benches"]) {
+        Some(CrateKind::Bench)
+    } else if has_name_part(&["example", "examples"]) || has_dir(&["example", "examples"]) {
+        Some(CrateKind::Example)
+    } else if has_name_parts("test", "utils")
+        || has_name_parts("test", "util")
+        || has_name_part(&["testing"])
+        || has_dir(&["test-utils", "tests"])
+    {
+        Some(CrateKind::TestUtil)
+    } else {
+        None
+    }
+}
+
 /// An iterator that hands back all "dependencies"/"dev-dependencies"/"build-dependencies" (according to the
 /// label provided), by looking in the top level `[label]` section as well as any `[target.'foo'.label]` sections.
-fn get_all_dependency_sections<'a>(
+pub fn get_all_dependency_sections<'a>(
     document: &'a toml_edit::Document,
     label: &'a str,
 ) -> impl Iterator<Item = &'a toml_edit::Item> + 'a {
@@ -371,50 +930,3 @@ fn get_all_dependency_sections<'a>(
 
     document.get(label).into_iter().chain(target)
 }
-
-// TODO: use cargo_metadata instead
-/// Given a path to some dependencies in a TOML file, pull out the package names
-/// for any path based dependencies (ie dependencies in the same workspace).
-fn filter_workspace_dependencies(val: &toml_edit::Item) -> anyhow::Result<HashSet<String>> {
-    let arr = match val.as_table() {
-        Some(arr) => arr,
-        None => return Err(anyhow!("dependencies should be a TOML table.")),
-    };
-
-    let mut deps = HashSet::new();
-    for (name, props) in arr {
-        // If props arent a table eg { path = "/foo" }, this is
-        // not a workspace dependency (since it needs a "path" prop)
-        // so skip over it.
-        let props = match props.as_table_like() {
-            Some(props) => props,
-            None => continue,
-        };
-
-        // Ignore any dependency without a "path" (not a workspace dep
-        // if it doesn't point to another crate via a path).
-        let path = match props.get("path") {
-            Some(path) => path,
-            None => continue,
-        };
-
-        // Expect path to be a string. Error if it's not.
-        path.as_str()
-            .ok_or_else(|| anyhow!("{}.path is not a string.", name))?;
-
-        // What is the actual package name?
-        let package_name = props
-            .get("package")
-            .map(|package| {
-                package
-                    .as_str()
-                    .map(|s| s.to_string())
-                    .ok_or_else(|| anyhow!("{}.package is not a string.", name))
-            })
-            .unwrap_or_else(|| Ok(name.to_string()))?;
-
-        deps.insert(package_name);
-    }
-
-    Ok(deps)
-}
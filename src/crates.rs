@@ -14,37 +14,56 @@
 // You should have received a copy of the GNU General Public License
 // along with subpub.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::crate_details::CrateDetails;
+use crate::artifacts;
+use crate::config::Config;
+use crate::context::Context;
+use crate::crate_details::{get_all_dependency_sections, CrateDetails};
 use crate::external;
-use crate::git::*;
+use crate::external::cargo::Cargo;
+use crate::external::crates_io::{VersionDependency, VersionMetadata};
+use crate::git::GCKP;
+use crate::groups::wildcard_match;
+use crate::ordering::{dependency_closure, DependencyGraph};
+use crate::provenance::Provenance;
 use crate::toml::toml_read;
-use crate::toml::toml_write;
-use anyhow::Context;
+use crate::toml::{set_value, toml_write};
+use crate::version::{is_breaking_requirement_change, RequirementStyle};
+use anyhow::Context as _;
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path};
 use strum::EnumString;
+use tracing::{info, warn};
 
 use anyhow::anyhow;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use std::path::PathBuf;
-use walkdir::WalkDir;
 
 #[derive(Debug, Clone)]
 pub struct Crates {
     root: PathBuf,
+    /// The settings the crates are published with.
+    ctx: Context,
     // Details for a given crate, including dependencies.
     pub details: HashMap<String, CrateDetails>,
 }
 
 impl Crates {
+    /// Load the crates of the workspace at `root` for looking into them rather
+    /// than publishing them: Cargo is run as it is, and requirements are written
+    /// in the styles the configuration of the workspace gives.
+    pub fn load(root: PathBuf) -> anyhow::Result<Crates> {
+        let ctx = Context::local(&Config::load(&root)?);
+        Crates::load_crates_in_workspace(root, &ctx)
+    }
+
     /// Return a map of all substrate crates, in the form `crate_name => ( path, details )`.
-    pub fn load_crates_in_workspace(root: PathBuf) -> anyhow::Result<Crates> {
+    pub fn load_crates_in_workspace(root: PathBuf, ctx: &Context) -> anyhow::Result<Crates> {
         // Load details:
-        let details = crate_cargo_tomls(root.clone())
+        let details = workspace_packages(&ctx.cargo, &root)?
             .into_iter()
-            .map(|path| {
-                let details = CrateDetails::load(path)?;
+            .map(|(path, package)| {
+                let details = CrateDetails::load(&package, path, &root)?;
                 Ok((details.name.clone(), details))
             })
             .collect::<anyhow::Result<HashMap<_, _>>>()?;
@@ -61,7 +80,103 @@ impl Crates {
             }
         }
 
-        Ok(Crates { root, details })
+        Ok(Crates {
+            root,
+            ctx: ctx.clone(),
+            details,
+        })
+    }
+
+    /// The settings the crates are published with.
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// The dependencies of `krate` whose current versions aren't compatible
+    /// with the requirements `krate` had on them when it was loaded, i.e. the
+    /// ones whose requirement was, or will be, rewritten in a breaking way.
+    pub fn breaking_requirement_changes(&self, krate: &str) -> anyhow::Result<Vec<String>> {
+        let details = self
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?;
+        let mut deps = details
+            .deps_relevant_during_publish()
+            .filter(
+                |dep| match (details.dep_version_reqs.get(*dep), self.details.get(*dep)) {
+                    (Some(version_req), Some(dep_details)) => {
+                        is_breaking_requirement_change(version_req, &dep_details.version)
+                    }
+                    _ => false,
+                },
+            )
+            .cloned()
+            .collect::<Vec<_>>();
+        deps.sort();
+        deps.dedup();
+        Ok(deps)
+    }
+
+    /// Make every crate of the workspace, and the dependencies declared by the
+    /// workspace itself, refer to `version` of `dependency`.
+    pub fn write_dependency_version(
+        &self,
+        dependency: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<()> {
+        for details in self.details.values() {
+            details.write_dependency_version(&self.ctx, dependency, version)?;
+        }
+        self.write_workspace_dependency_version(dependency, version)
+    }
+
+    /// Update the entry of `dependency` in `[workspace.dependencies]` of the
+    /// root manifest, which the crates inheriting it get their version from.
+    pub fn write_workspace_dependency_version(
+        &self,
+        dependency: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<()> {
+        let toml_path = self.root.join("Cargo.toml");
+        if toml_path.exists() {
+            write_dependency_version(&self.ctx.requirement_styles, toml_path, dependency, version)?;
+        }
+        Ok(())
+    }
+
+    /// Set the version in `[workspace.package]` of the root manifest, which
+    /// the crates inheriting it get their version from. Returns whether the
+    /// workspace declares a version.
+    pub fn write_workspace_version(&self, version: &semver::Version) -> anyhow::Result<bool> {
+        let toml_path = self.root.join("Cargo.toml");
+        if !toml_path.exists() {
+            return Ok(false);
+        }
+        let mut toml = toml_read(&toml_path)?;
+        let item = &mut toml["workspace"]["package"]["version"];
+        if item.is_none() {
+            return Ok(false);
+        }
+        set_value(item, version.to_string());
+        toml_write(toml_path, &toml)?;
+        Ok(true)
+    }
+
+    /// Make the dependencies of every crate, and those declared by the
+    /// workspace, come from `registry`.
+    pub fn set_registry(&self, registry: &str) -> anyhow::Result<()> {
+        for details in self.details.values() {
+            details.set_registry(registry)?;
+        }
+        let toml_path = self.root.join("Cargo.toml");
+        if toml_path.exists() {
+            let mut toml = toml_read(&toml_path)?;
+            if let Some(item) = workspace_dependencies_mut(&mut toml) {
+                set_dependencies_registry(item, registry);
+                toml_write(&toml_path, &toml)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn setup_crates(&self) -> anyhow::Result<()> {
@@ -92,105 +207,533 @@ impl Crates {
         Ok(())
     }
 
-    /// Remove any dev-dependency sections in the TOML file and publish.
-    pub fn strip_dev_deps_and_publish(&self, name: &str) -> anyhow::Result<()> {
+    /// Remove any dev-dependency sections in the TOML file and publish. The
+    /// crate won't be immediately visible on the registry; use
+    /// [`Crates::wait_until_published`] before publishing crates which depend
+    /// on it.
+    pub fn strip_dev_deps_and_publish(
+        &self,
+        name: &str,
+        provenance: Option<&Provenance>,
+        allow_dirty: bool,
+        verify_targets: &[String],
+        attempts: u32,
+    ) -> anyhow::Result<()> {
+        let details = match self.details.get(name) {
+            Some(details) => details,
+            None => anyhow::bail!("Crate '{name}' not found"),
+        };
+
+        details.run_pre_package_hook(&self.ctx, &self.root)?;
+        if let Some(hook) = &details.pre_package {
+            artifacts::verify_packaged(&self.ctx, details, hook)?;
+        }
+        if let Some(provenance) = provenance {
+            details.write_provenance(&self.ctx, &self.root, provenance)?;
+        }
+
+        details.write_template_metadata(&self.ctx, &self.root)?;
+        details.strip_dev_deps(&self.ctx, &self.root)?;
+        details.verify(&self.ctx, verify_targets, allow_dirty)?;
+        details.publish(
+            &self.ctx,
+            &self.ctx.cargo.target_registry(),
+            allow_dirty,
+            attempts,
+        )?;
+        self.ctx.git.checkpoint_revert(&self.root)?;
+
+        Ok(())
+    }
+
+    /// Publish a crate which was published to the target registry to a mirror
+    /// as well, with its dependencies pointed at the mirror. The crates it
+    /// depends on have to be on the mirror already.
+    pub fn publish_to_mirror(
+        &self,
+        name: &str,
+        registry: &str,
+        provenance: Option<&Provenance>,
+        allow_dirty: bool,
+        attempts: u32,
+    ) -> anyhow::Result<()> {
         let details = match self.details.get(name) {
             Some(details) => details,
             None => anyhow::bail!("Crate '{name}' not found"),
         };
 
-        details.strip_dev_deps(&self.root)?;
-        details.publish()?;
-        git_checkpoint_revert(&self.root)?;
+        details.run_pre_package_hook(&self.ctx, &self.root)?;
+        if let Some(provenance) = provenance {
+            details.write_provenance(&self.ctx, &self.root, provenance)?;
+        }
+
+        details.write_template_metadata(&self.ctx, &self.root)?;
+        details.strip_dev_deps(&self.ctx, &self.root)?;
+        self.ctx.git.checkpoint(&self.root, GCKP::Save)?;
+        self.set_registry(registry)?;
+        self.ctx.git.checkpoint(&self.root, GCKP::RevertLater)?;
+        // The run goes on when a mirror fails, so the manifests are restored
+        // either way.
+        let result = details.publish(&self.ctx, registry, allow_dirty, attempts);
+        self.ctx.git.checkpoint_revert(&self.root)?;
+
+        result
+    }
 
-        // Don't return until the crate has finished being published; it won't
-        // be immediately visible on crates.io, so wait until it shows up.
-        while !external::crates_io::does_crate_exist(name, &details.version)? {
-            std::thread::sleep(std::time::Duration::from_millis(2500))
+    /// Don't return until the given crates have finished being published.
+    /// Crates of the same level can be published one after another and then
+    /// waited for at once, instead of waiting for each of them.
+    pub fn wait_until_published(
+        &self,
+        published: &[(String, semver::Version)],
+        timeout: std::time::Duration,
+    ) -> anyhow::Result<()> {
+        let registry = self.ctx.cargo.target_registry();
+        for (name, version) in published {
+            info!("Waiting for {name} {version} to be available on {registry}");
+            self.ctx
+                .registry
+                .wait_until_available(name, version, timeout)?;
         }
 
-        // Wait for the crate to be uploaded to the index after it is registered
-        // on crates.io's database
-        std::thread::sleep(std::time::Duration::from_millis(2500));
+        Ok(())
+    }
+
+    /// Compare the metadata the registry reports for a published crate against
+    /// what its manifest says, failing if the registry normalized or dropped
+    /// anything.
+    pub fn check_published_metadata(
+        &self,
+        name: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<()> {
+        let details = self
+            .details
+            .get(name)
+            .with_context(|| format!("Crate not found: {name}"))?;
+        let expected = details.expected_metadata()?;
+        let published = self.ctx.registry.version_metadata(name, version)?;
+
+        let registry = self.ctx.cargo.target_registry();
+        let differences = metadata_differences(&expected, &published);
+        if !differences.is_empty() {
+            anyhow::bail!(
+                "The metadata {registry} reports for {name} {version} doesn't match its manifest:\n{}",
+                differences.join("\n")
+            );
+        }
+        info!("The metadata {registry} reports for {name} {version} matches its manifest");
 
         Ok(())
     }
 
+    /// Build the dependency graph of the workspace, where the dependencies of
+    /// each crate are picked by `deps`.
+    pub fn dependency_graph<'a, F, I>(&'a self, deps: F) -> DependencyGraph
+    where
+        F: Fn(&'a CrateDetails) -> I,
+        I: Iterator<Item = &'a String>,
+    {
+        self.details
+            .iter()
+            .map(|(krate, details)| (krate.clone(), deps(details).cloned().collect()))
+            .collect()
+    }
+
     pub fn what_needs_publishing<Crate: AsRef<str>>(
         &self,
         krate: Crate,
         publish_order: &[String],
     ) -> anyhow::Result<Vec<String>> {
-        let mut registered_crates: HashSet<&str> = HashSet::new();
-        fn register_crates<'a>(
-            crates: &'a Crates,
-            registered_crates: &mut HashSet<&'a str>,
-            krate: &'a str,
-        ) -> anyhow::Result<()> {
-            if registered_crates.get(krate).is_none() {
-                registered_crates.insert(krate);
-
-                let details = crates
-                    .details
-                    .get(krate)
-                    .with_context(|| format!("Crate not found: {krate}"))?;
-
-                for dep in details.deps_to_publish() {
-                    register_crates(crates, registered_crates, dep)?;
+        let graph = self.dependency_graph(CrateDetails::deps_to_publish);
+        dependency_closure(&graph, krate.as_ref(), publish_order)
+    }
+}
+
+/// Describe how the published metadata of a crate differs from the expected
+/// one. Feature lists are compared regardless of their order and version
+/// requirements regardless of their formatting, e.g. `1.0` and `^1.0`.
+fn metadata_differences(expected: &VersionMetadata, published: &VersionMetadata) -> Vec<String> {
+    let mut differences = vec![];
+
+    let sorted = |enables: &Vec<String>| {
+        let mut enables = enables.clone();
+        enables.sort();
+        enables
+    };
+    for (feature, enables) in &expected.features {
+        match published.features.get(feature) {
+            None => differences.push(format!("feature {feature} is missing")),
+            Some(published_enables) if sorted(published_enables) != sorted(enables) => differences
+                .push(format!(
+                    "feature {feature} enables {published_enables:?} instead of {enables:?}"
+                )),
+            Some(_) => {}
+        }
+    }
+
+    if expected.links != published.links {
+        differences.push(format!(
+            "links is {:?} instead of {:?}",
+            published.links, expected.links
+        ));
+    }
+
+    let normalize = |dependencies: &[VersionDependency]| {
+        dependencies
+            .iter()
+            .map(|dep| VersionDependency {
+                req: semver::VersionReq::parse(&dep.req)
+                    .map(|req| req.to_string())
+                    .unwrap_or_else(|_| dep.req.clone()),
+                ..dep.clone()
+            })
+            .filter(|dep| dep.kind != "dev")
+            .collect::<HashSet<_>>()
+    };
+    let expected_deps = normalize(&expected.dependencies);
+    let published_deps = normalize(&published.dependencies);
+    for dep in expected_deps.difference(&published_deps) {
+        differences.push(format!("dependency {dep} is missing"));
+    }
+    for dep in published_deps.difference(&expected_deps) {
+        differences.push(format!("dependency {dep} is unexpected"));
+    }
+
+    differences
+}
+
+/// Crates in a directory containing this file, or any directory below it, are
+/// ignored.
+pub const IGNORE_FILE: &str = ".subpubignore";
+
+/// The packages of the workspace, along with the paths to their Cargo.toml
+/// files under `root`, leaving out the ones which are ignored.
+fn workspace_packages(
+    cargo: &Cargo,
+    root: &Path,
+) -> anyhow::Result<Vec<(PathBuf, external::cargo::Package)>> {
+    let missing = missing_members(root)?;
+    let metadata = if missing.is_empty() {
+        cargo.metadata(root)?
+    } else {
+        warn!(
+            "Leaving out the workspace members which aren't checked out: {}",
+            missing.join(", ")
+        );
+        let dependents = dependents_on_members(root, &missing)?;
+        if !dependents.is_empty() {
+            anyhow::bail!(
+                "The crates which are checked out depend on workspace members which are not: {}. Check them out as well, e.g. with `git sparse-checkout add`.",
+                dependents
+                    .iter()
+                    .map(|(dependent, member)| format!("{dependent} on {member}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        metadata_without_members(cargo, root, &missing)?
+    };
+    let root_toml = root.join("Cargo.toml");
+    let mut packages = vec![];
+    let mut ignored_dirs = HashMap::new();
+    for mut package in metadata.packages {
+        if !metadata.workspace_members.contains(&package.id) {
+            continue;
+        }
+        // Cargo gives absolute paths, but the rest of subpub works with paths
+        // under the root as it was given.
+        let toml_path = root.join(
+            package
+                .manifest_path
+                .strip_prefix(&metadata.workspace_root)
+                .with_context(|| {
+                    format!(
+                        "{:?} is outside of the workspace",
+                        package.manifest_path.as_os_str()
+                    )
+                })?,
+        );
+        // The root manifest only describes the workspace
+        if toml_path == root_toml {
+            continue;
+        }
+        let ignored = toml_path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+            .any(|dir| {
+                *ignored_dirs
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| is_ignored_dir(dir))
+            });
+        if !ignored {
+            // Path dependencies outside of the workspace, e.g. on the crates
+            // of another root published in the same run, are treated like
+            // dependencies on published crates.
+            for dep in &mut package.dependencies {
+                if matches!(&dep.path, Some(path) if !path.starts_with(&metadata.workspace_root)) {
+                    dep.path = None;
                 }
             }
-            Ok(())
+            packages.push((toml_path, package));
         }
-        register_crates(self, &mut registered_crates, krate.as_ref())?;
+    }
+    Ok(packages)
+}
 
-        Ok(publish_order
+/// The members listed in the root manifest of the workspace which don't exist
+/// on disk, e.g. because of a sparse checkout. Members given by a glob only
+/// match the directories which exist, so they are never missing.
+fn missing_members(root: &Path) -> anyhow::Result<Vec<String>> {
+    let toml_path = root.join("Cargo.toml");
+    if !toml_path.exists() {
+        return Ok(vec![]);
+    }
+    let toml = toml_read(&toml_path)?;
+    let members = toml
+        .get("workspace")
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(|members| members.as_array());
+    Ok(members
+        .into_iter()
+        .flat_map(|members| members.iter())
+        .filter_map(|member| member.as_str())
+        .filter(|member| !member.contains(['*', '?', '[']))
+        .filter(|member| !root.join(member).join("Cargo.toml").exists())
+        .map(String::from)
+        .collect())
+}
+
+/// The checked out members of the workspace which have path dependencies on
+/// `members`, directly or through `[workspace.dependencies]`, as pairs of the
+/// dependent and the member it depends on.
+fn dependents_on_members(root: &Path, members: &[String]) -> anyhow::Result<Vec<(String, String)>> {
+    let toml = toml_read(root.join("Cargo.toml"))?;
+    let member_dirs = members
+        .iter()
+        .map(|member| (normalize_path(&root.join(member)), member))
+        .collect::<Vec<_>>();
+    let member_at = |path: &Path| {
+        let path = normalize_path(path);
+        member_dirs
             .iter()
-            .filter_map(|krate| {
-                if registered_crates.iter().any(|reg_crate| reg_crate == krate) {
-                    Some(krate.into())
-                } else {
-                    None
+            .find(|(dir, _)| *dir == path)
+            .map(|(_, member)| member.to_string())
+    };
+    let workspace_paths = toml
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(|deps| deps.as_table_like())
+        .into_iter()
+        .flat_map(|deps| deps.iter())
+        .filter_map(|(name, dep)| Some((name.to_string(), root.join(dep.get("path")?.as_str()?))))
+        .collect::<HashMap<_, _>>();
+
+    let mut dirs = toml
+        .get("workspace")
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(|members| members.as_array())
+        .into_iter()
+        .flat_map(|members| members.iter())
+        .filter_map(|member| member.as_str())
+        .flat_map(|member| member_dirs_matching(root, member))
+        .collect::<Vec<_>>();
+    if toml.get("package").is_some() {
+        dirs.push(root.to_path_buf());
+    }
+
+    let mut dependents = vec![];
+    for dir in dirs {
+        let toml = toml_read(dir.join("Cargo.toml"))?;
+        for key in CRATE_DEPENDENCY_KEYS {
+            let label = key.to_string();
+            for section in get_all_dependency_sections(&toml, &label) {
+                let deps = section
+                    .as_table_like()
+                    .into_iter()
+                    .flat_map(|deps| deps.iter());
+                for (name, dep) in deps {
+                    let path = if is_inherited(dep) {
+                        workspace_paths.get(name).cloned()
+                    } else {
+                        dep.get("path")
+                            .and_then(|path| path.as_str())
+                            .map(|path| dir.join(path))
+                    };
+                    if let Some(member) = path.as_deref().and_then(member_at) {
+                        let dependent = dir.strip_prefix(root).unwrap_or(&dir);
+                        dependents.push((dependent.display().to_string(), member));
+                    }
                 }
+            }
+        }
+    }
+    dependents.sort();
+    dependents.dedup();
+    Ok(dependents)
+}
+
+/// The directories with a Cargo.toml which a `workspace.members` entry stands
+/// for. Only `*` is expanded in globs.
+fn member_dirs_matching(root: &Path, member: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_path_buf()];
+    for component in member.split('/') {
+        dirs = dirs
+            .into_iter()
+            .flat_map(|dir| {
+                if !component.contains('*') {
+                    return vec![dir.join(component)];
+                }
+                fs::read_dir(&dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .filter(|entry| wildcard_match(component, &entry.file_name().to_string_lossy()))
+                    .map(|entry| dir.join(entry.file_name()))
+                    .collect()
             })
-            .collect())
+            .collect();
+    }
+    dirs.retain(|dir| dir.join("Cargo.toml").exists());
+    dirs
+}
+
+/// Resolve `.` and `..` in a path without touching the file system, since the
+/// path may not exist.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
 }
 
-// TODO: use cargo_metadata instead
-/// find all of the crates, returning paths to their Cargo.toml files.
-fn crate_cargo_tomls(root: PathBuf) -> Vec<PathBuf> {
-    let root_toml = {
-        let mut p = root.clone();
-        p.push("Cargo.toml");
-        p
+/// The metadata of the workspace at `root` with `members` left out of it.
+/// Cargo reads a copy of the root manifest without them in a scratch directory
+/// which links to everything else in the workspace, so that the workspace
+/// itself is never written to.
+fn metadata_without_members(
+    cargo: &Cargo,
+    root: &Path,
+    members: &[String],
+) -> anyhow::Result<external::cargo::Metadata> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve {:?}", root.as_os_str()))?;
+    let scratch = tempfile::tempdir()?;
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "Cargo.toml" || name == "Cargo.lock" {
+            continue;
+        }
+        symlink(&entry.path(), &scratch.path().join(&name))?;
+    }
+
+    let mut toml = toml_read(root.join("Cargo.toml"))?;
+    for key in ["members", "default-members"] {
+        if let Some(list) = toml["workspace"]
+            .get_mut(key)
+            .and_then(|list| list.as_array_mut())
+        {
+            for index in (0..list.len()).rev() {
+                let missing = list
+                    .get(index)
+                    .and_then(|member| member.as_str())
+                    .map(|member| members.iter().any(|missing| missing == member))
+                    .unwrap_or(false);
+                if missing {
+                    list.remove(index);
+                }
+            }
+        }
+    }
+    toml_write(scratch.path().join("Cargo.toml"), &toml)?;
+
+    let mut metadata = cargo.metadata(scratch.path())?;
+    // Put the paths back under the workspace, in case Cargo doesn't resolve
+    // the links
+    let scratch_root = metadata.workspace_root.clone();
+    let rebase = |path: &mut PathBuf| {
+        if let Ok(rest) = path.strip_prefix(&scratch_root) {
+            *path = root.join(rest);
+        }
+    };
+    for package in &mut metadata.packages {
+        rebase(&mut package.manifest_path);
+        for dep in &mut package.dependencies {
+            if let Some(path) = &mut dep.path {
+                rebase(path);
+            }
+        }
+    }
+    metadata.workspace_root = root;
+    Ok(metadata)
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(original, link).with_context(|| {
+        format!(
+            "Cannot link {:?} to {:?}",
+            link.as_os_str(),
+            original.as_os_str()
+        )
+    })
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> anyhow::Result<()> {
+    let result = if original.is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
     };
+    result.with_context(|| {
+        format!(
+            "Cannot link {:?} to {:?}",
+            link.as_os_str(),
+            original.as_os_str()
+        )
+    })
+}
 
-    WalkDir::new(root)
-        .into_iter()
-        // Ignore hidden files and folders, and anything in "target" folders
-        .filter_entry(|entry| {
-            entry
-                .file_name()
-                .to_str()
-                .map(|s| !s.starts_with('.') && s != "target")
-                .unwrap_or(false)
-        })
-        // Ignore errors
-        .filter_map(|entry| entry.ok())
-        // Keep files
-        .filter(|entry| entry.file_type().is_file())
-        // Keep files named Cargo.toml
-        .filter(|entry| {
-            entry
-                .file_name()
-                .to_str()
-                .map(|s| s == "Cargo.toml")
-                .unwrap_or(false)
-        })
-        // Filter the root Cargo.toml file
-        .filter(|entry| entry.path() != root_toml)
-        .map(|entry| entry.into_path())
-        .collect()
+/// A folder opts out of publishing, along with everything below it, if it has
+/// an [`IGNORE_FILE`] or if its crate sets `package.metadata.subpub.ignore`.
+fn is_ignored_dir(dir: &Path) -> bool {
+    if dir.join(IGNORE_FILE).exists() {
+        info!("Ignoring crates in {dir:?} because of its {IGNORE_FILE}");
+        return true;
+    }
+
+    let toml_path = dir.join("Cargo.toml");
+    let ignore = toml_path.exists()
+        && toml_read(&toml_path)
+            .ok()
+            .and_then(|toml| {
+                toml.get("package")?
+                    .get("metadata")?
+                    .get("subpub")?
+                    .get("ignore")?
+                    .as_bool()
+            })
+            .unwrap_or(false);
+    if ignore {
+        info!(
+            "Ignoring crates in {dir:?} because of package.metadata.subpub.ignore in {toml_path:?}"
+        );
+    }
+    ignore
 }
 
 #[derive(EnumString, strum::Display)]
@@ -238,16 +781,108 @@ pub fn edit_all_dependency_sections<T, F: FnMut(&mut toml_edit::Item) -> anyhow:
     Ok(())
 }
 
+/// Whether a dependency entry, e.g. `foo = { workspace = true }`, is inherited
+/// from `[workspace.dependencies]` of the root manifest. Only its features and
+/// whether it's optional can be changed in the member manifest.
+pub fn is_inherited(item: &toml_edit::Item) -> bool {
+    item.get("workspace")
+        .and_then(|workspace| workspace.as_bool())
+        .unwrap_or(false)
+}
+
+/// The `[workspace.dependencies]` of a root manifest, if it has any.
+pub fn workspace_dependencies_mut(
+    document: &mut toml_edit::Document,
+) -> Option<&mut toml_edit::Item> {
+    document.get_mut("workspace")?.get_mut("dependencies")
+}
+
+/// Make the dependencies of a dependency section, e.g. `[dependencies]`, come
+/// from `registry`. For crates.io, the `registry` keys are removed.
+pub fn set_dependencies_registry(item: &mut toml_edit::Item, registry: &str) {
+    let table = match item.as_table_like_mut() {
+        Some(table) => table,
+        None => return,
+    };
+
+    for (_, item) in table.iter_mut() {
+        if is_inherited(item) {
+            continue;
+        }
+        if registry == external::cargo::CRATES_IO {
+            if let Some(item) = item.as_table_like_mut() {
+                item.remove("registry");
+            }
+            continue;
+        }
+        if let Some(version) = item.as_str() {
+            let mut tbl = toml_edit::InlineTable::new();
+            tbl.insert("version", version.into());
+            tbl.insert("registry", registry.into());
+            *item = toml_edit::Item::Value(toml_edit::Value::InlineTable(tbl));
+        } else {
+            set_value(&mut item["registry"], registry);
+        }
+    }
+}
+
+/// The name of the package a dependency entry is on: the one given by
+/// `package` if the dependency is renamed, otherwise its key.
+pub fn dependency_package<'a>(key: &'a str, item: &'a toml_edit::Item) -> &'a str {
+    item.get("package")
+        .and_then(|package| package.as_str())
+        .unwrap_or(key)
+}
+
+/// Whether the version requirement of a dependency entry, either a plain
+/// version string or a table with a "version" key, already allows `version`.
+/// Unless a requirement style is configured for the dependency, such
+/// requirements are left alone, so that a compatible release of a dependency
+/// doesn't change its dependents and force them to be republished.
+fn allows_version(item: &toml_edit::Item, version: &semver::Version) -> bool {
+    item.as_str()
+        .or_else(|| item.get("version").and_then(|version| version.as_str()))
+        .and_then(|version_req| semver::VersionReq::parse(version_req).ok())
+        .map(|version_req| version_req.matches(version))
+        .unwrap_or(false)
+}
+
+/// The styles requirements on new versions of dependencies are written in:
+/// the one used for every dependency, if one is configured, and those of
+/// dependencies which have their own. Without either, they are written in the
+/// default style, and only when they don't allow the new version already.
+#[derive(Debug, Clone, Default)]
+pub struct RequirementStyles {
+    pub style: Option<RequirementStyle>,
+    pub per_dependency: BTreeMap<String, RequirementStyle>,
+}
+
+impl RequirementStyles {
+    /// The style configured for requirements on new versions of `dependency`,
+    /// if any.
+    pub fn of(&self, dependency: &str) -> Option<RequirementStyle> {
+        self.per_dependency.get(dependency).copied().or(self.style)
+    }
+}
+
 pub fn write_dependency_version<P: AsRef<Path>>(
+    styles: &RequirementStyles,
     toml_path: P,
     dependency: &str,
     version: &semver::Version,
 ) -> anyhow::Result<()> {
     let mut toml = toml_read(&toml_path)?;
+    let style = styles.of(dependency);
+    let requirement = style.unwrap_or_default().requirement(version);
+    // A configured style applies to every requirement on the dependency, even
+    // those which allow the new version already.
+    let keep_compatible = style.is_none();
 
     fn do_set<P: AsRef<Path>>(
         item: &mut toml_edit::Item,
         version: &semver::Version,
+        requirement: &str,
+        keep_compatible: bool,
         dep: &str,
         dep_type: &str,
         toml_path: P,
@@ -258,30 +893,28 @@ pub fn write_dependency_version<P: AsRef<Path>>(
         };
 
         for (key, item) in table.iter_mut() {
-            if key == dep {
-                if item.is_str() {
-                    *item = toml_edit::value(version.to_string());
-                } else {
-                    item["version"] = toml_edit::value(version.to_string());
-                }
+            // Inherited dependencies get their version from the workspace
+            // entry, which is updated instead.
+            if is_inherited(item) {
+                continue;
+            }
+            if !item.is_str() && !item.is_table_like() {
+                anyhow::bail!(
+                    "{dep_type} 's key {key} should be a string or table-like in {:?}",
+                    toml_path.as_ref().as_os_str()
+                );
+            }
+            // A renamed dependency, e.g. `foo = { package = "bar" }`, is on
+            // the package it names rather than on its key.
+            if dependency_package(key.get(), item) != dep
+                || (keep_compatible && allows_version(item, version))
+            {
+                continue;
+            }
+            if item.is_str() {
+                set_value(item, requirement);
             } else {
-                let item = if item.as_str().is_some() {
-                    continue;
-                } else {
-                    item.as_table_like_mut().with_context(|| {
-                        format!(
-                            "{dep_type} 's key {key} should be a string or table-like in {:?}",
-                            toml_path.as_ref().as_os_str()
-                        )
-                    })?
-                };
-                if item
-                    .get("package")
-                    .map(|pkg| pkg.as_str() == Some(dep))
-                    .unwrap_or(false)
-                {
-                    item.insert("version", toml_edit::value(version.to_string()));
-                }
+                set_value(&mut item["version"], requirement);
             }
         }
 
@@ -291,11 +924,102 @@ pub fn write_dependency_version<P: AsRef<Path>>(
     for dep_key in CRATE_DEPENDENCY_KEYS {
         let key = &dep_key.to_string();
         edit_all_dependency_sections(&mut toml, key, |item| {
-            do_set(item, version, dependency, key, &toml_path)
+            do_set(
+                item,
+                version,
+                &requirement,
+                keep_compatible,
+                dependency,
+                key,
+                &toml_path,
+            )
         })?;
     }
+    if let Some(item) = workspace_dependencies_mut(&mut toml) {
+        do_set(
+            item,
+            version,
+            &requirement,
+            keep_compatible,
+            dependency,
+            "workspace.dependencies",
+            &toml_path,
+        )?;
+    }
 
     toml_write(toml_path, &toml)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dep(krate: &str, req: &str, kind: &str) -> VersionDependency {
+        VersionDependency {
+            crate_id: krate.into(),
+            req: req.into(),
+            kind: kind.into(),
+            optional: false,
+            target: None,
+        }
+    }
+
+    fn metadata(
+        features: &[(&str, &[&str])],
+        dependencies: Vec<VersionDependency>,
+    ) -> VersionMetadata {
+        VersionMetadata {
+            features: features
+                .iter()
+                .map(|(feature, enables)| {
+                    (
+                        feature.to_string(),
+                        enables.iter().map(|enable| enable.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            links: None,
+            dependencies,
+        }
+    }
+
+    #[test]
+    fn equivalent_metadata_has_no_differences() {
+        let expected = metadata(
+            &[("std", &["a/std", "b/std"])],
+            vec![dep("a", "1.0", "normal"), dep("c", "2", "dev")],
+        );
+        let published = metadata(
+            &[("std", &["b/std", "a/std"])],
+            vec![dep("a", "^1.0", "normal")],
+        );
+        assert_eq!(
+            metadata_differences(&expected, &published),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn differences_are_described() {
+        let expected = metadata(
+            &[("std", &["a/std"]), ("serde", &[])],
+            vec![dep("a", "1.0", "normal")],
+        );
+        let mut published = metadata(&[("std", &[])], vec![dep("a", "1.1", "normal")]);
+        published.links = Some("z".into());
+        let mut differences = metadata_differences(&expected, &published);
+        differences.sort();
+        assert_eq!(
+            differences,
+            vec![
+                "dependency a ^1.0 (normal) is missing",
+                "dependency a ^1.1 (normal) is unexpected",
+                "feature serde is missing",
+                "feature std enables [] instead of [\"a/std\"]",
+                "links is Some(\"z\") instead of None",
+            ]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keep the snippets of documentation which show how to depend on the crates
+//! of the workspace, e.g. `sp-core = "21.0"` in a README, at the released
+//! versions, so that the install instructions of the published docs are
+//! accurate.
+
+use crate::crates::Crates;
+use anyhow::Context;
+use semver::{Version, VersionReq};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The files which can contain examples: the README of every crate and of the
+/// workspace, and the sources of the crates, whose doc comments are searched.
+pub fn doc_files(root: &Path, crates: &Crates) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![root.join("README.md")];
+    for details in crates.details.values() {
+        let dir = details.crate_dir()?;
+        files.push(dir.join(details.readme.as_deref().unwrap_or("README.md")));
+        rust_files(&dir.join("src"), &mut files)?;
+    }
+    files.retain(|file| file.is_file());
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn rust_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("Cannot read {dir:?}"))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            rust_files(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "rs") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Update the requirements on `krate` in the TOML code blocks of `files` to
+/// `version`, keeping their precision, e.g. "1.2" becomes "1.3" for 1.3.0.
+/// Pre-releases are left out, since users don't install them by default.
+/// Returns the files which changed.
+pub fn bump_doc_examples(
+    files: &[PathBuf],
+    krate: &str,
+    version: &Version,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if !version.pre.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut changed = vec![];
+    for file in files {
+        let contents = fs::read_to_string(file).with_context(|| format!("Cannot read {file:?}"))?;
+        let is_rust = file.extension().is_some_and(|extension| extension == "rs");
+        let mut in_toml_block = false;
+        let mut file_changed = false;
+        let mut lines = vec![];
+        for line in contents.split_inclusive('\n') {
+            // Code blocks of doc comments are written after `///` or `//!`.
+            let text = if is_rust {
+                match line
+                    .trim_start()
+                    .strip_prefix("///")
+                    .or_else(|| line.trim_start().strip_prefix("//!"))
+                {
+                    Some(text) => text.trim_start(),
+                    None => {
+                        in_toml_block = false;
+                        lines.push(line.to_owned());
+                        continue;
+                    }
+                }
+            } else {
+                line.trim_start()
+            };
+            if let Some(info) = text.strip_prefix("```") {
+                in_toml_block = !in_toml_block && info.trim_start().starts_with("toml");
+            } else if in_toml_block {
+                if let Some(bumped) = bump_line(line, krate, version) {
+                    lines.push(bumped);
+                    file_changed = true;
+                    continue;
+                }
+            }
+            lines.push(line.to_owned());
+        }
+        if file_changed {
+            fs::write(file, lines.concat()).with_context(|| format!("Cannot write {file:?}"))?;
+            changed.push(file.clone());
+        }
+    }
+    Ok(changed)
+}
+
+/// Rewrite the requirement of a line like `krate = "1.2"` or
+/// `krate = { version = "1.2", ... }` for `version`, if it changes.
+fn bump_line(line: &str, krate: &str, version: &Version) -> Option<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let start = line
+        .match_indices(krate)
+        .map(|(start, _)| start)
+        .find(|&start| {
+            !line[..start].ends_with(is_name_char)
+                && !line[start + krate.len()..].starts_with(is_name_char)
+        })?;
+    let rest = line[start + krate.len()..].trim_start().strip_prefix('=')?;
+    let rest = rest.trim_start();
+    let requirement_start = if rest.starts_with('"') {
+        line.len() - rest.len() + 1
+    } else {
+        let table = rest.strip_prefix('{')?;
+        let table = &table[..table.find('}')?];
+        let key = table.find("version")?;
+        let value = table[key + "version".len()..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start()
+            .strip_prefix('"')?;
+        line.len() - rest.len() + 1 + table.len() - value.len()
+    };
+    let requirement_end = requirement_start + line[requirement_start..].find('"')?;
+    let requirement = &line[requirement_start..requirement_end];
+    let bumped = bump_requirement(requirement, version)?;
+    (bumped != requirement).then(|| {
+        format!(
+            "{}{bumped}{}",
+            &line[..requirement_start],
+            &line[requirement_end..]
+        )
+    })
+}
+
+/// `version` written with the operator and as many components as
+/// `requirement`, or as a 0.x version needs, unless the requirement isn't a
+/// single version.
+fn bump_requirement(requirement: &str, version: &Version) -> Option<String> {
+    VersionReq::parse(requirement).ok()?;
+    let operator_len = requirement
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(requirement.len());
+    let (operator, old) = requirement.split_at(operator_len);
+    if old.is_empty() || old.contains([',', ' ', '*', 'x', 'X', '-', '+']) {
+        return None;
+    }
+    // A 0.x requirement needs the components Cargo tells compatible versions
+    // apart by, e.g. `0.4` rather than `0` for 0.4.0, which would still allow
+    // 0.3.
+    let significant = if version.major > 0 {
+        1
+    } else if version.minor > 0 {
+        2
+    } else {
+        3
+    };
+    let bumped = match old.split('.').count().max(significant) {
+        1 => version.major.to_string(),
+        2 => format!("{}.{}", version.major, version.minor),
+        _ => version.to_string(),
+    };
+    Some(format!("{operator}{bumped}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    #[test]
+    fn requirements_keep_their_operator_and_precision() {
+        assert_eq!(bump_requirement("1.2", &v("1.3.0")).as_deref(), Some("1.3"));
+        assert_eq!(bump_requirement("1", &v("2.0.1")).as_deref(), Some("2"));
+        assert_eq!(
+            bump_requirement("=1.2.3", &v("1.2.4")).as_deref(),
+            Some("=1.2.4")
+        );
+        assert_eq!(
+            bump_requirement("~1.2", &v("1.3.0")).as_deref(),
+            Some("~1.3")
+        );
+    }
+
+    #[test]
+    fn zero_major_requirements_keep_the_components_cargo_compares() {
+        assert_eq!(bump_requirement("0", &v("0.4.0")).as_deref(), Some("0.4"));
+        assert_eq!(
+            bump_requirement("0.3", &v("0.0.2")).as_deref(),
+            Some("0.0.2")
+        );
+    }
+
+    #[test]
+    fn requirements_which_are_not_a_single_version_are_left_alone() {
+        assert_eq!(bump_requirement(">=1.2, <2", &v("2.0.0")), None);
+        assert_eq!(bump_requirement("1.*", &v("2.0.0")), None);
+        assert_eq!(bump_requirement("*", &v("2.0.0")), None);
+        assert_eq!(bump_requirement("1.0.0-rc.1", &v("2.0.0")), None);
+        assert_eq!(bump_requirement("not a version", &v("2.0.0")), None);
+    }
+
+    #[test]
+    fn lines_are_rewritten_for_the_crate_only() {
+        assert_eq!(
+            bump_line("sp-core = \"21.0\"\n", "sp-core", &v("22.0.0")).as_deref(),
+            Some("sp-core = \"22.0\"\n")
+        );
+        assert_eq!(
+            bump_line(
+                "sp-core = { version = \"21\", default-features = false }",
+                "sp-core",
+                &v("22.1.0")
+            )
+            .as_deref(),
+            Some("sp-core = { version = \"22\", default-features = false }")
+        );
+        assert_eq!(
+            bump_line("sp-core-hashing = \"21.0\"", "sp-core", &v("22.0.0")),
+            None
+        );
+        assert_eq!(
+            bump_line("sp-core = \"22.0\"", "sp-core", &v("22.0.1")),
+            None
+        );
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Line-delimited JSON events printed to stdout with `--porcelain`, so that
+//! tools wrapping subpub don't depend on the format of its logs. Logs and the
+//! output of the commands subpub runs go to stderr in that mode.
+
+use crate::secrets;
+use semver::Version;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The crate which is being processed, which a failure is attributed to.
+static CURRENT_CRATE: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// The crates which are going to be processed, grouped by level and in
+    /// publish order.
+    Plan { levels: Vec<Vec<&'a str>> },
+    /// A crate started to be processed.
+    CrateStarted {
+        #[serde(rename = "crate")]
+        krate: &'a str,
+        version: &'a Version,
+    },
+    /// A crate was published.
+    Published {
+        #[serde(rename = "crate")]
+        krate: &'a str,
+        version: &'a Version,
+    },
+    /// The run failed, while processing `krate` if it's set.
+    Failed {
+        #[serde(rename = "crate")]
+        krate: Option<String>,
+        error: String,
+    },
+    /// The run finished.
+    Done { success: bool },
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Print an event, if events are enabled. Every event is written as a single
+/// line, so events from different threads don't interleave.
+pub fn emit(event: Event) {
+    if let Event::CrateStarted { krate, .. } = &event {
+        *current_crate() = Some(krate.to_string());
+    }
+    if !is_enabled() {
+        return;
+    }
+    let line = match serde_json::to_string(&event) {
+        Ok(line) => secrets::redact(&line),
+        Err(err) => {
+            tracing::error!("Cannot serialize event {event:?}: {err}");
+            return;
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    if let Err(err) = writeln!(stdout, "{line}").and_then(|_| stdout.flush()) {
+        tracing::error!("Cannot print event: {err}");
+    }
+}
+
+/// Mark the crate which was started last as done, so that later failures
+/// aren't attributed to it.
+pub fn finish_crate() {
+    *current_crate() = None;
+}
+
+/// Emit the events which end a run.
+pub fn emit_result(result: &anyhow::Result<()>) {
+    if let Err(err) = result {
+        let krate = current_crate().clone();
+        emit(Event::Failed {
+            krate,
+            error: format!("{err:#}"),
+        });
+    }
+    emit(Event::Done {
+        success: result.is_ok(),
+    });
+}
+
+/// Where commands run by subpub should print their standard output: stdout
+/// is reserved for events when they are enabled.
+pub fn child_stdout() -> Stdio {
+    if is_enabled() {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+fn current_crate() -> std::sync::MutexGuard<'static, Option<String>> {
+    CURRENT_CRATE.lock().unwrap_or_else(|err| err.into_inner())
+}
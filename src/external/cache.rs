@@ -0,0 +1,139 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A cache on disk for the responses of the registry, so that repeated or
+//! resumed runs don't query it again for everything. Published `.crate` files
+//! never change, so they are kept for good, while other responses are reused
+//! for `SPUB_CACHE_TTL` seconds and then revalidated with their ETag.
+//!
+//! The cache lives in `SPUB_CACHE_DIR`, or `subpub` in the user's cache
+//! directory. Setting `SPUB_CACHE_DIR` to an empty string disables it.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// How long cached responses are used without asking the registry, in
+/// seconds.
+const DEFAULT_TTL: u64 = 300;
+
+/// A response of the registry as it's cached.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    /// When the response was received or last revalidated, in seconds since
+    /// the Unix epoch.
+    pub fetched: u64,
+    pub body: String,
+}
+
+impl CachedResponse {
+    pub fn new(etag: Option<String>, body: String) -> CachedResponse {
+        CachedResponse {
+            etag,
+            fetched: now(),
+            body,
+        }
+    }
+
+    /// Whether the response can be used without revalidating it.
+    pub fn is_fresh(&self) -> bool {
+        now().saturating_sub(self.fetched) < ttl().as_secs()
+    }
+
+    pub fn revalidated(self) -> CachedResponse {
+        CachedResponse {
+            fetched: now(),
+            ..self
+        }
+    }
+}
+
+/// The directory of the cache for the registry whose API is at `api`, if
+/// caching isn't disabled.
+pub fn dir(api: &str) -> Option<PathBuf> {
+    let root = match std::env::var_os("SPUB_CACHE_DIR") {
+        Some(dir) if dir.is_empty() => return None,
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?
+            .join("subpub"),
+    };
+    let api = api
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-',
+            "_",
+        );
+    Some(root.join(api))
+}
+
+pub fn ttl() -> Duration {
+    Duration::from_secs(
+        std::env::var("SPUB_CACHE_TTL")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(DEFAULT_TTL),
+    )
+}
+
+pub fn read_response(path: &Path) -> Option<CachedResponse> {
+    serde_json::from_slice(&read(path)?).ok()
+}
+
+pub fn write_response(path: &Path, response: &CachedResponse) {
+    match serde_json::to_vec(response) {
+        Ok(bytes) => write(path, &bytes),
+        Err(err) => debug!("Cannot serialize the response cached at {path:?}: {err}"),
+    }
+}
+
+pub fn read(path: &Path) -> Option<Vec<u8>> {
+    std::fs::read(path).ok()
+}
+
+/// Write a file of the cache, replacing it at once so that concurrent runs
+/// never see it half written. Failing to write is not an error, the cache is
+/// only there to save requests.
+pub fn write(path: &Path, bytes: &[u8]) {
+    let write = || -> anyhow::Result<()> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow::anyhow!("{path:?} has no parent"))?;
+        std::fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(bytes)?;
+        file.persist(path)?;
+        Ok(())
+    };
+    if let Err(err) = write() {
+        debug!("Cannot write {path:?} to the cache: {err}");
+    }
+}
+
+pub fn remove(path: &Path) {
+    let _ = std::fs::remove_file(path);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
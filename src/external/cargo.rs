@@ -14,33 +14,528 @@
 // You should have received a copy of the GNU General Public License
 // along with subpub.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::path::Path;
-use std::process::Command;
+use crate::interrupt;
+use crate::secrets;
+use anyhow::Context;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tracing::{info, warn};
 
-pub fn publish_crate(root: &Path, package: &str) -> anyhow::Result<()> {
-    let mut cmd = Command::new("cargo");
+/// The name Cargo gives to crates.io in its configuration.
+pub const CRATES_IO: &str = "crates-io";
 
-    cmd.current_dir(root).arg("publish");
+/// How Cargo is run: with which toolchain, and against which registry.
+#[derive(Debug, Clone, Default)]
+pub struct Cargo {
+    /// The rustup toolchain every Cargo command is run with, if it's pinned.
+    toolchain: Option<String>,
+    /// The registry crates are published to, if it's chosen with `--registry`.
+    registry: Option<String>,
+}
+
+impl Cargo {
+    /// Run all Cargo commands with `toolchain`, e.g. "1.77.0", instead of the
+    /// default toolchain, and publish to `registry`, as named in the Cargo
+    /// configuration, instead of crates.io.
+    pub fn new(toolchain: Option<&str>, registry: Option<&str>) -> Cargo {
+        Cargo {
+            toolchain: toolchain.map(|toolchain| toolchain.trim_start_matches('+').to_owned()),
+            registry: registry.map(|registry| registry.to_owned()),
+        }
+    }
+
+    /// A Cargo command, using the pinned toolchain if there's one.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new("cargo");
+        if let Some(toolchain) = &self.toolchain {
+            cmd.arg(format!("+{toolchain}"));
+        }
+        cmd
+    }
+
+    /// The version of Cargo the commands are run with, e.g.
+    /// "cargo 1.77.0 (3fe68eabf 2024-02-29)".
+    pub fn version(&self) -> anyhow::Result<String> {
+        let output = self
+            .command()
+            .arg("--version")
+            .output()
+            .context("Cannot run cargo --version")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to get the version of cargo: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// The registry crates are published to: the one given to [`Cargo::new`],
+    /// the one named by SPUB_REGISTRY, or crates.io.
+    pub fn target_registry(&self) -> String {
+        if let Some(registry) = &self.registry {
+            return registry.clone();
+        }
+        std::env::var("SPUB_REGISTRY").unwrap_or_else(|_| CRATES_IO.into())
+    }
+
+    /// The packages of the workspace at `root`, with the manifests resolved by
+    /// Cargo, e.g. the fields inherited from the workspace filled in.
+    pub fn metadata(&self, root: &Path) -> anyhow::Result<Metadata> {
+        let output = self
+            .command()
+            .current_dir(root)
+            .arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--no-deps")
+            .output()
+            .context("Cannot run cargo metadata")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to read the metadata of the workspace at {root:?}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        serde_json::from_slice(&output.stdout).context("Cannot parse the output of cargo metadata")
+    }
+
+    /// Publish a package, retrying up to `attempts` times in all when the
+    /// registry fails in a way which is likely to go away, like a server error or
+    /// a timeout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn publish_crate(
+        &self,
+        root: &Path,
+        package: &str,
+        version: &semver::Version,
+        registry: &str,
+        features: &Features,
+        allow_dirty: bool,
+        attempts: u32,
+    ) -> anyhow::Result<()> {
+        let mut attempt = 1;
+        loop {
+            let mut cmd = self.command();
+
+            cmd.current_dir(root).arg("publish");
+            self.use_registry(&mut cmd, registry)?;
+
+            // Everything subpub changes is committed by checkpoints, so only changes
+            // made by others make the crate dirty.
+            if allow_dirty {
+                cmd.arg("--allow-dirty");
+            }
+            features.apply(&mut cmd);
+
+            // The upload isn't cut short by Ctrl-C, so that an interrupted run
+            // knows whether the crate was published.
+            interrupt::shield(&mut cmd);
+            let (status, stderr) = secrets::run_redacted_capturing_stderr(
+                cmd.arg("--locked").arg("-vv").arg("-p").arg(package),
+            )?;
+            if status.success() {
+                return Ok(());
+            }
+            // A request which timed out might have gone through after all.
+            if attempt > 1 && is_already_uploaded(&stderr, package, version) {
+                info!("{package} {version} was published by an earlier attempt");
+                return Ok(());
+            }
+            if attempt >= attempts || !is_transient_failure(&stderr) {
+                anyhow::bail!("Failed to publish crate {package}");
+            }
 
-    if let Ok(registry) = std::env::var("SPUB_REGISTRY") {
-        cmd.env("CARGO_REGISTRY_DEFAULT", &registry)
-            .arg("--registry")
-            .arg(registry)
-            .arg("--token")
-            .arg(std::env::var("SPUB_REGISTRY_TOKEN").unwrap());
+            interrupt::check()?;
+            let delay = backoff(attempt);
+            warn!(
+                "Publishing {package} failed with what looks like a transient error, retrying in {}s (attempt {} of {attempts})",
+                delay.as_secs(),
+                attempt + 1
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
     }
 
-    if !cmd
-        .arg("--locked")
-        .arg("--allow-dirty")
-        .arg("-vv")
-        .arg("-p")
-        .arg(package)
-        .status()?
+    /// Build the package of a crate for `target`, like `cargo publish` verifies
+    /// it for the host, since some crates only break on other targets, e.g. no-std
+    /// crates on wasm32-unknown-unknown.
+    pub fn verify_crate(
+        &self,
+        root: &Path,
+        package: &str,
+        target: &str,
+        features: &Features,
+        allow_dirty: bool,
+    ) -> anyhow::Result<()> {
+        let mut cmd = self.command();
+        cmd.current_dir(root).arg("package");
+        self.use_target_registry(&mut cmd)?;
+        if allow_dirty {
+            cmd.arg("--allow-dirty");
+        }
+        features.apply(&mut cmd);
+
+        if !secrets::run_redacted(
+            cmd.arg("--locked")
+                .arg("-p")
+                .arg(package)
+                .arg("--target")
+                .arg(target),
+        )?
         .success()
+        {
+            anyhow::bail!("Failed to verify crate {package} for {target}");
+        }
+
+        Ok(())
+    }
+
+    /// Remove a published version from the index of the target registry, so that
+    /// it's no longer picked for new lockfiles.
+    pub fn yank_crate(
+        &self,
+        root: &Path,
+        package: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<()> {
+        let mut cmd = self.command();
+        cmd.current_dir(root).arg("yank");
+        self.use_target_registry(&mut cmd)?;
+
+        if !secrets::run_redacted(cmd.arg("--version").arg(version.to_string()).arg(package))?
+            .success()
+        {
+            anyhow::bail!("Failed to yank {package} {version}");
+        }
+
+        Ok(())
+    }
+
+    /// Point a Cargo command which talks to the registry at the target registry.
+    fn use_target_registry(&self, cmd: &mut Command) -> anyhow::Result<()> {
+        self.use_registry(cmd, &self.target_registry())
+    }
+
+    /// Point a Cargo command which talks to the registry at `registry`.
+    fn use_registry(&self, cmd: &mut Command, registry: &str) -> anyhow::Result<()> {
+        if registry != CRATES_IO {
+            cmd.env("CARGO_REGISTRY_DEFAULT", registry)
+                .arg("--registry")
+                .arg(registry);
+        }
+        // Hand the token of the registry to Cargo explicitly, so that it doesn't
+        // depend on which registry Cargo's own configuration would pick.
+        if let Some(token) = self.registry_token(registry)? {
+            cmd.env(token_env_var(registry), token);
+        }
+        Ok(())
+    }
+
+    /// The token to publish to `registry` with: SPUB_REGISTRY_TOKEN if it's set
+    /// and `registry` is the target registry, otherwise the one stored with
+    /// `subpub login`.
+    pub fn registry_token(&self, registry: &str) -> anyhow::Result<Option<String>> {
+        let token = match std::env::var("SPUB_REGISTRY_TOKEN") {
+            Ok(token) if registry == self.target_registry() => Some(token),
+            _ => stored_token(registry)?,
+        };
+        if let Some(token) = &token {
+            secrets::register(token.as_str());
+        }
+        Ok(token)
+    }
+
+    /// Store the token of a registry in Cargo's credential store.
+    pub fn login(&self, registry: &str, token: &str) -> anyhow::Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("login");
+        if registry != CRATES_IO {
+            cmd.arg("--registry").arg(registry);
+        }
+        // The token is passed through stdin rather than as an argument so that it
+        // doesn't show up in the process list.
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Cannot run cargo login")?;
+        writeln!(
+            child.stdin.take().context("Cannot write to cargo login")?,
+            "{token}"
+        )?;
+        if !child.wait()?.success() {
+            anyhow::bail!("Failed to store the token of {registry}");
+        }
+        Ok(())
+    }
+}
+
+/// Make sure Cargo knows `registry`, i.e. that it's crates.io or that it has
+/// an index in `[registries]` of the Cargo configuration which applies to the
+/// workspace at `root`, or in a `CARGO_REGISTRIES_<NAME>_INDEX` variable.
+pub fn check_registry_configured(root: &Path, registry: &str) -> anyhow::Result<()> {
+    if registry == CRATES_IO || registry_index(root, registry)?.is_some() {
+        return Ok(());
+    }
+    anyhow::bail!(
+        "The registry {registry} is not configured. Add its index to [registries.{registry}] in .cargo/config.toml, or set {}.",
+        registry_index_env_var(registry)
+    )
+}
+
+/// The index of `registry` as Cargo is configured with it for the workspace at
+/// `root`, e.g. `sparse+https://...`, or `None` for crates.io and registries
+/// Cargo doesn't know.
+pub fn registry_index(root: &Path, registry: &str) -> anyhow::Result<Option<String>> {
+    if registry == CRATES_IO {
+        return Ok(None);
+    }
+    if let Ok(index) = std::env::var(registry_index_env_var(registry)) {
+        return Ok(Some(index));
+    }
+
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    let mut config_files = root
+        .ancestors()
+        .flat_map(|dir| [dir.join(".cargo/config.toml"), dir.join(".cargo/config")])
+        .collect::<Vec<_>>();
+    if let Some(cargo_home) = cargo_home {
+        config_files.push(cargo_home.join("config.toml"));
+        config_files.push(cargo_home.join("config"));
+    }
+    for config_file in config_files.iter().filter(|file| file.exists()) {
+        let config = crate::toml::toml_read(config_file)?;
+        let index = config
+            .get("registries")
+            .and_then(|registries| registries.get(registry))
+            .and_then(|registry| registry.get("index"))
+            .and_then(|index| index.as_str());
+        if let Some(index) = index {
+            return Ok(Some(index.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// The variable Cargo reads the index of `registry` from.
+fn registry_index_env_var(registry: &str) -> String {
+    format!(
+        "CARGO_REGISTRIES_{}_INDEX",
+        registry.to_uppercase().replace('-', "_")
+    )
+}
+
+/// The output of `cargo metadata`, as far as subpub uses it.
+#[derive(Debug, serde::Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: semver::Version,
+    pub manifest_path: PathBuf,
+    pub dependencies: Vec<Dependency>,
+    pub targets: Vec<Target>,
+    /// The registries the package can be published to, where an empty list
+    /// means it can't be published at all.
+    pub publish: Option<Vec<String>>,
+    pub readme: Option<String>,
+    /// `package.metadata` of the manifest.
+    #[serde(default)]
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Dependency {
+    /// The name of the package, even if the dependency is renamed.
+    pub name: String,
+    pub req: semver::VersionReq,
+    /// `None` for normal dependencies, otherwise "dev" or "build".
+    pub kind: Option<String>,
+    /// Set for dependencies on packages given by a path.
+    pub path: Option<PathBuf>,
+    /// Where the package comes from, e.g. `git+https://...`, unless it's
+    /// given by a path.
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Target {
+    pub kind: Vec<String>,
+}
+
+/// The features the package of a crate is built with when it's verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Features {
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+}
+
+impl Features {
+    fn apply(&self, cmd: &mut Command) {
+        if self.no_default_features {
+            cmd.arg("--no-default-features");
+        }
+        if !self.features.is_empty() {
+            cmd.arg("--features").arg(self.features.join(","));
+        }
+    }
+}
+
+/// Whether the output of `cargo publish` points at a problem of the registry
+/// or the network rather than of the crate.
+fn is_transient_failure(stderr: &str) -> bool {
+    const TRANSIENT: [&str; 10] = [
+        "500 Internal Server Error",
+        "502 Bad Gateway",
+        "503 Service Unavailable",
+        "504 Gateway Timeout",
+        "timed out",
+        "Timeout was reached",
+        "Connection reset",
+        "connection reset",
+        "spurious network error",
+        "failed to get a 200 OK response",
+    ];
+    TRANSIENT.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Whether `cargo publish` failed because the version is published already, as
+/// the registry words it when the upload is refused, or as Cargo words it when
+/// it checks the index before uploading. The output is verbose, so only these
+/// exact errors count.
+fn is_already_uploaded(stderr: &str, package: &str, version: &semver::Version) -> bool {
+    let refused = format!("crate version `{version}` is already uploaded");
+    let exists = format!("crate {package}@{version} already exists on");
+    stderr
+        .lines()
+        .any(|line| line.contains(&refused) || line.contains(&exists))
+}
+
+/// How long to wait before the next attempt: exponentially longer after each
+/// attempt, capped at five minutes, and with up to half of it added at random
+/// so that parallel runs don't retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let base = Duration::from_secs(5)
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(Duration::from_secs(300));
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+    base + base.mul_f64(jitter as f64 / 2000.0)
+}
+
+/// The token of a registry in Cargo's credential store, if there's one.
+fn stored_token(registry: &str) -> anyhow::Result<Option<String>> {
+    let cargo_home = cargo_home()?;
+    let path = match ["credentials.toml", "credentials"]
+        .iter()
+        .map(|file| cargo_home.join(file))
+        .find(|path| path.exists())
     {
-        anyhow::bail!("Failed to publish crate {package}");
+        Some(path) => path,
+        None => return Ok(None),
     };
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Cannot read {:?}", path.as_os_str()))?;
+    let credentials: toml_edit::easy::Value = toml_edit::easy::from_str(&contents)
+        .with_context(|| format!("Cannot parse {:?}", path.as_os_str()))?;
+    let section = if registry == CRATES_IO {
+        credentials.get("registry")
+    } else {
+        credentials
+            .get("registries")
+            .and_then(|registries| registries.get(registry))
+    };
+    Ok(section
+        .and_then(|section| section.get("token"))
+        .and_then(|token| token.as_str())
+        .map(|token| token.to_owned()))
+}
+
+fn cargo_home() -> anyhow::Result<PathBuf> {
+    if let Ok(cargo_home) = std::env::var("CARGO_HOME") {
+        return Ok(PathBuf::from(cargo_home));
+    }
+    let home = std::env::var("HOME").context("Neither CARGO_HOME nor HOME is set")?;
+    Ok(PathBuf::from(home).join(".cargo"))
+}
 
-    Ok(())
+/// The environment variable Cargo reads the token of a registry from.
+fn token_env_var(registry: &str) -> String {
+    if registry == CRATES_IO {
+        "CARGO_REGISTRY_TOKEN".into()
+    } else {
+        format!(
+            "CARGO_REGISTRIES_{}_TOKEN",
+            registry.to_uppercase().replace('-', "_")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_and_network_errors_are_transient() {
+        assert!(is_transient_failure(
+            "error: failed to publish to registry at https://crates.io\n\nCaused by:\n  the remote server responded with an error (status 503 Service Unavailable)"
+        ));
+        assert!(is_transient_failure(
+            "warning: spurious network error (2 tries remaining): [28] Timeout was reached"
+        ));
+        assert!(!is_transient_failure(
+            "error: failed to verify package tarball\n\nCaused by:\n  failed to compile `a v0.1.0`"
+        ));
+    }
+
+    #[test]
+    fn already_uploaded_versions_are_recognized() {
+        let version = semver::Version::new(1, 2, 3);
+        assert!(is_already_uploaded(
+            "error: failed to publish to registry at https://crates.io\n\nCaused by:\n  the remote server responded with an error: crate version `1.2.3` is already uploaded",
+            "a",
+            &version
+        ));
+        assert!(is_already_uploaded(
+            "error: crate a@1.2.3 already exists on crates.io index",
+            "a",
+            &version
+        ));
+        assert!(!is_already_uploaded(
+            "error: crate a@1.2.4 already exists on crates.io index",
+            "a",
+            &version
+        ));
+        assert!(!is_already_uploaded(
+            "error: crate ab@1.2.3 already exists on crates.io index",
+            "a",
+            &version
+        ));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_five_minutes() {
+        for (attempt, base) in [(1, 5), (2, 10), (3, 20), (7, 300), (40, 300)] {
+            let delay = backoff(attempt);
+            let base = Duration::from_secs(base);
+            assert!(
+                delay >= base && delay <= base.mul_f64(1.5),
+                "{attempt}: {delay:?}"
+            );
+        }
+    }
 }
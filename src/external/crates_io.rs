@@ -14,91 +14,819 @@
 // You should have received a copy of the GNU General Public License
 // along with subpub.  If not, see <http://www.gnu.org/licenses/>.
 
+use super::cache::{self, CachedResponse};
+use crate::interrupt;
 use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
 
-pub fn does_crate_exist(name: &str, version: &semver::Version) -> anyhow::Result<bool> {
-    let client = reqwest::blocking::Client::new();
-    let crates_api = std::env::var("SPUB_CRATES_API").unwrap();
-    let url = format!("{crates_api}/crates/{name}/{version}");
-    let res = client.get(&url)
+/// How long to wait between checks of whether a published crate is available.
+const POLL_INTERVAL: Duration = Duration::from_millis(2500);
+
+/// How many requests [`Registry::prefetch_crate_versions`] makes at once.
+const PREFETCH_CONCURRENCY: usize = 16;
+
+/// The registry crates are published to, as its API and its index are queried,
+/// along with the versions of crates fetched from it so far. Clones share
+/// those versions.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    /// The name Cargo knows the registry by.
+    name: String,
+    /// The API set by [`Registry::use_api`].
+    api_url: Option<String>,
+    /// The token sent to the API, see [`Registry::use_api`].
+    api_token: Option<String>,
+    /// The sparse index set by [`Registry::read_versions_from_index`].
+    index_url: Option<String>,
+    /// The sparse index of the registry from the Cargo configuration, see
+    /// [`Registry::use_registry_index`].
+    registry_index: Option<String>,
+    /// The versions fetched by [`Registry::prefetch_crate_versions`], by crate.
+    prefetched_versions: Arc<Mutex<BTreeMap<String, Vec<semver::Version>>>>,
+}
+
+impl Registry {
+    /// The registry Cargo knows as `name`, queried through SPUB_CRATES_API and
+    /// SPUB_CRATES_INDEX unless told otherwise.
+    pub fn new(name: &str) -> Registry {
+        Registry {
+            name: name.to_owned(),
+            api_url: None,
+            api_token: None,
+            index_url: None,
+            registry_index: None,
+            prefetched_versions: Arc::default(),
+        }
+    }
+
+    /// The name Cargo knows the registry by.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Query the crates.io-compatible API at `url`, e.g. the one of kellnr or
+    /// Artifactory, rather than the one in SPUB_CRATES_API. When `token` is given,
+    /// it's sent in the Authorization header of the requests to the API and to the
+    /// configured index, which private registries usually require.
+    pub fn use_api(&mut self, url: &str, token: Option<String>) {
+        self.api_url = Some(url.trim_end_matches('/').to_string());
+        self.api_token = token;
+    }
+
+    /// The API of the registry: the one given to [`Registry::use_api`], or SPUB_CRATES_API.
+    pub fn crates_api(&self) -> anyhow::Result<String> {
+        if let Some(url) = &self.api_url {
+            return Ok(url.clone());
+        }
+        std::env::var("SPUB_CRATES_API")
+            .context("The API of the registry has to be given with --api-url or SPUB_CRATES_API")
+    }
+
+    /// A GET request for `url`, with the token given to [`Registry::use_api`] if `url` is
+    /// under the API or the configured index of the registry the token is for, so
+    /// that the token doesn't go to other hosts, e.g. the index of crates.io or
+    /// the one of a mirror.
+    fn authorized(
+        &self,
+        client: &reqwest::blocking::Client,
+        url: &str,
+    ) -> reqwest::blocking::RequestBuilder {
+        let is_registry_url = self
+            .api_url
+            .iter()
+            .chain(self.configured_index().iter())
+            .any(|base| is_under(url, base));
+        with_token(
+            client.get(url),
+            self.api_token.as_deref().filter(|_| is_registry_url),
+        )
+    }
+
+    pub fn does_crate_exist(&self, name: &str, version: &semver::Version) -> anyhow::Result<bool> {
+        let client = reqwest::blocking::Client::new();
+        let crates_api = self.crates_api()?;
+        let url = format!("{crates_api}/crates/{name}/{version}");
+        let res = self.authorized(&client, &url)
         .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate")
         .send()
         .with_context(|| format!("Cannot download {name}"))?;
 
-    let res_status = res.status();
-    if res_status == reqwest::StatusCode::NOT_FOUND {
-        return Ok(false);
+        let res_status = res.status();
+        if res_status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+
+        if !res_status.is_success() {
+            // We get a 200 back even if we ask for crates/versions that don't exist,
+            // so a non-200 means something worse went wrong.
+            anyhow::bail!(
+                "Non-200 status trying to connect to {url} ({})",
+                res.status()
+            );
+        }
+
+        Ok(true)
     }
 
-    if !res_status.is_success() {
-        // We get a 200 back even if we ask for crates/versions that don't exist,
-        // so a non-200 means something worse went wrong.
-        anyhow::bail!(
-            "Non-200 status trying to connect to {url} ({})",
-            res.status()
-        );
+    /// Read the versions of crates from the sparse index at `url`, e.g.
+    /// `https://index.crates.io`, rather than from the API, which has much
+    /// stricter rate limits. The index is also the one new versions are waited
+    /// for in. Cargo's `sparse+` prefix is accepted.
+    pub fn read_versions_from_index(&mut self, url: &str) {
+        let url = url.strip_prefix("sparse+").unwrap_or(url);
+        self.index_url = Some(url.trim_end_matches('/').to_string());
     }
 
-    Ok(true)
-}
+    /// The sparse index given to [`Registry::read_versions_from_index`], if any.
+    fn index_url(&self) -> Option<String> {
+        self.index_url.clone()
+    }
 
-pub fn crate_versions<Name: AsRef<str>>(name: Name) -> anyhow::Result<Vec<semver::Version>> {
-    let client = reqwest::blocking::Client::new();
-    let crates_api = std::env::var("SPUB_CRATES_API").unwrap();
-    let url = format!("{crates_api}/crates/{}/versions", name.as_ref());
-    let res = client.get(&url)
+    /// Wait for new versions in `index`, the index Cargo is configured with for
+    /// the target registry, when no other index is given. Only sparse indexes,
+    /// with the `sparse+` prefix, can be waited in.
+    pub fn use_registry_index(&mut self, index: Option<&str>) {
+        self.registry_index = index
+            .and_then(|index| index.strip_prefix("sparse+"))
+            .map(|index| index.trim_end_matches('/').to_string());
+    }
+
+    /// The sparse index of the registry which was configured: the one given to
+    /// [`Registry::read_versions_from_index`], SPUB_CRATES_INDEX, or the one given to
+    /// [`Registry::use_registry_index`].
+    fn configured_index(&self) -> Option<String> {
+        self.index_url()
+            .or_else(|| std::env::var("SPUB_CRATES_INDEX").ok())
+            .or_else(|| self.registry_index.clone())
+    }
+
+    /// The sparse index new versions are waited for in: the configured one, see
+    /// [`Registry::configured_index`], or the one of crates.io when publishing there. The
+    /// sparse index of another registry isn't known otherwise, and a crate of the
+    /// same name on crates.io would be mistaken for the published one.
+    fn crates_index(&self) -> Option<String> {
+        self.configured_index().or_else(|| {
+            (self.name == super::cargo::CRATES_IO).then(|| "https://index.crates.io".into())
+        })
+    }
+
+    /// Whether the sparse index at `index` lists the version, which is when Cargo
+    /// can resolve it.
+    pub fn is_in_index(
+        &self,
+        index: &str,
+        name: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<bool> {
+        let client = reqwest::blocking::Client::new();
+        let url = index_entry_url(index, name);
+        is_in_index_entry(self.authorized(&client, &url), &url, name, version)
+    }
+
+    /// Poll the API and then the index until the version can be resolved, giving
+    /// up after `timeout`. The index is skipped when its address isn't known.
+    pub fn wait_until_available(
+        &self,
+        name: &str,
+        version: &semver::Version,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let poll = |available: &dyn Fn() -> anyhow::Result<bool>, place: &str| {
+            while !available()? {
+                interrupt::check()?;
+                if Instant::now() >= deadline {
+                    anyhow::bail!(
+                        "{name} {version} did not show up in the {place} within {}s",
+                        timeout.as_secs()
+                    );
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Ok(())
+        };
+        poll(&|| self.does_crate_exist(name, version), "registry API")?;
+        match self.crates_index() {
+        Some(index) => poll(&|| self.is_in_index(&index, name, version), "registry index")?,
+        None => info!(
+            "Not waiting for {name} {version} to show up in the index of {}, since it has no sparse index configured",
+            self.name
+        ),
+    }
+        self.forget_crate_versions(name);
+        Ok(())
+    }
+
+    /// Fetch the versions of many crates at once, so that [`Registry::crate_versions`]
+    /// doesn't have to query the registry for each of them in turn. Crates whose
+    /// versions can't be fetched are left out, [`Registry::crate_versions`] reports the
+    /// error when they are needed.
+    pub fn prefetch_crate_versions<Name: AsRef<str> + Sync>(&self, names: &[Name]) {
+        let queue = Mutex::new(names.iter());
+        let workers = PREFETCH_CONCURRENCY.min(names.len());
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let name = match queue.lock().unwrap().next() {
+                        Some(name) => name.as_ref(),
+                        None => break,
+                    };
+                    match self.fetch_crate_versions(name) {
+                        Ok(versions) => {
+                            self.prefetched_versions
+                                .lock()
+                                .unwrap()
+                                .insert(name.to_string(), versions);
+                        }
+                        Err(err) => debug!("Cannot prefetch the versions of {name}: {err:?}"),
+                    }
+                });
+            }
+        });
+    }
+
+    /// The versions of a crate published to the registry.
+    pub fn crate_versions<Name: AsRef<str>>(
+        &self,
+        name: Name,
+    ) -> anyhow::Result<Vec<semver::Version>> {
+        let name = name.as_ref();
+        if let Some(versions) = self.prefetched_versions.lock().unwrap().get(name) {
+            return Ok(versions.clone());
+        }
+        self.fetch_crate_versions(name)
+    }
+
+    /// Query the registry for the versions of a crate, or take them from the
+    /// cache while it's fresh.
+    fn fetch_crate_versions(&self, name: &str) -> anyhow::Result<Vec<semver::Version>> {
+        Ok(self
+            .fetch_version_entries(name)?
+            .into_iter()
+            .map(|(version, _)| version)
+            .collect())
+    }
+
+    /// The versions of a crate which are yanked, as the registry reports them.
+    pub fn yanked_crate_versions(&self, name: &str) -> anyhow::Result<Vec<semver::Version>> {
+        Ok(self
+            .fetch_version_entries(name)?
+            .into_iter()
+            .filter(|(_, yanked)| *yanked)
+            .map(|(version, _)| version)
+            .collect())
+    }
+
+    /// Query the registry for the versions of a crate and whether each of them is
+    /// yanked, or take them from the cache while it's fresh.
+    fn fetch_version_entries(&self, name: &str) -> anyhow::Result<Vec<(semver::Version, bool)>> {
+        let index = self.index_url();
+        let url = match &index {
+            Some(index) => format!("{index}/{}", index_path(name)),
+            None => {
+                let crates_api = self.crates_api()?;
+                format!("{crates_api}/crates/{name}/versions")
+            }
+        };
+        let body = match self.fetch_cached(&url, self.versions_cache_path(name).as_deref(), name)? {
+            Some(body) => body,
+            None => return Ok(vec![]),
+        };
+
+        parse_version_entries(&body, index.is_some(), name)
+    }
+
+    /// The versions of a crate as subpub last saw them, either prefetched or in
+    /// the cache however old they are, without asking the registry.
+    pub fn known_crate_versions(&self, name: &str) -> Option<Vec<semver::Version>> {
+        if let Some(versions) = self.prefetched_versions.lock().unwrap().get(name) {
+            return Some(versions.clone());
+        }
+        let cached = cache::read_response(&self.versions_cache_path(name)?)?;
+        parse_versions(&cached.body, self.index_url().is_some(), name).ok()
+    }
+
+    /// Get `url`, or take its response from the cache while it's fresh. Returns
+    /// `None` if there's nothing at `url`.
+    fn fetch_cached(
+        &self,
+        url: &str,
+        cache_path: Option<&Path>,
+        name: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let cached = cache_path.and_then(cache::read_response);
+        if let Some(cached) = &cached {
+            if cached.is_fresh() {
+                return Ok(Some(cached.body.clone()));
+            }
+        }
+
+        let client = reqwest::blocking::Client::new();
+        let mut req = self.authorized(&client, url)
+        .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate");
+        if let Some(etag) = cached.as_ref().and_then(|cached| cached.etag.as_ref()) {
+            req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        let res = req
+            .send()
+            .with_context(|| format!("Cannot download {name}"))?;
+
+        let res_status = res.status();
+        if res_status == reqwest::StatusCode::NOT_FOUND {
+            if let Some(cache_path) = cache_path {
+                cache::remove(cache_path);
+            }
+            return Ok(None);
+        }
+
+        let response = match cached {
+            Some(cached) if res_status == reqwest::StatusCode::NOT_MODIFIED => cached.revalidated(),
+            _ => {
+                if !res_status.is_success() {
+                    anyhow::bail!("Non-200 status from response of {url} ({})", res.status());
+                }
+                let etag = res
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(|etag| etag.to_string());
+                CachedResponse::new(etag, res.text()?)
+            }
+        };
+        if let Some(cache_path) = cache_path {
+            cache::write_response(cache_path, &response);
+        }
+        Ok(Some(response.body))
+    }
+
+    /// Where the versions of a crate are cached, which depends on whether they
+    /// are read from the API or from the index.
+    fn versions_cache_path(&self, name: &str) -> Option<PathBuf> {
+        match self.index_url() {
+            Some(index) => cache::dir(&index).map(|dir| dir.join("index").join(name)),
+            None => {
+                let crates_api = self.crates_api().ok()?;
+                cache::dir(&crates_api).map(|dir| dir.join("versions").join(format!("{name}.json")))
+            }
+        }
+    }
+
+    /// Drop the cached versions of a crate, e.g. once a new version of it is
+    /// published.
+    pub fn forget_crate_versions(&self, name: &str) {
+        self.prefetched_versions.lock().unwrap().remove(name);
+        if let Some(cache_path) = self.versions_cache_path(name) {
+            cache::remove(&cache_path);
+        }
+    }
+
+    /// The logins of the owners of a crate, where teams are named like
+    /// `github:org:team`.
+    pub fn crate_owners(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        #[derive(serde::Deserialize)]
+        struct ResponseUser {
+            pub login: String,
+        }
+        #[derive(serde::Deserialize, Default)]
+        struct ResponseMeta {
+            #[serde(default)]
+            pub next_page: Option<String>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Response {
+            pub users: Vec<ResponseUser>,
+            #[serde(default)]
+            pub meta: Option<ResponseMeta>,
+        }
+
+        let client = reqwest::blocking::Client::new();
+        let base = format!("{}/crates/{name}/owners", self.crates_api()?);
+        let mut url = base.clone();
+        let mut owners = vec![];
+        // The registry may split the owners in pages, each one pointing to the
+        // next with a query string.
+        loop {
+            let res = send_rate_limited(|| {
+            self.authorized(&client, &url).header(
+                "User-Agent",
+                "Called from https://github.com/paritytech/subpub for checking the owners of a crate",
+            )
+        })
+        .with_context(|| format!("Cannot download the owners of {name}"))?;
+            if !res.status().is_success() {
+                anyhow::bail!("Non-200 status from response of {url} ({})", res.status());
+            }
+
+            let page = res.json::<Response>()?;
+            if page.users.is_empty() {
+                break;
+            }
+            for user in page.users {
+                if !owners.contains(&user.login) {
+                    owners.push(user.login);
+                }
+            }
+            match page.meta.unwrap_or_default().next_page {
+                Some(next_page) if !next_page.is_empty() => {
+                    let next = format!("{base}{next_page}");
+                    if next == url {
+                        break;
+                    }
+                    url = next;
+                }
+                _ => break,
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Add owners to a crate, or remove them, with a token of one of its owners.
+    /// Users are invited rather than added right away; teams are added directly.
+    pub fn change_owners(
+        &self,
+        name: &str,
+        logins: &[String],
+        add: bool,
+        token: &str,
+    ) -> anyhow::Result<()> {
+        let client = reqwest::blocking::Client::new();
+        let url = format!("{}/crates/{name}/owners", self.crates_api()?);
+        let res = send_rate_limited(|| {
+            let req = if add {
+                client.put(&url)
+            } else {
+                client.delete(&url)
+            };
+            req.header(
+            "User-Agent",
+            "Called from https://github.com/paritytech/subpub for changing the owners of a crate",
+        )
+        .header(reqwest::header::AUTHORIZATION, token)
+        .json(&serde_json::json!({ "users": logins }))
+        })
+        .with_context(|| format!("Cannot change the owners of {name}"))?;
+
+        let res_status = res.status();
+        if !res_status.is_success() {
+            anyhow::bail!(
+                "Failed to change the owners of {name} ({res_status}): {}",
+                res.text().unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    /// Download a crate from crates.io, or take it from the cache if it was
+    /// downloaded before.
+    pub fn try_download_crate(
+        &self,
+        name: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let client = reqwest::blocking::Client::new();
+        let version = version.to_string();
+        let crates_api = self.crates_api()?;
+
+        let cache_path = cache::dir(&crates_api).map(|dir| {
+            dir.join("crates")
+                .join(name)
+                .join(format!("{name}-{version}.crate"))
+        });
+        if let Some(bytes) = cache_path.as_deref().and_then(cache::read) {
+            return Ok(Some(bytes));
+        }
+
+        let req_url = format!("{crates_api}/crates/{name}/{version}/download");
+        let res = self.authorized(&client, &req_url)
         .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate")
         .send()
-        .with_context(|| format!("Cannot download {}", name.as_ref()))?;
+        .with_context(|| format!("Cannot download {name}"))?;
+
+        let res_status = res.status();
+        if res_status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        } else if !res.status().is_success() {
+            anyhow::bail!("Request to {req_url} failed with HTTP status code {res_status}");
+        }
+
+        let bytes = res.bytes()?.to_vec();
+        if let Some(cache_path) = &cache_path {
+            cache::write(cache_path, &bytes);
+        }
+        Ok(Some(bytes))
+    }
+
+    /// Fetch the metadata crates.io has for a published version of a crate.
+    pub fn version_metadata(
+        &self,
+        name: &str,
+        version: &semver::Version,
+    ) -> anyhow::Result<VersionMetadata> {
+        let client = reqwest::blocking::Client::new();
+        let crates_api = self.crates_api()?;
+
+        let url = format!("{crates_api}/crates/{name}/{version}");
+        let res = self.authorized(&client, &url)
+        .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate")
+        .send()
+        .with_context(|| format!("Cannot download metadata of {name} {version}"))?;
+        if !res.status().is_success() {
+            anyhow::bail!("Non-200 status from response of {url} ({})", res.status());
+        }
+        #[derive(serde::Deserialize)]
+        struct ResponseVersion {
+            #[serde(default)]
+            pub features: BTreeMap<String, Vec<String>>,
+            pub links: Option<String>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Response {
+            pub version: ResponseVersion,
+        }
+        let version_res = res.json::<Response>()?.version;
+
+        let url = format!("{crates_api}/crates/{name}/{version}/dependencies");
+        let res = self.authorized(&client, &url)
+        .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate")
+        .send()
+        .with_context(|| format!("Cannot download dependencies of {name} {version}"))?;
+        if !res.status().is_success() {
+            anyhow::bail!("Non-200 status from response of {url} ({})", res.status());
+        }
+        #[derive(serde::Deserialize)]
+        struct DependenciesResponse {
+            pub dependencies: Vec<VersionDependency>,
+        }
+        let mut dependencies = res.json::<DependenciesResponse>()?.dependencies;
+        dependencies.sort();
+
+        Ok(VersionMetadata {
+            features: version_res.features,
+            links: version_res.links,
+            dependencies,
+        })
+    }
+}
+
+/// Add `token`, if any, to a request.
+fn with_token(
+    req: reqwest::blocking::RequestBuilder,
+    token: Option<&str>,
+) -> reqwest::blocking::RequestBuilder {
+    match token {
+        Some(token) => req.header(reqwest::header::AUTHORIZATION, token),
+        None => req,
+    }
+}
+
+/// Whether `url` is `base`, or a path or query under it.
+fn is_under(url: &str, base: &str) -> bool {
+    let base = base.strip_prefix("sparse+").unwrap_or(base);
+    url.strip_prefix(base.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || rest.starts_with('?'))
+}
+
+/// The path of a crate in a sparse index.
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+/// The URL of the entry of a crate in the sparse index at `index`.
+fn index_entry_url(index: &str, name: &str) -> String {
+    let index = index.strip_prefix("sparse+").unwrap_or(index);
+    format!("{}/{}", index.trim_end_matches('/'), index_path(name))
+}
+
+/// Whether the index entry at `url`, fetched with `req`, lists the version.
+fn is_in_index_entry(
+    req: reqwest::blocking::RequestBuilder,
+    url: &str,
+    name: &str,
+    version: &semver::Version,
+) -> anyhow::Result<bool> {
+    let res = req
+        .header("User-Agent", "Called from https://github.com/paritytech/subpub for checking that a published crate can be resolved")
+        .send()
+        .with_context(|| format!("Cannot download the index entry of {name}"))?;
 
     let res_status = res.status();
     if res_status == reqwest::StatusCode::NOT_FOUND {
-        return Ok(vec![]);
+        return Ok(false);
     }
-
     if !res_status.is_success() {
         anyhow::bail!("Non-200 status from response of {url} ({})", res.status());
     }
 
     #[derive(serde::Deserialize)]
-    struct ResponseVersion {
-        pub num: String,
-    }
-    #[derive(serde::Deserialize)]
-    struct Response {
-        pub versions: Vec<ResponseVersion>,
+    struct IndexEntry {
+        vers: String,
     }
-    res.json::<Response>()?
-        .versions
-        .into_iter()
-        .map(|version| -> anyhow::Result<semver::Version> {
-            semver::Version::parse(&version.num)
-                .with_context(|| format!("Failed to parse {} as semver::Version", version.num,))
-        })
-        .collect()
+    let version = version.to_string();
+    Ok(res.text()?.lines().any(|line| {
+        serde_json::from_str::<IndexEntry>(line)
+            .map(|entry| entry.vers == version)
+            .unwrap_or(false)
+    }))
 }
 
-/// Download a crate from crates.io.
-pub fn try_download_crate(
+/// Poll the sparse index at `index`, e.g. the one of a mirror, until it lists
+/// the version, giving up after `timeout`. The requests carry `token`, the one
+/// of the registry the index belongs to, if any.
+pub fn wait_until_in_index(
+    index: &str,
+    token: Option<&str>,
     name: &str,
     version: &semver::Version,
-) -> anyhow::Result<Option<Vec<u8>>> {
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
     let client = reqwest::blocking::Client::new();
-    let version = version.to_string();
-    let crates_api = std::env::var("SPUB_CRATES_API").unwrap();
+    let url = index_entry_url(index, name);
+    while !is_in_index_entry(with_token(client.get(&url), token), &url, name, version)? {
+        interrupt::check()?;
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "{name} {version} did not show up in {index} within {}s",
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
 
-    let req_url = format!("{crates_api}/crates/{name}/{version}/download");
-    let res = client.get(&req_url)
-        .header("User-Agent", "Called from https://github.com/paritytech/subpub for comparing local crate against published crate")
+/// Parse the versions of a crate from an index entry or an API response.
+fn parse_versions(
+    body: &str,
+    from_index: bool,
+    name: &str,
+) -> anyhow::Result<Vec<semver::Version>> {
+    Ok(parse_version_entries(body, from_index, name)?
+        .into_iter()
+        .map(|(version, _)| version)
+        .collect())
+}
+
+/// Parse the versions of a crate, and whether each of them is yanked, from an
+/// index entry or an API response.
+fn parse_version_entries(
+    body: &str,
+    from_index: bool,
+    name: &str,
+) -> anyhow::Result<Vec<(semver::Version, bool)>> {
+    let versions = if from_index {
+        #[derive(serde::Deserialize)]
+        struct IndexEntry {
+            vers: String,
+            #[serde(default)]
+            yanked: bool,
+        }
+        body.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str::<IndexEntry>(line)
+                    .map(|entry| (entry.vers, entry.yanked))
+                    .with_context(|| format!("Cannot parse the index entry of {name}: {line}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        #[derive(serde::Deserialize)]
+        struct ResponseVersion {
+            pub num: String,
+            #[serde(default)]
+            pub yanked: bool,
+        }
+        #[derive(serde::Deserialize)]
+        struct Response {
+            pub versions: Vec<ResponseVersion>,
+        }
+        serde_json::from_str::<Response>(body)
+            .with_context(|| format!("Cannot parse the versions of {name}"))?
+            .versions
+            .into_iter()
+            .map(|version| (version.num, version.yanked))
+            .collect()
+    };
+    versions
+        .into_iter()
+        .map(
+            |(version, yanked)| -> anyhow::Result<(semver::Version, bool)> {
+                let version = semver::Version::parse(&version)
+                    .with_context(|| format!("Failed to parse {version} as semver::Version"))?;
+                Ok((version, yanked))
+            },
+        )
+        .collect()
+}
+
+/// Check a token against the API of a registry, returning the login of the
+/// user it belongs to.
+pub fn token_owner(crates_api: &str, token: &str) -> anyhow::Result<String> {
+    let client = reqwest::blocking::Client::new();
+    let url = format!("{crates_api}/me");
+    let res = client
+        .get(&url)
+        .header(
+            "User-Agent",
+            "Called from https://github.com/paritytech/subpub for checking a registry token",
+        )
+        .header("Authorization", token)
         .send()
-        .with_context(|| format!("Cannot download {name}"))?;
+        .with_context(|| format!("Cannot connect to {url}"))?;
 
     let res_status = res.status();
-    if res_status == reqwest::StatusCode::NOT_FOUND {
-        return Ok(None);
-    } else if !res.status().is_success() {
-        anyhow::bail!("Request to {req_url} failed with HTTP status code {res_status}");
+    if res_status == reqwest::StatusCode::UNAUTHORIZED
+        || res_status == reqwest::StatusCode::FORBIDDEN
+    {
+        anyhow::bail!("The token was rejected by {url} ({res_status})");
+    }
+    if !res_status.is_success() {
+        anyhow::bail!("Non-200 status from response of {url} ({res_status})");
     }
 
-    Ok(Some(res.bytes()?.to_vec()))
+    #[derive(serde::Deserialize)]
+    struct ResponseUser {
+        pub login: String,
+    }
+    #[derive(serde::Deserialize)]
+    struct Response {
+        pub user: ResponseUser,
+    }
+    Ok(res.json::<Response>()?.user.login)
+}
+
+/// How many times a request is sent while the registry answers that too many
+/// requests were made.
+const RATE_LIMITED_ATTEMPTS: u32 = 6;
+
+/// Send a request, and send it again for as long as the registry answers 429
+/// Too Many Requests, waiting for what its Retry-After header asks for, or
+/// for a delay doubling with each attempt when it doesn't say.
+fn send_rate_limited(
+    request: impl Fn() -> reqwest::blocking::RequestBuilder,
+) -> reqwest::Result<reqwest::blocking::Response> {
+    let mut attempt = 1;
+    loop {
+        let res = request().send()?;
+        if res.status() != reqwest::StatusCode::TOO_MANY_REQUESTS
+            || attempt == RATE_LIMITED_ATTEMPTS
+        {
+            return Ok(res);
+        }
+        let delay = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(1 << attempt));
+        debug!(
+            "Rate limited by the registry, sending the request again in {}s",
+            delay.as_secs()
+        );
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+/// The metadata of a published version of a crate which is derived from its
+/// manifest.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct VersionMetadata {
+    pub features: BTreeMap<String, Vec<String>>,
+    pub links: Option<String>,
+    pub dependencies: Vec<VersionDependency>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionDependency {
+    pub crate_id: String,
+    pub req: String,
+    pub kind: String,
+    pub optional: bool,
+    pub target: Option<String>,
+}
+
+impl fmt::Display for VersionDependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({}", self.crate_id, self.req, self.kind)?;
+        if self.optional {
+            write!(f, ", optional")?;
+        }
+        if let Some(target) = &self.target {
+            write!(f, ", for {target}")?;
+        }
+        write!(f, ")")
+    }
 }
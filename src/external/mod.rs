@@ -16,5 +16,7 @@
 
 //! Expose external services here.
 
+pub mod cache;
 pub mod cargo;
 pub mod crates_io;
+pub mod semver_checks;
@@ -0,0 +1,175 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare the public API of a crate with a version of it which was
+//! published, using cargo-semver-checks, to tell which kind of release its
+//! changes need.
+
+use crate::context::Context;
+use crate::package;
+use crate::version::Bump;
+use anyhow::Context as _;
+use semver::Version;
+use std::path::Path;
+
+/// How the public API of a crate changed since a published version.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApiChanges {
+    /// The published version the API was compared with.
+    pub baseline: Version,
+    /// The kind of release the changes need, by the rules of semver; 0.x
+    /// versions are bumped one level less, see [`crate::version::bump_for_change`].
+    pub required: Bump,
+    /// The checks which failed, i.e. why the changes need more than a patch
+    /// release.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<ApiFailure>,
+}
+
+/// A check of cargo-semver-checks which failed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApiFailure {
+    /// The name of the check, e.g. `function_missing`.
+    pub check: String,
+    /// What the check found, e.g. "pub fn removed or renamed".
+    pub summary: String,
+    /// The items of the API which failed the check.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<String>,
+}
+
+/// Compare the public API of the crate whose manifest is at `manifest` with
+/// its `baseline` version, as published to the registry.
+pub fn check(
+    ctx: &Context,
+    krate: &str,
+    manifest: &Path,
+    baseline: &Version,
+) -> anyhow::Result<ApiChanges> {
+    let published = ctx
+        .registry
+        .try_download_crate(krate, baseline)?
+        .with_context(|| format!("Cannot download {krate} {baseline} to compare its API with"))?;
+    let tmp_dir = tempfile::tempdir()?;
+    let baseline_root = package::unpack(&published, tmp_dir.path())?;
+
+    // Every check is run, whatever the version in the manifest says, so that
+    // the report says which release the changes need.
+    let output = ctx
+        .cargo
+        .command()
+        .arg("semver-checks")
+        .arg("check-release")
+        .arg("--manifest-path")
+        .arg(manifest)
+        .arg("--baseline-root")
+        .arg(&baseline_root)
+        .arg("--release-type")
+        .arg("patch")
+        .env("CARGO_TERM_COLOR", "never")
+        .output()
+        .context("Cannot run cargo semver-checks")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("no such command") {
+        anyhow::bail!(
+            "cargo-semver-checks isn't installed, install it with `cargo install cargo-semver-checks`"
+        );
+    }
+    match parse_report(&format!("{stdout}\n{stderr}"), baseline) {
+        Some(changes) => Ok(changes),
+        None if output.status.success() => Ok(ApiChanges {
+            baseline: baseline.clone(),
+            required: Bump::Patch,
+            failures: vec![],
+        }),
+        None => anyhow::bail!(
+            "Failed to compare the API of {krate} with {baseline}: {}",
+            stderr.trim()
+        ),
+    }
+}
+
+/// Read the failed checks and the summary out of the output of
+/// cargo-semver-checks, e.g.
+///
+/// ```text
+/// --- failure function_missing: pub fn removed or renamed ---
+///
+/// Description:
+/// A publicly-visible function cannot be imported by its prior path. [...]
+///
+/// Failed in:
+///   function foo::bar, previously in file src/lib.rs:1
+///
+///      Summary semver requires new major version: 1 major and 0 minor checks failed
+/// ```
+fn parse_report(output: &str, baseline: &Version) -> Option<ApiChanges> {
+    let mut failures: Vec<ApiFailure> = vec![];
+    let mut required = None;
+    let mut in_items = false;
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(failure) = trimmed
+            .strip_prefix("--- failure ")
+            .and_then(|failure| failure.strip_suffix(" ---"))
+        {
+            let (check, summary) = failure.split_once(": ").unwrap_or((failure, ""));
+            failures.push(ApiFailure {
+                check: check.to_owned(),
+                summary: summary.to_owned(),
+                items: vec![],
+            });
+            in_items = false;
+        } else if trimmed == "Failed in:" {
+            in_items = true;
+        } else if in_items && !trimmed.is_empty() {
+            if let Some(failure) = failures.last_mut() {
+                failure.items.push(trimmed.to_owned());
+            }
+        } else if in_items {
+            in_items = false;
+        } else if let Some(summary) = trimmed.strip_prefix("Summary ") {
+            required = Some(required_bump(summary));
+        }
+    }
+    required.map(|required| ApiChanges {
+        baseline: baseline.clone(),
+        required,
+        failures,
+    })
+}
+
+/// The release the summary of cargo-semver-checks asks for, going by the
+/// levels of the checks which failed, e.g. "semver requires new major
+/// version: 1 major and 0 minor checks failed".
+fn required_bump(summary: &str) -> Bump {
+    let words = summary.split_whitespace().collect::<Vec<_>>();
+    let failed = |level: &str| {
+        words
+            .windows(2)
+            .any(|pair| pair[1] == level && pair[0].parse::<usize>().is_ok_and(|count| count > 0))
+    };
+    if failed("major") || summary.contains("new major version") {
+        Bump::Major
+    } else if failed("minor") || summary.contains("new minor version") {
+        Bump::Minor
+    } else {
+        Bump::Patch
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Freeze windows: periods during which crates can't be published, e.g.
+//! while they are audited, unless the freeze is explicitly overridden.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// A calendar day, written as `YYYY-MM-DD`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    /// The current day in UTC.
+    pub fn today() -> Date {
+        let days = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() / 86400)
+            .unwrap_or(0) as i64;
+        Date::from_days_since_epoch(days)
+    }
+
+    /// Convert a number of days since 1970-01-01 to a date, following
+    /// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    fn from_days_since_epoch(days: i64) -> Date {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        Date { year, month, day }
+    }
+}
+
+impl FromStr for Date {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Date> {
+        let parse = || -> Option<Date> {
+            let mut parts = s.splitn(3, '-');
+            let year = parts.next()?.parse().ok()?;
+            let month = parts.next()?.parse().ok()?;
+            let day = parts.next()?.parse().ok()?;
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return None;
+            }
+            Some(Date { year, month, day })
+        };
+        parse().ok_or_else(|| anyhow::anyhow!("Expected a date in the form YYYY-MM-DD, got {s:?}"))
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Date> {
+        s.parse()
+    }
+}
+
+impl From<Date> for String {
+    fn from(date: Date) -> String {
+        date.to_string()
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Crates which can't be published between `from` and `until`, both
+/// inclusive. Without dates, the crates always require the freeze to be
+/// overridden, i.e. a manual approval.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Freeze {
+    pub crates: Vec<String>,
+    pub from: Option<Date>,
+    pub until: Option<Date>,
+    pub reason: String,
+}
+
+impl Freeze {
+    pub fn applies_to(&self, krate: &str, today: Date) -> bool {
+        self.crates.iter().any(|frozen| frozen == krate)
+            && self.from.map(|from| from <= today).unwrap_or(true)
+            && self.until.map(|until| today <= until).unwrap_or(true)
+    }
+}
+
+impl fmt::Display for Freeze {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.from, self.until) {
+            (Some(from), Some(until)) => write!(f, "is frozen from {from} until {until}")?,
+            (Some(from), None) => write!(f, "is frozen since {from}")?,
+            (None, Some(until)) => write!(f, "is frozen until {until}")?,
+            (None, None) => write!(f, "requires manual approval")?,
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// Fail if any of `krates` is frozen today, unless the freeze is overridden
+/// with a reason, which is logged.
+pub fn check_freezes<'a, I: IntoIterator<Item = &'a String>>(
+    freezes: &[Freeze],
+    krates: I,
+    override_reason: Option<&str>,
+) -> anyhow::Result<()> {
+    let today = Date::today();
+    let frozen = krates
+        .into_iter()
+        .flat_map(|krate| {
+            freezes
+                .iter()
+                .filter(move |freeze| freeze.applies_to(krate, today))
+                .map(move |freeze| format!("{krate} {freeze}"))
+        })
+        .collect::<Vec<_>>();
+    if frozen.is_empty() {
+        return Ok(());
+    }
+
+    match override_reason {
+        Some(reason) => {
+            for frozen in frozen {
+                info!("Overriding freeze, {frozen}. Reason for the override: {reason}");
+            }
+            Ok(())
+        }
+        None => anyhow::bail!(
+            "The following crates are frozen: {}. Pass --override-freeze with a reason to publish them anyway.",
+            frozen.join("; ")
+        ),
+    }
+}
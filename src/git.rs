@@ -1,100 +1,610 @@
-use std::path::Path;
-use std::process::Command;
+use crate::events;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 const CHECKPOINT_SAVE: &str = "[subpub] CHECKPOINT_SAVE";
 const CHECKPOINT_REVERT: &str = "[subpub] CHECKPOINT_REVERT";
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum GCKP {
     Save,
     RevertLater,
 }
 
-pub fn git_checkpoint<P: AsRef<Path>>(root: P, op: GCKP) -> anyhow::Result<()> {
-    let mut cmd = Command::new("git");
-    let git_status_output = cmd
-        .current_dir(&root)
-        .arg("status")
-        .arg("--porcelain=v1")
-        .output()?;
-    if !git_status_output.status.success() {
-        anyhow::bail!(
-            "Failed to get git status for {:?}",
-            root.as_ref().as_os_str()
-        );
+/// How git is run: whether the commits and tags subpub creates are signed,
+/// and which workspace git isn't allowed to run in.
+#[derive(Debug, Clone, Default)]
+pub struct Git {
+    sign: bool,
+    forbidden_root: Option<PathBuf>,
+}
+
+impl Git {
+    /// Make every git command which would run in `root` fail instead, so that
+    /// read-only subcommands can promise not to create checkpoints or touch the
+    /// index, e.g. in pre-commit hooks or on read-only checkouts.
+    pub fn forbid_git_in<P: AsRef<Path>>(&mut self, root: P) -> anyhow::Result<()> {
+        self.forbidden_root = Some(root.as_ref().canonicalize()?);
+        Ok(())
+    }
+
+    /// Sign the commits and tags created from now on, with GPG or SSH depending on
+    /// `gpg.format`, using the key of `user.signingkey` if it's set.
+    pub fn sign_commits_and_tags(&mut self, sign: bool) {
+        self.sign = sign;
     }
 
-    let git_status_output = String::from_utf8_lossy(&git_status_output.stdout[..]);
-    let git_status_output = git_status_output.trim();
-    if !git_status_output.is_empty() {
+    fn ensure_git_allowed(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(forbidden) = &self.forbidden_root {
+            let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            if path.starts_with(forbidden) {
+                anyhow::bail!(
+                    "Refusing to run git in {:?} since --no-git was given",
+                    path.as_os_str()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// A git command running in `dir`.
+    pub fn command(&self, dir: &Path) -> anyhow::Result<Command> {
+        self.ensure_git_allowed(dir)?;
         let mut cmd = Command::new("git");
+        cmd.current_dir(dir);
+        Ok(cmd)
+    }
+
+    pub fn checkpoint<P: AsRef<Path>>(&self, root: P, op: GCKP) -> anyhow::Result<()> {
+        let mut cmd = self.command(root.as_ref())?;
+        let git_status_output = cmd.arg("status").arg("--porcelain=v1").output()?;
+        if !git_status_output.status.success() {
+            anyhow::bail!(
+                "Failed to get git status for {:?}",
+                root.as_ref().as_os_str()
+            );
+        }
+
+        let git_status_output = String::from_utf8_lossy(&git_status_output.stdout[..]);
+        let git_status_output = git_status_output.trim();
+        if !git_status_output.is_empty() {
+            let mut cmd = self.command(root.as_ref())?;
+            if !cmd
+                .arg("add")
+                .arg(".")
+                .stdout(events::child_stdout())
+                .status()?
+                .success()
+            {
+                anyhow::bail!(
+                    "Failed to `git add` files for {:?}",
+                    root.as_ref().as_os_str()
+                );
+            }
+
+            let commit_msg = match op {
+                GCKP::Save => CHECKPOINT_SAVE,
+                GCKP::RevertLater => CHECKPOINT_REVERT,
+            };
+            let mut cmd = self.command(root.as_ref())?;
+            cmd.arg("commit");
+            if self.sign {
+                cmd.arg("-S");
+            }
+            if !cmd
+                .arg("--quiet")
+                .arg("-m")
+                .arg(commit_msg)
+                .stdout(events::child_stdout())
+                .status()?
+                .success()
+            {
+                anyhow::bail!(
+                    "Failed to `git commit` files for {:?}",
+                    root.as_ref().as_os_str()
+                );
+            }
+        };
+
+        Ok(())
+    }
+
+    /// The commit below the checkpoints saved on top of the history of the
+    /// repository at `root`, or `None` if the latest commit isn't a checkpoint.
+    pub fn checkpoints_base<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<Option<String>> {
+        const BATCH: usize = 256;
+        let mut skip = 0;
+        loop {
+            let output = self
+                .command(root.as_ref())?
+                .arg("log")
+                .arg("--format=%H %s")
+                .arg(format!("--max-count={BATCH}"))
+                .arg(format!("--skip={skip}"))
+                .output()?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "Failed to read the history of {:?}",
+                    root.as_ref().as_os_str()
+                );
+            }
+            let log = String::from_utf8_lossy(&output.stdout[..]);
+            let mut commits = 0;
+            for line in log.lines() {
+                commits += 1;
+                let (commit, subject) = line.split_once(' ').unwrap_or((line, ""));
+                if subject != CHECKPOINT_SAVE {
+                    return Ok((skip + commits > 1).then(|| commit.to_owned()));
+                }
+            }
+            if commits < BATCH {
+                // The whole history is made of checkpoints.
+                return Ok(None);
+            }
+            skip += commits;
+        }
+    }
+
+    /// Replace the commits since `base` by a single commit with `message`.
+    pub fn squash<P: AsRef<Path>>(&self, root: P, base: &str, message: &str) -> anyhow::Result<()> {
+        if !self
+            .command(root.as_ref())?
+            .arg("reset")
+            .arg("--quiet")
+            .arg("--soft")
+            .arg(base)
+            .status()?
+            .success()
+        {
+            anyhow::bail!("Failed to reset {:?} to {base}", root.as_ref().as_os_str());
+        }
+        let mut cmd = self.command(root.as_ref())?;
+        cmd.arg("commit");
+        if self.sign {
+            cmd.arg("-S");
+        }
         if !cmd
-            .current_dir(&root)
-            .arg("add")
-            .arg(".")
+            .arg("--quiet")
+            .arg("-m")
+            .arg(message)
+            .stdout(events::child_stdout())
             .status()?
             .success()
         {
+            anyhow::bail!("Failed to commit {:?}", root.as_ref().as_os_str());
+        }
+        Ok(())
+    }
+
+    pub fn checkpoint_revert<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<()> {
+        loop {
+            let mut cmd = self.command(root.as_ref())?;
+            let output = cmd.arg("log").arg("-1").arg("--pretty=%B").output()?;
+            if !output.status.success() {
+                anyhow::bail!("Failed to get commit message of last commit");
+            }
+
+            let last_commit_msg = String::from_utf8_lossy(&output.stdout[..]);
+            let last_commit_msg = last_commit_msg.trim();
+            if last_commit_msg == CHECKPOINT_REVERT {
+                let mut cmd = self.command(root.as_ref())?;
+                if !cmd
+                    .arg("reset")
+                    .arg("--quiet")
+                    .arg("--hard")
+                    .arg("HEAD~1")
+                    .stdout(events::child_stdout())
+                    .status()?
+                    .success()
+                {
+                    anyhow::bail!("Failed to revert checkpoint commit");
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve a path inside the git directory of the repository at `root`. The
+    /// git directory shared by all the worktrees of the repository is used, so
+    /// that what subpub keeps there is the same whichever worktree it runs in.
+    pub fn path<P: AsRef<Path>>(&self, root: P, path: &str) -> anyhow::Result<PathBuf> {
+        let mut cmd = self.command(root.as_ref())?;
+        let output = cmd.arg("rev-parse").arg("--git-common-dir").output()?;
+        if !output.status.success() {
             anyhow::bail!(
-                "Failed to `git add` files for {:?}",
+                "Failed to resolve {path} in the git directory of {:?}",
                 root.as_ref().as_os_str()
             );
         }
+        let git_dir = String::from_utf8_lossy(&output.stdout[..]);
+        Ok(root.as_ref().join(git_dir.trim()).join(path))
+    }
 
-        let commit_msg = match op {
-            GCKP::Save => CHECKPOINT_SAVE,
-            GCKP::RevertLater => CHECKPOINT_REVERT,
-        };
-        let mut cmd = Command::new("git");
+    /// The commit the repository at `root` is at.
+    pub fn head<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<String> {
+        let mut cmd = self.command(root.as_ref())?;
+        let output = cmd.arg("rev-parse").arg("HEAD").output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to get the HEAD commit of {:?}",
+                root.as_ref().as_os_str()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout[..])
+            .trim()
+            .to_owned())
+    }
+
+    /// Throw away every change made to the repository at `root` since `commit`.
+    pub fn reset_hard<P: AsRef<Path>>(&self, root: P, commit: &str) -> anyhow::Result<()> {
+        self.reset(root, "--hard", commit)
+    }
+
+    /// Move the branch of the repository at `root` back to `commit`, leaving
+    /// what was committed since as uncommitted changes.
+    pub fn reset_mixed<P: AsRef<Path>>(&self, root: P, commit: &str) -> anyhow::Result<()> {
+        self.reset(root, "--mixed", commit)
+    }
+
+    fn reset<P: AsRef<Path>>(&self, root: P, mode: &str, commit: &str) -> anyhow::Result<()> {
+        let mut cmd = self.command(root.as_ref())?;
         if !cmd
-            .current_dir(&root)
-            .arg("commit")
+            .arg("reset")
             .arg("--quiet")
+            .arg(mode)
+            .arg(commit)
+            .stdout(events::child_stdout())
+            .status()?
+            .success()
+        {
+            anyhow::bail!(
+                "Failed to reset {:?} to {commit}",
+                root.as_ref().as_os_str()
+            );
+        }
+        Ok(())
+    }
+
+    /// A setting of the git configuration of the repository at `root`, if it's
+    /// set.
+    pub fn config<P: AsRef<Path>>(&self, root: P, key: &str) -> anyhow::Result<Option<String>> {
+        let output = self
+            .command(root.as_ref())?
+            .arg("config")
+            .arg(key)
+            .output()?;
+        let value = String::from_utf8_lossy(&output.stdout[..])
+            .trim()
+            .to_owned();
+        Ok((output.status.success() && !value.is_empty()).then_some(value))
+    }
+
+    /// Who commits to the repository at `root`, as `Name <email>`, according to
+    /// its git configuration.
+    pub fn user<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<Option<String>> {
+        let config = |key: &str| self.config(&root, key);
+        Ok(match (config("user.name")?, config("user.email")?) {
+            (Some(name), Some(email)) => Some(format!("{name} <{email}>")),
+            (name, email) => name.or(email),
+        })
+    }
+
+    /// Commit the files at `path`, relative to `root`, and only those.
+    pub fn commit<P: AsRef<Path>>(
+        &self,
+        root: P,
+        path: &Path,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let git = |args: &[&std::ffi::OsStr]| -> anyhow::Result<()> {
+            if !self
+                .command(root.as_ref())?
+                .args(args)
+                .stdout(events::child_stdout())
+                .status()?
+                .success()
+            {
+                anyhow::bail!(
+                    "Failed to commit {:?} in {:?}",
+                    path.as_os_str(),
+                    root.as_ref().as_os_str()
+                );
+            }
+            Ok(())
+        };
+        git(&["add".as_ref(), "--".as_ref(), path.as_os_str()])?;
+        let mut commit: Vec<&std::ffi::OsStr> = vec!["commit".as_ref()];
+        if self.sign {
+            commit.push("-S".as_ref());
+        }
+        commit.extend([
+            "--quiet".as_ref(),
+            "-m".as_ref(),
+            message.as_ref(),
+            "--".as_ref(),
+            path.as_os_str(),
+        ]);
+        git(&commit)
+    }
+
+    /// Create an annotated tag at `commit`, unless the tag already points there.
+    pub fn tag<P: AsRef<Path>>(
+        &self,
+        root: P,
+        tag: &str,
+        commit: &str,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let output = self
+            .command(root.as_ref())?
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(format!("refs/tags/{tag}^{{commit}}"))
+            .output()?;
+        if output.status.success() {
+            let tagged = String::from_utf8_lossy(&output.stdout[..])
+                .trim()
+                .to_owned();
+            if tagged == commit {
+                return Ok(());
+            }
+            anyhow::bail!("Tag {tag} already exists and points at {tagged} rather than {commit}");
+        }
+
+        if !self
+            .command(root.as_ref())?
+            .arg("tag")
+            .arg(if self.sign { "-s" } else { "-a" })
             .arg("-m")
-            .arg(commit_msg)
+            .arg(message)
+            .arg(tag)
+            .arg(commit)
+            .stdout(events::child_stdout())
+            .status()?
+            .success()
+        {
+            anyhow::bail!("Failed to create tag {tag}");
+        }
+        Ok(())
+    }
+
+    /// Push a tag of the repository at `root` to `remote`.
+    pub fn push_tag<P: AsRef<Path>>(&self, root: P, remote: &str, tag: &str) -> anyhow::Result<()> {
+        if !self
+            .command(root.as_ref())?
+            .arg("push")
+            .arg("--quiet")
+            .arg(remote)
+            .arg(format!("refs/tags/{tag}"))
+            .stdout(events::child_stdout())
             .status()?
             .success()
         {
+            anyhow::bail!("Failed to push tag {tag} to {remote}");
+        }
+        Ok(())
+    }
+
+    /// Delete a tag of the repository at `root`.
+    pub fn delete_tag<P: AsRef<Path>>(&self, root: P, tag: &str) -> anyhow::Result<()> {
+        if !self
+            .command(root.as_ref())?
+            .arg("tag")
+            .arg("--delete")
+            .arg(tag)
+            .stdout(events::child_stdout())
+            .status()?
+            .success()
+        {
+            anyhow::bail!("Failed to delete tag {tag}");
+        }
+        Ok(())
+    }
+
+    /// Delete a tag from `remote`, as pushed from the repository at `root`.
+    pub fn delete_remote_tag<P: AsRef<Path>>(
+        &self,
+        root: P,
+        remote: &str,
+        tag: &str,
+    ) -> anyhow::Result<()> {
+        if !self
+            .command(root.as_ref())?
+            .arg("push")
+            .arg("--quiet")
+            .arg(remote)
+            .arg(format!(":refs/tags/{tag}"))
+            .stdout(events::child_stdout())
+            .status()?
+            .success()
+        {
+            anyhow::bail!("Failed to delete tag {tag} from {remote}");
+        }
+        Ok(())
+    }
+
+    /// Whether `ancestor` is `commit` or one of the commits it comes from.
+    pub fn is_ancestor<P: AsRef<Path>>(
+        &self,
+        root: P,
+        ancestor: &str,
+        commit: &str,
+    ) -> anyhow::Result<bool> {
+        let status = self
+            .command(root.as_ref())?
+            .arg("merge-base")
+            .arg("--is-ancestor")
+            .arg(ancestor)
+            .arg(commit)
+            .status()?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => anyhow::bail!("Failed to check whether {commit} comes from {ancestor}"),
+        }
+    }
+
+    /// Whether the repository at `root` has `commit`, e.g. it isn't left out by a
+    /// shallow clone.
+    pub fn has_commit<P: AsRef<Path>>(&self, root: P, commit: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .command(root.as_ref())?
+            .arg("cat-file")
+            .arg("-e")
+            .arg(format!("{commit}^{{commit}}"))
+            .stderr(Stdio::null())
+            .status()?
+            .success())
+    }
+
+    /// The commits of HEAD which touched `dir` and which `since` doesn't have,
+    /// or all of them without `since`, newest first, as their abbreviated id and
+    /// message.
+    pub fn log<P: AsRef<Path>>(
+        &self,
+        root: P,
+        since: Option<&str>,
+        dir: &Path,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let mut cmd = self.command(root.as_ref())?;
+        cmd.arg("log").arg("--format=%h%x1f%B%x1e");
+        match since {
+            Some(since) => cmd.arg(format!("{since}..HEAD")),
+            None => cmd.arg("HEAD"),
+        };
+        let output = cmd.arg("--").arg(dir).output()?;
+        if !output.status.success() {
+            anyhow::bail!("Failed to list the commits of {:?}", dir.as_os_str());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout[..])
+            .split('\x1e')
+            .filter_map(|commit| {
+                let (id, message) = commit.trim_start().split_once('\x1f')?;
+                Some((id.to_owned(), message.trim().to_owned()))
+            })
+            .collect())
+    }
+
+    /// Whether the repository at `root` has uncommitted changes, including
+    /// untracked files.
+    pub fn has_changes<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<bool> {
+        let output = self
+            .command(root.as_ref())?
+            .arg("status")
+            .arg("--porcelain=v1")
+            .output()?;
+        if !output.status.success() {
             anyhow::bail!(
-                "Failed to `git commit` files for {:?}",
+                "Failed to get git status for {:?}",
                 root.as_ref().as_os_str()
             );
         }
-    };
+        Ok(!output.stdout.trim_ascii().is_empty())
+    }
 
-    Ok(())
-}
+    /// The names of the tags of the repository at `root`.
+    pub fn tags<P: AsRef<Path>>(&self, root: P) -> anyhow::Result<Vec<String>> {
+        let output = self
+            .command(root.as_ref())?
+            .arg("tag")
+            .arg("--list")
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!("Failed to list the tags of {:?}", root.as_ref().as_os_str());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout[..])
+            .lines()
+            .map(String::from)
+            .collect())
+    }
 
-pub fn git_checkpoint_revert<P: AsRef<Path>>(root: P) -> anyhow::Result<()> {
-    loop {
+    /// The id git gives to the contents of the file at `path`.
+    pub fn hash_object<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<String> {
+        self.ensure_git_allowed(path.as_ref())?;
         let mut cmd = Command::new("git");
+        let output = cmd.arg("hash-object").arg(path.as_ref()).output()?;
+        if !output.status.success() {
+            anyhow::bail!("Failed to hash {:?}", path.as_ref().as_os_str());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout[..])
+            .trim()
+            .to_owned())
+    }
+
+    /// The id git would give to a file with `contents`, which stays the same
+    /// across versions of git and subpub.
+    pub fn hash_contents<P: AsRef<Path>>(&self, root: P, contents: &str) -> anyhow::Result<String> {
+        let mut child = self
+            .command(root.as_ref())?
+            .arg("hash-object")
+            .arg("--stdin")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(contents.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!("Failed to hash contents with git");
+        }
+        Ok(String::from_utf8_lossy(&output.stdout[..])
+            .trim()
+            .to_owned())
+    }
+
+    /// The files of `dir` in the HEAD commit of the repository at `root`, as
+    /// listed by `git ls-tree`.
+    pub fn ls_tree<P: AsRef<Path>>(&self, root: P, dir: &Path) -> anyhow::Result<String> {
+        let mut cmd = self.command(root.as_ref())?;
         let output = cmd
-            .current_dir(&root)
-            .arg("log")
-            .arg("-1")
-            .arg("--pretty=%B")
+            .arg("ls-tree")
+            .arg("-r")
+            .arg("HEAD")
+            .arg("--")
+            .arg(dir)
             .output()?;
         if !output.status.success() {
-            anyhow::bail!("Failed to get commit message of last commit");
+            anyhow::bail!("Failed to list the files of {:?}", dir.as_os_str());
         }
+        Ok(String::from_utf8_lossy(&output.stdout[..]).into_owned())
+    }
+}
 
-        let last_commit_msg = String::from_utf8_lossy(&output.stdout[..]);
-        let last_commit_msg = last_commit_msg.trim();
-        if last_commit_msg == CHECKPOINT_REVERT {
-            let mut cmd = Command::new("git");
-            if !cmd
-                .current_dir(&root)
-                .arg("reset")
-                .arg("--quiet")
-                .arg("--hard")
-                .arg("HEAD~1")
-                .status()?
-                .success()
-            {
-                anyhow::bail!("Failed to revert checkpoint commit");
-            }
-        } else {
-            break;
-        }
+/// Clone the latest commit of a repository, or of one of its branches, into
+/// `dest`.
+pub fn git_clone<P: AsRef<Path>>(url: &str, branch: Option<&str>, dest: P) -> anyhow::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.arg("clone").arg("--quiet").arg("--depth").arg("1");
+    if let Some(branch) = branch {
+        cmd.arg("--branch").arg(branch);
+    }
+    if !cmd
+        .arg(url)
+        .arg(dest.as_ref())
+        .stdout(events::child_stdout())
+        .status()?
+        .success()
+    {
+        anyhow::bail!("Failed to clone {url}");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_are_hashed_like_git_blobs() {
+        let dir = std::env::temp_dir();
+        assert_eq!(
+            Git::default().hash_contents(&dir, "hello\n").unwrap(),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Render the dependency graph of the workspace, e.g. to find out why the
+//! publish order looks the way it does.
+
+use crate::ordering::{DependencyGraph, Edge};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+/// Crates which are drawn differently from the rest.
+#[derive(Debug, Default)]
+pub struct Highlights {
+    pub excluded: HashSet<String>,
+    pub needs_publishing: HashSet<String>,
+}
+
+/// Render the graph, with an edge from every crate to each of its
+/// dependencies. Edges which were removed to break cycles are drawn dashed.
+/// Crates and edges are sorted by name so that the output is deterministic.
+pub fn render(
+    graph: &DependencyGraph,
+    broken_edges: &[Edge],
+    highlights: &Highlights,
+    format: GraphFormat,
+) -> String {
+    let mut krates = graph.keys().collect::<Vec<_>>();
+    krates.sort();
+    let mut edges = graph
+        .iter()
+        .flat_map(|(krate, deps)| deps.iter().map(move |dep| (krate, dep, false)))
+        .chain(
+            broken_edges
+                .iter()
+                .map(|edge| (&edge.krate, &edge.dependency, true)),
+        )
+        .collect::<Vec<_>>();
+    edges.sort();
+
+    match format {
+        GraphFormat::Dot => render_dot(&krates, &edges, highlights),
+        GraphFormat::Mermaid => render_mermaid(&krates, &edges, highlights),
+    }
+}
+
+fn render_dot(
+    krates: &[&String],
+    edges: &[(&String, &String, bool)],
+    highlights: &Highlights,
+) -> String {
+    let mut out = String::from("digraph workspace {\n");
+    for krate in krates {
+        let style = if highlights.excluded.contains(*krate) {
+            " [style=dashed, color=gray, fontcolor=gray]"
+        } else if highlights.needs_publishing.contains(*krate) {
+            " [style=filled, fillcolor=gold]"
+        } else {
+            ""
+        };
+        let _ = writeln!(out, "    {krate:?}{style};");
+    }
+    for (krate, dep, broken) in edges {
+        let style = if *broken { " [style=dashed]" } else { "" };
+        let _ = writeln!(out, "    {krate:?} -> {dep:?}{style};");
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid(
+    krates: &[&String],
+    edges: &[(&String, &String, bool)],
+    highlights: &Highlights,
+) -> String {
+    // Crate names can contain characters Mermaid doesn't accept in node IDs,
+    // so nodes are numbered and labelled with the crate name.
+    let ids = krates
+        .iter()
+        .enumerate()
+        .map(|(index, krate)| (*krate, format!("n{index}")))
+        .collect::<HashMap<_, _>>();
+    let id = |krate: &String| ids[krate].clone();
+
+    let mut out = String::from("flowchart LR\n");
+    for krate in krates {
+        let class = if highlights.excluded.contains(*krate) {
+            ":::excluded"
+        } else if highlights.needs_publishing.contains(*krate) {
+            ":::publish"
+        } else {
+            ""
+        };
+        let _ = writeln!(out, "    {}[\"{krate}\"]{class}", id(krate));
+    }
+    for (krate, dep, broken) in edges {
+        let arrow = if *broken { "-.->" } else { "-->" };
+        let _ = writeln!(out, "    {} {arrow} {}", id(krate), id(dep));
+    }
+    out.push_str("    classDef excluded stroke-dasharray: 5 5, color: gray\n");
+    out.push_str("    classDef publish fill: gold\n");
+    out
+}
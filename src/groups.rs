@@ -0,0 +1,107 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Display groups, e.g. all crates under `frame/`, which structure the plans
+//! and summaries of large releases.
+
+use crate::crate_details::CrateDetails;
+use std::path::{Component, Path};
+
+/// The heading of the crates which aren't in any group.
+pub const UNGROUPED: &str = "Other crates";
+
+/// A named group of crates, given by patterns matching their directories
+/// relative to the workspace root.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Group {
+    pub name: String,
+    /// Patterns like `frame/*` or `primitives/sp-*`. A `*` matches any part
+    /// of a directory name, and crates nested in a matching directory are in
+    /// the group too.
+    pub paths: Vec<String>,
+}
+
+impl Group {
+    fn contains(&self, dir: &Path) -> bool {
+        let components = dir
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.paths.iter().any(|pattern| {
+            let segments = pattern
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .collect::<Vec<_>>();
+            segments.len() <= components.len()
+                && segments
+                    .iter()
+                    .zip(&components)
+                    .all(|(segment, component)| wildcard_match(segment, component))
+        })
+    }
+}
+
+/// The first of `groups` the crate is in.
+pub fn group_of<'a>(groups: &'a [Group], root: &Path, details: &CrateDetails) -> Option<&'a str> {
+    let dir = details.toml_path.parent()?;
+    let dir = dir.strip_prefix(root).unwrap_or(dir);
+    groups
+        .iter()
+        .find(|group| group.contains(dir))
+        .map(|group| group.name.as_str())
+}
+
+/// Split `items` by group, keeping their order within each group. Groups come
+/// in the order their first item appears, followed by the items which aren't
+/// in any group.
+pub fn group_by<'a, T>(
+    items: impl IntoIterator<Item = T>,
+    group: impl Fn(&T) -> Option<&'a str>,
+) -> Vec<(&'a str, Vec<T>)> {
+    let mut grouped: Vec<(&'a str, Vec<T>)> = vec![];
+    let mut ungrouped = vec![];
+    for item in items {
+        match group(&item) {
+            Some(name) => match grouped.iter_mut().find(|(other, _)| *other == name) {
+                Some((_, items)) => items.push(item),
+                None => grouped.push((name, vec![item])),
+            },
+            None => ungrouped.push(item),
+        }
+    }
+    if !ungrouped.is_empty() {
+        grouped.push((UNGROUPED, ungrouped));
+    }
+    grouped
+}
+
+/// Match `name` against a pattern where `*` stands for any sequence of
+/// characters.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => match name.strip_prefix(prefix) {
+            Some(name) => (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &name[i..])),
+            None => false,
+        },
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A record of every release subpub made from the workspace, appended to as
+//! crates are published. It's kept in the git directory like the run state,
+//! but unlike it, never cleared, so that it's an audit trail of the releases
+//! which doesn't depend on the registry.
+
+use crate::freeze::Date;
+use crate::git::Git;
+use anyhow::Context;
+use semver::Version;
+use std::io::Write;
+use std::path::Path;
+
+const HISTORY_FILE: &str = "subpub/history.jsonl";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Release {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub version: Version,
+    /// The registry the crate was published to.
+    pub registry: String,
+    pub date: Date,
+    /// The commit of the workspace the crate was published from.
+    pub commit: String,
+    /// Who published the crate, according to the git configuration.
+    pub operator: Option<String>,
+}
+
+/// Add a release to the history of the workspace at `root`.
+pub fn record<P: AsRef<Path>>(git: &Git, root: P, release: &Release) -> anyhow::Result<()> {
+    let path = git.path(root, HISTORY_FILE)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Cannot open the history at {:?}", path.as_os_str()))?;
+    writeln!(file, "{}", serde_json::to_string(release)?)
+        .with_context(|| format!("Cannot write the history at {:?}", path.as_os_str()))
+}
+
+/// The releases made from the workspace at `root`, oldest first, limited to
+/// those of `krate` if it's given.
+pub fn load<P: AsRef<Path>>(
+    git: &Git,
+    root: P,
+    krate: Option<&str>,
+) -> anyhow::Result<Vec<Release>> {
+    let path = git.path(root, HISTORY_FILE)?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Cannot read the history at {:?}", path.as_os_str()))?;
+    let mut releases = vec![];
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let release: Release = serde_json::from_str(line).with_context(|| {
+            format!(
+                "Cannot parse line {} of the history at {:?}",
+                line_number + 1,
+                path.as_os_str()
+            )
+        })?;
+        if krate.is_none_or(|krate| release.krate == krate) {
+            releases.push(release);
+        }
+    }
+    Ok(releases)
+}
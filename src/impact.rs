@@ -0,0 +1,121 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Work out what a version bump of a crate cascades into, without changing
+//! anything.
+
+use crate::crates::Crates;
+use crate::version::{bump, bump_for_breaking_change, Bump};
+use anyhow::Context;
+use semver::{Version, VersionReq};
+use std::collections::{BTreeMap, VecDeque};
+
+/// A dependent whose requirement on a dependency doesn't allow its new version.
+pub struct RequirementUpdate {
+    pub krate: String,
+    pub dependency: String,
+    pub version_req: Option<VersionReq>,
+    pub new_version: Version,
+}
+
+/// A crate which needs a new release.
+pub struct Release {
+    pub krate: String,
+    pub version: Version,
+    pub new_version: Version,
+    /// The dependency whose new version made this release necessary, if it's
+    /// not the bumped crate itself.
+    pub cause: Option<String>,
+}
+
+pub struct Impact {
+    pub requirement_updates: Vec<RequirementUpdate>,
+    pub releases: Vec<Release>,
+}
+
+/// Follow a bump of `krate` through its dependents. A dependent whose
+/// requirement doesn't match the new version needs its requirement updated,
+/// and unless only its dev-dependencies are affected, it also needs a new
+/// release with a breaking change, since it might expose the dependency in
+/// its own API.
+pub fn impact(crates: &Crates, krate: &str, level: Bump) -> anyhow::Result<Impact> {
+    let details = crates
+        .details
+        .get(krate)
+        .with_context(|| format!("Crate not found: {krate}"))?;
+
+    let mut new_versions = BTreeMap::new();
+    let mut impact = Impact {
+        requirement_updates: vec![],
+        releases: vec![],
+    };
+    let new_version = bump(details.version.clone(), level);
+    new_versions.insert(krate.to_owned(), new_version.clone());
+    impact.releases.push(Release {
+        krate: krate.to_owned(),
+        version: details.version.clone(),
+        new_version,
+        cause: None,
+    });
+
+    let mut dependents = crates.details.values().collect::<Vec<_>>();
+    dependents.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut to_visit = VecDeque::from([krate.to_owned()]);
+    while let Some(dependency) = to_visit.pop_front() {
+        let new_version = new_versions[&dependency].clone();
+        for dependent in dependents
+            .iter()
+            .filter(|dependent| dependent.all_deps().any(|dep| *dep == dependency))
+        {
+            let version_req = dependent.dep_version_reqs.get(&dependency).cloned();
+            if version_req
+                .as_ref()
+                .map(|version_req| version_req.matches(&new_version))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            impact.requirement_updates.push(RequirementUpdate {
+                krate: dependent.name.clone(),
+                dependency: dependency.clone(),
+                version_req,
+                new_version: new_version.clone(),
+            });
+
+            let is_dev_only = !dependent
+                .deps_relevant_during_publish()
+                .any(|dep| *dep == dependency);
+            if is_dev_only
+                || !dependent.should_be_published
+                || new_versions.contains_key(&dependent.name)
+            {
+                continue;
+            }
+            let dependent_new_version = bump_for_breaking_change(dependent.version.clone());
+            new_versions.insert(dependent.name.clone(), dependent_new_version.clone());
+            impact.releases.push(Release {
+                krate: dependent.name.clone(),
+                version: dependent.version.clone(),
+                new_version: dependent_new_version,
+                cause: Some(dependency.clone()),
+            });
+            to_visit.push_back(dependent.name.clone());
+        }
+    }
+
+    Ok(impact)
+}
@@ -0,0 +1,232 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Import the settings of other release tools, cargo-release and release-plz,
+//! into `subpub.toml`.
+
+use crate::config::CONFIG_FILE;
+use crate::crates::Crates;
+use anyhow::Context;
+use std::path::Path;
+
+const CARGO_RELEASE_FILE: &str = "release.toml";
+const RELEASE_PLZ_FILES: [&str; 2] = ["release-plz.toml", ".release-plz.toml"];
+
+/// The settings which were found, mapped to subpub's settings where possible.
+#[derive(Debug, Default)]
+pub struct Import {
+    /// Crates which the other tools don't publish.
+    pub exclude: Vec<String>,
+    /// The registry the workspace is published to, if it isn't crates.io.
+    pub registry: Option<String>,
+    /// Settings which can't be imported, mostly because subpub has no
+    /// counterpart for them, e.g.
+    /// `"release.toml: tag-name"`, with a hint if there's a way around it.
+    pub unsupported: Vec<String>,
+}
+
+/// Read the cargo-release settings, from `release.toml` files and
+/// `[workspace.metadata.release]` or `[package.metadata.release]`, and the
+/// release-plz settings of the workspace.
+pub fn import<P: AsRef<Path>>(root: P, crates: &Crates) -> anyhow::Result<Import> {
+    let root = root.as_ref();
+    let mut import = Import::default();
+
+    // Workspace-wide settings of cargo-release.
+    if let Some(table) = read(&root.join(CARGO_RELEASE_FILE))? {
+        import_cargo_release(&mut import, CARGO_RELEASE_FILE, None, table.as_table());
+    }
+    if let Some(toml) = read(&root.join("Cargo.toml"))? {
+        if let Some(table) = toml
+            .get("workspace")
+            .and_then(|workspace| workspace.get("metadata"))
+            .and_then(|metadata| metadata.get("release"))
+            .and_then(|release| release.as_table_like())
+        {
+            import_cargo_release(&mut import, "[workspace.metadata.release]", None, table);
+        }
+    }
+
+    // Settings of cargo-release for each crate.
+    let mut krates = crates.details.values().collect::<Vec<_>>();
+    krates.sort_by(|a, b| a.name.cmp(&b.name));
+    for details in krates {
+        let crate_dir = details
+            .toml_path
+            .parent()
+            .with_context(|| format!("{:?} has no parent directory", details.toml_path))?;
+        if crate_dir != root {
+            if let Some(table) = read(&crate_dir.join(CARGO_RELEASE_FILE))? {
+                import_cargo_release(
+                    &mut import,
+                    &format!("{}/{CARGO_RELEASE_FILE}", details.name),
+                    Some(&details.name),
+                    table.as_table(),
+                );
+            }
+        }
+        if let Some(toml) = read(&details.toml_path)? {
+            if let Some(table) = toml
+                .get("package")
+                .and_then(|package| package.get("metadata"))
+                .and_then(|metadata| metadata.get("release"))
+                .and_then(|release| release.as_table_like())
+            {
+                import_cargo_release(
+                    &mut import,
+                    &format!("{} [package.metadata.release]", details.name),
+                    Some(&details.name),
+                    table,
+                );
+            }
+        }
+    }
+
+    for file in RELEASE_PLZ_FILES {
+        if let Some(toml) = read(&root.join(file))? {
+            import_release_plz(&mut import, file, &toml, crates);
+        }
+    }
+
+    Ok(import)
+}
+
+fn read(path: &Path) -> anyhow::Result<Option<toml_edit::Document>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read {:?}", path.as_os_str()))?;
+    contents
+        .parse()
+        .map(Some)
+        .with_context(|| format!("Cannot parse {:?}", path.as_os_str()))
+}
+
+fn import_cargo_release(
+    import: &mut Import,
+    source: &str,
+    krate: Option<&String>,
+    table: &dyn toml_edit::TableLike,
+) {
+    for (key, value) in table.iter() {
+        match (key, krate) {
+            ("publish" | "release", Some(krate)) if value.as_bool() == Some(false) => {
+                exclude(import, krate)
+            }
+            ("registry", None) => match value.as_str() {
+                Some(registry) => import.registry = Some(registry.to_owned()),
+                None => import.unsupported.push(format!("{source}: {key}")),
+            },
+            ("registry", Some(_)) => import.unsupported.push(format!(
+                "{source}: {key} (only a registry for the whole workspace is supported)"
+            )),
+            _ => import.unsupported.push(format!("{source}: {key}")),
+        }
+    }
+}
+
+fn import_release_plz(
+    import: &mut Import,
+    source: &str,
+    toml: &toml_edit::Document,
+    crates: &Crates,
+) {
+    for (key, value) in toml.iter() {
+        match key {
+            "workspace" => {
+                if let Some(table) = value.as_table_like() {
+                    for (key, _) in table.iter() {
+                        import
+                            .unsupported
+                            .push(format!("{source}: workspace.{key}"));
+                    }
+                }
+            }
+            "package" => {
+                let packages = value
+                    .as_array_of_tables()
+                    .map(|packages| packages.iter().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                for package in packages {
+                    let name = match package.get("name").and_then(|name| name.as_str()) {
+                        Some(name) => name.to_owned(),
+                        None => continue,
+                    };
+                    for (key, value) in package.iter() {
+                        match key {
+                            "name" => {}
+                            "publish" | "release" if value.as_bool() == Some(false) => {
+                                if crates.details.contains_key(&name) {
+                                    exclude(import, &name);
+                                } else {
+                                    import.unsupported.push(format!(
+                                        "{source}: package {name} (not found in the workspace)"
+                                    ));
+                                }
+                            }
+                            _ => import
+                                .unsupported
+                                .push(format!("{source}: package {name}: {key}")),
+                        }
+                    }
+                }
+            }
+            _ => import.unsupported.push(format!("{source}: {key}")),
+        }
+    }
+}
+
+fn exclude(import: &mut Import, krate: &str) {
+    if !import.exclude.iter().any(|excluded| excluded == krate) {
+        import.exclude.push(krate.to_owned());
+    }
+}
+
+/// Merge the imported settings into the `subpub.toml` of the workspace,
+/// keeping its existing settings, and return the result.
+pub fn merge_into_config<P: AsRef<Path>>(
+    root: P,
+    import: &Import,
+) -> anyhow::Result<toml_edit::Document> {
+    let mut config = read(&root.as_ref().join(CONFIG_FILE))?.unwrap_or_default();
+
+    if !import.exclude.is_empty() {
+        let mut exclude = config
+            .get("exclude")
+            .and_then(|exclude| exclude.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for krate in &import.exclude {
+            if !exclude
+                .iter()
+                .any(|excluded| excluded.as_str() == Some(krate))
+            {
+                exclude.push(krate.as_str());
+            }
+        }
+        config["exclude"] = toml_edit::value(exclude);
+    }
+
+    if let Some(registry) = &import.registry {
+        if !config.contains_key("publish") {
+            config["publish"] = toml_edit::table();
+        }
+        config["publish"]["registry"] = toml_edit::value(registry.as_str());
+    }
+
+    Ok(config)
+}
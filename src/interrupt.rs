@@ -0,0 +1,77 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Interrupting `publish` with Ctrl-C. Rather than dying halfway through
+//! changing the workspace, the run stops at the next point where it can stop
+//! cleanly: the crate being uploaded finishes, the changes made for packaging
+//! the next one are undone, and the run state records where to resume. A
+//! second Ctrl-C stops right away.
+
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    const MESSAGE: &[u8] =
+        b"Interrupted, stopping as soon as the run can be resumed; press Ctrl-C again to stop right away\n";
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+    unsafe {
+        libc::write(
+            libc::STDERR_FILENO,
+            MESSAGE.as_ptr() as *const libc::c_void,
+            MESSAGE.len(),
+        );
+    }
+}
+
+/// Handle SIGINT and SIGTERM by recording that the run was interrupted,
+/// which [`check`] then reports, instead of exiting.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Whether the run was interrupted.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fail if the run was interrupted.
+pub fn check() -> anyhow::Result<()> {
+    if interrupted() {
+        anyhow::bail!("Interrupted");
+    }
+    Ok(())
+}
+
+/// Keep Ctrl-C in the terminal from reaching a command, which then runs to
+/// completion even if the run is interrupted, e.g. the upload of a crate.
+pub fn shield(cmd: &mut Command) -> &mut Command {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    cmd
+}
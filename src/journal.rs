@@ -0,0 +1,229 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A write-ahead journal of the publish run: every step which changes the
+//! workspace or the registry is appended to it, and flushed to disk, before
+//! it's taken, and its outcome once it's done. When a run dies halfway, e.g.
+//! because the machine went down, the journal tells `--resume` which step was
+//! interrupted, and `yank --last-run` what was published, even if the run
+//! state wasn't saved in time.
+
+use crate::git::Git;
+use anyhow::Context;
+use semver::Version;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+const JOURNAL_FILE: &str = "subpub/journal.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "step", rename_all = "kebab-case")]
+pub enum Step {
+    /// A run started from `commit`, clearing the journal of the previous
+    /// one. `worktree_branch` is the branch the run created for publishing
+    /// from a worktree, if it did.
+    Started {
+        commit: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        worktree_branch: Option<String>,
+    },
+    /// The run was resumed.
+    Resumed,
+    /// The version of a crate is about to be bumped.
+    Bump {
+        #[serde(rename = "crate")]
+        krate: String,
+        from: Version,
+        to: Version,
+    },
+    /// The requirements of the workspace on a crate are about to be updated.
+    Requirements {
+        #[serde(rename = "crate")]
+        krate: String,
+        version: Version,
+    },
+    /// A crate is about to be published, from `commit`.
+    PublishAttempt {
+        #[serde(rename = "crate")]
+        krate: String,
+        version: Version,
+        commit: String,
+    },
+    /// A crate was published.
+    Published {
+        #[serde(rename = "crate")]
+        krate: String,
+        version: Version,
+        commit: String,
+    },
+    /// A tag is about to be created.
+    Tag { tag: String },
+    /// A tag is about to be pushed to `remote`.
+    PushTag { tag: String, remote: String },
+    /// Everything the run does for a crate is done.
+    Processed {
+        #[serde(rename = "crate")]
+        krate: String,
+    },
+    /// The run finished.
+    Finished,
+    /// What the run did was undone by `rollback`.
+    RolledBack,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    /// When the step was recorded, in seconds since the Unix epoch.
+    pub time: u64,
+    #[serde(flatten)]
+    pub step: Step,
+}
+
+/// Start the journal of a new run from `commit`, throwing the one of the
+/// previous run away.
+pub fn start<P: AsRef<Path>>(
+    git: &Git,
+    root: P,
+    commit: &str,
+    worktree_branch: Option<&str>,
+) -> anyhow::Result<()> {
+    let path = git.path(root, JOURNAL_FILE)?;
+    if path.exists() {
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Cannot clear the journal at {:?}", path.as_os_str()))?;
+    }
+    append(
+        &path,
+        Step::Started {
+            commit: commit.to_owned(),
+            worktree_branch: worktree_branch.map(String::from),
+        },
+    )
+}
+
+/// Append a step to the journal of the workspace at `root`, returning once
+/// it's on disk.
+pub fn record<P: AsRef<Path>>(git: &Git, root: P, step: Step) -> anyhow::Result<()> {
+    append(&git.path(root, JOURNAL_FILE)?, step)
+}
+
+fn append(path: &Path, step: Step) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let entry = Entry {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default(),
+        step,
+    };
+    // The last line might have been cut short by a run which died while
+    // writing it, in which case the new one starts on a line of its own.
+    let torn = std::fs::read(path)
+        .map(|contents| contents.last().is_some_and(|last| *last != b'\n'))
+        .unwrap_or(false);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open the journal at {:?}", path.as_os_str()))?;
+    let mut line = serde_json::to_string(&entry)?;
+    if torn {
+        line.insert(0, '\n');
+    }
+    writeln!(file, "{line}")
+        .and_then(|_| file.sync_data())
+        .with_context(|| format!("Cannot write the journal at {:?}", path.as_os_str()))
+}
+
+/// The steps of the last run in the journal of the workspace at `root`,
+/// oldest first. Lines which were only partly written, because the run died
+/// while writing them, are left out.
+pub fn load<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<Vec<Step>> {
+    let path = git.path(root, JOURNAL_FILE)?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Cannot read the journal at {:?}", path.as_os_str()))?;
+    let mut steps = vec![];
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => steps.push(entry.step),
+            Err(_) => warn!(
+                "Skipping line {} of the journal at {:?}, which was only partly written",
+                line_number + 1,
+                path.as_os_str()
+            ),
+        }
+    }
+    Ok(steps)
+}
+
+/// The crates the run attempted to publish without recording whether that
+/// went through, along with the version and the commit, in the order they
+/// were attempted.
+pub fn unconfirmed_publishes(steps: &[Step]) -> Vec<(String, Version, String)> {
+    steps
+        .iter()
+        .enumerate()
+        .filter_map(|(index, step)| match step {
+            Step::PublishAttempt {
+                krate,
+                version,
+                commit,
+            } if !steps[index..].iter().any(|later| {
+                matches!(later, Step::Published { krate: published, version: published_version, .. }
+                    if published == krate && published_version == version)
+            }) =>
+            {
+                Some((krate.clone(), version.clone(), commit.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The crates the run recorded as published, along with the version and the
+/// commit, in the order they were published.
+pub fn published(steps: &[Step]) -> Vec<(String, Version, String)> {
+    steps
+        .iter()
+        .filter_map(|step| match step {
+            Step::Published {
+                krate,
+                version,
+                commit,
+            } => Some((krate.clone(), version.clone(), commit.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the run died while changing the manifests of the workspace, which
+/// might then be left half written.
+pub fn stopped_while_writing(steps: &[Step]) -> bool {
+    matches!(
+        steps.last(),
+        Some(Step::Bump { .. }) | Some(Step::Requirements { .. })
+    )
+}
@@ -0,0 +1,78 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Publish the crates of a Cargo workspace in dependency order, bumping their
+//! versions where needed. The `subpub` binary is a thin command line
+//! interface over [`publish`]; other release tooling can use the planner and
+//! publisher from here.
+
+mod approvals;
+mod artifacts;
+mod changelog;
+mod checkpoint;
+mod checks;
+mod cli;
+mod config;
+mod consumers;
+mod context;
+pub mod crate_details;
+mod crates;
+mod doc_examples;
+mod events;
+mod external;
+mod freeze;
+mod git;
+mod graph;
+mod groups;
+mod history;
+mod impact;
+mod import;
+mod interrupt;
+mod journal;
+mod lockfile;
+mod multi_root;
+mod notarize;
+pub mod ordering;
+mod owners;
+mod package;
+mod plan;
+mod post_check;
+mod preflight;
+mod provenance;
+mod publish;
+mod rate_limit;
+mod rollback;
+mod run_lock;
+mod schema;
+mod scratch;
+mod secrets;
+mod self_check;
+mod set_version;
+mod state;
+mod status;
+mod sync_state;
+mod tags;
+mod template;
+mod toml;
+mod train;
+mod version;
+mod worktree;
+mod yank;
+
+pub use cli::run_cli;
+pub use crates::Crates;
+pub use plan::{Plan, PlannedRelease};
+pub use publish::{plan, publish, PublishOpts};
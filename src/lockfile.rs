@@ -0,0 +1,100 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Refreshing the lock file of the workspace once crates are published.
+
+use crate::context::Context;
+use crate::events;
+use crate::toml::toml_read;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+/// Update `krates` in the lock file of the workspace at `root`, or create the
+/// lock file if there isn't one. Crates which aren't in the lock file, e.g.
+/// because they were renamed since it was written, are left to Cargo to add
+/// the next time it resolves the workspace, since `cargo update -p` fails for
+/// them. Crates which are locked at several versions, e.g. a workspace crate
+/// which some other crate takes from the registry, are updated at every one of
+/// them, since Cargo can't tell which one `-p` means.
+pub fn update_lockfile<Crate: AsRef<str>>(
+    ctx: &Context,
+    root: &Path,
+    krates: &[Crate],
+) -> anyhow::Result<()> {
+    let mut cmd = ctx.cargo.command();
+    let mut cmd = cmd.current_dir(root).arg("update").arg("-v");
+
+    let lockfile = root.join("Cargo.lock");
+    if lockfile.exists() {
+        let locked = locked_versions(&lockfile)?;
+        let mut skipped = vec![];
+        let mut specs = vec![];
+        for krate in krates {
+            let krate = krate.as_ref();
+            match locked.get(krate).map(|versions| versions.as_slice()) {
+                None | Some([]) => skipped.push(krate),
+                Some([_]) => specs.push(krate.to_string()),
+                Some(versions) => {
+                    specs.extend(versions.iter().map(|version| format!("{krate}@{version}")))
+                }
+            }
+        }
+        if !skipped.is_empty() {
+            info!(
+                "Not updating the crates which aren't in the lock file: {}",
+                skipped.join(", ")
+            );
+        }
+        if specs.is_empty() {
+            info!("None of the crates are in the lock file");
+            return Ok(());
+        }
+        for spec in specs {
+            cmd = cmd.arg("-p").arg(spec);
+        }
+    } else {
+        info!("Creating the lock file at {lockfile:?}");
+    }
+
+    if !cmd.stdout(events::child_stdout()).status()?.success() {
+        anyhow::bail!("Command failed: {cmd:?}");
+    };
+    Ok(())
+}
+
+/// Return the versions every package of a lock file is locked at.
+fn locked_versions(lockfile: &Path) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    let lock = toml_read(lockfile)?;
+    let mut locked: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some(packages) = lock
+        .get("package")
+        .and_then(|packages| packages.as_array_of_tables())
+    {
+        for package in packages.iter() {
+            if let (Some(name), Some(version)) = (
+                package.get("name").and_then(|name| name.as_str()),
+                package.get("version").and_then(|version| version.as_str()),
+            ) {
+                locked
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(version.to_string());
+            }
+        }
+    }
+    Ok(locked)
+}
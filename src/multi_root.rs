@@ -0,0 +1,281 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Publish the crates of several workspaces, or roots, in one run, for release
+//! units which span a couple of small repositories rather than one monorepo.
+//! Each root is published with its own selection of crates, after the roots
+//! it depends on.
+
+use crate::checkpoint::with_save_checkpoint;
+use crate::crates::{write_dependency_version, Crates};
+use crate::ordering::{self, DependencyGraph};
+use crate::plan::{Plan, PlannedRelease};
+use crate::publish::{self, PublishOpts};
+use semver::VersionReq;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+/// A dependency of a crate of one root on a crate of another root.
+struct CrossRootDependency {
+    krate: String,
+    root: usize,
+    dependency: String,
+    dependency_root: usize,
+    req: VersionReq,
+    /// How the dependency is given: "path", "git" or "registry".
+    source: &'static str,
+    dev: bool,
+}
+
+/// The crates of each root, along with the dependencies between them.
+struct Roots {
+    crates: Vec<Crates>,
+    dependencies: Vec<CrossRootDependency>,
+}
+
+impl Roots {
+    fn load(opts: &[PublishOpts]) -> anyhow::Result<Roots> {
+        let mut crates = vec![];
+        let mut root_of: HashMap<String, usize> = HashMap::new();
+        for (index, root_opts) in opts.iter().enumerate() {
+            let ctx = publish::context(root_opts)?;
+            let root_crates = Crates::load_crates_in_workspace(root_opts.root.clone(), &ctx)?;
+            for name in root_crates.details.keys() {
+                if let Some(other) = root_of.insert(name.clone(), index) {
+                    anyhow::bail!(
+                        "Crate {name} is in both {:?} and {:?}",
+                        opts[other].root,
+                        opts[index].root
+                    );
+                }
+            }
+            crates.push(root_crates);
+        }
+
+        let mut dependencies = vec![];
+        for (index, root_opts) in opts.iter().enumerate() {
+            let metadata = crates[index].context().cargo.metadata(&root_opts.root)?;
+            for package in &metadata.packages {
+                if !metadata.workspace_members.contains(&package.id) {
+                    continue;
+                }
+                for dep in &package.dependencies {
+                    let dependency_root = match root_of.get(&dep.name) {
+                        Some(&dependency_root) if dependency_root != index => dependency_root,
+                        _ => continue,
+                    };
+                    let source = if dep.path.is_some() {
+                        "path"
+                    } else if dep
+                        .source
+                        .as_deref()
+                        .is_some_and(|source| source.starts_with("git+"))
+                    {
+                        "git"
+                    } else {
+                        "registry"
+                    };
+                    dependencies.push(CrossRootDependency {
+                        krate: package.name.clone(),
+                        root: index,
+                        dependency: dep.name.clone(),
+                        dependency_root,
+                        req: dep.req.clone(),
+                        source,
+                        dev: dep.kind.as_deref() == Some("dev"),
+                    });
+                }
+            }
+        }
+
+        Ok(Roots {
+            crates,
+            dependencies,
+        })
+    }
+
+    /// The crates which would be published from each root: the selected ones,
+    /// or every crate which can be published apart from the excluded ones.
+    fn selected(&self, opts: &[PublishOpts]) -> Vec<HashSet<String>> {
+        opts.iter()
+            .zip(&self.crates)
+            .map(|(opts, crates)| {
+                if opts.crates.is_empty() {
+                    crates
+                        .details
+                        .values()
+                        .filter(|details| details.should_be_published)
+                        .filter(|details| !opts.exclude.contains(&details.name))
+                        .map(|details| details.name.clone())
+                        .collect()
+                } else {
+                    opts.crates.iter().cloned().collect()
+                }
+            })
+            .collect()
+    }
+
+    /// Check that the selected crates can be published along with their
+    /// dependencies on the crates of other roots: the dependencies need a
+    /// version, which path dependencies have to match, and the crates they're
+    /// on have to be publishable.
+    fn validate(&self, opts: &[PublishOpts]) -> anyhow::Result<()> {
+        let selected = self.selected(opts);
+        let mut problems = vec![];
+        for dep in &self.dependencies {
+            if dep.dev || !selected[dep.root].contains(&dep.krate) {
+                continue;
+            }
+            let details = &self.crates[dep.dependency_root].details[&dep.dependency];
+            let dependency_root = &opts[dep.dependency_root].root;
+            if dep.req == VersionReq::STAR {
+                problems.push(format!(
+                    "{} depends on {} of {dependency_root:?} through a {} dependency without a version",
+                    dep.krate, dep.dependency, dep.source
+                ));
+            } else if !details.should_be_published {
+                problems.push(format!(
+                    "{} depends on {} of {dependency_root:?}, which cannot be published",
+                    dep.krate, dep.dependency
+                ));
+            } else if dep.source == "path" && !dep.req.matches(&details.version) {
+                problems.push(format!(
+                    "{} requires {} {}, but {dependency_root:?} has version {}",
+                    dep.krate, dep.dependency, dep.req, details.version
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "The crates can't be published across the roots:\n{}",
+                problems.join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// The roots in the order they have to be published in, so that each root
+    /// comes after the roots its crates depend on.
+    fn publish_order(&self, opts: &[PublishOpts]) -> anyhow::Result<Vec<usize>> {
+        let mut graph: DependencyGraph = (0..opts.len())
+            .map(|index| (index.to_string(), HashSet::new()))
+            .collect();
+        for dep in self.dependencies.iter().filter(|dep| !dep.dev) {
+            graph
+                .get_mut(&dep.root.to_string())
+                .expect("every root is in the graph")
+                .insert(dep.dependency_root.to_string());
+        }
+        let order = ordering::publish_levels(&graph)
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if order.len() < opts.len() {
+            let cycles = ordering::explain_unordered(&graph, &order)
+                .cycles
+                .iter()
+                .map(|cycle| {
+                    cycle
+                        .iter()
+                        .map(|index| {
+                            format!(
+                                "{:?}",
+                                opts[index
+                                    .parse::<usize>()
+                                    .expect("the graph only has root indexes")]
+                                .root
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                })
+                .collect::<Vec<_>>();
+            anyhow::bail!(
+                "The roots depend on each other, so they can't be published one after another:\n{}",
+                cycles.join("\n")
+            );
+        }
+        Ok(order
+            .iter()
+            .map(|index| index.parse().expect("the graph only has root indexes"))
+            .collect())
+    }
+}
+
+/// Publish the crates of each root given by `opts`, one root after another.
+/// Before a root is published, the requirements of its crates on crates
+/// published from the previous roots are updated to the published versions.
+pub fn publish_roots(opts: Vec<PublishOpts>) -> anyhow::Result<Vec<PlannedRelease>> {
+    // The requirements of the roots are rewritten in their checkouts, which a
+    // worktree per root would miss.
+    if opts.iter().any(|opts| opts.worktree) {
+        anyhow::bail!("--worktree can't publish several roots at once");
+    }
+    let roots = Roots::load(&opts)?;
+    roots.validate(&opts)?;
+    let order = roots.publish_order(&opts)?;
+
+    let mut released: Vec<PlannedRelease> = vec![];
+    for index in order {
+        let root_opts = opts[index].clone();
+        let updates = roots
+            .dependencies
+            .iter()
+            .filter(|dep| dep.root == index)
+            .filter_map(|dep| {
+                released
+                    .iter()
+                    .find(|release| release.krate == dep.dependency)
+                    .map(|release| (dep, release))
+            })
+            .collect::<Vec<_>>();
+        if !updates.is_empty() && !root_opts.dry_run {
+            let ctx = roots.crates[index].context();
+            with_save_checkpoint(&ctx.git, &root_opts.root, || -> anyhow::Result<()> {
+                for (dep, release) in &updates {
+                    let details = &roots.crates[index].details[&dep.krate];
+                    write_dependency_version(
+                        &ctx.requirement_styles,
+                        &details.toml_path,
+                        &dep.dependency,
+                        &release.next_version,
+                    )?;
+                }
+                Ok(())
+            })??;
+        }
+
+        info!("Publishing the crates of {:?}", root_opts.root);
+        released.extend(publish::publish(root_opts)?);
+    }
+    Ok(released)
+}
+
+/// Work out what [`publish_roots`] would do with the same options, as one plan
+/// with the releases of every root in publish order.
+pub fn plan_roots(opts: Vec<PublishOpts>) -> anyhow::Result<Plan> {
+    let roots = Roots::load(&opts)?;
+    roots.validate(&opts)?;
+    let order = roots.publish_order(&opts)?;
+
+    let mut plan = Plan::default();
+    for index in order {
+        let root_plan = publish::plan(opts[index].clone())?;
+        plan.crates.extend(root_plan.crates);
+        plan.releases.extend(root_plan.releases);
+    }
+    Ok(plan)
+}
@@ -0,0 +1,78 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Version what a publish run did next to the code: once the run finishes,
+//! the plan it followed and its report, i.e. its final run state, are
+//! committed to `releases/` of the workspace.
+
+use crate::context::Context;
+use crate::freeze::Date;
+use crate::plan::{Plan, PlanFormat, PlannedRelease};
+use crate::state::RunState;
+use anyhow::Context as _;
+use std::path::{Path, PathBuf};
+
+pub const RELEASES_DIR: &str = "releases";
+
+/// Commit the plan and the report of a finished run to a directory of
+/// [`RELEASES_DIR`] named after the date and the commit the crates were
+/// published from, returning that directory.
+pub fn notarize<P: AsRef<Path>>(
+    ctx: &Context,
+    root: P,
+    run_state: &RunState,
+    releases: &[PlannedRelease],
+) -> anyhow::Result<PathBuf> {
+    let root = root.as_ref();
+    let head = ctx.git.head(root)?;
+    let dir = Path::new(RELEASES_DIR).join(format!("{}-{}", Date::today(), &head[..12]));
+    std::fs::create_dir_all(root.join(&dir))
+        .with_context(|| format!("Cannot create {:?}", dir.as_os_str()))?;
+
+    let plan = Plan {
+        crates: releases
+            .iter()
+            .map(|release| release.krate.clone())
+            .collect(),
+        releases: releases.to_vec(),
+        ..Plan::default()
+    };
+    let write = |name: &str, contents: String| {
+        let path = root.join(&dir).join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Cannot write {:?}", path.as_os_str()))
+    };
+    write("plan.json", plan.render(PlanFormat::Json)?)?;
+    write(
+        "report.json",
+        format!("{}\n", serde_json::to_string_pretty(run_state)?),
+    )?;
+
+    ctx.git.commit(
+        root,
+        &dir,
+        &format!(
+            "Record the release of {}",
+            releases
+                .iter()
+                .map(|release| format!("{} {}", release.krate, release.next_version))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    )?;
+
+    Ok(dir)
+}
@@ -0,0 +1,408 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Work out in which order crates have to be published. This module only
+//! deals with crate names so that it can be benchmarked in isolation.
+
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// The workspace dependencies of each crate, in the form `crate_name => deps`.
+pub type DependencyGraph = HashMap<String, HashSet<String>>;
+
+/// A dependency of a crate on another crate, written as `crate -> dependency`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Edge {
+    pub krate: String,
+    pub dependency: String,
+}
+
+impl FromStr for Edge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Edge> {
+        let (krate, dependency) = s.split_once("->").with_context(|| {
+            format!("Expected an edge in the form \"crate -> dependency\", got {s:?}")
+        })?;
+        let (krate, dependency) = (krate.trim(), dependency.trim());
+        if krate.is_empty() || dependency.is_empty() {
+            anyhow::bail!("Expected an edge in the form \"crate -> dependency\", got {s:?}");
+        }
+        Ok(Edge {
+            krate: krate.into(),
+            dependency: dependency.into(),
+        })
+    }
+}
+
+impl TryFrom<String> for Edge {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Edge> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.krate, self.dependency)
+    }
+}
+
+/// Remove the given edges from the graph, so that the cycles they are part of
+/// no longer prevent ordering. Every edge has to exist in the graph.
+pub fn break_edges(graph: &mut DependencyGraph, edges: &[Edge]) -> anyhow::Result<()> {
+    for edge in edges {
+        let removed = graph
+            .get_mut(&edge.krate)
+            .map(|deps| deps.remove(&edge.dependency))
+            .unwrap_or(false);
+        if !removed {
+            anyhow::bail!(
+                "Cannot break the cycle at \"{edge}\" because {} does not depend on {}",
+                edge.krate,
+                edge.dependency
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Group the crates of the graph into levels, where every crate is one level
+/// above its highest level dependency. Crates of the same level don't depend
+/// on each other, so they can be published together. Crates of a level are
+/// sorted by name so that the result is deterministic.
+///
+/// Crates which cannot be ordered, e.g. because they are part of a cycle, are
+/// left out of the result.
+pub fn publish_levels(graph: &DependencyGraph) -> Vec<Vec<String>> {
+    // Kahn's algorithm, going through the crates a level at a time: a crate
+    // is on the level after the one its last remaining dependency is on.
+    let mut pending_deps: HashMap<&str, usize> = HashMap::with_capacity(graph.len());
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::with_capacity(graph.len());
+    for (krate, deps) in graph {
+        pending_deps.insert(krate.as_str(), deps.len());
+        for dep in deps {
+            dependents
+                .entry(dep.as_str())
+                .or_default()
+                .push(krate.as_str());
+        }
+    }
+
+    let mut publish_levels = vec![];
+    let mut level = pending_deps
+        .iter()
+        .filter(|(_, pending)| **pending == 0)
+        .map(|(krate, _)| *krate)
+        .collect::<Vec<_>>();
+    while !level.is_empty() {
+        level.sort_unstable();
+        let mut next_level = vec![];
+        for krate in &level {
+            for dependent in dependents.get(krate).into_iter().flatten() {
+                let pending = pending_deps
+                    .get_mut(dependent)
+                    .expect("every dependent is in the graph");
+                *pending -= 1;
+                if *pending == 0 {
+                    next_level.push(*dependent);
+                }
+            }
+        }
+        publish_levels.push(level.into_iter().map(str::to_owned).collect());
+        level = next_level;
+    }
+    publish_levels
+}
+
+/// Why the crates left out by [`publish_levels`] can't be ordered.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Unordered {
+    /// A cycle through each group of crates which depend on each other, e.g.
+    /// `[a, b, c, a]`.
+    pub cycles: Vec<Vec<String>>,
+    /// The crates which aren't part of a cycle, but depend on a crate which
+    /// can't be ordered, along with that dependency.
+    pub blocked: Vec<(String, String)>,
+}
+
+/// Explain why the crates of the graph which aren't in `ordered` can't be
+/// ordered: either they are part of a cycle, or they depend on such crates.
+pub fn explain_unordered(graph: &DependencyGraph, ordered: &[String]) -> Unordered {
+    let ordered = ordered.iter().map(String::as_str).collect::<HashSet<_>>();
+    let mut unordered = graph
+        .keys()
+        .map(String::as_str)
+        .filter(|krate| !ordered.contains(krate))
+        .collect::<Vec<_>>();
+    unordered.sort();
+    let unordered_deps = |krate: &str| {
+        let mut deps = graph
+            .get(krate)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .filter(|dep| !ordered.contains(dep))
+            .collect::<Vec<_>>();
+        deps.sort();
+        deps
+    };
+
+    // The crates reachable from each unordered crate through unordered crates.
+    let reachable = unordered
+        .iter()
+        .map(|&krate| {
+            let mut reachable = HashSet::new();
+            let mut to_visit = unordered_deps(krate);
+            while let Some(dep) = to_visit.pop() {
+                if reachable.insert(dep) {
+                    to_visit.extend(unordered_deps(dep));
+                }
+            }
+            (krate, reachable)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut result = Unordered::default();
+    let mut in_cycle = HashSet::new();
+    for &krate in &unordered {
+        if in_cycle.contains(krate) || !reachable[krate].contains(krate) {
+            continue;
+        }
+        // The crates which depend on each other, directly or not.
+        let group = unordered
+            .iter()
+            .copied()
+            .filter(|other| reachable[krate].contains(other) && reachable[other].contains(krate))
+            .collect::<HashSet<_>>();
+        in_cycle.extend(group.iter().copied());
+
+        // The shortest way back to the crate within the group.
+        let mut previous: HashMap<&str, &str> = HashMap::new();
+        let mut frontier = vec![krate];
+        'search: while !frontier.is_empty() {
+            let mut next = vec![];
+            for current in frontier {
+                for dep in unordered_deps(current) {
+                    if !group.contains(dep) || previous.contains_key(dep) {
+                        continue;
+                    }
+                    previous.insert(dep, current);
+                    if dep == krate {
+                        break 'search;
+                    }
+                    next.push(dep);
+                }
+            }
+            frontier = next;
+        }
+        let mut cycle = vec![krate.to_owned()];
+        let mut current = previous[krate];
+        while current != krate {
+            cycle.push(current.to_owned());
+            current = previous[current];
+        }
+        cycle.push(krate.to_owned());
+        cycle.reverse();
+        result.cycles.push(cycle);
+    }
+
+    for &krate in &unordered {
+        if !in_cycle.contains(krate) {
+            if let Some(dep) = unordered_deps(krate).first() {
+                result.blocked.push((krate.to_owned(), dep.to_string()));
+            }
+        }
+    }
+
+    result
+}
+
+/// Keep the crates of `publish_order` which are in `selected`, preserving the
+/// publish order.
+pub fn order_selection<'a>(publish_order: &'a [String], selected: &[String]) -> Vec<&'a String> {
+    let selected = selected.iter().collect::<HashSet<_>>();
+    publish_order
+        .iter()
+        .filter(|krate| selected.contains(krate))
+        .collect()
+}
+
+/// Return the crates which depend on any of `krates`, either directly or
+/// through other crates, up to `depth` dependency hops away from `krates`.
+/// `krates` themselves are not part of the result.
+pub fn dependents(
+    graph: &DependencyGraph,
+    krates: &[String],
+    depth: Option<usize>,
+) -> HashSet<String> {
+    let mut reverse_graph: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (krate, deps) in graph {
+        for dep in deps {
+            reverse_graph
+                .entry(dep.as_str())
+                .or_default()
+                .push(krate.as_str());
+        }
+    }
+
+    let mut visited = krates
+        .iter()
+        .map(|krate| krate.as_str())
+        .collect::<HashSet<_>>();
+    let mut dependents = HashSet::new();
+    let mut frontier = krates
+        .iter()
+        .map(|krate| krate.as_str())
+        .collect::<Vec<_>>();
+    let mut hops = 0;
+    while !frontier.is_empty() && depth.map(|depth| hops < depth).unwrap_or(true) {
+        frontier = frontier
+            .into_iter()
+            .flat_map(|krate| reverse_graph.get(krate).into_iter().flatten().copied())
+            .filter(|dependent| visited.insert(dependent))
+            .collect();
+        dependents.extend(frontier.iter().map(|dependent| dependent.to_string()));
+        hops += 1;
+    }
+    dependents
+}
+
+/// Return `krate` and all of its transitive dependencies, in publish order.
+pub fn dependency_closure(
+    graph: &DependencyGraph,
+    krate: &str,
+    publish_order: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let mut registered_crates: HashSet<&str> = HashSet::new();
+    let mut to_visit = vec![krate];
+    while let Some(krate) = to_visit.pop() {
+        if registered_crates.insert(krate) {
+            let deps = graph
+                .get(krate)
+                .with_context(|| format!("Crate not found: {krate}"))?;
+            to_visit.extend(deps.iter().map(|dep| dep.as_str()));
+        }
+    }
+
+    Ok(publish_order
+        .iter()
+        .filter(|krate| registered_crates.contains(krate.as_str()))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        edges
+            .iter()
+            .map(|(krate, deps)| {
+                (
+                    krate.to_string(),
+                    deps.iter().map(|dep| dep.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crates_are_one_level_above_their_highest_dependency() {
+        let graph = graph(&[("a", &[]), ("b", &["a"]), ("c", &["a", "b"]), ("d", &[])]);
+        assert_eq!(
+            publish_levels(&graph),
+            vec![vec!["a", "d"], vec!["b"], vec!["c"]]
+        );
+    }
+
+    #[test]
+    fn crates_of_a_level_are_sorted_by_name() {
+        let names = ["e", "b", "d", "a", "c", "f", "h", "g"];
+        let mut graph = graph(&[("top", &names)]);
+        for name in names {
+            graph.insert(name.to_string(), HashSet::new());
+        }
+        // The order of a HashMap changes between instances, so the levels
+        // only come out the same every time if ties are broken by name.
+        for _ in 0..16 {
+            let graph = graph.clone().into_iter().collect::<DependencyGraph>();
+            assert_eq!(
+                publish_levels(&graph),
+                vec![vec!["a", "b", "c", "d", "e", "f", "g", "h"], vec!["top"]]
+            );
+        }
+    }
+
+    #[test]
+    fn crates_in_cycles_are_left_out() {
+        let graph = graph(&[("a", &[]), ("b", &["a", "c"]), ("c", &["b"]), ("d", &["c"])]);
+        assert_eq!(publish_levels(&graph), vec![vec!["a"]]);
+    }
+
+    #[test]
+    fn unordered_crates_are_explained_by_their_cycle() {
+        let graph = graph(&[
+            ("a", &[]),
+            ("b", &["a", "d"]),
+            ("c", &["b"]),
+            ("d", &["c"]),
+            ("e", &["d"]),
+            ("f", &["f"]),
+        ]);
+        let ordered = publish_levels(&graph).concat();
+        assert_eq!(
+            explain_unordered(&graph, &ordered),
+            Unordered {
+                cycles: vec![
+                    vec!["b".into(), "d".into(), "c".into(), "b".into()],
+                    vec!["f".into(), "f".into()],
+                ],
+                blocked: vec![("e".into(), "d".into())],
+            }
+        );
+    }
+
+    #[test]
+    fn ordered_crates_need_no_explanation() {
+        let graph = graph(&[("a", &[]), ("b", &["a"])]);
+        let ordered = publish_levels(&graph).concat();
+        assert_eq!(explain_unordered(&graph, &ordered), Unordered::default());
+    }
+
+    #[test]
+    fn edges_are_parsed_with_or_without_spaces() {
+        let edge = Edge {
+            krate: "a".into(),
+            dependency: "b".into(),
+        };
+        assert_eq!("a -> b".parse::<Edge>().unwrap(), edge);
+        assert_eq!(" a->b ".parse::<Edge>().unwrap(), edge);
+        assert_eq!(edge.to_string(), "a -> b");
+    }
+
+    #[test]
+    fn edges_need_both_ends() {
+        assert!("a".parse::<Edge>().is_err());
+        assert!("a ->".parse::<Edge>().is_err());
+        assert!("-> b".parse::<Edge>().is_err());
+    }
+}
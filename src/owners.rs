@@ -0,0 +1,259 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keep the owners of the published crates of a workspace in sync, e.g. add a
+//! team to every crate after new crates were published, or hand all of them
+//! over to another team.
+
+use crate::context::Context;
+use crate::crates::Crates;
+use tracing::{info, warn};
+
+/// How the owners of the crates should change.
+#[derive(Debug, Clone, Default)]
+pub struct OwnerChanges {
+    /// The users or teams, e.g. `github:org:team`, every crate has to be
+    /// owned by.
+    pub add: Vec<String>,
+    /// The users or teams which must not own any crate.
+    pub remove: Vec<String>,
+}
+
+/// Apply `changes` to the published crates of the workspace which can be
+/// published, or to `krates` if they're given, leaving crates whose owners are
+/// already right alone. Returns the crates which were, or with `dry_run` would
+/// be, updated, along with what changed.
+pub fn sync_owners(
+    crates: &Crates,
+    krates: &[String],
+    changes: &OwnerChanges,
+    dry_run: bool,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let ctx = crates.context();
+    let token = registry_token(ctx)?;
+    let names = crate_names(crates, krates)?;
+
+    let mut updated = vec![];
+    for krate in &names {
+        if ctx.registry.crate_versions(krate)?.is_empty() {
+            info!("Skipping {krate}, which is not published yet");
+            continue;
+        }
+        let owners = ctx.registry.crate_owners(krate)?;
+        let to_add = changes
+            .add
+            .iter()
+            .filter(|login| !owners.contains(login))
+            .cloned()
+            .collect::<Vec<_>>();
+        let to_remove = changes
+            .remove
+            .iter()
+            .filter(|login| owners.contains(login))
+            .cloned()
+            .collect::<Vec<_>>();
+        if to_add.is_empty() && to_remove.is_empty() {
+            continue;
+        }
+
+        let mut change = vec![];
+        if !to_add.is_empty() {
+            change.push(format!("added {}", to_add.join(", ")));
+        }
+        if !to_remove.is_empty() {
+            change.push(format!("removed {}", to_remove.join(", ")));
+        }
+        let change = change.join(", ");
+        if dry_run {
+            info!("Would update the owners of {krate}: {change}");
+        } else {
+            if !to_add.is_empty() {
+                ctx.registry.change_owners(krate, &to_add, true, &token)?;
+            }
+            if !to_remove.is_empty() {
+                ctx.registry
+                    .change_owners(krate, &to_remove, false, &token)?;
+            }
+            info!("Updated the owners of {krate}: {change}");
+        }
+        updated.push((krate.clone(), change));
+    }
+    Ok(updated)
+}
+
+/// The token of the registry which is published to, which changing the owners
+/// of a crate requires.
+fn registry_token(ctx: &Context) -> anyhow::Result<String> {
+    match ctx.cargo.registry_token(&ctx.cargo.target_registry())? {
+        Some(token) => Ok(token),
+        None => anyhow::bail!(
+            "No token is known for {}, store one with subpub login or set SPUB_REGISTRY_TOKEN",
+            ctx.cargo.target_registry()
+        ),
+    }
+}
+
+/// The crates of the workspace which can be published, or `krates` if they're
+/// given, sorted by name.
+fn crate_names(crates: &Crates, krates: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut names = if krates.is_empty() {
+        crates
+            .details
+            .values()
+            .filter(|details| details.should_be_published)
+            .map(|details| details.name.clone())
+            .collect::<Vec<_>>()
+    } else {
+        for krate in krates {
+            if !crates.details.contains_key(krate) {
+                anyhow::bail!("Crate not found: {krate}");
+            }
+        }
+        krates.to_vec()
+    };
+    names.sort();
+    crates.context().registry.prefetch_crate_versions(&names);
+    Ok(names)
+}
+
+/// Whether a login is a team, e.g. `github:org:team`, rather than a user.
+fn is_team(login: &str) -> bool {
+    login.contains(':')
+}
+
+/// The outcome of [`transfer_owners`].
+#[derive(Debug, Default)]
+pub struct Transfer {
+    /// The crates whose owners were, or with `dry_run` would be, updated,
+    /// along with what changed.
+    pub updated: Vec<(String, String)>,
+    /// The crates still owned by the departing owner because the new one is
+    /// a user who has yet to accept the invitation; running the transfer
+    /// again after that completes it.
+    pub pending: Vec<String>,
+    /// The crates whose owners couldn't be updated, along with why.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Hand the published crates of the workspace which can be published, or
+/// `krates` if they're given, over to `to`: it's invited to every crate it
+/// doesn't own yet, and `from`, if given, is removed from the crates which
+/// `to` already owns. Since teams are added right away but users only own a
+/// crate once they accept the invitation, `from` is kept on the crates a
+/// user was invited to, so that no crate is ever left without the intended
+/// owners. A crate which fails doesn't stop the others from being updated.
+pub fn transfer_owners(
+    crates: &Crates,
+    krates: &[String],
+    to: &str,
+    from: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<Transfer> {
+    let ctx = crates.context();
+    let token = registry_token(ctx)?;
+    let names = crate_names(crates, krates)?;
+
+    let mut transfer = Transfer::default();
+    for krate in &names {
+        let result = (|| -> anyhow::Result<Option<(String, bool)>> {
+            if ctx.registry.crate_versions(krate)?.is_empty() {
+                info!("Skipping {krate}, which is not published yet");
+                return Ok(None);
+            }
+            let owners = ctx.registry.crate_owners(krate)?;
+            let add = !owners.iter().any(|login| login == to);
+            let remove = from.filter(|from| owners.iter().any(|login| login == from));
+            // Only once the new owner owns the crate can the departing one
+            // be removed from it.
+            let owned = !add || is_team(to);
+
+            let mut change = vec![];
+            if add {
+                if !dry_run {
+                    ctx.registry
+                        .change_owners(krate, &[to.to_string()], true, &token)?;
+                }
+                change.push(if is_team(to) {
+                    format!("added {to}")
+                } else {
+                    format!("invited {to}")
+                });
+            }
+            if let Some(from) = remove {
+                if owned {
+                    if !dry_run {
+                        ctx.registry
+                            .change_owners(krate, &[from.to_string()], false, &token)?;
+                    }
+                    change.push(format!("removed {from}"));
+                }
+            }
+            let pending = remove.is_some() && !owned;
+            if change.is_empty() {
+                return Ok(if pending {
+                    Some((String::new(), true))
+                } else {
+                    None
+                });
+            }
+            Ok(Some((change.join(", "), pending)))
+        })();
+
+        match result {
+            Ok(None) => {}
+            Ok(Some((change, pending))) => {
+                if !change.is_empty() {
+                    if dry_run {
+                        info!("Would update the owners of {krate}: {change}");
+                    } else {
+                        info!("Updated the owners of {krate}: {change}");
+                    }
+                    transfer.updated.push((krate.clone(), change));
+                }
+                if pending {
+                    transfer.pending.push(krate.clone());
+                }
+            }
+            Err(err) => {
+                warn!("Failed to update the owners of {krate}: {err:#}");
+                transfer.failed.push((krate.clone(), format!("{err:#}")));
+            }
+        }
+    }
+    Ok(transfer)
+}
+
+/// Add `owners` to a crate which was just published for the first time,
+/// leaving out those which already own it, e.g. the owner of the token.
+/// Returns the owners which were added.
+pub fn add_initial_owners(
+    ctx: &Context,
+    krate: &str,
+    owners: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let token = registry_token(ctx)?;
+    let current = ctx.registry.crate_owners(krate)?;
+    let to_add = owners
+        .iter()
+        .filter(|login| !current.contains(login))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !to_add.is_empty() {
+        ctx.registry.change_owners(krate, &to_add, true, &token)?;
+        info!("Added {} as owners of {krate}", to_add.join(", "));
+    }
+    Ok(to_add)
+}
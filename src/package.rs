@@ -0,0 +1,196 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare packages, i.e. `.crate` files, by the files they contain rather
+//! than byte for byte, since packages with the same files differ in the
+//! commit Cargo records in them.
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Files which Cargo generates when packaging, and which differ between
+/// packages of the same sources, e.g. because of the commit they record.
+const GENERATED_FILES: [&str; 1] = [".cargo_vcs_info.json"];
+
+/// The files which differ between the package at `local` and the `published`
+/// one, e.g. `src/lib.rs (changed)`, leaving out [`GENERATED_FILES`].
+pub fn differences(local: &Path, published: &[u8]) -> anyhow::Result<Vec<String>> {
+    let tmp_dir = tempfile::tempdir()?;
+    let published_path = tmp_dir.path().join("published.crate");
+    std::fs::write(&published_path, published)?;
+
+    let local_files = unpacked_files(local, &tmp_dir.path().join("local"))?;
+    let published_files = unpacked_files(&published_path, &tmp_dir.path().join("published"))?;
+
+    let mut differences = vec![];
+    for (path, contents) in &local_files {
+        match published_files.get(path) {
+            None => differences.push(format!("{} (added)", path.display())),
+            Some(published) if published != contents => {
+                differences.push(format!("{} (changed)", path.display()))
+            }
+            Some(_) => {}
+        }
+    }
+    for path in published_files.keys() {
+        if !local_files.contains_key(path) {
+            differences.push(format!("{} (removed)", path.display()));
+        }
+    }
+    Ok(differences)
+}
+
+/// The manifests of a package: the one Cargo normalized, the original one,
+/// and the lock file, which is taken from the workspace.
+const PACKAGED_MANIFESTS: [&str; 3] = ["Cargo.toml", "Cargo.toml.orig", "Cargo.lock"];
+
+/// The sections of a manifest which hold dependencies, both at the top level
+/// and under `[target.<cfg>]`.
+const DEPENDENCY_SECTIONS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Whether the crate at `crate_dir` only differs from the `published` package
+/// in its dependencies and its version, i.e. whether it's only published again
+/// because of dependency updates. The packaged files are compared with those
+/// of the crate directory, and the local manifest with the original one Cargo
+/// keeps in the package. Files which aren't found in the crate directory, e.g.
+/// a README from the parent directory, count as changed.
+pub fn only_dependencies_changed(crate_dir: &Path, published: &[u8]) -> anyhow::Result<bool> {
+    let tmp_dir = tempfile::tempdir()?;
+    let published_path = tmp_dir.path().join("published.crate");
+    std::fs::write(&published_path, published)?;
+    let published_files = unpacked_files(&published_path, &tmp_dir.path().join("published"))?;
+
+    let published_manifest = match published_files.get(Path::new("Cargo.toml.orig")) {
+        Some(manifest) => String::from_utf8_lossy(manifest).into_owned(),
+        None => return Ok(false),
+    };
+    for (path, contents) in &published_files {
+        if PACKAGED_MANIFESTS
+            .iter()
+            .any(|file| path == Path::new(file))
+        {
+            continue;
+        }
+        match std::fs::read(crate_dir.join(path)) {
+            Ok(local) if local == *contents => {}
+            _ => return Ok(false),
+        }
+    }
+
+    let local_manifest = std::fs::read_to_string(crate_dir.join("Cargo.toml"))
+        .with_context(|| format!("Cannot read the manifest in {:?}", crate_dir.as_os_str()))?;
+    Ok(without_dependencies(&local_manifest)? == without_dependencies(&published_manifest)?)
+}
+
+/// A manifest without its dependencies and its version.
+fn without_dependencies(manifest: &str) -> anyhow::Result<toml_edit::easy::Value> {
+    let mut manifest: toml_edit::easy::Value =
+        toml_edit::easy::from_str(manifest).context("Cannot parse the manifest")?;
+    let remove_dependencies = |table: &mut toml_edit::easy::Value| {
+        if let Some(table) = table.as_table_mut() {
+            for section in DEPENDENCY_SECTIONS {
+                table.remove(section);
+            }
+        }
+    };
+    remove_dependencies(&mut manifest);
+    if let Some(targets) = manifest
+        .get_mut("target")
+        .and_then(|targets| targets.as_table_mut())
+    {
+        for (_, target) in targets.iter_mut() {
+            remove_dependencies(target);
+        }
+    }
+    if let Some(package) = manifest
+        .get_mut("package")
+        .and_then(|package| package.as_table_mut())
+    {
+        package.remove("version");
+    }
+    Ok(manifest)
+}
+
+/// Unpack the `published` package into `dir`, returning the directory of the
+/// crate, which has the normalized manifest Cargo published.
+pub fn unpack(published: &[u8], dir: &Path) -> anyhow::Result<PathBuf> {
+    let package = dir.join("published.crate");
+    std::fs::write(&package, published)?;
+    let unpacked = dir.join("published");
+    unpack_into(&package, &unpacked)?;
+    // Packages contain a single directory, named after the crate and version.
+    match std::fs::read_dir(&unpacked)?.next() {
+        Some(entry) => Ok(entry?.path()),
+        None => anyhow::bail!("The package at {:?} is empty", package.as_os_str()),
+    }
+}
+
+fn unpack_into(package: &Path, dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    if !Command::new("tar")
+        .arg("-xzf")
+        .arg(package)
+        .arg("-C")
+        .arg(dir)
+        .status()
+        .context("Cannot run tar")?
+        .success()
+    {
+        anyhow::bail!("Failed to unpack {:?}", package.as_os_str());
+    }
+    Ok(())
+}
+
+/// Unpack a package into `dir` and read its files, keyed by their path
+/// relative to the package's top level directory.
+fn unpacked_files(package: &Path, dir: &Path) -> anyhow::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    unpack_into(package, dir)?;
+
+    let mut files = BTreeMap::new();
+    // Packages contain a single directory, named after the crate and version.
+    for entry in std::fs::read_dir(dir)? {
+        let top = entry?.path();
+        read_files(&top, &top, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn read_files(
+    top: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<PathBuf, Vec<u8>>,
+) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_files(top, &path, files)?;
+        } else {
+            let relative = path.strip_prefix(top)?.to_path_buf();
+            if GENERATED_FILES
+                .iter()
+                .any(|file| relative == Path::new(file))
+            {
+                continue;
+            }
+            let contents = std::fs::read(&path)
+                .with_context(|| format!("Cannot read {:?}", path.as_os_str()))?;
+            files.insert(relative, contents);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Publish plans: the crates a release consists of, written down so that the
+//! release can be reviewed and approved before it's executed.
+
+use crate::external::semver_checks::ApiChanges;
+use crate::groups;
+use crate::schema;
+use anyhow::Context;
+use semver::Version;
+use std::fmt::Write;
+use std::path::Path;
+
+/// How a plan is printed. Besides subpub's own JSON, plans can be printed
+/// like the dry runs of other workspace release tools, so that their results
+/// can be compared or fed to the same automation.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Json,
+    /// A table of the releases for people to review, split into the
+    /// configured groups.
+    Summary,
+    /// Like the summary of `cargo ws version`.
+    CargoWorkspaces,
+    /// Like the dry run of `cargo smart-release`.
+    CargoSmartRelease,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Plan {
+    /// The version of the schema of the plan, see `schema::SCHEMA_VERSION`.
+    #[serde(default = "schema::schema_version")]
+    pub schema_version: u32,
+    /// The crates to publish, in publish order.
+    pub crates: Vec<String>,
+    /// Why each crate needs to be published, and at which version.
+    #[serde(default)]
+    pub releases: Vec<PlannedRelease>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedRelease {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    /// The version of the crate in the workspace.
+    pub version: Version,
+    /// The version the crate is published at, which differs from `version`
+    /// if the crate needs a version bump.
+    pub next_version: Version,
+    pub reason: String,
+    /// Whether the crate only changed in its dependencies since the latest
+    /// published version, e.g. because a dependency was bumped, as opposed to
+    /// changes to its code.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dependency_only: bool,
+    /// How the public API of the crate changed since the latest published
+    /// version, with `--semver-checks`, which decided how its version is
+    /// bumped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_changes: Option<ApiChanges>,
+    /// The display group of the crate, if it's in one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl Default for Plan {
+    fn default() -> Plan {
+        Plan {
+            schema_version: schema::SCHEMA_VERSION,
+            crates: vec![],
+            releases: vec![],
+        }
+    }
+}
+
+impl Plan {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Plan> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the plan at {:?}", path.as_os_str()))?;
+        let plan: Plan = serde_json::from_str(&contents)
+            .with_context(|| format!("Cannot parse the plan at {:?}", path.as_os_str()))?;
+        schema::check_version("plan", plan.schema_version)?;
+        Ok(plan)
+    }
+
+    pub fn render(&self, format: PlanFormat) -> anyhow::Result<String> {
+        let mut out = String::new();
+        match format {
+            PlanFormat::Json => {
+                out = serde_json::to_string_pretty(self)?;
+                out.push('\n');
+            }
+            PlanFormat::Summary => {
+                let width = self
+                    .releases
+                    .iter()
+                    .map(|release| release.krate.len())
+                    .max()
+                    .unwrap_or(0);
+                for (group, releases) in self.grouped() {
+                    writeln!(out, "{group} ({}):", releases.len())?;
+                    for release in releases {
+                        writeln!(
+                            out,
+                            "  {:width$}  {} -> {}  {}{}",
+                            release.krate,
+                            release.version,
+                            release.next_version,
+                            release.reason,
+                            if release.dependency_only {
+                                " (dependency updates only)"
+                            } else {
+                                ""
+                            }
+                        )?;
+                        if let Some(changes) = &release.api_changes {
+                            writeln!(
+                                out,
+                                "  {:width$}  API changes since {} need a {} release",
+                                "", changes.baseline, changes.required
+                            )?;
+                            for failure in &changes.failures {
+                                writeln!(
+                                    out,
+                                    "  {:width$}    {}: {}",
+                                    "", failure.check, failure.summary
+                                )?;
+                                for item in &failure.items {
+                                    writeln!(out, "  {:width$}      {item}", "")?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            PlanFormat::CargoWorkspaces => {
+                out.push_str("Changes:\n");
+                for release in &self.releases {
+                    writeln!(
+                        out,
+                        " - {}: {} => {}",
+                        release.krate, release.version, release.next_version
+                    )?;
+                }
+            }
+            PlanFormat::CargoSmartRelease => {
+                for release in &self.releases {
+                    if release.version != release.next_version {
+                        writeln!(
+                            out,
+                            "WOULD auto-bump package '{}' from {} to {} for publishing",
+                            release.krate, release.version, release.next_version
+                        )?;
+                    }
+                }
+                for release in &self.releases {
+                    writeln!(
+                        out,
+                        "WOULD publish {} v{}",
+                        release.krate, release.next_version
+                    )?;
+                }
+            }
+        }
+        Ok(out)
+    }
+    /// The releases split by display group, see `groups::group_by`.
+    pub fn grouped(&self) -> Vec<(&str, Vec<&PlannedRelease>)> {
+        groups::group_by(&self.releases, |release| release.group.as_deref())
+    }
+}
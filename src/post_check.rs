@@ -0,0 +1,111 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The checks run on the workspace after publishing, to make sure it still
+//! builds against the crates as they were published.
+
+use crate::context::Context;
+use crate::crates::Crates;
+use crate::events;
+use crate::lockfile;
+use crate::ordering;
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::info;
+
+/// Which crates the post checks are run for.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostCheckScope {
+    /// Only the crates which were published.
+    Published,
+    /// The crates which were published and the crates depending on them.
+    Dependents,
+    /// Every crate of the workspace.
+    Workspace,
+}
+
+/// What to run after publishing.
+#[derive(Debug, Clone)]
+pub struct PostCheck<'a> {
+    pub scope: PostCheckScope,
+    /// Cargo subcommands, optionally followed by their flags, e.g.
+    /// `test --doc`.
+    pub commands: &'a [String],
+    pub features: &'a [String],
+    pub all_features: bool,
+}
+
+/// Update the lock file for the crates which were processed, then run the
+/// commands of `check` for every crate in its scope, in publish order.
+pub fn post_check(
+    ctx: &Context,
+    root: &Path,
+    crates: &Crates,
+    publish_order: &[String],
+    processed: &HashSet<String>,
+    published: &[String],
+    check: &PostCheck,
+) -> anyhow::Result<()> {
+    let processed =
+        ordering::order_selection(publish_order, &Vec::from_iter(processed.iter().cloned()));
+    lockfile::update_lockfile(ctx, root, &processed)?;
+
+    let krates = match check.scope {
+        PostCheckScope::Workspace => publish_order.iter().collect(),
+        PostCheckScope::Published => ordering::order_selection(publish_order, published),
+        PostCheckScope::Dependents => {
+            let graph = crates.dependency_graph(|details| {
+                details
+                    .deps
+                    .iter()
+                    .chain(details.build_deps.iter())
+                    .chain(details.dev_deps.iter())
+            });
+            let mut krates = published.to_vec();
+            krates.extend(ordering::dependents(&graph, published, None));
+            ordering::order_selection(publish_order, &krates)
+        }
+    };
+    if krates.is_empty() {
+        info!("No crates to run post checks for");
+        return Ok(());
+    }
+
+    for command in check.commands {
+        let mut args = command.split_whitespace();
+        let subcommand = match args.next() {
+            Some(subcommand) => subcommand,
+            None => anyhow::bail!("Empty post check command"),
+        };
+        let args = args.collect::<Vec<_>>();
+        for krate in &krates {
+            info!("Running cargo {command} for {krate}");
+            let mut cmd = ctx.cargo.command();
+            cmd.current_dir(root).arg(subcommand).arg("-p").arg(krate);
+            if check.all_features {
+                cmd.arg("--all-features");
+            } else if !check.features.is_empty() {
+                cmd.arg("--features").arg(check.features.join(","));
+            }
+            cmd.args(&args).stdout(events::child_stdout());
+            if !cmd.status()?.success() {
+                anyhow::bail!("Command failed: {cmd:?}");
+            };
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Check before publishing anything that the run can't fail half way for
+//! reasons which are known up front, e.g. because the token of the registry
+//! can't publish one of the crates.
+
+use crate::context::Context;
+use crate::external;
+use anyhow::Context as _;
+use tracing::{info, warn};
+
+/// Check that the registry is reachable, that its token is valid and that the
+/// owner of the token owns every crate of `krates` which was published before.
+/// Crates owned by teams can't be checked, since subpub can't tell who is in a
+/// team, so they are only warned about.
+pub fn preflight<Crate: AsRef<str>>(
+    ctx: &Context,
+    registry: &str,
+    krates: &[Crate],
+) -> anyhow::Result<()> {
+    let token = match ctx.cargo.registry_token(registry)? {
+        Some(token) => token,
+        None => {
+            warn!("No token is known for {registry}, so it can't be checked before publishing");
+            return Ok(());
+        }
+    };
+    let owner = external::crates_io::token_owner(&ctx.registry.crates_api()?, &token)
+        .with_context(|| format!("The pre-flight check of {registry} failed"))?;
+    info!("Publishing to {registry} as {owner}");
+
+    let mut not_owned = vec![];
+    let mut team_owned = vec![];
+    for krate in krates {
+        let krate = krate.as_ref();
+        if ctx.registry.crate_versions(krate)?.is_empty() {
+            // Whoever publishes a new crate owns it.
+            continue;
+        }
+        let owners = ctx.registry.crate_owners(krate)?;
+        if owners.contains(&owner) {
+            continue;
+        }
+        // Teams are named like github:org:team.
+        if owners.iter().any(|owner| owner.contains(':')) {
+            team_owned.push(format!("{krate} (owned by {})", owners.join(", ")));
+        } else {
+            not_owned.push(format!("{krate} (owned by {})", owners.join(", ")));
+        }
+    }
+    if !team_owned.is_empty() {
+        warn!(
+            "{owner} is not an owner of these crates, so they can only be published if {owner} is in one of the teams which own them: {}",
+            team_owned.join(", ")
+        );
+    }
+    if !not_owned.is_empty() {
+        anyhow::bail!(
+            "{owner} is not an owner of the following crates, so publishing them would fail:\n{}",
+            not_owned.join("\n")
+        );
+    }
+    Ok(())
+}
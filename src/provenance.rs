@@ -0,0 +1,70 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Provenance of published crates, recorded in their manifests so that a
+//! published crate tells which commit and release produced it.
+
+use crate::context::Context;
+use crate::freeze::Date;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// The commit the release was started from.
+    pub source_commit: String,
+    pub build_date: Date,
+    pub subpub_version: &'static str,
+    /// The version of Cargo the crates are packaged with.
+    pub toolchain: String,
+    /// The git object id of the plan the release follows, if any.
+    pub plan_digest: Option<String>,
+}
+
+impl Provenance {
+    /// Gather the provenance of a release of the workspace at `root`. This has
+    /// to be done before the release commits anything.
+    pub fn collect(
+        ctx: &Context,
+        root: &Path,
+        plan_path: Option<&Path>,
+    ) -> anyhow::Result<Provenance> {
+        Ok(Provenance {
+            source_commit: ctx.git.head(root)?,
+            build_date: Date::today(),
+            subpub_version: env!("CARGO_PKG_VERSION"),
+            toolchain: ctx.cargo.version()?,
+            plan_digest: plan_path
+                .map(|plan_path| ctx.git.hash_object(plan_path))
+                .transpose()?,
+        })
+    }
+
+    /// The `[package.metadata.release]` table describing this provenance.
+    pub fn to_table(&self) -> toml_edit::Table {
+        let mut table = toml_edit::Table::new();
+        table.insert(
+            "source-commit",
+            toml_edit::value(self.source_commit.as_str()),
+        );
+        table.insert("build-date", toml_edit::value(self.build_date.to_string()));
+        table.insert("subpub-version", toml_edit::value(self.subpub_version));
+        table.insert("toolchain", toml_edit::value(self.toolchain.as_str()));
+        if let Some(plan_digest) = &self.plan_digest {
+            table.insert("plan-digest", toml_edit::value(plan_digest.as_str()));
+        }
+        table
+    }
+}
@@ -0,0 +1,2654 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The publish pipeline: select crates, order them, check them, bump their
+//! versions and publish them.
+
+use crate::approvals;
+use crate::changelog;
+use crate::checkpoint::{with_changes_undone, with_save_checkpoint};
+use crate::checks;
+use crate::config::{self, Config, PublishDefaults, RegistryProfile};
+use crate::consumers;
+use crate::context::Context;
+use crate::crate_details::CrateDetails;
+use crate::crates::Crates;
+use crate::doc_examples;
+use crate::events;
+use crate::external;
+use crate::external::cargo::{self, Cargo};
+use crate::freeze;
+use crate::groups;
+use crate::history;
+use crate::interrupt;
+use crate::journal;
+use crate::notarize;
+use crate::ordering;
+use crate::owners;
+use crate::plan::{Plan, PlannedRelease};
+use crate::post_check::{self, PostCheck, PostCheckScope};
+use crate::preflight;
+use crate::provenance::Provenance;
+use crate::rate_limit::RateLimit;
+use crate::run_lock;
+use crate::self_check;
+use crate::state::{PublishedCrate, RunState};
+use crate::tags;
+use crate::train;
+use crate::version::{self, Bump, BumpOverride, RequirementStyle};
+use crate::worktree;
+use crate::yank;
+use anyhow::anyhow;
+use anyhow::Context as _;
+use clap::Parser;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info, span, warn, Level};
+
+#[derive(Parser, Debug, Clone)]
+#[clap(author, version, about, long_about = None)]
+pub struct PublishOpts {
+    #[clap(long, help = "Path to the workspace root")]
+    pub root: PathBuf,
+
+    #[clap(
+        short = 'c',
+        long = "crate",
+        help = "Select crates to be published. If empty, all crates in the workspace of --root will be published."
+    )]
+    pub crates: Vec<String>,
+
+    #[clap(
+        short = 's',
+        long = "start-from",
+        help = "Start publishing from this crate. Prefer --resume to continue a run which failed. The selection is checked against the state recorded by the previous run, so that crates before this one which weren't processed yet aren't skipped. The crates before this one are checked against the registry, and the run is aborted if any of them needs publishing, unless --ack-skipped is given."
+    )]
+    pub start_from: Option<String>,
+
+    #[clap(
+        long = "ack-skipped",
+        requires = "start-from",
+        help = "Publish even if crates skipped by --start-from need publishing themselves, which is otherwise an error."
+    )]
+    pub ack_skipped: bool,
+
+    #[clap(
+        long = "resume",
+        conflicts_with_all = &["crates", "exclude", "start-from", "train", "from-plan", "unified-version"],
+        help = "Continue the last run which didn't finish, with the same selection, skipping the crates it processed already."
+    )]
+    pub resume: bool,
+
+    #[clap(
+        short = 'e',
+        long = "exclude",
+        help = "Crates to be excluded from the publishing process."
+    )]
+    pub exclude: Vec<String>,
+
+    #[clap(
+        long = "include-crates-dependents",
+        help = "Also publish the workspace crates which depend on the crates selected with --crate."
+    )]
+    pub include_crates_dependents: bool,
+
+    #[clap(
+        long = "dependents-depth",
+        requires = "include-crates-dependents",
+        help = "Only include dependents up to this many dependency hops away from the selected crates, e.g. 1 for their direct dependents."
+    )]
+    pub dependents_depth: Option<usize>,
+
+    #[clap(
+        long = "dependents-kind",
+        arg_enum,
+        requires = "include-crates-dependents",
+        help = "Only follow these kinds of dependencies when including dependents. All kinds are followed by default."
+    )]
+    pub dependents_kind: Vec<DependencyKind>,
+
+    #[clap(
+        long = "train",
+        conflicts_with = "crates",
+        help = "Publish the crates of this release train, defined in the config file, at exactly their pinned versions."
+    )]
+    pub train: Option<String>,
+
+    #[clap(
+        long = "from-plan",
+        conflicts_with_all = &["crates", "train"],
+        help = "Publish the crates listed in this plan file. If the config file requires approvals, the plan has to be approved by enough people first."
+    )]
+    pub from_plan: Option<PathBuf>,
+
+    #[clap(
+        long = "unified-version",
+        value_name = "VERSION",
+        conflicts_with_all = &["train", "from-plan", "bump"],
+        help = "Publish every selected crate at this same version, e.g. 1.2.0, updating the requirements of the workspace on them, as if they were a release train. Crates which are published at it already, and didn't change, are skipped."
+    )]
+    pub unified_version: Option<semver::Version>,
+
+    #[clap(
+        long = "check-registry-metadata",
+        help = "After publishing, check the features, dependencies and links the registry reports for each crate against its manifest, and fail if the registry normalized or dropped anything."
+    )]
+    pub check_registry_metadata: bool,
+
+    #[clap(
+        long = "publish-timeout",
+        value_name = "SECONDS",
+        help = "How long to wait for a published crate to show up in the registry's API and index before giving up. Defaults to 600."
+    )]
+    pub publish_timeout: Option<u64>,
+
+    #[clap(
+        long = "publish-attempts",
+        value_name = "ATTEMPTS",
+        help = "How many times to try publishing a crate when the registry fails with a server error or times out, waiting exponentially longer between attempts. Defaults to 5."
+    )]
+    pub publish_attempts: Option<u32>,
+
+    #[clap(
+        long = "continue-on-error",
+        help = "When a crate fails to publish, go on with the crates which don't depend on it, and attempt it, along with the crates it held back, once more at the end of the run. The run fails if it still fails then."
+    )]
+    pub continue_on_error: bool,
+
+    #[clap(
+        long = "override-freeze",
+        value_name = "REASON",
+        help = "Publish crates even if they are frozen by the config file. The reason is logged."
+    )]
+    pub override_freeze: Option<String>,
+
+    #[clap(
+        long = "docs-rs",
+        arg_enum,
+        help = "Make sure every published crate sets the features and targets docs.rs builds: either check that they are set, or add the defaults from the config file where they are missing."
+    )]
+    pub docs_rs: Option<DocsRsMode>,
+
+    #[clap(
+        long = "provenance",
+        help = "Record the commit the release was started from, the date, the version of subpub and the digest of the plan, if any, under [package.metadata.release] in the manifest of every published crate."
+    )]
+    pub provenance: bool,
+
+    #[clap(
+        long = "allow-placeholder-version",
+        value_name = "CRATE",
+        help = "Allow this crate to be published at a placeholder version, i.e. 0.0.0, or 0.0.1 for its first release."
+    )]
+    pub allow_placeholder_version: Vec<String>,
+
+    #[clap(
+        long = "allow-dirty",
+        help = "Let cargo publish crates with uncommitted changes, e.g. generated files. Use allow-dirty under [crates.<name>] in the config file to only allow it for some crates."
+    )]
+    pub allow_dirty: bool,
+
+    #[clap(
+        long = "snapshot",
+        help = "Publish a snapshot release, which allows versions with a -dev pre-release, e.g. 1.0.0-dev.1."
+    )]
+    pub snapshot: bool,
+
+    #[clap(
+        long = "dry-run",
+        help = "Go through the whole process, including version bumps and manifest rewrites, but don't publish anything. What would be published is printed at the end, and the changes to the workspace are undone, leaving the uncommitted changes it started from as they were."
+    )]
+    pub dry_run: bool,
+
+    #[clap(
+        long = "porcelain",
+        help = "Print line-delimited JSON events (plan, crate-started, published, failed, done) to stdout for tools wrapping subpub. Logs are printed to stderr instead."
+    )]
+    pub porcelain: bool,
+
+    #[clap(
+        short = 'k',
+        long = "post-check",
+        help = "Run post checks, e.g. cargo check, after publishing."
+    )]
+    pub post_check: bool,
+
+    #[clap(
+        long = "post-check-scope",
+        arg_enum,
+        help = "Which crates to run the post checks for: the published crates, the published crates and their dependents, or every crate of the workspace. Defaults to the whole workspace."
+    )]
+    pub post_check_scope: Option<PostCheckScope>,
+
+    #[clap(
+        long = "post-check-command",
+        value_name = "COMMAND",
+        help = "A Cargo command to run for every crate as a post check, with its flags, e.g. \"test --lib\". Can be given more than once. Defaults to check."
+    )]
+    pub post_check_command: Vec<String>,
+
+    #[clap(
+        long = "post-check-features",
+        value_name = "FEATURES",
+        help = "The features to enable for the post checks."
+    )]
+    pub post_check_features: Vec<String>,
+
+    #[clap(
+        long = "post-check-all-features",
+        help = "Enable all features for the post checks."
+    )]
+    pub post_check_all_features: bool,
+
+    #[clap(
+        long = "skip-consumers",
+        help = "Don't check the consumers listed in the config file against the published crates."
+    )]
+    pub skip_consumers: bool,
+
+    #[clap(
+        long = "skip-preflight",
+        help = "Don't check before publishing that the registry is reachable, that its token is valid and that the owner of the token owns the crates which were published before."
+    )]
+    pub skip_preflight: bool,
+
+    #[clap(
+        long = "require-changelog",
+        help = "Fail if the changelog of a crate has no entry for the version it would be published at. See the changelog setting for where the changelogs are and what their entries look like."
+    )]
+    pub require_changelog: bool,
+
+    #[clap(
+        long = "generate-changelog",
+        help = "Before publishing a crate, add an entry for the version it's published at to its changelog, listing the commits which touched the crate since its latest published version, grouped by their conventional commit type. Changelogs which have an entry for the version already are left alone. See the changelog setting for where the changelogs are and what their entries look like."
+    )]
+    pub generate_changelog: bool,
+
+    #[clap(
+        long = "tag",
+        help = "Create an annotated git tag at the commit each crate is published from, named after --tag-format."
+    )]
+    pub tag: bool,
+
+    #[clap(
+        long = "tag-format",
+        value_name = "FORMAT",
+        help = "The name of the tags created by --tag, where {crate} and {version} are filled in. Defaults to {crate}-v{version}."
+    )]
+    pub tag_format: Option<String>,
+
+    #[clap(
+        long = "push-tags",
+        value_name = "REMOTE",
+        help = "Push the tags created by --tag to this remote as soon as they are created. Implies --tag."
+    )]
+    pub push_tags: Option<String>,
+
+    #[clap(
+        long = "check-tags",
+        help = "Before publishing, compare the version of each crate in the workspace with its latest tag, named after --tag-format, and its latest version in the registry, and fail if a tagged version isn't published, a published version isn't tagged, or the workspace is behind either of them."
+    )]
+    pub check_tags: bool,
+
+    #[clap(
+        long = "sign",
+        help = "Sign the commits and tags subpub creates, with GPG or SSH depending on gpg.format, using the key of user.signingkey."
+    )]
+    pub sign: bool,
+
+    #[clap(
+        long = "squash-checkpoints",
+        help = "Once the crates are published, replace the checkpoint commits of the run by a single commit, with the message of --release-commit-message."
+    )]
+    pub squash_checkpoints: bool,
+
+    #[clap(
+        long = "release-commit-message",
+        value_name = "MESSAGE",
+        help = "The message of the commit --squash-checkpoints creates. Defaults to \"Release: bump versions\"."
+    )]
+    pub release_commit_message: Option<String>,
+
+    #[clap(
+        long = "bump-doc-examples",
+        help = "Before publishing a crate, update the requirements on it in the TOML code blocks of the READMEs and doc comments of the workspace, e.g. `foo = \"1.2\"`, to the version it's published at."
+    )]
+    pub bump_doc_examples: bool,
+
+    #[clap(
+        long = "bump",
+        value_name = "[CRATE=]LEVEL",
+        help = "Bump the crates which need publishing by this level, i.e. major, minor or patch, rather than the one subpub picks, or only CRATE with CRATE=LEVEL, e.g. --bump polkadot-core=minor. Levels are taken literally for 0.x versions, e.g. minor bumps 0.2.3 to 0.3.0. Can be given several times, and the level given for a crate wins over the one for every crate."
+    )]
+    pub bump: Vec<BumpOverride>,
+
+    #[clap(
+        long = "semver-checks",
+        help = "Compare the public API of the crates which were published before with their latest published version using cargo-semver-checks, and bump them for a major, minor or patch release depending on what changed, rather than always for a breaking change. The checks which failed are added to the plan."
+    )]
+    pub semver_checks: bool,
+
+    #[clap(
+        long = "requirement-style",
+        arg_enum,
+        help = "How dependents require the new versions of crates: exact (=1.2.3), caret (1.2.3, the default), short (1.2), tilde (~1.2.3) or compatible-range (>=1.2.3, <2.0.0). Once a style is given, every requirement on a new version is written in it; only without one are the requirements which already allow the new version left alone."
+    )]
+    pub requirement_style: Option<RequirementStyle>,
+
+    #[clap(
+        long = "propagate",
+        arg_enum,
+        help = "Which crates depending on a crate which is published are assumed to need publishing too where their contents can't be compared with the registry, i.e. during dry runs and with --fast-plan: always (the default), or only those whose requirement on the crate doesn't allow its new version (incompatible), e.g. not for a patch release."
+    )]
+    pub propagate: Option<Propagation>,
+
+    #[clap(
+        long = "worktree",
+        help = "Publish from a git worktree of the workspace, on a branch of its own, rather than from the checkout: checkpoints, manifest edits and commits all happen there, and the checkout and its branch are left untouched. The worktree is removed once the run succeeds, leaving the branch to be merged, and kept after a failure for --resume."
+    )]
+    pub worktree: bool,
+
+    #[clap(
+        long = "worktree-branch",
+        value_name = "BRANCH",
+        help = "The branch --worktree creates, starting at the commit the checkout is at. Defaults to subpub/release."
+    )]
+    pub worktree_branch: Option<String>,
+
+    #[clap(
+        long = "toolchain",
+        value_name = "TOOLCHAIN",
+        help = "Run every cargo command with this rustup toolchain, e.g. 1.77.0, as in cargo +1.77.0. The resulting cargo version is logged, recorded in the run state and, with --provenance, in the published manifests."
+    )]
+    pub toolchain: Option<String>,
+
+    #[clap(
+        long = "index-url",
+        value_name = "URL",
+        help = "Read the published versions of crates from this sparse index, e.g. https://index.crates.io, rather than from the registry API, which has much stricter rate limits. New versions are also waited for in this index."
+    )]
+    pub index_url: Option<String>,
+
+    #[clap(
+        long = "api-url",
+        value_name = "URL",
+        help = "Query the crates.io-compatible API at this URL, e.g. https://kellnr.example.com/api/v1, for the published versions and packages of crates, rather than the one in SPUB_CRATES_API. Requests carry SPUB_CRATES_API_TOKEN, or else the token of the registry crates are published to. Use api under [registries.<name>] in the config file to set it for a registry."
+    )]
+    pub api_url: Option<String>,
+
+    #[clap(
+        long = "registry",
+        value_name = "REGISTRY",
+        help = "Publish to this registry, as named in [registries] of the Cargo configuration, instead of crates.io. The dependencies between published crates are pointed at it too. Defaults to SPUB_REGISTRY. Can be given more than once to mirror the crates: each crate is published to the first registry, then to the others in order, with its dependencies pointed at each of them. crates-io names crates.io."
+    )]
+    pub registry: Vec<String>,
+
+    #[clap(
+        long = "verify-target",
+        value_name = "TARGET",
+        help = "Also check that the packages build for this target, e.g. wasm32-unknown-unknown, before publishing them, since cargo publish only verifies them for the host. Use verify-targets under [crates.<name>] in the config file to set the targets of some crates."
+    )]
+    pub verify_target: Vec<String>,
+
+    #[clap(
+        long = "notarize",
+        help = "Once the crates are published, commit the plan which was followed and the report of the run to releases/ of the workspace, so that the exact inputs and outputs of every release are versioned next to the code."
+    )]
+    pub notarize: bool,
+
+    #[clap(
+        long = "self-check",
+        help = "Before publishing each crate, re-read the manifests and check that the path dependencies have versions which match, that the version of the crate increased and that its dependencies come first. Any violation aborts the run, to catch bugs of subpub before they reach the registry."
+    )]
+    pub self_check: bool,
+
+    #[clap(
+        long = "compare-contents",
+        help = "Decide whether a crate whose version is published changed since by comparing the files of its package with those of the published one, rather than the packages byte for byte. The commit Cargo records in packages is ignored, so crates whose files didn't change aren't published again."
+    )]
+    pub compare_contents: bool,
+
+    #[clap(
+        long = "yank-superseded-prereleases",
+        help = "Once a stable version of a crate is published, yank the pre-releases of that version, e.g. 1.2.0-rc.1 and 1.2.0-rc.2 once 1.2.0 is published."
+    )]
+    pub yank_superseded_prereleases: bool,
+
+    #[clap(
+        long = "yank-prerelease-kind",
+        value_name = "KIND",
+        help = "Only yank the superseded pre-releases whose first identifier is this one, e.g. rc for 1.2.0-rc.1. Can be given more than once. Every pre-release is yanked by default."
+    )]
+    pub yank_prerelease_kind: Vec<String>,
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DocsRsMode {
+    Check,
+    Inject,
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    Normal,
+    Build,
+    Dev,
+}
+
+/// Which dependents of a crate which is published are assumed to need
+/// publishing too, see `--propagate`.
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Propagation {
+    /// Every dependent.
+    Always,
+    /// The dependents whose requirement on the crate doesn't allow its new
+    /// version, i.e. whose manifest changes.
+    Incompatible,
+}
+
+/// The dependency of `details` released in `releases` which makes it need
+/// publishing too, if any, according to `propagation`.
+fn released_dependency<'a>(
+    details: &'a CrateDetails,
+    releases: &[PlannedRelease],
+    propagation: Option<Propagation>,
+) -> Option<&'a String> {
+    details.deps_to_publish().find(|dep| {
+        releases.iter().any(|release| {
+            release.krate == **dep
+                && match propagation.unwrap_or(Propagation::Always) {
+                    Propagation::Always => true,
+                    Propagation::Incompatible => !details
+                        .dep_version_reqs
+                        .get(*dep)
+                        .is_some_and(|version_req| version_req.matches(&release.next_version)),
+                }
+        })
+    })
+}
+
+/// How long to wait for a published crate to be available, in seconds.
+const DEFAULT_PUBLISH_TIMEOUT: u64 = 600;
+
+/// The message of the commit `--squash-checkpoints` creates.
+const DEFAULT_RELEASE_COMMIT_MESSAGE: &str = "Release: bump versions";
+
+/// How many times to try publishing a crate before giving up.
+const DEFAULT_PUBLISH_ATTEMPTS: u32 = 5;
+
+impl PublishOpts {
+    /// Fill in the options which weren't given on the command line from the
+    /// config file.
+    pub fn with_defaults(self, defaults: &PublishDefaults) -> PublishOpts {
+        let selected = !self.crates.is_empty()
+            || self.train.is_some()
+            || self.from_plan.is_some()
+            || self.unified_version.is_some()
+            || self.resume;
+        let or_defaults = |values: Vec<String>, defaults: &Vec<String>| {
+            if values.is_empty() {
+                defaults.clone()
+            } else {
+                values
+            }
+        };
+        PublishOpts {
+            crates: if selected {
+                self.crates
+            } else {
+                defaults.crates.clone()
+            },
+            exclude: or_defaults(self.exclude, &defaults.exclude),
+            include_crates_dependents: self.include_crates_dependents
+                || defaults.include_crates_dependents,
+            dependents_depth: self.dependents_depth.or(defaults.dependents_depth),
+            dependents_kind: if self.dependents_kind.is_empty() {
+                defaults.dependents_kind.clone()
+            } else {
+                self.dependents_kind
+            },
+            check_registry_metadata: self.check_registry_metadata
+                || defaults.check_registry_metadata,
+            publish_timeout: self.publish_timeout.or(defaults.publish_timeout),
+            publish_attempts: self.publish_attempts.or(defaults.publish_attempts),
+            continue_on_error: self.continue_on_error || defaults.continue_on_error,
+            docs_rs: self.docs_rs.or(defaults.docs_rs),
+            provenance: self.provenance || defaults.provenance,
+            allow_placeholder_version: or_defaults(
+                self.allow_placeholder_version,
+                &defaults.allow_placeholder_version,
+            ),
+            allow_dirty: self.allow_dirty || defaults.allow_dirty,
+            snapshot: self.snapshot || defaults.snapshot,
+            post_check: self.post_check || defaults.post_check,
+            post_check_scope: self.post_check_scope.or(defaults.post_check_scope),
+            post_check_command: or_defaults(self.post_check_command, &defaults.post_check_command),
+            post_check_features: or_defaults(
+                self.post_check_features,
+                &defaults.post_check_features,
+            ),
+            post_check_all_features: self.post_check_all_features
+                || defaults.post_check_all_features,
+            skip_consumers: self.skip_consumers || defaults.skip_consumers,
+            skip_preflight: self.skip_preflight || defaults.skip_preflight,
+            require_changelog: self.require_changelog || defaults.require_changelog,
+            generate_changelog: self.generate_changelog || defaults.generate_changelog,
+            tag: self.tag || defaults.tag,
+            tag_format: self.tag_format.or_else(|| defaults.tag_format.clone()),
+            push_tags: self.push_tags.or_else(|| defaults.push_tags.clone()),
+            check_tags: self.check_tags || defaults.check_tags,
+            sign: self.sign || defaults.sign,
+            squash_checkpoints: self.squash_checkpoints || defaults.squash_checkpoints,
+            release_commit_message: self
+                .release_commit_message
+                .or_else(|| defaults.release_commit_message.clone()),
+            bump_doc_examples: self.bump_doc_examples || defaults.bump_doc_examples,
+            semver_checks: self.semver_checks || defaults.semver_checks,
+            bump: if self.bump.is_empty() {
+                defaults.bump.clone()
+            } else {
+                self.bump
+            },
+            requirement_style: self.requirement_style.or(defaults.requirement_style),
+            propagate: self.propagate.or(defaults.propagate),
+            worktree: self.worktree || defaults.worktree,
+            worktree_branch: self
+                .worktree_branch
+                .or_else(|| defaults.worktree_branch.clone()),
+            toolchain: self.toolchain.or_else(|| defaults.toolchain.clone()),
+            index_url: self.index_url.or_else(|| defaults.index_url.clone()),
+            api_url: self.api_url.or_else(|| defaults.api_url.clone()),
+            registry: or_defaults(self.registry, &defaults.registry),
+            verify_target: or_defaults(self.verify_target, &defaults.verify_target),
+            notarize: self.notarize || defaults.notarize,
+            self_check: self.self_check || defaults.self_check,
+            compare_contents: self.compare_contents || defaults.compare_contents,
+            yank_superseded_prereleases: self.yank_superseded_prereleases
+                || defaults.yank_superseded_prereleases,
+            yank_prerelease_kind: or_defaults(
+                self.yank_prerelease_kind,
+                &defaults.yank_prerelease_kind,
+            ),
+            ..self
+        }
+    }
+}
+
+/// Work out what [`publish`] would do with the same options, without
+/// publishing anything.
+pub fn plan(opts: PublishOpts) -> anyhow::Result<Plan> {
+    plan_with(&context(&opts)?, opts)
+}
+
+/// [`plan`] in the given context.
+pub fn plan_with(ctx: &Context, opts: PublishOpts) -> anyhow::Result<Plan> {
+    let releases = publish_with(
+        ctx,
+        PublishOpts {
+            dry_run: true,
+            ..opts
+        },
+    )?;
+    Ok(Plan {
+        crates: releases
+            .iter()
+            .map(|release| release.krate.clone())
+            .collect(),
+        releases,
+        ..Plan::default()
+    })
+}
+
+/// What [`fast_plan`] found.
+#[derive(Debug)]
+pub struct FastPlan {
+    pub plan: Plan,
+    /// Why some of the crates couldn't be published as they are, e.g.
+    /// because they depend on a crate with `publish = false`.
+    pub blockers: Vec<String>,
+}
+
+/// Work out what [`publish`] would publish from the workspace alone, quickly
+/// enough to run on every pull request. The registry isn't queried: versions
+/// are looked up among those subpub saw last, crates aren't packaged, so a
+/// crate whose version is published is assumed not to have changed unless one
+/// of its dependencies would be published, and the workspace isn't touched.
+/// What would block the release is returned rather than failing.
+pub fn fast_plan(opts: PublishOpts) -> anyhow::Result<FastPlan> {
+    let config = Config::load(&opts.root)?;
+    let ctx = context(&opts)?;
+    let opts = opts.with_defaults(&config.publish);
+    let crates = Crates::load_crates_in_workspace(opts.root.clone(), &ctx)?;
+    check_bump_overrides(&opts, &crates)?;
+
+    let publish_order = match publish_levels(&crates, &config) {
+        Ok(publish_levels) => publish_levels.concat(),
+        Err(err) => {
+            return Ok(FastPlan {
+                plan: Plan::default(),
+                blockers: vec![format!("{err:#}")],
+            })
+        }
+    };
+    let exclude = excluded_crates(&crates, &config, &publish_order, &opts.exclude)?;
+    let plan = opts.from_plan.as_ref().map(Plan::load).transpose()?;
+    let train = train_of(&opts, &config)?;
+    let input_crates = select_crates(
+        &opts,
+        &crates,
+        train,
+        plan.as_ref(),
+        &publish_order,
+        &exclude,
+    )?;
+    let mut selected = vec![];
+    for krate in &input_crates {
+        selected.extend(crates.what_needs_publishing(krate, &publish_order)?);
+    }
+    // Like publish, a train only publishes its own crates, and so does a
+    // unified version.
+    if let Some((_, train)) = train {
+        selected.retain(|krate| train.contains_key(krate));
+    } else if opts.unified_version.is_some() {
+        selected.retain(|krate| input_crates.contains(krate));
+    }
+    let selection = ordering::order_selection(&publish_order, &selected)
+        .into_iter()
+        .filter(|krate| !exclude.contains(krate))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut blockers = vec![];
+    let mut releases: Vec<PlannedRelease> = vec![];
+    for krate in &selection {
+        let details = &crates.details[krate];
+        for dep in details.deps_relevant_during_publish() {
+            let dep_details = crates
+                .details
+                .get(dep)
+                .with_context(|| format!("Crate not found: {dep}"))?;
+            if !dep_details.should_be_published {
+                blockers.push(format!(
+                    "Crate {krate} depends on {dep}, which has \"publish = false\" in {:?}",
+                    dep_details.toml_path
+                ));
+            } else if dep_details.is_bin_only() {
+                blockers.push(format!("Crate {krate} depends on {dep}, but {dep} only has binary targets, so it can't be used as a dependency"));
+            }
+        }
+        if !details.should_be_published {
+            continue;
+        }
+
+        // The version a train or a unified version publishes the crate at.
+        let pinned = match train {
+            Some((_, train)) => train.get(krate),
+            None => opts.unified_version.as_ref(),
+        };
+        let version = pinned.unwrap_or(&details.version);
+        let known_versions = ctx.registry.known_crate_versions(krate);
+        let published_dep = released_dependency(details, &releases, opts.propagate);
+        let reason = match (&known_versions, published_dep) {
+            (_, Some(dep)) => format!("depends on {dep}, which would be published"),
+            (None, None) => format!("{version} might not be published"),
+            (Some(versions), None) if !versions.contains(version) => {
+                format!("{version} is not published")
+            }
+            (Some(_), None) => continue,
+        };
+        let next_version = match (pinned, known_versions) {
+            (Some(pinned), _) => pinned.clone(),
+            (None, Some(versions)) => match version::forced_bump(&opts.bump, krate) {
+                Some(forced) => {
+                    version::maybe_bump_to_level(versions, details.version.clone(), forced)
+                }
+                None => version::maybe_bump_for_breaking_change(versions, details.version.clone()),
+            }
+            .unwrap_or_else(|| details.version.clone()),
+            (None, None) => details.version.clone(),
+        };
+        releases.push(PlannedRelease {
+            krate: krate.clone(),
+            version: details.version.clone(),
+            next_version,
+            reason,
+            dependency_only: false,
+            api_changes: None,
+            group: groups::group_of(&config.groups, &opts.root, details).map(String::from),
+        });
+    }
+
+    Ok(FastPlan {
+        plan: Plan {
+            crates: releases
+                .iter()
+                .map(|release| release.krate.clone())
+                .collect(),
+            releases,
+            ..Plan::default()
+        },
+        blockers,
+    })
+}
+
+/// Publish the selected crates, returning the releases which were made, or
+/// would have been made by a dry run.
+pub fn publish(opts: PublishOpts) -> anyhow::Result<Vec<PlannedRelease>> {
+    publish_with(&context(&opts)?, opts)
+}
+
+/// The context of a run with `opts`: the toolchain, the registry and its
+/// mirrors they name, which have to be configured, and the requirement style
+/// they give.
+pub fn context(opts: &PublishOpts) -> anyhow::Result<Context> {
+    let config = Config::load(&opts.root)?;
+    let opts = opts.clone().with_defaults(&config.publish);
+    let cargo = Cargo::new(
+        opts.toolchain.as_deref(),
+        opts.registry.first().map(String::as_str),
+    );
+    cargo::check_registry_configured(&opts.root, &cargo.target_registry())?;
+    for mirror in mirrors(&opts) {
+        cargo::check_registry_configured(&opts.root, mirror)?;
+    }
+    let mut ctx = Context::new(
+        &opts.root,
+        &config,
+        cargo,
+        opts.index_url.as_deref(),
+        opts.api_url.as_deref(),
+    )?;
+    ctx.requirement_styles.style = opts.requirement_style;
+    Ok(ctx)
+}
+
+/// [`publish`] in the given context.
+pub fn publish_with(ctx: &Context, opts: PublishOpts) -> anyhow::Result<Vec<PlannedRelease>> {
+    let config = Config::load(&opts.root)?;
+    let opts = opts.with_defaults(&config.publish);
+    // Worktrees share the git directory of the checkout, and so its lock.
+    let _lock = run_lock::lock(&ctx.git, &opts.root)?;
+    if !opts.worktree {
+        return publish_workspace(ctx, opts);
+    }
+
+    let branch = opts
+        .worktree_branch
+        .clone()
+        .unwrap_or_else(|| worktree::DEFAULT_BRANCH.to_owned());
+    let worktree = worktree::open(&ctx.git, &opts.root, &branch, opts.resume)?;
+    let dry_run = opts.dry_run;
+    let result = publish_workspace(
+        ctx,
+        PublishOpts {
+            root: worktree.root.clone(),
+            ..opts
+        },
+    );
+    match &result {
+        // The dry run undid its commits, so its branch has nothing to merge.
+        _ if dry_run => worktree.remove(&ctx.git, false)?,
+        Ok(_) => {
+            let branch = worktree.branch.clone();
+            worktree.remove(&ctx.git, true)?;
+            info!("The commits of the release are on {branch}, ready to be merged");
+        }
+        Err(_) => warn!(
+            "The worktree at {:?} is kept, run again with --resume to pick up where the run stopped",
+            worktree.dir.as_os_str()
+        ),
+    }
+    result
+}
+
+fn publish_workspace(ctx: &Context, opts: PublishOpts) -> anyhow::Result<Vec<PlannedRelease>> {
+    let opts = if opts.resume {
+        let run_state = RunState::load(&ctx.git, &opts.root)?
+            .context("There is no unfinished run to resume")?;
+        PublishOpts {
+            crates: run_state.crates,
+            exclude: run_state.exclude,
+            train: run_state.train,
+            unified_version: run_state.unified_version,
+            from_plan: run_state.from_plan,
+            ..opts
+        }
+    } else {
+        opts
+    };
+
+    let mut ctx = ctx.clone();
+    // The commits of dry runs are thrown away, so they aren't signed.
+    ctx.git.sign_commits_and_tags(opts.sign && !opts.dry_run);
+    let ctx = &ctx;
+    if opts.sign && !opts.dry_run {
+        match ctx.git.config(&opts.root, "user.signingkey")? {
+            Some(key) => info!("Signing commits and tags with {key}"),
+            None => warn!(
+                "user.signingkey is not set, so git signs with the default key of the committer"
+            ),
+        }
+    }
+    if opts.toolchain.is_some() {
+        info!("Using {}", ctx.cargo.version()?);
+    }
+
+    if !opts.dry_run {
+        let root = opts.root.clone();
+        if opts.resume {
+            recover_from_journal(ctx, &root)?;
+        }
+        let result = publish_crates(ctx, opts);
+        if result.is_err() && interrupt::interrupted() {
+            // Undo what was changed for packaging the crate the run stopped
+            // at, which is packaged again when the run is resumed.
+            ctx.git.checkpoint_revert(&root)?;
+            anyhow::bail!("The run was interrupted, continue it with publish --resume");
+        }
+        return result;
+    }
+
+    // Everything the dry run changes is undone afterwards, including the
+    // checkpoints it commits.
+    let root = opts.root.clone();
+    with_changes_undone(&ctx.git, &root, || {
+        let result = publish_crates(ctx, opts);
+        info!("Undoing the changes of the dry run");
+        result
+    })?
+}
+
+/// Pick up the pieces of a run which died halfway, according to its journal:
+/// what it didn't commit of a manifest it was writing, and what it changed
+/// for packaging a crate, are thrown away, and the crates it published after
+/// its state was last saved, including those it was publishing when it died
+/// if the registry has them, are added to the state.
+fn recover_from_journal(ctx: &Context, root: &Path) -> anyhow::Result<()> {
+    let steps = journal::load(&ctx.git, root)?;
+    if journal::stopped_while_writing(&steps) {
+        warn!("The previous run stopped while changing the manifests, throwing away what it didn't commit");
+        ctx.git.reset_hard(root, "HEAD")?;
+    }
+    ctx.git.checkpoint_revert(root)?;
+
+    let mut run_state = match RunState::load(&ctx.git, root)? {
+        Some(run_state) => run_state,
+        None => return Ok(()),
+    };
+    let mut recovered = false;
+    let is_recorded = |run_state: &RunState, krate: &str, version: &semver::Version| {
+        run_state
+            .published
+            .iter()
+            .any(|published| published.krate == krate && published.version == *version)
+    };
+    for (krate, version, commit) in journal::published(&steps) {
+        if !is_recorded(&run_state, &krate, &version) {
+            info!("{krate} {version} was published by the previous run after its state was last saved");
+            run_state.published.push(PublishedCrate {
+                krate,
+                version,
+                commit,
+            });
+            recovered = true;
+        }
+    }
+    for (krate, version, commit) in journal::unconfirmed_publishes(&steps) {
+        if is_recorded(&run_state, &krate, &version) {
+            continue;
+        }
+        ctx.registry.forget_crate_versions(&krate);
+        if ctx.registry.crate_versions(&krate)?.contains(&version) {
+            info!(
+                "The previous run stopped while publishing {krate} {version}, which went through"
+            );
+            journal::record(
+                &ctx.git,
+                root,
+                journal::Step::Published {
+                    krate: krate.clone(),
+                    version: version.clone(),
+                    commit: commit.clone(),
+                },
+            )?;
+            run_state.published.push(PublishedCrate {
+                krate,
+                version,
+                commit,
+            });
+            recovered = true;
+        } else {
+            info!("The previous run stopped while publishing {krate} {version}, which didn't go through");
+        }
+    }
+    if recovered {
+        run_state.save(&ctx.git, root)?;
+    }
+    Ok(())
+}
+
+fn publish_crates(ctx: &Context, opts: PublishOpts) -> anyhow::Result<Vec<PlannedRelease>> {
+    let config = Config::load(&opts.root)?;
+    // What `rollback` resets the workspace to.
+    let start_commit = ctx.git.head(&opts.root)?;
+    let plan = opts.from_plan.as_ref().map(Plan::load).transpose()?;
+    if let Some(policy) = &config.approvals {
+        let plan_path = opts.from_plan.as_ref().with_context(|| {
+            format!(
+                "{} requires plans to be approved before they are published, so crates can only be published with --from-plan",
+                config::CONFIG_FILE
+            )
+        })?;
+        approvals::verify_approvals(&opts.root, policy, plan_path)?;
+    }
+
+    let provenance = if opts.provenance {
+        Some(Provenance::collect(
+            ctx,
+            &opts.root,
+            opts.from_plan.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    let mut crates = Crates::load_crates_in_workspace(opts.root.clone(), ctx)?;
+    check_bump_overrides(&opts, &crates)?;
+    let fast_path = single_crate_fast_path(&opts, &crates)?;
+    // Commit the files which are generated for the crates, so that Cargo
+    // doesn't consider them uncommitted changes.
+    with_save_checkpoint(&ctx.git, &opts.root, || crates.setup_crates())??;
+    for details in crates.details.values_mut() {
+        if fast_path
+            .as_ref()
+            .is_some_and(|krate| *krate != details.name)
+        {
+            continue;
+        }
+        details.render_template(&config.templates)?;
+        details.pre_package = config.crate_settings(&details.name).pre_package;
+    }
+    let doc_files = if opts.bump_doc_examples {
+        doc_examples::doc_files(&opts.root, &crates)?
+    } else {
+        vec![]
+    };
+
+    let train = train_of(&opts, &config)?;
+
+    let publish_levels = match &fast_path {
+        Some(krate) => {
+            info!("Publishing {krate} on its own, since the workspace crates it depends on are published at their current versions");
+            vec![vec![krate.clone()]]
+        }
+        None => publish_levels(&crates, &config)?,
+    };
+    let publish_order = publish_levels.concat();
+    let crate_levels = publish_levels
+        .iter()
+        .enumerate()
+        .flat_map(|(level, krates)| krates.iter().map(move |krate| (krate.clone(), level)))
+        .collect::<HashMap<_, _>>();
+    if fast_path.is_none() {
+        info!(
+            "If we were to publish all crates, it would be in this order: {}",
+            publish_order
+                .iter()
+                .map(|krate| krate.to_owned())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let exclude = excluded_crates(&crates, &config, &publish_order, &opts.exclude)?;
+
+    let previous_run_state = RunState::load(&ctx.git, &opts.root)?;
+    let input_crates = if let Some(run_state) = previous_run_state.as_ref().filter(|_| opts.resume)
+    {
+        run_state.selection.clone()
+    } else {
+        select_crates(
+            &opts,
+            &crates,
+            train,
+            plan.as_ref(),
+            &publish_order,
+            &exclude,
+        )?
+    };
+    let selection = ordering::order_selection(&publish_order, &input_crates)
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    // A unified version is released like a train which pins every selected
+    // crate to it.
+    let unified_train = opts.unified_version.as_ref().map(|version| {
+        (
+            UNIFIED_VERSION_TRAIN.to_owned(),
+            selection
+                .iter()
+                .map(|krate| (krate.clone(), version.clone()))
+                .collect::<train::Train>(),
+        )
+    });
+    let train = match &unified_train {
+        Some((name, train)) => Some((name, train)),
+        None => train,
+    };
+    let mut run_state = match (&opts.start_from, previous_run_state) {
+        (_, Some(run_state)) if opts.resume => {
+            info!(
+                "Resuming the previous run, which processed: {}",
+                run_state.processed.join(", ")
+            );
+            run_state
+        }
+        (Some(start_from), Some(mut run_state)) => {
+            run_state.reconcile(&selection, start_from, &publish_order)?;
+            run_state
+        }
+        (Some(_), None) => {
+            info!("No state of a previous run was found, so the selection can't be checked against it");
+            RunState {
+                selection,
+                ..Default::default()
+            }
+        }
+        (None, previous_run_state) => {
+            if previous_run_state.is_some() {
+                info!("The previous run did not finish, starting over");
+            }
+            RunState {
+                selection,
+                ..Default::default()
+            }
+        }
+    };
+    run_state.crates = opts.crates.clone();
+    run_state.exclude = opts.exclude.clone();
+    run_state.train = opts.train.clone();
+    run_state.unified_version = opts.unified_version.clone();
+    run_state.from_plan = opts.from_plan.clone();
+    run_state.toolchain = Some(ctx.cargo.version()?);
+    let operator = ctx.git.user(&opts.root)?;
+    let registry = ctx.cargo.target_registry();
+
+    let (selected_crates, selected_crates_order) = if let Some(start_from) = &opts.start_from {
+        let mut keep = false;
+        let (selected_crates, skipped_crates): (Vec<_>, Vec<_>) =
+            input_crates.into_iter().partition(|krate| {
+                if krate == start_from {
+                    keep = true;
+                }
+                keep
+            });
+        check_skipped_crates(&crates, &skipped_crates, &opts)?;
+
+        let start_from_position = publish_order
+            .iter()
+            .position(|krate| krate == start_from)
+            .unwrap_or(publish_order.len());
+        let selected_crates_order =
+            ordering::order_selection(&publish_order[start_from_position..], &selected_crates);
+
+        (selected_crates, selected_crates_order)
+    } else {
+        let selected_crates_order = ordering::order_selection(&publish_order, &input_crates);
+
+        (input_crates, selected_crates_order)
+    };
+    if selected_crates.is_empty() {
+        anyhow::bail!("No crates could be selected from the CLI options");
+    }
+
+    info!(
+        "Selected the following crates to be published, in order: {}",
+        selected_crates_order
+            .iter()
+            .map(|krate| (*krate).into())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    let unordered_selected_crates = selected_crates
+        .iter()
+        .filter(|sel_crate| {
+            !selected_crates_order
+                .iter()
+                .any(|sel_crate_ordered| sel_crate_ordered == sel_crate)
+        })
+        .collect::<Vec<_>>();
+    if !unordered_selected_crates.is_empty() {
+        anyhow::bail!(
+            "Failed to determine publish order for the following selected crates: {}",
+            unordered_selected_crates
+                .iter()
+                .map(|krate| (*krate).into())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    for krate in &selected_crates {
+        info!("Validating crate {krate}");
+        let details = crates
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?;
+        for dep in details.deps_relevant_during_publish() {
+            let dep_details = crates
+                .details
+                .get(dep)
+                .with_context(|| format!("Crate not found: {dep}"))?;
+            if dep_details.is_bin_only() {
+                anyhow::bail!("Crate {krate} depends on {dep}, but {dep} only has binary targets, so it can't be used as a dependency.");
+            }
+        }
+    }
+
+    freeze::check_freezes(
+        &config.freeze,
+        &selected_crates,
+        opts.override_freeze.as_deref(),
+    )?;
+
+    let registry_profile = config.registry_profile_of(ctx.registry.name());
+    let mut crates_to_check = HashSet::new();
+    for krate in &selected_crates {
+        crates_to_check.extend(crates.what_needs_publishing(krate, &publish_order)?);
+    }
+    let crates_to_check =
+        ordering::order_selection(&publish_order, &Vec::from_iter(crates_to_check));
+    ctx.registry.prefetch_crate_versions(&crates_to_check);
+    if !opts.dry_run && !opts.skip_preflight {
+        preflight::preflight(ctx, &registry, &crates_to_check)?;
+    }
+    if opts.check_tags {
+        let problems = tags::check_tags(
+            ctx,
+            &opts.root,
+            opts.tag_format.as_deref(),
+            &crates_to_check
+                .iter()
+                .map(|krate| {
+                    crates
+                        .details
+                        .get(*krate)
+                        .with_context(|| format!("Crate not found: {krate}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )?;
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "The tags, the registry and the workspace disagree, which has to be reconciled before publishing:\n{}",
+                problems.join("\n")
+            );
+        }
+    }
+    let docs_rs_mode = |krate: &str| config.crate_settings(krate).docs_rs.or(opts.docs_rs);
+    if crates_to_check
+        .iter()
+        .any(|krate| docs_rs_mode(krate) == Some(DocsRsMode::Inject))
+    {
+        with_save_checkpoint(&ctx.git, &opts.root, || -> anyhow::Result<()> {
+            for krate in crates_to_check
+                .iter()
+                .filter(|krate| docs_rs_mode(krate) == Some(DocsRsMode::Inject))
+            {
+                let details = crates
+                    .details
+                    .get(*krate)
+                    .with_context(|| format!("Crate not found: {krate}"))?;
+                if details.write_docs_rs_defaults(&config.docs_rs)? {
+                    info!("Added docs.rs defaults to {krate}");
+                }
+            }
+            Ok(())
+        })??;
+    }
+    let mut problems = vec![];
+    let mut details_to_check = vec![];
+    for krate in crates_to_check {
+        let details = crates
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?;
+        details_to_check.push(details);
+        problems.extend(checks::check_feature_syntax(details, &registry_profile)?);
+        problems.extend(checks::check_manifest_markers(details, &registry_profile)?);
+        if docs_rs_mode(krate) == Some(DocsRsMode::Check) {
+            problems.extend(checks::check_docs_rs(details)?);
+        }
+        for warning in checks::check_native_requirements(details, &config.native)? {
+            warn!("{warning}. The crate might fail to build for its users.");
+        }
+    }
+    for warning in checks::check_duplicated_metadata(&details_to_check)? {
+        warn!("{warning}. A template could generate it, see the templates setting.");
+    }
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "The following crates can't be published to the registry:\n{}",
+            problems.join("\n")
+        );
+    }
+
+    let mut plan = vec![];
+    for (level, krates) in publish_levels.iter().enumerate() {
+        let selected_krates = ordering::order_selection(krates, &selected_crates);
+        if !selected_krates.is_empty() {
+            plan.push(selected_krates.iter().map(|krate| krate.as_str()).collect());
+            info!(
+                "Level {}: {}",
+                level + 1,
+                selected_krates
+                    .iter()
+                    .map(|krate| krate.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+
+    events::emit(events::Event::Plan { levels: plan });
+
+    if registry != external::cargo::CRATES_IO {
+        crates.set_registry(&registry)?;
+    }
+
+    if let Some((name, train)) = train {
+        train::validate_train(&crates, name, train)?;
+        with_save_checkpoint(&ctx.git, &opts.root, || {
+            train::apply_train(&mut crates, train)
+        })??;
+    }
+
+    if !opts.dry_run {
+        run_state.save(&ctx.git, &opts.root)?;
+        if opts.resume {
+            journal::record(&ctx.git, &opts.root, journal::Step::Resumed)?;
+        } else {
+            let worktree_branch = opts.worktree.then(|| {
+                opts.worktree_branch
+                    .clone()
+                    .unwrap_or_else(|| worktree::DEFAULT_BRANCH.to_owned())
+            });
+            journal::start(
+                &ctx.git,
+                &opts.root,
+                &start_commit,
+                worktree_branch.as_deref(),
+            )?;
+        }
+    }
+
+    let mut processed_crates: HashSet<String> = HashSet::new();
+    // Crates which were published but might not be available yet, along with
+    // their level. They are waited for once all crates below the level of the
+    // next crate have been published.
+    let mut pending_crates: Vec<(String, semver::Version, usize)> = vec![];
+    if opts.resume {
+        processed_crates.extend(run_state.processed.iter().cloned());
+        for published in &run_state.published {
+            let details = crates
+                .details
+                .get(&published.krate)
+                .with_context(|| format!("Crate not found: {}", published.krate))?;
+            if details.version != published.version {
+                anyhow::bail!(
+                    "Crate {} was published at {} from commit {}, but its version in the workspace is {}. The workspace changed since the previous run stopped, so it can't be resumed.",
+                    published.krate,
+                    published.version,
+                    published.commit,
+                    details.version
+                );
+            }
+            let level = *crate_levels
+                .get(&published.krate)
+                .with_context(|| format!("Crate level not found for crate: {}", published.krate))?;
+            pending_crates.push((published.krate.clone(), published.version.clone(), level));
+        }
+    }
+    let registry_profile = config.registry_profile_of(ctx.registry.name());
+    let rate_limit = registry_rate_limit(ctx.registry.name(), &registry_profile)?;
+    // Each mirror is throttled by its own profile.
+    let mut mirror_rate_limits = HashMap::new();
+    for mirror in mirrors(&opts) {
+        if let Some(rate_limit) = registry_rate_limit(mirror, &config.registry_profile_of(mirror))?
+        {
+            mirror_rate_limits.insert(mirror.clone(), rate_limit);
+        }
+    }
+    let mut run = PublishRun {
+        ctx,
+        opts: &opts,
+        config: &config,
+        provenance: &provenance,
+        doc_files: &doc_files,
+        train,
+        fast_path: &fast_path,
+        publish_order: &publish_order,
+        crate_levels: &crate_levels,
+        selected_crates: &selected_crates,
+        registry: registry.clone(),
+        operator,
+        registry_profile: &registry_profile,
+        crates,
+        run_state,
+        processed_crates,
+        pending_crates,
+        rate_limit,
+        mirror_rate_limits,
+        releases: vec![],
+        mirror_failures: vec![],
+        owner_failures: vec![],
+        tag_failures: vec![],
+        publish_failures: vec![],
+        held_back: HashSet::new(),
+        retried: HashMap::new(),
+    };
+    for sel_crate in selected_crates_order.iter().copied() {
+        run.publish_selected(sel_crate)?;
+    }
+    // Only --continue-on-error gets past failures, so the crates which failed
+    // to publish are attempted once more, along with those held back.
+    if !run.publish_failures.is_empty() {
+        info!(
+            "Attempting the crates which failed to publish once more: {}",
+            run.publish_failures
+                .iter()
+                .map(|failure| failure.krate.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        run.retried = run
+            .publish_failures
+            .drain(..)
+            .map(|failure| (failure.krate, failure.version))
+            .collect();
+        run.held_back.clear();
+        for sel_crate in selected_crates_order.iter().copied() {
+            run.publish_selected(sel_crate)?;
+        }
+    }
+    let PublishRun {
+        crates,
+        run_state,
+        processed_crates,
+        pending_crates,
+        releases,
+        mirror_failures,
+        owner_failures,
+        tag_failures,
+        publish_failures,
+        held_back,
+        ..
+    } = run;
+
+    if opts.dry_run {
+        if releases.is_empty() {
+            info!("The dry run would not have published any crate");
+        } else {
+            log_summary("The dry run would have published", &releases, &config);
+        }
+        if opts.yank_superseded_prereleases {
+            yank::yank_superseded_prereleases(
+                ctx,
+                &opts.root,
+                &published_versions(&releases),
+                &opts.yank_prerelease_kind,
+                true,
+            )?;
+        }
+        return Ok(releases);
+    }
+
+    wait_until_published(&crates, pending_crates, &opts)?;
+    if !publish_failures.is_empty() {
+        let skipped = publish_order
+            .iter()
+            .filter(|krate| {
+                held_back.contains(*krate)
+                    && !publish_failures
+                        .iter()
+                        .any(|failure| &failure.krate == *krate)
+            })
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "These crates could not be published, even when they were attempted once more:\n{}{}",
+            publish_failures
+                .iter()
+                .map(|failure| format!(
+                    "{} {}: {:#}",
+                    failure.krate, failure.version, failure.error
+                ))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            if skipped.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "\nThe crates depending on them were not published either: {}",
+                    skipped.join(", ")
+                )
+            }
+        );
+    }
+    RunState::finish(&ctx.git, &opts.root)?;
+    record_step(ctx, &opts, journal::Step::Finished)?;
+    if opts.squash_checkpoints {
+        if let Some(base) = ctx.git.checkpoints_base(&opts.root)? {
+            ctx.git.squash(
+                &opts.root,
+                &base,
+                opts.release_commit_message
+                    .as_deref()
+                    .unwrap_or(DEFAULT_RELEASE_COMMIT_MESSAGE),
+            )?;
+            info!(
+                "Squashed the checkpoints of the run into {}",
+                ctx.git.head(&opts.root)?
+            );
+        }
+    }
+    if !releases.is_empty() {
+        log_summary("Published", &releases, &config);
+        if opts.notarize {
+            let dir = notarize::notarize(ctx, &opts.root, &run_state, &releases)?;
+            info!("Committed the plan and the report of the run to {dir:?}");
+        }
+    }
+    if opts.yank_superseded_prereleases {
+        let yanked = yank::yank_superseded_prereleases(
+            ctx,
+            &opts.root,
+            &published_versions(&releases),
+            &opts.yank_prerelease_kind,
+            false,
+        )?;
+        if !yanked.is_empty() {
+            info!("Yanked {} superseded pre-releases", yanked.len());
+        }
+    }
+    if !mirror_failures.is_empty() {
+        anyhow::bail!(
+            "The crates were published to {registry}, but publishing to the mirrors failed for:\n{}",
+            mirror_failures
+                .iter()
+                .map(|failure| format!(
+                    "{} {} to {}: {:#}",
+                    failure.krate, failure.version, failure.registry, failure.error
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    if !owner_failures.is_empty() {
+        anyhow::bail!(
+            "The crates were published, but the owners of these new crates could not be added, which `subpub owners --add {}` can retry:\n{}",
+            registry_profile.owners.join(" --add "),
+            owner_failures
+                .iter()
+                .map(|(krate, err)| format!("{krate}: {err:#}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    if !tag_failures.is_empty() {
+        anyhow::bail!(
+            "The crates were published, but these could not be tagged:\n{}",
+            tag_failures
+                .iter()
+                .map(|(krate, err)| format!("{krate}: {err:#}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    if opts.post_check {
+        let default_commands = ["check".to_string()];
+        post_check::post_check(
+            ctx,
+            &opts.root,
+            &crates,
+            &publish_order,
+            &processed_crates,
+            &releases
+                .iter()
+                .map(|release| release.krate.clone())
+                .collect::<Vec<_>>(),
+            &PostCheck {
+                scope: opts.post_check_scope.unwrap_or(PostCheckScope::Workspace),
+                commands: if opts.post_check_command.is_empty() {
+                    &default_commands
+                } else {
+                    &opts.post_check_command
+                },
+                features: &opts.post_check_features,
+                all_features: opts.post_check_all_features,
+            },
+        )?;
+    }
+
+    if !opts.skip_consumers && !releases.is_empty() {
+        consumers::check_consumers(ctx, &config.consumers, &releases)?;
+    }
+
+    Ok(releases)
+}
+
+/// Dry runs are undone, so there's nothing for the journal to recover.
+fn record_step(ctx: &Context, opts: &PublishOpts, step: journal::Step) -> anyhow::Result<()> {
+    if opts.dry_run {
+        Ok(())
+    } else {
+        journal::record(&ctx.git, &opts.root, step)
+    }
+}
+
+/// What [`publish_crates`] works with while it goes through the selected
+/// crates, and what it found out along the way.
+struct PublishRun<'a> {
+    ctx: &'a Context,
+    opts: &'a PublishOpts,
+    config: &'a Config,
+    provenance: &'a Option<Provenance>,
+    doc_files: &'a [PathBuf],
+    train: Option<(&'a String, &'a train::Train)>,
+    fast_path: &'a Option<String>,
+    publish_order: &'a [String],
+    crate_levels: &'a HashMap<String, usize>,
+    selected_crates: &'a [String],
+    registry: String,
+    operator: Option<String>,
+    registry_profile: &'a RegistryProfile,
+    crates: Crates,
+    run_state: RunState,
+    processed_crates: HashSet<String>,
+    pending_crates: Vec<(String, semver::Version, usize)>,
+    rate_limit: Option<RateLimit>,
+    mirror_rate_limits: HashMap<String, RateLimit>,
+    // Crates which were published, or would have been by a dry run.
+    releases: Vec<PlannedRelease>,
+    mirror_failures: Vec<MirrorFailure>,
+    // New crates whose owners from the registry profile couldn't be added.
+    owner_failures: Vec<(String, anyhow::Error)>,
+    // Published crates which couldn't be tagged.
+    tag_failures: Vec<(String, anyhow::Error)>,
+    // With --continue-on-error, the crates which fail to publish are held back
+    // along with the crates depending on them, and attempted once more after
+    // the other crates.
+    publish_failures: Vec<PublishFailure>,
+    held_back: HashSet<String>,
+    // The crates attempted once more, with the versions they were bumped to.
+    retried: HashMap<String, semver::Version>,
+}
+
+impl PublishRun<'_> {
+    /// Publish what a selected crate needs published, starting with the
+    /// workspace crates it depends on.
+    fn publish_selected(&mut self, sel_crate: &str) -> anyhow::Result<()> {
+        let PublishRun {
+            ctx,
+            opts,
+            publish_order,
+            train,
+            crates,
+            processed_crates,
+            ..
+        } = self;
+        let span = span!(Level::INFO, "_", crate = sel_crate);
+        let _enter = span.enter();
+
+        if processed_crates.contains(sel_crate) {
+            info!("Crate was already processed",);
+            return Ok(());
+        }
+
+        info!("Processing crate");
+
+        with_save_checkpoint(&ctx.git, &opts.root, || -> anyhow::Result<()> {
+            let details = crates
+                .details
+                .get(sel_crate)
+                .with_context(|| format!("Crate not found: {sel_crate}"))?;
+            for krate in publish_order.iter() {
+                if krate == sel_crate {
+                    break;
+                }
+                let crate_details = crates
+                    .details
+                    .get(krate)
+                    .with_context(|| format!("Crate details not found for crate: {krate}"))?;
+                details.write_dependency_version(ctx, krate, &crate_details.version)?;
+                crates.write_workspace_dependency_version(krate, &crate_details.version)?;
+            }
+            Ok(())
+        })??;
+
+        let mut crates_to_publish = crates.what_needs_publishing(sel_crate, publish_order)?;
+        if let Some((_, train)) = train {
+            // The dependencies which aren't part of the train were validated
+            // to be published already.
+            crates_to_publish.retain(|krate| train.contains_key(krate));
+        }
+
+        if crates_to_publish.is_empty() {
+            info!("Crate does not need to be published");
+            return Ok(());
+        } else if crates_to_publish.len() == 1 {
+            info!("Publishing crate {}", crates_to_publish[0])
+        } else {
+            info!(
+                "Crates will be processed in the following order for publishing {sel_crate}: {}",
+                crates_to_publish
+                    .iter()
+                    .map(|krate| (krate).into())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+
+        for krate in crates_to_publish {
+            self.publish_crate(krate)?;
+        }
+
+        if !self.held_back.contains(sel_crate) {
+            self.processed_crates.insert(sel_crate.into());
+        }
+        Ok(())
+    }
+
+    /// Bump, publish and tag a crate if it needs publishing, then update the
+    /// requirements of the workspace on it.
+    fn publish_crate(&mut self, krate: String) -> anyhow::Result<()> {
+        let PublishRun {
+            ctx,
+            opts,
+            config,
+            provenance,
+            doc_files,
+            train,
+            fast_path,
+            crate_levels,
+            selected_crates,
+            registry,
+            operator,
+            registry_profile,
+            crates,
+            run_state,
+            processed_crates,
+            pending_crates,
+            rate_limit,
+            mirror_rate_limits,
+            releases,
+            mirror_failures,
+            owner_failures,
+            tag_failures,
+            publish_failures,
+            held_back,
+            retried,
+            ..
+        } = self;
+        if processed_crates.contains(&krate) {
+            info!("Crate {krate} was already processed",);
+            return Ok(());
+        }
+        if held_back.contains(&krate) {
+            return Ok(());
+        }
+        let held_back_dep = crates.details.get(&krate).and_then(|details| {
+            details
+                .deps_relevant_during_publish()
+                .find(|dep| held_back.contains(*dep))
+        });
+        if let Some(dep) = held_back_dep {
+            info!("Holding {krate} back, since {dep} could not be published");
+            held_back.insert(krate.clone());
+            return Ok(());
+        }
+        interrupt::check()?;
+
+        if let Some(details) = crates.details.get(&krate) {
+            events::emit(events::Event::CrateStarted {
+                krate: &krate,
+                version: &details.version,
+            });
+        }
+
+        for edge in config
+            .break_cycle
+            .iter()
+            .filter(|edge| edge.krate == krate && !processed_crates.contains(&edge.dependency))
+        {
+            // The dependency is published after this crate, so the version
+            // this crate refers to has to be available already.
+            let dep_details = crates
+                .details
+                .get(&edge.dependency)
+                .with_context(|| format!("Crate not found: {}", edge.dependency))?;
+            if !ctx
+                .registry
+                .crate_versions(&edge.dependency)?
+                .contains(&dep_details.version)
+            {
+                anyhow::bail!(
+                    "Crate {krate} is published before {} because the cycle was broken at \"{edge}\", but {} {} is not published yet, so {krate} would refer to a version which does not exist.",
+                    edge.dependency,
+                    edge.dependency,
+                    dep_details.version
+                );
+            }
+        }
+
+        let level = *crate_levels
+            .get(&krate)
+            .with_context(|| format!("Crate level not found for crate: {krate}"))?;
+        let (lower_crates, other_crates) = std::mem::take(pending_crates)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, _, pending_level)| *pending_level < level);
+        *pending_crates = other_crates;
+        wait_until_published(crates, lower_crates, opts)?;
+
+        let breaking_requirements = crates.breaking_requirement_changes(&krate)?;
+        let last_version = {
+            let details = crates
+                .details
+                .get_mut(&krate)
+                .with_context(|| format!("Crate not found: {krate}"))?;
+            let previous_version = details.version.clone();
+            let prev_versions = ctx.registry.crate_versions(&krate)?;
+            let first_release = prev_versions.is_empty();
+            let latest_version = prev_versions.iter().max().cloned();
+            // During a dry run, dependencies which would have been
+            // published aren't available, so the crate can't be packaged
+            // to compare it against the registry.
+            let unpublished_dep = released_dependency(details, releases, opts.propagate);
+            let reason = match unpublished_dep {
+                Some(dep) if opts.dry_run => {
+                    info!("Assuming {krate} needs to be published since {dep} would be published");
+                    Some(format!("depends on {dep}, which would be published"))
+                }
+                _ => details
+                    .needs_publishing(ctx, &opts.root, &prev_versions, opts.compare_contents)?
+                    .then(|| {
+                        if prev_versions.contains(&details.version) {
+                            format!("changed since {} was published", details.version)
+                        } else {
+                            format!("{} is not published", details.version)
+                        }
+                    }),
+            };
+            if let Some(reason) = reason {
+                if !selected_crates.contains(&krate) {
+                    freeze::check_freezes(
+                        &config.freeze,
+                        [&krate],
+                        opts.override_freeze.as_deref(),
+                    )?;
+                }
+                let api_changes = match &latest_version {
+                    Some(latest_version) if opts.semver_checks && train.is_none() => {
+                        let changes = external::semver_checks::check(
+                            ctx,
+                            &krate,
+                            &details.toml_path,
+                            latest_version,
+                        )?;
+                        info!(
+                            "The API of {krate} changed since {latest_version} in a way which needs a {} release",
+                            changes.required
+                        );
+                        for failure in &changes.failures {
+                            info!(
+                                "{krate} failed {}: {}, in {}",
+                                failure.check,
+                                failure.summary,
+                                failure.items.join("; ")
+                            );
+                        }
+                        Some(changes)
+                    }
+                    _ => None,
+                };
+                // Without comparing APIs, every change is assumed to be
+                // breaking.
+                let required = api_changes
+                    .as_ref()
+                    .map_or(Bump::Major, |changes| changes.required);
+                let forced = version::forced_bump(&opts.bump, &krate);
+                if let Some((name, _)) = train {
+                    if prev_versions.contains(&details.version) {
+                        anyhow::bail!("Crate {krate} is pinned to {} by train {name}, but that version is already published with different contents.", details.version);
+                    }
+                } else if let Some(version) = retried.get(&krate) {
+                    info!("Attempting to publish {krate} {version} once more");
+                } else {
+                    let next_version = match forced {
+                        Some(forced) => version::maybe_bump_to_level(
+                            prev_versions,
+                            details.version.clone(),
+                            forced,
+                        ),
+                        None => version::maybe_bump_for_change(
+                            prev_versions,
+                            details.version.clone(),
+                            required,
+                        ),
+                    };
+                    // Judged by Cargo's rules rather than by the level, since
+                    // e.g. a forced minor release of 0.2.3 is a breaking one.
+                    if let (Some(forced), Some(changes), Some(latest_version)) =
+                        (forced, &api_changes, &latest_version)
+                    {
+                        let to = next_version.as_ref().unwrap_or(&details.version);
+                        if !version::is_enough_for_change(latest_version, to, changes.required) {
+                            warn!("Bumping {krate} for a {forced} release as forced by --bump, although its API changes need a {} release", changes.required);
+                        }
+                    }
+                    if let Some(to) = next_version {
+                        record_step(
+                            ctx,
+                            opts,
+                            journal::Step::Bump {
+                                krate: krate.clone(),
+                                from: details.version.clone(),
+                                to: to.clone(),
+                            },
+                        )?;
+                        with_save_checkpoint(&ctx.git, &opts.root, || details.bump_version(to))??;
+                    }
+                }
+                // A dependency the crate might expose changed incompatibly,
+                // so a patch release isn't enough.
+                if let (Some(latest_version), Some(dep)) =
+                    (&latest_version, breaking_requirements.first())
+                {
+                    if !version::is_at_least(latest_version, &details.version, Bump::Minor) {
+                        let required = version::bump(latest_version.clone(), Bump::Minor);
+                        if let Some((name, _)) = train {
+                            anyhow::bail!("Crate {krate} is pinned to {} by train {name}, but its requirement on {dep} changes in a breaking way, which needs at least {required}.", details.version);
+                        }
+                        info!("Bumping crate {krate} from {} to {required} since its requirement on {dep} changes in a breaking way", details.version);
+                        record_step(
+                            ctx,
+                            opts,
+                            journal::Step::Bump {
+                                krate: krate.clone(),
+                                from: details.version.clone(),
+                                to: required.clone(),
+                            },
+                        )?;
+                        with_save_checkpoint(&ctx.git, &opts.root, || {
+                            details.write_own_version(required)
+                        })??;
+                    }
+                }
+                let last_version = details.version.clone();
+                if let Some(problem) = checks::check_placeholder_version(
+                    &krate,
+                    &last_version,
+                    first_release,
+                    opts.allow_placeholder_version.contains(&krate)
+                        || config.crate_settings(&krate).allow_placeholder_version,
+                    opts.snapshot,
+                ) {
+                    anyhow::bail!(problem);
+                }
+                if opts.generate_changelog {
+                    with_save_checkpoint(&ctx.git, &opts.root, || {
+                        changelog::update_changelog(
+                            ctx,
+                            &opts.root,
+                            details,
+                            &config.changelog,
+                            &last_version,
+                            latest_version.as_ref(),
+                            opts.tag_format.as_deref(),
+                            opts.release_commit_message
+                                .as_deref()
+                                .unwrap_or(DEFAULT_RELEASE_COMMIT_MESSAGE),
+                        )
+                    })??;
+                }
+                if opts.require_changelog {
+                    if let Some(problem) =
+                        checks::check_changelog(details, &config.changelog, &last_version)?
+                    {
+                        anyhow::bail!(problem);
+                    }
+                }
+                let dependency_only = match &latest_version {
+                    Some(latest_version) => {
+                        // Only an annotation, so it doesn't stop the run.
+                        let dependency_only = details
+                            .only_dependencies_changed(ctx, latest_version)
+                            .unwrap_or_else(|err| {
+                                warn!("Cannot tell whether {krate} only changed in its dependencies: {err:#}");
+                                false
+                            });
+                        if dependency_only {
+                            info!("Crate {krate} only changed in its dependencies since {latest_version} was published");
+                        }
+                        dependency_only
+                    }
+                    None => false,
+                };
+                releases.push(PlannedRelease {
+                    krate: krate.clone(),
+                    version: previous_version,
+                    next_version: last_version.clone(),
+                    reason,
+                    dependency_only,
+                    api_changes,
+                    group: groups::group_of(&config.groups, &opts.root, details).map(String::from),
+                });
+                if opts.bump_doc_examples {
+                    let changed = with_save_checkpoint(&ctx.git, &opts.root, || {
+                        doc_examples::bump_doc_examples(doc_files, &krate, &last_version)
+                    })??;
+                    if !changed.is_empty() {
+                        info!(
+                            "Updated the examples of {krate} to {last_version} in {}",
+                            changed
+                                .iter()
+                                .map(|file| file
+                                    .strip_prefix(&opts.root)
+                                    .unwrap_or(file)
+                                    .display()
+                                    .to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+                }
+                if opts.self_check {
+                    self_check::check_before_publishing(
+                        crates,
+                        &krate,
+                        latest_version.as_ref(),
+                        processed_crates,
+                    )?;
+                }
+                if opts.dry_run {
+                    info!("Would publish {krate} {last_version}");
+                    for mirror in mirrors(opts) {
+                        info!("Would publish {krate} {last_version} to {mirror}");
+                    }
+                    if opts.tag || opts.push_tags.is_some() {
+                        info!(
+                            "Would tag {krate} {last_version} as {}",
+                            tags::tag_name(opts.tag_format.as_deref(), &krate, &last_version)
+                        );
+                    }
+                } else {
+                    if let Some(rate_limit) = rate_limit {
+                        rate_limit.wait(&krate);
+                    }
+                    let crate_settings = config.crate_settings(&krate);
+                    let verify_targets = crate_settings
+                        .verify_targets
+                        .clone()
+                        .or_else(|| {
+                            crates
+                                .details
+                                .get(&krate)
+                                .and_then(|details| details.verify.targets.clone())
+                        })
+                        .unwrap_or_else(|| opts.verify_target.clone());
+                    record_step(
+                        ctx,
+                        opts,
+                        journal::Step::PublishAttempt {
+                            krate: krate.clone(),
+                            version: last_version.clone(),
+                            commit: ctx.git.head(&opts.root)?,
+                        },
+                    )?;
+                    let result = crates.strip_dev_deps_and_publish(
+                        &krate,
+                        provenance.as_ref(),
+                        opts.allow_dirty || crate_settings.allow_dirty,
+                        &verify_targets,
+                        opts.publish_attempts.unwrap_or(DEFAULT_PUBLISH_ATTEMPTS),
+                    );
+                    if let Err(err) = result {
+                        if !opts.continue_on_error || interrupt::interrupted() {
+                            return Err(err);
+                        }
+                        error!("Could not publish {krate} {last_version}: {err:#}");
+                        // Undo what was changed for packaging the crate,
+                        // which is packaged again when it's attempted once
+                        // more.
+                        ctx.git.checkpoint_revert(&opts.root)?;
+                        releases.retain(|release| release.krate != krate);
+                        held_back.insert(krate.clone());
+                        publish_failures.push(PublishFailure {
+                            krate: krate.clone(),
+                            version: last_version,
+                            error: err,
+                        });
+                        events::finish_crate();
+                        return Ok(());
+                    }
+                    let commit = ctx.git.head(&opts.root)?;
+                    record_step(
+                        ctx,
+                        opts,
+                        journal::Step::Published {
+                            krate: krate.clone(),
+                            version: last_version.clone(),
+                            commit: commit.clone(),
+                        },
+                    )?;
+                    history::record(
+                        &ctx.git,
+                        &opts.root,
+                        &history::Release {
+                            krate: krate.clone(),
+                            version: last_version.clone(),
+                            registry: registry.clone(),
+                            date: freeze::Date::today(),
+                            commit: commit.clone(),
+                            operator: operator.clone(),
+                        },
+                    )?;
+                    run_state.published.push(PublishedCrate {
+                        krate: krate.clone(),
+                        version: last_version.clone(),
+                        commit: commit.clone(),
+                    });
+                    run_state.save(&ctx.git, &opts.root)?;
+                    events::emit(events::Event::Published {
+                        krate: &krate,
+                        version: &last_version,
+                    });
+                    pending_crates.push((krate.clone(), last_version.clone(), level));
+
+                    if first_release && !registry_profile.owners.is_empty() {
+                        if let Err(err) =
+                            owners::add_initial_owners(ctx, &krate, &registry_profile.owners)
+                        {
+                            error!("Could not add the owners of {krate}: {err:#}");
+                            owner_failures.push((krate.clone(), err));
+                        }
+                    }
+
+                    if opts.tag || opts.push_tags.is_some() {
+                        if let Err(err) = tag_release(ctx, opts, &krate, &last_version, &commit) {
+                            error!("Could not tag {krate} {last_version}: {err:#}");
+                            tag_failures.push((krate.clone(), err));
+                        }
+                    }
+
+                    for mirror in mirrors(opts) {
+                        let missing_dep = crates.details.get(&krate).and_then(|details| {
+                            details.deps_to_publish().find(|dep| {
+                                mirror_failures.iter().any(|failure| {
+                                    &failure.krate == *dep && &failure.registry == mirror
+                                })
+                            })
+                        });
+                        let result = match missing_dep {
+                            Some(dep) => Err(anyhow!("{dep} could not be published to it")),
+                            None => {
+                                if let Some(rate_limit) = mirror_rate_limits.get_mut(mirror) {
+                                    rate_limit.wait(&format!("{krate} to {mirror}"));
+                                }
+                                publish_to_mirror(
+                                    crates,
+                                    &krate,
+                                    &last_version,
+                                    mirror,
+                                    config,
+                                    provenance.as_ref(),
+                                    opts,
+                                )
+                            }
+                        };
+                        match result {
+                            Ok(()) => history::record(
+                                &ctx.git,
+                                &opts.root,
+                                &history::Release {
+                                    krate: krate.clone(),
+                                    version: last_version.clone(),
+                                    registry: mirror.clone(),
+                                    date: freeze::Date::today(),
+                                    commit: ctx.git.head(&opts.root)?,
+                                    operator: operator.clone(),
+                                },
+                            )?,
+                            Err(err) => {
+                                error!(
+                                    "Could not publish {krate} {last_version} to {mirror}: {err:#}"
+                                );
+                                mirror_failures.push(MirrorFailure {
+                                    krate: krate.clone(),
+                                    version: last_version.clone(),
+                                    registry: mirror.clone(),
+                                    error: err,
+                                });
+                            }
+                        }
+                    }
+                }
+                last_version
+            } else {
+                info!("Crate {krate} does not need to be published");
+                details.version.clone()
+            }
+        };
+
+        // Nothing can depend on crates which only have binary targets
+        let is_bin_only = crates
+            .details
+            .get(&krate)
+            .map(|details| details.is_bin_only())
+            .unwrap_or(false);
+        // On the fast path, the requirements of the workspace on the crate
+        // only have to be updated if its version was bumped.
+        let bumped = releases
+            .iter()
+            .any(|release| release.krate == krate && release.version != release.next_version);
+        if !is_bin_only && (fast_path.is_none() || bumped) {
+            record_step(
+                ctx,
+                opts,
+                journal::Step::Requirements {
+                    krate: krate.clone(),
+                    version: last_version.clone(),
+                },
+            )?;
+            with_save_checkpoint(&ctx.git, &opts.root, || {
+                crates.write_dependency_version(&krate, &last_version)
+            })??;
+        }
+
+        if !opts.dry_run {
+            run_state.processed.push(krate.clone());
+            run_state.save(&ctx.git, &opts.root)?;
+            record_step(
+                ctx,
+                opts,
+                journal::Step::Processed {
+                    krate: krate.clone(),
+                },
+            )?;
+        }
+        processed_crates.insert(krate);
+        events::finish_crate();
+        Ok(())
+    }
+}
+
+/// The train `--train` names, if any.
+fn train_of<'a>(
+    opts: &'a PublishOpts,
+    config: &'a Config,
+) -> anyhow::Result<Option<(&'a String, &'a train::Train)>> {
+    opts.train
+        .as_ref()
+        .map(|name| {
+            config
+                .trains
+                .get(name)
+                .map(|train| (name, train))
+                .with_context(|| format!("Train {name} is not defined in {}", config::CONFIG_FILE))
+        })
+        .transpose()
+}
+
+/// The crates the options select, in no particular order: those of the train
+/// or the plan, those given with `--crate` along with their dependents if
+/// asked to, or else every crate which can be published and isn't excluded.
+fn select_crates(
+    opts: &PublishOpts,
+    crates: &Crates,
+    train: Option<(&String, &train::Train)>,
+    plan: Option<&Plan>,
+    publish_order: &[String],
+    exclude: &[String],
+) -> anyhow::Result<Vec<String>> {
+    if let Some((_, train)) = train {
+        return Ok(train.keys().cloned().collect());
+    }
+    if let Some(plan) = plan {
+        for krate in &plan.crates {
+            if !crates.details.contains_key(krate) {
+                anyhow::bail!("Crate {krate} of the plan was not found in the workspace");
+            }
+        }
+        return Ok(plan.crates.clone());
+    }
+    if opts.crates.is_empty() {
+        return publish_order
+            .iter()
+            .filter(|krate| !exclude.contains(krate))
+            .filter_map(|krate| match crates.details.get(krate) {
+                Some(details) => details.should_be_published.then(|| Ok(krate.clone())),
+                None => Some(Err(anyhow!("Crate not found: {}", krate))),
+            })
+            .collect();
+    }
+
+    let mut input_crates = opts.crates.clone();
+    if opts.include_crates_dependents {
+        let kinds = if opts.dependents_kind.is_empty() {
+            vec![
+                DependencyKind::Normal,
+                DependencyKind::Build,
+                DependencyKind::Dev,
+            ]
+        } else {
+            opts.dependents_kind.clone()
+        };
+        let graph = crates.dependency_graph(|details| {
+            kinds.iter().flat_map(move |kind| match kind {
+                DependencyKind::Normal => details.deps.iter(),
+                DependencyKind::Build => details.build_deps.iter(),
+                DependencyKind::Dev => details.dev_deps.iter(),
+            })
+        });
+        let dependents = ordering::dependents(&graph, &opts.crates, opts.dependents_depth);
+        for krate in ordering::order_selection(publish_order, &Vec::from_iter(dependents)) {
+            let details = crates
+                .details
+                .get(krate)
+                .with_context(|| format!("Crate not found: {krate}"))?;
+            if exclude.contains(krate) || !details.should_be_published {
+                continue;
+            }
+            info!("Including {krate} because it depends on the selected crates");
+            input_crates.push(krate.clone());
+        }
+    }
+    Ok(input_crates)
+}
+
+/// The crates and the versions they were published at.
+fn published_versions(releases: &[PlannedRelease]) -> Vec<(String, semver::Version)> {
+    releases
+        .iter()
+        .map(|release| (release.krate.clone(), release.next_version.clone()))
+        .collect()
+}
+
+/// The name the crates published with `--unified-version` go by in messages
+/// about the train they form.
+const UNIFIED_VERSION_TRAIN: &str = "unified-version";
+
+/// Make sure that the crates `--bump` forces a level for are in the workspace,
+/// so that a typo doesn't go unnoticed.
+fn check_bump_overrides(opts: &PublishOpts, crates: &Crates) -> anyhow::Result<()> {
+    for bump_override in &opts.bump {
+        if let Some(krate) = &bump_override.krate {
+            if !crates.details.contains_key(krate) {
+                anyhow::bail!(
+                    "--bump {bump_override} is given for {krate}, which is not a crate of the workspace"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How `profile` limits the rate of publishes to `registry`, if it does.
+fn registry_rate_limit(
+    registry: &str,
+    profile: &RegistryProfile,
+) -> anyhow::Result<Option<RateLimit>> {
+    if profile
+        .publish_rate
+        .is_some_and(|per_minute| per_minute <= 0.0)
+    {
+        anyhow::bail!("The publish-rate of {registry} has to be positive");
+    }
+    Ok(profile
+        .publish_rate
+        .map(|per_minute| RateLimit::new(per_minute, profile.publish_burst.unwrap_or(1))))
+}
+
+/// The registries crates are mirrored to: those given after the first
+/// `--registry`.
+fn mirrors(opts: &PublishOpts) -> &[String] {
+    opts.registry.get(1..).unwrap_or_default()
+}
+
+/// A crate which couldn't be published with `--continue-on-error`, along with
+/// the version it was bumped to.
+struct PublishFailure {
+    krate: String,
+    version: semver::Version,
+    error: anyhow::Error,
+}
+
+/// A crate which was published to the target registry, but not to a mirror.
+struct MirrorFailure {
+    krate: String,
+    version: semver::Version,
+    registry: String,
+    error: anyhow::Error,
+}
+
+/// Tag the commit a crate was published from, and push the tag with
+/// `--push-tags`.
+fn tag_release(
+    ctx: &Context,
+    opts: &PublishOpts,
+    krate: &str,
+    version: &semver::Version,
+    commit: &str,
+) -> anyhow::Result<()> {
+    let tag = tags::tag_name(opts.tag_format.as_deref(), krate, version);
+    journal::record(
+        &ctx.git,
+        &opts.root,
+        journal::Step::Tag { tag: tag.clone() },
+    )?;
+    ctx.git
+        .tag(&opts.root, &tag, commit, &format!("{krate} {version}"))?;
+    info!("Tagged {krate} {version} as {tag}");
+    if let Some(remote) = &opts.push_tags {
+        journal::record(
+            &ctx.git,
+            &opts.root,
+            journal::Step::PushTag {
+                tag: tag.clone(),
+                remote: remote.clone(),
+            },
+        )?;
+        ctx.git.push_tag(&opts.root, remote, &tag)?;
+        info!("Pushed {tag} to {remote}");
+    }
+    Ok(())
+}
+
+/// Publish a crate which was just published to the target registry to a
+/// mirror, then wait until the mirror can resolve it, as set by `index` or
+/// `delay` under `[registries.<mirror>]`, since the crates depending on it are
+/// published to the mirror next.
+fn publish_to_mirror(
+    crates: &Crates,
+    krate: &str,
+    version: &semver::Version,
+    mirror: &str,
+    config: &Config,
+    provenance: Option<&Provenance>,
+    opts: &PublishOpts,
+) -> anyhow::Result<()> {
+    let ctx = crates.context();
+    info!("Publishing {krate} {version} to {mirror}");
+    crates.publish_to_mirror(
+        krate,
+        mirror,
+        provenance,
+        opts.allow_dirty || config.crate_settings(krate).allow_dirty,
+        opts.publish_attempts.unwrap_or(DEFAULT_PUBLISH_ATTEMPTS),
+    )?;
+    let profile = config.registry_profile_of(mirror);
+    if let Some(index) = &profile.index {
+        // The index of a private mirror is read with the mirror's own token,
+        // never with the one of the target registry.
+        let token = ctx.cargo.registry_token(mirror)?;
+        external::crates_io::wait_until_in_index(
+            index,
+            token.as_deref(),
+            krate,
+            version,
+            Duration::from_secs(opts.publish_timeout.unwrap_or(DEFAULT_PUBLISH_TIMEOUT)),
+        )?;
+    }
+    if let Some(delay) = profile.delay {
+        std::thread::sleep(Duration::from_secs(delay));
+    }
+    Ok(())
+}
+
+/// Make sure that the crates `--start-from` skips don't need publishing, since
+/// the crates after them would otherwise be published against versions which
+/// aren't on the registry. Unless `--ack-skipped` is given, the run is aborted
+/// if any of them changed.
+fn check_skipped_crates(
+    crates: &Crates,
+    skipped_crates: &[String],
+    opts: &PublishOpts,
+) -> anyhow::Result<()> {
+    let ctx = crates.context();
+    let mut changed = vec![];
+    for krate in skipped_crates {
+        let details = crates
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?;
+        if !details.should_be_published {
+            continue;
+        }
+        let prev_versions = ctx.registry.crate_versions(krate)?;
+        if !prev_versions.contains(&details.version) {
+            changed.push(format!("{krate} ({} is not published)", details.version));
+            continue;
+        }
+        match details.needs_publishing(ctx, &opts.root, &prev_versions, opts.compare_contents) {
+            Ok(false) => {}
+            Ok(true) => changed.push(format!(
+                "{krate} (changed since {} was published)",
+                details.version
+            )),
+            Err(err) => changed.push(format!(
+                "{krate} (could not be compared against the registry: {err})"
+            )),
+        }
+    }
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "--start-from {} skips crates which need publishing: {}",
+        opts.start_from.as_deref().unwrap_or_default(),
+        changed.join(", ")
+    );
+    if !opts.ack_skipped {
+        anyhow::bail!("{message}. Use --resume to continue a run which failed, or pass --ack-skipped to publish without them anyway.");
+    }
+    warn!("{message}. Publishing without them since --ack-skipped was given, so crates depending on them might be published against versions which aren't on the registry.");
+    Ok(())
+}
+
+/// Log the releases of a run, split into the configured groups if there are
+/// any.
+fn log_summary(what: &str, releases: &[PlannedRelease], config: &Config) {
+    let describe = |releases: &[&PlannedRelease]| {
+        releases
+            .iter()
+            .map(|release| {
+                let mut description = if release.version == release.next_version {
+                    format!("{} {}", release.krate, release.next_version)
+                } else {
+                    format!(
+                        "{} {} (bumped from {})",
+                        release.krate, release.next_version, release.version
+                    )
+                };
+                if release.dependency_only {
+                    description.push_str(" (dependency updates only)");
+                }
+                description
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if config.groups.is_empty() {
+        info!("{what}: {}", describe(&releases.iter().collect::<Vec<_>>()));
+        return;
+    }
+    for (group, releases) in groups::group_by(releases, |release| release.group.as_deref()) {
+        info!(
+            "{what}, {group} ({}): {}",
+            releases.len(),
+            describe(&releases)
+        );
+    }
+}
+
+/// Group the crates of the workspace into levels, where the crates of each
+/// level only depend on crates of the levels before it, after breaking the
+/// cycles given by the `break-cycle` setting. Fails with the dependency
+/// cycles which are left, if any.
+pub fn publish_levels(crates: &Crates, config: &Config) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut graph = crates.dependency_graph(CrateDetails::deps_relevant_during_publish);
+    ordering::break_edges(&mut graph, &config.break_cycle)?;
+    let publish_levels = ordering::publish_levels(&graph);
+
+    let unordered = ordering::explain_unordered(&graph, &publish_levels.concat());
+    if !unordered.cycles.is_empty() {
+        let mut message = format!(
+            "Failed to determine publish order because of dependency cycles: {}.",
+            unordered
+                .cycles
+                .iter()
+                .map(|cycle| cycle.join(" -> "))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if !unordered.blocked.is_empty() {
+            message.push_str(&format!(
+                " These crates can't be ordered either, since they depend on crates of the cycles: {}.",
+                unordered
+                    .blocked
+                    .iter()
+                    .map(|(krate, dep)| format!("{krate} (depends on {dep})"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        let cycle = &unordered.cycles[0];
+        message.push_str(&format!(
+            " A cycle can be broken with the \"break-cycle\" setting of {}, e.g. break-cycle = [\"{} -> {}\"].",
+            config::CONFIG_FILE,
+            cycle[cycle.len() - 2],
+            cycle[cycle.len() - 1]
+        ));
+        anyhow::bail!(message);
+    }
+
+    Ok(publish_levels)
+}
+
+/// The crate to publish without ordering the whole workspace, if a single
+/// crate is selected with --crate and every workspace crate it depends on is
+/// published at its current version, so that nothing else has to be published
+/// before it.
+fn single_crate_fast_path(opts: &PublishOpts, crates: &Crates) -> anyhow::Result<Option<String>> {
+    let krate = match opts.crates.as_slice() {
+        [krate] => krate,
+        _ => return Ok(None),
+    };
+    if opts.include_crates_dependents
+        || opts.resume
+        || opts.start_from.is_some()
+        || opts.train.is_some()
+        || opts.from_plan.is_some()
+        || opts.unified_version.is_some()
+    {
+        return Ok(None);
+    }
+    let details = match crates.details.get(krate) {
+        Some(details) => details,
+        None => return Ok(None),
+    };
+    let ctx = crates.context();
+    for dep in details.deps_relevant_during_publish() {
+        let dep_details = crates
+            .details
+            .get(dep)
+            .with_context(|| format!("Crate not found: {dep}"))?;
+        if !ctx
+            .registry
+            .crate_versions(dep)?
+            .contains(&dep_details.version)
+        {
+            return Ok(None);
+        }
+    }
+    Ok(Some(krate.clone()))
+}
+
+/// The crates given with --exclude, followed by those of `krates` which are
+/// excluded by the `exclude`, `exclude-kinds` and `skip-bin-only` settings.
+pub fn excluded_crates(
+    crates: &Crates,
+    config: &Config,
+    krates: &[String],
+    exclude: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let mut exclude = exclude.to_vec();
+    for krate in krates {
+        let details = crates
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?;
+        if exclude.contains(krate) {
+            continue;
+        }
+        if config.exclude.contains(krate) || config.crate_settings(krate).exclude {
+            info!("Excluding {krate} because of the exclude setting");
+            exclude.push(krate.clone());
+        } else if let Some(kind) = details
+            .kind
+            .filter(|kind| config.exclude_kinds.contains(kind))
+        {
+            info!("Excluding {krate} because it looks like a {kind} crate");
+            exclude.push(krate.clone());
+        } else if config.skip_bin_only && details.is_bin_only() {
+            info!("Excluding {krate} because it only has binary targets");
+            exclude.push(krate.clone());
+        }
+    }
+    Ok(exclude)
+}
+
+/// Wait until the published crates are available on the registry, and check
+/// the metadata it reports for them if asked to.
+fn wait_until_published(
+    crates: &Crates,
+    published: Vec<(String, semver::Version, usize)>,
+    opts: &PublishOpts,
+) -> anyhow::Result<()> {
+    let published = published
+        .into_iter()
+        .map(|(name, version, _)| (name, version))
+        .collect::<Vec<_>>();
+    crates.wait_until_published(
+        &published,
+        Duration::from_secs(opts.publish_timeout.unwrap_or(DEFAULT_PUBLISH_TIMEOUT)),
+    )?;
+    if opts.check_registry_metadata {
+        for (name, version) in &published {
+            crates.check_published_metadata(name, version)?;
+        }
+    }
+    Ok(())
+}
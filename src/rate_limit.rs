@@ -0,0 +1,65 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keep the rate of publishes under what a registry tolerates, so that a run
+//! doesn't get throttled part way.
+
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// A token bucket: up to `burst` publishes can happen right away, after which
+/// they are spread out to `per_minute`.
+#[derive(Debug)]
+pub struct RateLimit {
+    per_minute: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimit {
+    pub fn new(per_minute: f64, burst: u32) -> RateLimit {
+        let burst = f64::from(burst.max(1));
+        RateLimit {
+            per_minute,
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Wait until the next publish is allowed.
+    pub fn wait(&mut self, what: &str) {
+        self.refill();
+        if self.tokens < 1.0 {
+            let delay = Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / self.per_minute);
+            info!(
+                "Waiting {}s before publishing {what} to stay under the rate limit of the registry",
+                delay.as_secs()
+            );
+            std::thread::sleep(delay);
+            self.refill();
+        }
+        self.tokens -= 1.0;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_minute / 60.0).min(self.burst);
+        self.refilled_at = now;
+    }
+}
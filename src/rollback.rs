@@ -0,0 +1,159 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Undo the last publish run according to its journal, e.g. after it failed
+//! halfway: the commits it made are thrown away, the tags it created are
+//! deleted, and the versions it published can be yanked as well.
+
+use crate::context::Context;
+use crate::journal::{self, Step};
+use crate::run_lock;
+use crate::state::RunState;
+use crate::worktree;
+use crate::yank;
+use semver::Version;
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// What [`rollback`] undid, or would undo with `dry_run`.
+#[derive(Debug, Default)]
+pub struct Rollback {
+    /// The commit the workspace was reset to, if the run made commits in the
+    /// checkout.
+    pub reset_to: Option<String>,
+    /// The branch the run published from in a worktree, which was deleted.
+    pub deleted_branch: Option<String>,
+    /// The tags which were deleted, most recent first.
+    pub deleted_tags: Vec<String>,
+    /// The tags which were deleted from a remote, along with the remote.
+    pub deleted_remote_tags: Vec<(String, String)>,
+    /// The versions which were yanked.
+    pub yanked: Vec<(String, Version)>,
+}
+
+/// Undo what the last run did to the workspace at `root` according to its
+/// journal: the checkout is reset to the commit the run started from, or the
+/// branch of its worktree is deleted, the tags it created and pushed are
+/// deleted, and its state is thrown away so that it isn't resumed. With
+/// `yank`, the versions it published are yanked as well.
+pub fn rollback(ctx: &Context, root: &Path, yank: bool, dry_run: bool) -> anyhow::Result<Rollback> {
+    let _lock = run_lock::lock(&ctx.git, root)?;
+    let steps = journal::load(&ctx.git, root)?;
+    if steps.is_empty() {
+        anyhow::bail!("There is no journal of a previous run to roll back");
+    }
+    if steps.last() == Some(&Step::RolledBack) {
+        anyhow::bail!("The last run was already rolled back");
+    }
+    let (start_commit, worktree_branch) = match steps.iter().find_map(|step| match step {
+        Step::Started {
+            commit,
+            worktree_branch,
+        } => Some((commit.clone(), worktree_branch.clone())),
+        _ => None,
+    }) {
+        Some(started) => started,
+        None => anyhow::bail!("The journal doesn't say which commit the last run started from"),
+    };
+
+    // Check that the checkout can be reset before anything is undone.
+    if worktree_branch.is_none() {
+        if ctx.git.has_changes(root)? {
+            anyhow::bail!(
+                "The workspace at {:?} has uncommitted changes, which rolling back would throw away; commit or stash them first",
+                root.as_os_str()
+            );
+        }
+        if !ctx.git.is_ancestor(root, &start_commit, "HEAD")? {
+            anyhow::bail!(
+                "The history of the workspace at {:?} doesn't go through {start_commit}, where the last run started, anymore",
+                root.as_os_str()
+            );
+        }
+    }
+
+    let mut rollback = Rollback::default();
+    let tags = ctx.git.tags(root)?;
+    let mut seen = HashSet::new();
+    for step in steps.iter().rev() {
+        match step {
+            Step::PushTag { tag, remote } if seen.insert((tag.clone(), Some(remote.clone()))) => {
+                if dry_run {
+                    info!("Would delete {tag} from {remote}");
+                } else if let Err(err) = ctx.git.delete_remote_tag(root, remote, tag) {
+                    // The run might have died before pushing it.
+                    warn!("{err:#}");
+                    continue;
+                } else {
+                    info!("Deleted {tag} from {remote}");
+                }
+                rollback
+                    .deleted_remote_tags
+                    .push((remote.clone(), tag.clone()));
+            }
+            Step::Tag { tag } if tags.contains(tag) && seen.insert((tag.clone(), None)) => {
+                if dry_run {
+                    info!("Would delete {tag}");
+                } else {
+                    ctx.git.delete_tag(root, tag)?;
+                    info!("Deleted {tag}");
+                }
+                rollback.deleted_tags.push(tag.clone());
+            }
+            _ => (),
+        }
+    }
+
+    match worktree_branch {
+        Some(branch) => {
+            if dry_run {
+                info!("Would delete {branch}, which the last run published from");
+                rollback.deleted_branch = Some(branch);
+            } else if worktree::discard(&ctx.git, root, &branch)? {
+                info!("Deleted {branch}, which the last run published from");
+                rollback.deleted_branch = Some(branch);
+            }
+        }
+        None if ctx.git.head(root)? != start_commit => {
+            if dry_run {
+                info!("Would reset the workspace to {start_commit}, where the last run started");
+            } else {
+                ctx.git.reset_hard(root, &start_commit)?;
+                info!("Reset the workspace to {start_commit}, where the last run started");
+            }
+            rollback.reset_to = Some(start_commit);
+        }
+        None => (),
+    }
+
+    if !dry_run && RunState::discard(&ctx.git, root)? {
+        info!("Threw away the state of the last run, so that it isn't resumed");
+    }
+
+    if yank {
+        if yank::journal_published(ctx, root)?.is_empty() {
+            info!("The last run didn't publish anything, so there is nothing to yank");
+        } else {
+            rollback.yanked = yank::yank(ctx, root, &[], None, true, dry_run)?;
+        }
+    }
+
+    if !dry_run {
+        journal::record(&ctx.git, root, Step::RolledBack)?;
+    }
+    Ok(rollback)
+}
@@ -0,0 +1,157 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A lock on the workspace held for as long as subpub changes it, so that two
+//! runs on the same checkout, e.g. by someone and by CI, don't interleave
+//! their checkpoints. It's kept in the git directory, along with the state of
+//! the run, so that checkpoints don't pick it up.
+
+use crate::git::Git;
+use anyhow::Context;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+const LOCK_FILE: &str = "subpub/run.lock";
+
+/// Who holds the lock.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Holder {
+    pid: u32,
+    host: String,
+    /// When the lock was taken, in seconds since the Unix epoch.
+    since: u64,
+    command: String,
+}
+
+impl Holder {
+    fn current() -> Holder {
+        Holder {
+            pid: std::process::id(),
+            host: host_name(),
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    /// Whether the run which took the lock is gone without releasing it,
+    /// e.g. because it was killed. Runs on other hosts can't be checked, so
+    /// they are assumed to go on.
+    fn is_stale(&self) -> bool {
+        self.host == host_name() && !is_running(self.pid)
+    }
+}
+
+/// The lock on a workspace, released when it's dropped.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+/// Lock the workspace at `root`, failing right away if another run holds the
+/// lock. A lock left behind by a run which is gone is taken over.
+pub fn lock<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<RunLock> {
+    let path = git.path(&root, LOCK_FILE)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let holder = Holder::current();
+    // The lock is taken over at most once, so that two runs taking over the
+    // same stale lock don't go on removing each other's.
+    for _ in 0..2 {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string_pretty(&holder)?.as_bytes())
+                    .with_context(|| format!("Cannot write the lock at {:?}", path.as_os_str()))?;
+                return Ok(RunLock { path });
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Cannot create the lock at {:?}", path.as_os_str()))
+            }
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap_or_default();
+        match serde_json::from_str::<Holder>(&contents) {
+            Ok(other) if !other.is_stale() => anyhow::bail!(
+                "Another subpub run holds the lock on the workspace: process {} on {}, started {}s ago with `{}`. Wait for it to finish, or remove {:?} if it's gone.",
+                other.pid,
+                other.host,
+                holder.since.saturating_sub(other.since),
+                other.command,
+                path.as_os_str()
+            ),
+            Ok(other) => warn!(
+                "Taking over the lock left behind by process {}, which is gone",
+                other.pid
+            ),
+            // The lock is written right after it's created, so it can only be
+            // unreadable for a moment, unless its run died in between.
+            Err(_) => anyhow::bail!(
+                "The lock at {:?} is unreadable; remove it if no other subpub run is going on",
+                path.as_os_str()
+            ),
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Cannot remove the lock at {:?}", path.as_os_str()))?;
+    }
+    anyhow::bail!(
+        "Another subpub run took the lock at {:?} over at the same time",
+        path.as_os_str()
+    )
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Cannot remove the lock at {:?}: {err}",
+                self.path.as_os_str()
+            );
+        }
+    }
+}
+
+fn host_name() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_owned())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // Signal 0 only checks whether the process exists; EPERM means it does,
+    // but belongs to someone else.
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
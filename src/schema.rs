@@ -0,0 +1,183 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The JSON schemas of the documents subpub writes for other tools: plans,
+//! the state of runs and their reports. Every document records the version of
+//! its schema, so that tools can tell whether they understand it.
+
+use serde_json::{json, Value};
+
+/// The version of the schemas. It's increased whenever a document changes in
+/// a way which could break the tools reading it, e.g. when a field is removed
+/// or changes meaning; new optional fields don't change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The schema version of documents which don't record one, which were written
+/// before schemas were versioned or by hand.
+pub fn schema_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+/// Make sure that subpub understands a document, i.e. that it wasn't written
+/// by a newer subpub with an incompatible schema.
+pub fn check_version(document: &str, version: u32) -> anyhow::Result<()> {
+    if version > SCHEMA_VERSION {
+        anyhow::bail!(
+            "The {document} was written with version {version} of its schema, but this subpub only understands version {SCHEMA_VERSION}. Upgrade subpub to read it."
+        );
+    }
+    Ok(())
+}
+
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    /// A plan, as printed by `subpub plan` and read by `publish --from-plan`.
+    Plan,
+    /// The report of a run, as committed to releases/ by `--notarize`.
+    Report,
+    /// The state of a run, as kept in `.git/subpub/state.json`.
+    State,
+}
+
+/// The JSON schema of a document.
+pub fn schema(document: Document) -> Value {
+    let (id, title, mut schema) = match document {
+        Document::Plan => ("plan", "subpub plan", plan_schema()),
+        Document::Report => ("report", "subpub run report", run_state_schema()),
+        Document::State => ("state", "subpub run state", run_state_schema()),
+    };
+    schema["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    schema["$id"] = json!(format!("urn:subpub:{id}:v{SCHEMA_VERSION}"));
+    schema["title"] = json!(title);
+    schema
+}
+
+fn plan_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["crates"],
+        "properties": {
+            "schema-version": schema_version_schema(),
+            "crates": {
+                "description": "The crates to publish, in publish order.",
+                "type": "array",
+                "items": { "type": "string" }
+            },
+            "releases": {
+                "description": "Why each crate needs to be published, and at which version.",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["crate", "version", "next-version", "reason"],
+                    "properties": {
+                        "crate": { "type": "string" },
+                        "version": {
+                            "description": "The version of the crate in the workspace.",
+                            "type": "string"
+                        },
+                        "next-version": {
+                            "description": "The version the crate is published at.",
+                            "type": "string"
+                        },
+                        "reason": { "type": "string" },
+                        "dependency-only": {
+                            "description": "Whether the crate only changed in its dependencies since the latest published version.",
+                            "type": "boolean"
+                        },
+                        "api-changes": {
+                            "description": "How the public API of the crate changed since the latest published version, with --semver-checks.",
+                            "type": "object",
+                            "required": ["baseline", "required"],
+                            "properties": {
+                                "baseline": {
+                                    "description": "The published version the API was compared with.",
+                                    "type": "string"
+                                },
+                                "required": {
+                                    "description": "The kind of release the changes need.",
+                                    "enum": ["major", "minor", "patch"]
+                                },
+                                "failures": {
+                                    "type": "array",
+                                    "items": {
+                                        "type": "object",
+                                        "required": ["check", "summary"],
+                                        "properties": {
+                                            "check": { "type": "string" },
+                                            "summary": { "type": "string" },
+                                            "items": { "type": "array", "items": { "type": "string" } }
+                                        }
+                                    }
+                                }
+                            }
+                        },
+                        "group": {
+                            "description": "The display group of the crate.",
+                            "type": "string"
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn run_state_schema() -> Value {
+    let names = json!({ "type": "array", "items": { "type": "string" } });
+    let optional_string = json!({ "type": ["string", "null"] });
+    json!({
+        "type": "object",
+        "required": ["crates", "exclude", "selection", "processed"],
+        "properties": {
+            "schema-version": schema_version_schema(),
+            "crates": names,
+            "exclude": names,
+            "train": optional_string,
+            "unified-version": {
+                "description": "The version given with --unified-version.",
+                "type": "string"
+            },
+            "from-plan": optional_string,
+            "toolchain": optional_string,
+            "selection": names,
+            "processed": names,
+            "published": {
+                "description": "The crates which were published, in the order they were published.",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["crate", "version", "commit"],
+                    "properties": {
+                        "crate": { "type": "string" },
+                        "version": { "type": "string" },
+                        "commit": {
+                            "description": "The commit of the workspace the crate was published from.",
+                            "type": "string"
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn schema_version_schema() -> Value {
+    json!({
+        "description": "The version of the schema of the document, 1 if it's missing.",
+        "type": "integer",
+        "minimum": 1
+    })
+}
@@ -0,0 +1,83 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Scratch copies of a workspace, for read-only subcommands which have to
+//! change the workspace to find out what they report, e.g. by bumping versions
+//! and packaging crates, without touching the checkout or its repository.
+
+use crate::git::Git;
+use std::path::{Path, PathBuf};
+
+/// Files and directories which are never copied. `.git` is a file rather than
+/// a directory in worktrees.
+const SKIPPED: [&str; 2] = [".git", "target"];
+
+/// A copy of a workspace which is removed when it's dropped.
+pub struct ScratchCopy {
+    _dir: tempfile::TempDir,
+    pub root: PathBuf,
+}
+
+/// Copy the files of the workspace at `root`, including uncommitted changes,
+/// into a temporary directory with a repository of its own, so that
+/// checkpoints can be made and undone there. Build artifacts and the git
+/// directory of the workspace are left out.
+pub fn scratch_copy(root: &Path) -> anyhow::Result<ScratchCopy> {
+    let dir = tempfile::tempdir()?;
+    let copy = dir.path().join("workspace");
+    copy_dir(root, &copy)?;
+
+    let git = |args: &[&str]| -> anyhow::Result<()> {
+        // The copy has a repository of its own, which --no-git doesn't cover.
+        let output = Git::default().command(&copy)?.args(args).output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to run git {} in the scratch copy: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    };
+    git(&["init", "--quiet"])?;
+    git(&["config", "user.name", "subpub"])?;
+    git(&["config", "user.email", "subpub@localhost"])?;
+    git(&["add", "--all"])?;
+    git(&["commit", "--quiet", "--allow-empty", "-m", "Scratch copy"])?;
+
+    Ok(ScratchCopy {
+        _dir: dir,
+        root: copy,
+    })
+}
+
+/// Copy the files of `from` to `to`, following symbolic links.
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if SKIPPED.iter().any(|skipped| entry.file_name() == *skipped) {
+            continue;
+        }
+        let dest = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Keep tokens out of everything subpub prints, so that the output of a run
+//! can be shared. Secrets are registered as soon as they are known, and
+//! replaced wherever they would show up in logs, errors or the output of the
+//! commands subpub runs.
+
+use crate::events;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Environment variables which hold tokens.
+pub const SECRET_ENV_VARS: [&str; 2] = ["SPUB_REGISTRY_TOKEN", "CARGO_REGISTRY_TOKEN"];
+
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Make sure `secret` never gets printed from now on.
+pub fn register<S: Into<String>>(secret: S) {
+    let secret = secret.into();
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap_or_else(|err| err.into_inner());
+    if !secrets.contains(&secret) {
+        secrets.push(secret);
+    }
+}
+
+/// Register the values of [`SECRET_ENV_VARS`] which are set.
+pub fn register_env_vars() {
+    for var in SECRET_ENV_VARS {
+        if let Ok(secret) = std::env::var(var) {
+            register(secret);
+        }
+    }
+}
+
+/// Replace the registered secrets in `text`.
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.lock().unwrap_or_else(|err| err.into_inner());
+    secrets.iter().fold(text.to_owned(), |text, secret| {
+        text.replace(secret, REDACTED)
+    })
+}
+
+/// Run a command like [`Command::status`] does, but redact the secrets from
+/// what it prints.
+pub fn run_redacted(cmd: &mut Command) -> io::Result<ExitStatus> {
+    run_redacted_capturing_stderr(cmd).map(|(status, _)| status)
+}
+
+/// Like [`run_redacted`], but also return what the command printed to stderr,
+/// redacted, e.g. to tell why it failed.
+pub fn run_redacted_capturing_stderr(cmd: &mut Command) -> io::Result<(ExitStatus, String)> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let forward_stdout = std::thread::spawn(move || {
+        if events::is_enabled() {
+            forward(stdout, io::stderr())
+        } else {
+            forward(stdout, io::stdout())
+        }
+    });
+    let captured = forward(stderr, io::stderr())?;
+    forward_stdout
+        .join()
+        .map_err(|_| io::Error::other("Failed to forward stdout"))??;
+
+    Ok((child.wait()?, captured))
+}
+
+/// Copy the lines of `from` to `to` with the secrets redacted, returning what
+/// was copied.
+fn forward<R: Read, W: Write>(from: R, mut to: W) -> io::Result<String> {
+    let mut copied = String::new();
+    for line in BufReader::new(from).split(b'\n') {
+        let line = redact(&String::from_utf8_lossy(&line?));
+        writeln!(to, "{line}")?;
+        copied.push_str(&line);
+        copied.push('\n');
+    }
+    Ok(copied)
+}
+
+/// Wraps the writer of a `tracing_subscriber` layer so that the secrets are
+/// redacted from every log line.
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Consistency checks of what a publish run did so far, enabled with
+//! `--self-check`. They re-read the manifests rather than trusting what was
+//! loaded, so that a bug in the planning or rewriting of manifests aborts the
+//! run before a broken release reaches the registry.
+
+use crate::crates::Crates;
+use anyhow::Context;
+use semver::{Version, VersionReq};
+use std::collections::HashSet;
+
+/// Check that `krate` is fit to be published at its current version:
+/// - every dependency given by a path has a version requirement which allows
+///   the current version of that dependency,
+/// - the version is newer than `latest_version`, the latest one published,
+/// - its dependencies were processed before it, or are published already.
+pub fn check_before_publishing(
+    crates: &Crates,
+    krate: &str,
+    latest_version: Option<&Version>,
+    processed_crates: &HashSet<String>,
+) -> anyhow::Result<()> {
+    let details = crates
+        .details
+        .get(krate)
+        .with_context(|| format!("Crate not found: {krate}"))?;
+    let mut violations = vec![];
+
+    for (dep, version_req) in details.path_dependencies()? {
+        let dep_details = match crates.details.get(&dep) {
+            Some(dep_details) => dep_details,
+            None => continue,
+        };
+        match version_req.map(|version_req| VersionReq::parse(&version_req)) {
+            None => violations.push(format!("the dependency on {dep} has no version")),
+            Some(Err(err)) => {
+                violations.push(format!("the requirement on {dep} cannot be parsed: {err}"))
+            }
+            Some(Ok(version_req)) if !version_req.matches(&dep_details.version) => {
+                violations.push(format!(
+                    "the requirement {version_req} on {dep} does not allow its version {}",
+                    dep_details.version
+                ))
+            }
+            Some(Ok(_)) => {}
+        }
+    }
+
+    if let Some(latest_version) = latest_version {
+        if details.version <= *latest_version {
+            violations.push(format!(
+                "its version {} is not newer than the published {latest_version}",
+                details.version
+            ));
+        }
+    }
+
+    for dep in details.deps_relevant_during_publish() {
+        if processed_crates.contains(dep) {
+            continue;
+        }
+        let dep_version = &crates.details[dep].version;
+        if !crates
+            .context()
+            .registry
+            .crate_versions(dep)?
+            .contains(dep_version)
+        {
+            violations.push(format!(
+                "it would be published before {dep} {dep_version}, which it depends on"
+            ));
+        }
+    }
+
+    if !violations.is_empty() {
+        anyhow::bail!(
+            "Self-check failed for {krate} {}: {}",
+            details.version,
+            violations.join("; ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Set the version of crates of a workspace by hand, along with the
+//! requirements of the rest of the workspace on them, e.g. ahead of a release
+//! whose versions were decided beforehand.
+
+use crate::crates::Crates;
+use crate::lockfile;
+use crate::run_lock;
+use semver::Version;
+use std::path::Path;
+use tracing::info;
+
+/// Set the version of `krate` in the workspace at `root`, or of every crate
+/// of the workspace if it's `None`, to `version`, and point the requirements
+/// of the workspace on them to it where they don't allow it. Setting the
+/// version of every crate also sets the one of `[workspace.package]`, so that
+/// the crates inheriting it keep doing so. Returns the crates whose version
+/// changed, along with their previous version.
+pub fn set_version(
+    root: &Path,
+    crates: &mut Crates,
+    krate: Option<&str>,
+    version: &Version,
+) -> anyhow::Result<Vec<(String, Version)>> {
+    let _lock = run_lock::lock(&crates.context().git, root)?;
+    let mut names = match krate {
+        Some(krate) if crates.details.contains_key(krate) => vec![krate.to_owned()],
+        Some(krate) => anyhow::bail!("Crate not found: {krate}"),
+        None => crates.details.keys().cloned().collect(),
+    };
+    names.sort();
+
+    let workspace_version = krate.is_none() && crates.write_workspace_version(version)?;
+    if workspace_version {
+        info!("Set the version of the workspace to {version}");
+    }
+    let mut changed = vec![];
+    for name in names {
+        let details = match crates.details.get_mut(&name) {
+            Some(details) => details,
+            None => anyhow::bail!("Crate not found: {name}"),
+        };
+        if details.version == *version {
+            continue;
+        }
+        let previous = details.version.clone();
+        if workspace_version && details.inherits_version()? {
+            details.version = version.clone();
+        } else {
+            details.write_own_version(version.clone())?;
+        }
+        info!("Set the version of {name} from {previous} to {version}");
+        changed.push((name, previous));
+    }
+
+    for (name, _) in &changed {
+        crates.write_dependency_version(name, version)?;
+    }
+    if !changed.is_empty() {
+        lockfile::update_lockfile(
+            crates.context(),
+            root,
+            &changed
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+        )?;
+    }
+    Ok(changed)
+}
@@ -0,0 +1,217 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The state of a publish run, kept in the git directory of the workspace so
+//! that checkpoints don't pick it up. It lets `publish --resume` pick up
+//! exactly where a failed run stopped, `--start-from` check that it doesn't
+//! skip crates the previous run didn't get to, and `yank` roll back what the
+//! last run published.
+
+use crate::git::Git;
+use crate::ordering;
+use crate::schema;
+use anyhow::Context;
+use semver::Version;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const STATE_FILE: &str = "subpub/state.json";
+/// Where the state of the last run which finished is kept.
+const LAST_RUN_FILE: &str = "subpub/last-run.json";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunState {
+    /// The version of the schema of the state, see [`schema::SCHEMA_VERSION`].
+    #[serde(default = "schema::schema_version")]
+    pub schema_version: u32,
+    /// The crates given with --crate.
+    pub crates: Vec<String>,
+    /// The crates given with --exclude.
+    pub exclude: Vec<String>,
+    /// The train given with --train.
+    #[serde(default)]
+    pub train: Option<String>,
+    /// The version given with --unified-version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unified_version: Option<Version>,
+    /// The plan given with --from-plan.
+    #[serde(default)]
+    pub from_plan: Option<PathBuf>,
+    /// The version of Cargo the crates were packaged with.
+    #[serde(default)]
+    pub toolchain: Option<String>,
+    /// The crates selected for publishing, in publish order.
+    pub selection: Vec<String>,
+    /// The crates which have been processed, in the order they were processed.
+    pub processed: Vec<String>,
+    /// The crates which have been published, in the order they were
+    /// published.
+    #[serde(default)]
+    pub published: Vec<PublishedCrate>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PublishedCrate {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub version: Version,
+    /// The commit of the workspace the crate was published from.
+    pub commit: String,
+}
+
+impl Default for RunState {
+    fn default() -> RunState {
+        RunState {
+            schema_version: schema::SCHEMA_VERSION,
+            crates: vec![],
+            exclude: vec![],
+            train: None,
+            unified_version: None,
+            from_plan: None,
+            toolchain: None,
+            selection: vec![],
+            processed: vec![],
+            published: vec![],
+        }
+    }
+}
+
+impl RunState {
+    /// Load the state of the last run which didn't finish, if there's one.
+    pub fn load<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<Option<RunState>> {
+        load_from(&state_path(git, root)?)
+    }
+
+    /// Load the state of the last run, whether it finished or not.
+    pub fn load_last<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<Option<RunState>> {
+        match RunState::load(git, &root)? {
+            Some(run_state) => Ok(Some(run_state)),
+            None => load_from(&git.path(root, LAST_RUN_FILE)?),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, git: &Git, root: P) -> anyhow::Result<()> {
+        let path = state_path(git, root)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Cannot write the run state to {:?}", path.as_os_str()))
+    }
+
+    /// Keep the state of a run which finished as the last run, so that it
+    /// isn't resumed but its releases can still be yanked.
+    pub fn finish<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<()> {
+        let path = state_path(git, &root)?;
+        if path.exists() {
+            let last_run_path = git.path(&root, LAST_RUN_FILE)?;
+            std::fs::rename(&path, &last_run_path).with_context(|| {
+                format!(
+                    "Cannot move the run state at {:?} to {:?}",
+                    path.as_os_str(),
+                    last_run_path.as_os_str()
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Throw away the state of the run which didn't finish, if there's one,
+    /// so that it isn't resumed. Returns whether there was one.
+    pub fn discard<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<bool> {
+        let path = state_path(git, root)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Cannot remove the run state at {:?}", path.as_os_str()))?;
+        Ok(true)
+    }
+
+    /// Check that resuming this run from `start_from` with a possibly
+    /// different `selection` doesn't skip crates which were never processed,
+    /// and take the new selection over if so.
+    pub fn reconcile(
+        &mut self,
+        selection: &[String],
+        start_from: &str,
+        publish_order: &[String],
+    ) -> anyhow::Result<()> {
+        let start_from_position = publish_order
+            .iter()
+            .position(|krate| krate == start_from)
+            .with_context(|| format!("Crate not found: {start_from}"))?;
+        let skipped = ordering::order_selection(&publish_order[..start_from_position], selection)
+            .into_iter()
+            .filter(|krate| !self.processed.contains(krate))
+            .map(|krate| {
+                if self.selection.contains(krate) {
+                    format!("{krate} (selected, but not processed yet)")
+                } else {
+                    format!("{krate} (not selected by the previous run)")
+                }
+            })
+            .collect::<Vec<_>>();
+        if !skipped.is_empty() {
+            anyhow::bail!(
+                "Resuming from {start_from} would skip crates which come before it: {}. The previous run was started with --crate [{}] and --exclude [{}], and processed [{}]. Resume from an earlier crate, or adjust the selection so that it matches the previous run.",
+                skipped.join(", "),
+                self.crates.join(", "),
+                self.exclude.join(", "),
+                self.processed.join(", ")
+            );
+        }
+
+        for krate in self
+            .selection
+            .iter()
+            .filter(|krate| !selection.contains(krate))
+        {
+            if self.processed.contains(krate) {
+                info!("{krate} is no longer selected, but it was processed already");
+            } else {
+                info!("{krate} is no longer selected, so it won't be published");
+            }
+        }
+        for krate in selection
+            .iter()
+            .filter(|krate| !self.selection.contains(krate))
+        {
+            info!("{krate} was added to the selection");
+        }
+        self.selection = selection.to_vec();
+
+        Ok(())
+    }
+}
+
+fn state_path<P: AsRef<Path>>(git: &Git, root: P) -> anyhow::Result<PathBuf> {
+    git.path(root, STATE_FILE)
+}
+
+fn load_from(path: &Path) -> anyhow::Result<Option<RunState>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read the run state at {:?}", path.as_os_str()))?;
+    let run_state: RunState = serde_json::from_str(&contents)
+        .with_context(|| format!("Cannot parse the run state at {:?}", path.as_os_str()))?;
+    schema::check_version("run state", run_state.schema_version)?;
+    Ok(Some(run_state))
+}
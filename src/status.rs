@@ -0,0 +1,87 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compare the crates of the workspace against the registry, to see how far
+//! behind the published versions are.
+
+use crate::checkpoint::with_changes_undone;
+use crate::config::Config;
+use crate::context::Context;
+use crate::crates::Crates;
+use crate::publish;
+use crate::run_lock;
+use semver::Version;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CrateStatus {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    /// The version of the crate in the workspace.
+    pub version: Version,
+    /// The latest version of the crate in the registry, if any.
+    pub published_version: Option<Version>,
+    /// Whether the crate has to be published, either because its version
+    /// isn't published or because it changed since.
+    pub needs_publishing: bool,
+}
+
+/// The status of every crate `publish` would consider, in publish order.
+/// Crates are packaged to compare them against the registry, and everything
+/// this changes in the workspace is undone afterwards, like for a dry run.
+/// `compare_contents` is as for
+/// [`crate::crate_details::CrateDetails::needs_publishing`].
+pub fn status(
+    ctx: &Context,
+    root: &Path,
+    compare_contents: bool,
+) -> anyhow::Result<Vec<CrateStatus>> {
+    let config = Config::load(root)?;
+    let crates = Crates::load_crates_in_workspace(root.to_path_buf(), ctx)?;
+    let publish_order = publish::publish_levels(&crates, &config)?.concat();
+    let exclude = publish::excluded_crates(&crates, &config, &publish_order, &[])?;
+    let krates = publish_order
+        .iter()
+        .filter(|krate| !exclude.contains(*krate) && crates.details[*krate].should_be_published)
+        .collect::<Vec<_>>();
+    ctx.registry.prefetch_crate_versions(&krates);
+
+    let _lock = run_lock::lock(&ctx.git, root)?;
+    with_changes_undone(&ctx.git, root, || {
+        let result = krates
+            .into_iter()
+            .map(|krate| {
+                let details = &crates.details[krate];
+                let published_versions = ctx.registry.crate_versions(krate)?;
+                Ok(CrateStatus {
+                    krate: krate.clone(),
+                    version: details.version.clone(),
+                    published_version: published_versions.iter().max().cloned(),
+                    needs_publishing: details.needs_publishing(
+                        ctx,
+                        root,
+                        &published_versions,
+                        compare_contents,
+                    )?,
+                })
+            })
+            .collect();
+        info!("Undoing the changes made to compare the crates");
+        result
+    })?
+}
@@ -0,0 +1,159 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Reconcile what subpub knows about a workspace with the registry after some
+//! of its crates were published by something else than subpub, e.g. by hand,
+//! so that later runs don't decide what to publish and how to bump it from
+//! stale assumptions.
+
+use crate::context::Context;
+use crate::crates::Crates;
+use crate::lockfile;
+use crate::run_lock;
+use crate::state::RunState;
+use semver::Version;
+use std::path::Path;
+use tracing::info;
+
+/// What [`sync_state`] changed, or would change with `dry_run`.
+#[derive(Debug, Default)]
+pub struct Sync {
+    /// The crates whose latest published version is newer than the version
+    /// of the workspace, along with both versions. The workspace, and the
+    /// requirements on them, are moved to the published version.
+    pub behind: Vec<(String, Version, Version)>,
+    /// The crates of the unfinished run whose version in the workspace was
+    /// published since, and which are recorded as processed so that
+    /// `--resume` doesn't go through them again.
+    pub processed: Vec<String>,
+    /// Whether every crate of the unfinished run is processed now, so that
+    /// the run was recorded as finished.
+    pub finished_run: bool,
+}
+
+/// Reconcile the crates of the workspace at `root` which can be published, or
+/// `krates` if they're given, with the registry: their cached versions are
+/// thrown away and downloaded again, the workspace is moved to the versions
+/// which were published outside of it, and the unfinished run, if there's
+/// one, records the crates which were published since it stopped.
+pub fn sync_state(
+    ctx: &Context,
+    root: &Path,
+    crates: &mut Crates,
+    krates: &[String],
+    dry_run: bool,
+) -> anyhow::Result<Sync> {
+    let _lock = run_lock::lock(&ctx.git, root)?;
+    let mut names = if krates.is_empty() {
+        crates
+            .details
+            .values()
+            .filter(|details| details.should_be_published)
+            .map(|details| details.name.clone())
+            .collect::<Vec<_>>()
+    } else {
+        for krate in krates {
+            if !crates.details.contains_key(krate) {
+                anyhow::bail!("Crate not found: {krate}");
+            }
+        }
+        krates.to_vec()
+    };
+    names.sort();
+    for name in &names {
+        ctx.registry.forget_crate_versions(name);
+    }
+    ctx.registry.prefetch_crate_versions(&names);
+
+    let mut sync = Sync::default();
+    for name in &names {
+        let latest = match ctx.registry.crate_versions(name)?.into_iter().max() {
+            Some(latest) => latest,
+            None => continue,
+        };
+        let details = match crates.details.get_mut(name) {
+            Some(details) => details,
+            None => anyhow::bail!("Crate not found: {name}"),
+        };
+        let current = details.version.clone();
+        if latest <= current {
+            continue;
+        }
+        if dry_run {
+            info!("Would move {name} from {current} to {latest}, which was published outside of subpub");
+        } else {
+            info!(
+                "Moving {name} from {current} to {latest}, which was published outside of subpub"
+            );
+            details.write_own_version(latest.clone())?;
+        }
+        sync.behind.push((name.clone(), current, latest));
+    }
+    if !dry_run && !sync.behind.is_empty() {
+        for (name, _, latest) in &sync.behind {
+            crates.write_dependency_version(name, latest)?;
+        }
+        lockfile::update_lockfile(
+            ctx,
+            root,
+            &sync
+                .behind
+                .iter()
+                .map(|(name, _, _)| name.as_str())
+                .collect::<Vec<_>>(),
+        )?;
+    }
+
+    if let Some(mut run_state) = RunState::load(&ctx.git, root)? {
+        for krate in &run_state.selection {
+            if run_state.processed.contains(krate) || !names.contains(krate) {
+                continue;
+            }
+            // A crate which was just moved to a newer version might still
+            // differ from what was published, which resuming finds out.
+            if sync.behind.iter().any(|(name, _, _)| name == krate) {
+                continue;
+            }
+            let details = match crates.details.get(krate) {
+                Some(details) => details,
+                None => continue,
+            };
+            // The version might have been published before the run, with the
+            // crate changing since, so the packages are compared.
+            let versions = ctx.registry.crate_versions(krate)?;
+            if !details.needs_publishing(ctx, root, &versions, false)? {
+                info!(
+                    "{krate} {} was published since the unfinished run stopped",
+                    details.version
+                );
+                sync.processed.push(krate.clone());
+            }
+        }
+        run_state.processed.extend(sync.processed.iter().cloned());
+        sync.finished_run = run_state
+            .selection
+            .iter()
+            .all(|krate| run_state.processed.contains(krate));
+        if !dry_run && !sync.processed.is_empty() {
+            run_state.save(&ctx.git, root)?;
+            if sync.finished_run {
+                RunState::finish(&ctx.git, root)?;
+            }
+        }
+    }
+
+    Ok(sync)
+}
@@ -0,0 +1,94 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The git tags of releases, named after `--tag-format`, and how they compare
+//! to the versions of the crates in the workspace and in the registry.
+
+use crate::context::Context;
+use crate::crate_details::CrateDetails;
+use semver::Version;
+use std::path::Path;
+
+/// The tag format used unless `--tag-format` is given.
+pub const DEFAULT_TAG_FORMAT: &str = "{crate}-v{version}";
+
+/// The name of the tag of a release, following `format`, or
+/// [`DEFAULT_TAG_FORMAT`].
+pub fn tag_name(format: Option<&str>, krate: &str, version: &Version) -> String {
+    format
+        .unwrap_or(DEFAULT_TAG_FORMAT)
+        .replace("{crate}", krate)
+        .replace("{version}", &version.to_string())
+}
+
+/// The versions of `krate` which have a tag among `tags`.
+fn tagged_versions(tags: &[String], format: &str, krate: &str) -> anyhow::Result<Vec<Version>> {
+    let format = format.replace("{crate}", krate);
+    let (prefix, suffix) = format
+        .split_once("{version}")
+        .ok_or_else(|| anyhow::anyhow!("The tag format {format:?} has to contain {{version}}"))?;
+    Ok(tags
+        .iter()
+        .filter_map(|tag| tag.strip_prefix(prefix)?.strip_suffix(suffix))
+        .filter_map(|version| Version::parse(version).ok())
+        .collect())
+}
+
+/// Compare the version of each crate in the workspace with its latest tag
+/// and its latest version in the registry. Returns a description of every
+/// mismatch: a tagged version which isn't published, a latest published
+/// version which isn't tagged, or a workspace version behind either of them.
+/// A workspace version ahead of both is what a release looks like, so it isn't
+/// reported.
+pub fn check_tags(
+    ctx: &Context,
+    root: &Path,
+    format: Option<&str>,
+    crates: &[&CrateDetails],
+) -> anyhow::Result<Vec<String>> {
+    let format = format.unwrap_or(DEFAULT_TAG_FORMAT);
+    let tags = ctx.git.tags(root)?;
+    let mut problems = vec![];
+    for details in crates {
+        let krate = &details.name;
+        let tagged = tagged_versions(&tags, format, krate)?;
+        let published = ctx.registry.crate_versions(krate)?;
+        let latest_tag = tagged.iter().max();
+        let latest_published = published.iter().max();
+
+        if let Some(tag) = latest_tag.filter(|tag| !published.contains(tag)) {
+            problems.push(format!(
+                "{krate} {tag} is tagged as {}, but not published",
+                tag_name(Some(format), krate, tag)
+            ));
+        }
+        if let Some(version) = latest_published.filter(|version| !tagged.contains(version)) {
+            problems.push(format!(
+                "{krate} {version} is published, but there's no {} tag",
+                tag_name(Some(format), krate, version)
+            ));
+        }
+        if let Some(latest) = latest_tag.into_iter().chain(latest_published).max() {
+            if details.version < *latest {
+                problems.push(format!(
+                    "{krate} is at {} in the workspace, behind {latest}, which is tagged or published",
+                    details.version
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
@@ -0,0 +1,87 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Templates for the metadata of families of similar crates, e.g. a
+//! description like "FRAME pallet for {subject}", filled in when the crates
+//! are published.
+
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+
+/// The `[package]` fields a template sets, by name.
+pub type MetadataTemplate = BTreeMap<String, TemplateValue>;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum TemplateValue {
+    String(String),
+    Array(Vec<String>),
+}
+
+impl TemplateValue {
+    pub fn to_item(&self) -> toml_edit::Item {
+        match self {
+            TemplateValue::String(value) => toml_edit::value(value.as_str()),
+            TemplateValue::Array(values) => {
+                toml_edit::value(values.iter().collect::<toml_edit::Array>())
+            }
+        }
+    }
+}
+
+/// Fill in the variables of the template, given like `{subject}`.
+pub fn render(
+    template: &MetadataTemplate,
+    variables: &HashMap<String, String>,
+) -> anyhow::Result<MetadataTemplate> {
+    template
+        .iter()
+        .map(|(field, value)| {
+            let value = match value {
+                TemplateValue::String(value) => {
+                    TemplateValue::String(render_str(value, variables)?)
+                }
+                TemplateValue::Array(values) => TemplateValue::Array(
+                    values
+                        .iter()
+                        .map(|value| render_str(value, variables))
+                        .collect::<anyhow::Result<_>>()?,
+                ),
+            };
+            Ok((field.clone(), value))
+        })
+        .collect::<anyhow::Result<_>>()
+        .with_context(|| "Cannot render the metadata template")
+}
+
+fn render_str(template: &str, variables: &HashMap<String, String>) -> anyhow::Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("Unclosed variable in \"{template}\""))?;
+        let variable = &rest[start + 1..start + end];
+        let value = variables
+            .get(variable)
+            .with_context(|| format!("Unknown variable {{{variable}}} in \"{template}\""))?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
@@ -29,3 +29,13 @@ pub fn toml_write<P: AsRef<Path>>(path: P, toml: &toml_edit::Document) -> anyhow
     })?;
     Ok(())
 }
+
+/// Set `item` to `value`, keeping the whitespace and comments around the value
+/// it replaces, so that rewritten manifests only change where they have to.
+pub fn set_value<V: Into<toml_edit::Value>>(item: &mut toml_edit::Item, value: V) {
+    let mut value = value.into();
+    if let Some(old) = item.as_value() {
+        *value.decor_mut() = old.decor().clone();
+    }
+    *item = toml_edit::Item::Value(value);
+}
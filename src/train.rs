@@ -0,0 +1,107 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Release trains: named sets of crates which are released together at
+//! pinned versions.
+
+use crate::crates::Crates;
+use crate::version::{bump, is_at_least, is_breaking_requirement_change, Bump};
+use anyhow::Context;
+use semver::Version;
+use std::collections::BTreeMap;
+
+/// The pinned versions of a train, in the form `crate_name => version`.
+pub type Train = BTreeMap<String, Version>;
+
+/// Check that the train can be released as it is: its crates can be
+/// published, their versions only move forward, and their dependencies which
+/// aren't part of the train are already published at the versions the
+/// workspace refers to.
+pub fn validate_train(crates: &Crates, name: &str, train: &Train) -> anyhow::Result<()> {
+    for (krate, version) in train {
+        let details = crates
+            .details
+            .get(krate)
+            .with_context(|| format!("Crate {krate} of train {name} not found"))?;
+        if !details.should_be_published {
+            anyhow::bail!("Crate {krate} is part of train {name}, but it should not be published. Check if {krate} has \"publish = false\" in {:?}.", details.toml_path);
+        }
+        if *version < details.version {
+            anyhow::bail!(
+                "Train {name} pins {krate} to {version}, which is older than its current version {}",
+                details.version
+            );
+        }
+
+        for dep in details.deps_relevant_during_publish() {
+            if let Some(dep_version) = train.get(dep) {
+                let breaking = details
+                    .dep_version_reqs
+                    .get(dep)
+                    .map(|version_req| is_breaking_requirement_change(version_req, dep_version))
+                    .unwrap_or(false);
+                if breaking {
+                    if let Some(latest_version) = crates
+                        .context()
+                        .registry
+                        .crate_versions(krate)?
+                        .into_iter()
+                        .max()
+                    {
+                        if !is_at_least(&latest_version, version, Bump::Minor) {
+                            anyhow::bail!(
+                                "Train {name} pins {krate} to {version}, but its requirement on {dep} changes in a breaking way, which needs at least {}.",
+                                bump(latest_version, Bump::Minor)
+                            );
+                        }
+                    }
+                }
+                continue;
+            }
+            let dep_details = crates
+                .details
+                .get(dep)
+                .with_context(|| format!("Crate not found: {dep}"))?;
+            if !crates
+                .context()
+                .registry
+                .crate_versions(dep)?
+                .contains(&dep_details.version)
+            {
+                anyhow::bail!(
+                    "Crate {krate} of train {name} depends on {dep} {}, which is neither part of the train nor published.",
+                    dep_details.version
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Set the crates of the train to their pinned versions, and make every
+/// crate of the workspace refer to those versions.
+pub fn apply_train(crates: &mut Crates, train: &Train) -> anyhow::Result<()> {
+    for (krate, version) in train {
+        crates
+            .details
+            .get_mut(krate)
+            .with_context(|| format!("Crate not found: {krate}"))?
+            .write_own_version(version.clone())?;
+        crates.write_dependency_version(krate, version)?;
+    }
+    Ok(())
+}
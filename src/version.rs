@@ -15,9 +15,120 @@
 // along with subpub.  If not, see <http://www.gnu.org/licenses/>.
 
 pub use semver::Version;
+use semver::{Op, VersionReq};
 use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 
-fn bump_for_breaking_change(mut version: Version) -> Version {
+/// How much to increase a version by.
+#[derive(
+    clap::ArgEnum,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::Display,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+}
+
+/// A bump level forced with `--bump`, either for every crate, written e.g.
+/// `minor`, or for one crate, written e.g. `polkadot-core=minor`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct BumpOverride {
+    pub krate: Option<String>,
+    pub bump: Bump,
+}
+
+impl FromStr for BumpOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<BumpOverride> {
+        let (krate, level) = match s.split_once('=') {
+            Some((krate, level)) => (Some(krate.trim()), level.trim()),
+            None => (None, s.trim()),
+        };
+        let bump = <Bump as clap::ArgEnum>::from_str(level, true).map_err(|_| {
+            anyhow::anyhow!(
+                "Expected a bump level, i.e. major, minor or patch, optionally preceded by \"crate=\", got {s:?}"
+            )
+        })?;
+        if krate.is_some_and(str::is_empty) {
+            anyhow::bail!("Expected a crate before \"=\", got {s:?}");
+        }
+        Ok(BumpOverride {
+            krate: krate.map(String::from),
+            bump,
+        })
+    }
+}
+
+impl TryFrom<String> for BumpOverride {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<BumpOverride> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for BumpOverride {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.krate {
+            Some(krate) => write!(f, "{krate}={}", self.bump),
+            None => write!(f, "{}", self.bump),
+        }
+    }
+}
+
+/// The bump level `overrides` force for `krate`: the one given for the crate
+/// if there's one, else the one given for every crate. Later overrides win.
+pub fn forced_bump(overrides: &[BumpOverride], krate: &str) -> Option<Bump> {
+    overrides
+        .iter()
+        .rev()
+        .find(|bump_override| bump_override.krate.as_deref() == Some(krate))
+        .or_else(|| {
+            overrides
+                .iter()
+                .rev()
+                .find(|bump_override| bump_override.krate.is_none())
+        })
+        .map(|bump_override| bump_override.bump)
+}
+
+/// Bump the version by the given level, clearing the prerelease label:
+///
+/// ```text
+/// 0.15.3 -> 1.0.0 (major), 0.16.0 (minor), 0.15.4 (patch)
+/// 4.0.0-dev -> 5.0.0 (major), 4.1.0 (minor), 4.0.1 (patch)
+/// ```
+pub fn bump(mut version: Version, bump: Bump) -> Version {
+    match bump {
+        Bump::Major => {
+            version.major += 1;
+            version.minor = 0;
+            version.patch = 0;
+        }
+        Bump::Minor => {
+            version.minor += 1;
+            version.patch = 0;
+        }
+        Bump::Patch => version.patch += 1,
+    }
+    version.pre = semver::Prerelease::EMPTY;
+    version
+}
+
+pub fn bump_for_breaking_change(mut version: Version) -> Version {
     if version.pre != semver::Prerelease::EMPTY {
         version.pre = semver::Prerelease::EMPTY;
     } else if version.major == 0 {
@@ -31,6 +142,25 @@ fn bump_for_breaking_change(mut version: Version) -> Version {
     version
 }
 
+/// Bump the version for a change which needs a `required` release, e.g. as
+/// found by comparing public APIs. Since Cargo considers 0.x.y and 0.x.z
+/// compatible, 0.x versions are bumped one level less:
+///
+/// ```text
+/// 1.2.3 -> 2.0.0 (major), 1.3.0 (minor), 1.2.4 (patch)
+/// 0.2.3 -> 0.3.0 (major), 0.2.4 (minor, patch)
+/// 4.0.0-dev -> 4.0.0 (remove prerelease label)
+/// ```
+pub fn bump_for_change(version: Version, required: Bump) -> Version {
+    if version.pre != semver::Prerelease::EMPTY || required == Bump::Major {
+        bump_for_breaking_change(version)
+    } else if version.major == 0 {
+        bump(version, Bump::Patch)
+    } else {
+        bump(version, required)
+    }
+}
+
 /// Bump the version for a breaking change and to release. Examples of bumps carried out:
 ///
 /// ```text
@@ -42,8 +172,63 @@ fn bump_for_breaking_change(mut version: Version) -> Version {
 ///
 /// Return the new version.
 pub fn maybe_bump_for_breaking_change(
+    prev_versions: Vec<Version>,
+    current_version: Version,
+) -> Option<Version> {
+    maybe_bump_for_change(prev_versions, current_version, Bump::Major)
+}
+
+/// Bump the version to release a change which needs a `required` release,
+/// see [`bump_for_change`], the same way as
+/// [`maybe_bump_for_breaking_change`] does for breaking changes.
+pub fn maybe_bump_for_change(
+    prev_versions: Vec<Version>,
+    current_version: Version,
+    required: Bump,
+) -> Option<Version> {
+    maybe_bump_with(prev_versions, current_version, |max_version, _| {
+        bump_for_change(max_version, required)
+    })
+}
+
+/// Bump the version by exactly `level` since the latest published version,
+/// as forced with `--bump`, taking the level literally for 0.x versions too:
+///
+/// ```text
+/// 0.2.3 -> 1.0.0 (major), 0.3.0 (minor), 0.2.4 (patch)
+/// 2.0.0-dev, with 1.5.0 published -> 2.0.0 (major, minor or patch)
+/// ```
+pub fn maybe_bump_to_level(
+    prev_versions: Vec<Version>,
+    current_version: Version,
+    level: Bump,
+) -> Option<Version> {
+    maybe_bump_with(
+        prev_versions,
+        current_version,
+        |max_version, latest_version| {
+            if max_version.pre == semver::Prerelease::EMPTY {
+                return bump(max_version, level);
+            }
+            // The pre-release of a version which is enough of a bump is released.
+            let mut release = max_version;
+            release.pre = semver::Prerelease::EMPTY;
+            if release > latest_version && is_at_least(&latest_version, &release, level) {
+                release
+            } else {
+                bump(latest_version, level)
+            }
+        },
+    )
+}
+
+/// Bump the greater of the current version and the latest of `prev_versions`
+/// with `bump_latest`, which is given both, or only release the current
+/// version if it's a pre-release and nothing is published.
+fn maybe_bump_with(
     prev_versions: Vec<Version>,
     mut current_version: Version,
+    bump_latest: impl FnOnce(Version, Version) -> Version,
 ) -> Option<Version> {
     prev_versions
         .into_iter()
@@ -51,9 +236,9 @@ pub fn maybe_bump_for_breaking_change(
         .map(|latest_version| {
             let max_version = match &current_version.cmp(&latest_version) {
                 Ordering::Greater => current_version.to_owned(),
-                _ => latest_version,
+                _ => latest_version.clone(),
             };
-            bump_for_breaking_change(max_version)
+            bump_latest(max_version, latest_version)
         })
         .or_else(|| {
             if current_version.pre == semver::Prerelease::EMPTY {
@@ -64,3 +249,282 @@ pub fn maybe_bump_for_breaking_change(
             }
         })
 }
+
+/// Whether moving a requirement which doesn't allow `new_version` over to it
+/// is a breaking change for the dependent, e.g. `1` to `2.0.0`, as opposed to
+/// e.g. `=1.0.0` to `1.0.1`. The requirement's lower bound is compared with
+/// `new_version` by Cargo's compatibility rules.
+pub fn is_breaking_requirement_change(version_req: &VersionReq, new_version: &Version) -> bool {
+    if version_req.matches(new_version) {
+        return false;
+    }
+    let lower_bound = version_req.comparators.iter().find(|comparator| {
+        matches!(
+            comparator.op,
+            Op::Exact | Op::Caret | Op::Tilde | Op::GreaterEq | Op::Wildcard
+        )
+    });
+    match lower_bound {
+        Some(comparator) => {
+            let base = Version::new(
+                comparator.major,
+                comparator.minor.unwrap_or(0),
+                comparator.patch.unwrap_or(0),
+            );
+            !is_compatible(&base, new_version)
+        }
+        None => true,
+    }
+}
+
+/// Whether `b` can be used where `a` was by Cargo's rules, e.g. 1.2.0 and
+/// 1.5.0, or 0.3.1 and 0.3.4.
+fn is_compatible(a: &Version, b: &Version) -> bool {
+    if a.major != b.major {
+        false
+    } else if a.major > 0 {
+        true
+    } else if a.minor != b.minor {
+        false
+    } else {
+        a.minor > 0 || a.patch == b.patch
+    }
+}
+
+/// Whether releasing `to` after `from` is enough for a change which needs a
+/// `required` release by Cargo's rules, ignoring prerelease labels. Since
+/// Cargo considers 0.x.y and 0.x.z compatible, a minor bump of a 0.x version
+/// is a breaking release:
+///
+/// ```text
+/// 1.2.3 -> 1.3.0: enough for minor and patch changes
+/// 0.2.3 -> 0.3.0: enough for any change
+/// 0.2.3 -> 0.2.4: enough for minor and patch changes
+/// ```
+pub fn is_enough_for_change(from: &Version, to: &Version, required: Bump) -> bool {
+    let mut from = from.clone();
+    from.pre = semver::Prerelease::EMPTY;
+    let needed = bump_for_change(from, required);
+    (to.major, to.minor, to.patch) >= (needed.major, needed.minor, needed.patch)
+}
+
+/// Whether going from `from` to `to` is at least a `level` bump, ignoring
+/// prerelease labels.
+pub fn is_at_least(from: &Version, to: &Version, level: Bump) -> bool {
+    let required = bump(from.clone(), level);
+    (to.major, to.minor, to.patch) >= (required.major, required.minor, required.patch)
+}
+
+/// How the requirements of dependents on a new version of a dependency are
+/// written.
+#[derive(
+    clap::ArgEnum, Debug, Default, Clone, Copy, PartialEq, Eq, strum::Display, serde::Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum RequirementStyle {
+    /// `=1.2.3`
+    Exact,
+    /// `1.2.3`, which Cargo reads as `^1.2.3`.
+    #[default]
+    Caret,
+    /// `1.2`, or `0.2` for 0.2.3. Versions which are compatible with fewer
+    /// ones, e.g. 0.0.3 or 1.2.3-rc.1, are written in full.
+    Short,
+    /// `~1.2.3`
+    Tilde,
+    /// `>=1.2.3, <2.0.0`
+    CompatibleRange,
+}
+
+impl RequirementStyle {
+    /// The requirement on `version` in this style.
+    ///
+    /// ```text
+    /// 1.2.3 -> =1.2.3 (exact), 1.2.3 (caret), 1.2 (short), ~1.2.3 (tilde), >=1.2.3, <2.0.0 (compatible-range)
+    /// 0.2.3 -> =0.2.3 (exact), 0.2.3 (caret), 0.2 (short), ~0.2.3 (tilde), >=0.2.3, <0.3.0 (compatible-range)
+    /// ```
+    pub fn requirement(self, version: &Version) -> String {
+        match self {
+            RequirementStyle::Exact => format!("={version}"),
+            RequirementStyle::Caret => version.to_string(),
+            RequirementStyle::Short => {
+                if version.pre != semver::Prerelease::EMPTY
+                    || (version.major == 0 && version.minor == 0)
+                {
+                    version.to_string()
+                } else if version.major == 0 {
+                    format!("0.{}", version.minor)
+                } else {
+                    format!("{}.{}", version.major, version.minor)
+                }
+            }
+            RequirementStyle::Tilde => format!("~{version}"),
+            RequirementStyle::CompatibleRange => {
+                let upper_bound = if version.major > 0 {
+                    Version::new(version.major + 1, 0, 0)
+                } else if version.minor > 0 {
+                    Version::new(0, version.minor + 1, 0)
+                } else {
+                    Version::new(0, 0, version.patch + 1)
+                };
+                format!(">={version}, <{upper_bound}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    fn req(req: &str) -> VersionReq {
+        VersionReq::parse(req).unwrap()
+    }
+
+    #[test]
+    fn zero_major_releases_are_breaking_by_cargo_rules() {
+        assert!(is_breaking_requirement_change(&req("0.3.1"), &v("0.4.0")));
+        assert!(is_breaking_requirement_change(&req("0.0.1"), &v("0.0.2")));
+        assert!(!is_breaking_requirement_change(&req("0.3.1"), &v("0.3.4")));
+
+        assert!(is_enough_for_change(&v("0.3.1"), &v("0.4.0"), Bump::Major));
+        assert!(!is_enough_for_change(&v("0.3.1"), &v("0.3.2"), Bump::Major));
+        assert!(is_enough_for_change(&v("0.3.1"), &v("0.3.2"), Bump::Minor));
+        assert!(is_enough_for_change(&v("1.2.3"), &v("1.3.0"), Bump::Minor));
+        assert!(!is_enough_for_change(&v("1.2.3"), &v("1.2.4"), Bump::Minor));
+    }
+
+    #[test]
+    fn compatible_requirements_are_not_breaking() {
+        assert!(!is_breaking_requirement_change(&req("^1.2"), &v("1.3.0")));
+        assert!(!is_breaking_requirement_change(&req("=1.0.0"), &v("1.0.1")));
+        assert!(is_breaking_requirement_change(&req("1"), &v("2.0.0")));
+    }
+
+    #[test]
+    fn tilde_requirements_are_judged_by_their_lower_bound() {
+        assert!(!is_breaking_requirement_change(&req("~1.2"), &v("1.3.0")));
+        assert!(is_breaking_requirement_change(&req("~1.2"), &v("2.0.0")));
+        assert!(is_breaking_requirement_change(&req("~0.2"), &v("0.3.0")));
+    }
+
+    #[test]
+    fn ranges_are_judged_by_their_lower_bound() {
+        assert!(is_breaking_requirement_change(&req(">=1, <2"), &v("2.0.0")));
+        assert!(!is_breaking_requirement_change(
+            &req(">=1, <2"),
+            &v("1.7.0")
+        ));
+        assert!(is_breaking_requirement_change(&req("<2"), &v("2.0.0")));
+    }
+
+    #[test]
+    fn prereleases_are_released() {
+        assert_eq!(bump(v("4.0.0-dev"), Bump::Major), v("5.0.0"));
+        assert_eq!(bump(v("4.0.0-dev"), Bump::Minor), v("4.1.0"));
+        assert_eq!(bump(v("4.0.0-dev"), Bump::Patch), v("4.0.1"));
+        assert_eq!(bump_for_change(v("4.0.0-dev"), Bump::Patch), v("4.0.0"));
+        assert_eq!(
+            maybe_bump_for_change(vec![], v("4.0.0-dev"), Bump::Minor),
+            Some(v("4.0.0"))
+        );
+        assert_eq!(
+            maybe_bump_for_change(vec![v("3.1.0")], v("4.0.0-dev"), Bump::Major),
+            Some(v("4.0.0"))
+        );
+        assert_eq!(
+            maybe_bump_to_level(vec![v("1.5.0")], v("2.0.0-dev"), Bump::Patch),
+            Some(v("2.0.0"))
+        );
+        assert_eq!(
+            maybe_bump_to_level(vec![v("2.0.0")], v("2.0.0-dev"), Bump::Minor),
+            Some(v("2.1.0"))
+        );
+    }
+
+    #[test]
+    fn zero_major_versions_are_bumped_one_level_less() {
+        assert_eq!(bump_for_change(v("0.2.3"), Bump::Major), v("0.3.0"));
+        assert_eq!(bump_for_change(v("0.2.3"), Bump::Minor), v("0.2.4"));
+        assert_eq!(bump_for_change(v("1.2.3"), Bump::Minor), v("1.3.0"));
+        assert_eq!(maybe_bump_for_change(vec![], v("0.2.3"), Bump::Major), None);
+        assert_eq!(
+            maybe_bump_for_breaking_change(vec![v("0.2.3"), v("0.1.0")], v("0.2.3")),
+            Some(v("0.3.0"))
+        );
+    }
+
+    #[test]
+    fn requirement_styles() {
+        let requirements = |version: &str| {
+            let version = v(version);
+            [
+                RequirementStyle::Exact,
+                RequirementStyle::Caret,
+                RequirementStyle::Short,
+                RequirementStyle::Tilde,
+                RequirementStyle::CompatibleRange,
+            ]
+            .map(|style| style.requirement(&version))
+        };
+        assert_eq!(
+            requirements("1.2.3"),
+            ["=1.2.3", "1.2.3", "1.2", "~1.2.3", ">=1.2.3, <2.0.0"]
+        );
+        assert_eq!(
+            requirements("0.2.3"),
+            ["=0.2.3", "0.2.3", "0.2", "~0.2.3", ">=0.2.3, <0.3.0"]
+        );
+        assert_eq!(
+            requirements("0.0.3"),
+            ["=0.0.3", "0.0.3", "0.0.3", "~0.0.3", ">=0.0.3, <0.0.4"]
+        );
+        assert_eq!(requirements("1.2.3-rc.1")[2], "1.2.3-rc.1");
+        for version in ["1.2.3", "0.2.3", "0.0.3", "1.2.3-rc.1"] {
+            for requirement in requirements(version) {
+                assert!(req(&requirement).matches(&v(version)), "{requirement}");
+            }
+        }
+    }
+
+    #[test]
+    fn bump_overrides_are_parsed_for_all_or_one_crate() {
+        assert_eq!(
+            "Minor".parse::<BumpOverride>().unwrap(),
+            BumpOverride {
+                krate: None,
+                bump: Bump::Minor,
+            }
+        );
+        let bump_override = "polkadot-core = patch".parse::<BumpOverride>().unwrap();
+        assert_eq!(
+            bump_override,
+            BumpOverride {
+                krate: Some("polkadot-core".into()),
+                bump: Bump::Patch,
+            }
+        );
+        assert_eq!(bump_override.to_string(), "polkadot-core=patch");
+
+        assert!("huge".parse::<BumpOverride>().is_err());
+        assert!("a=".parse::<BumpOverride>().is_err());
+        assert!("=major".parse::<BumpOverride>().is_err());
+    }
+
+    #[test]
+    fn bump_overrides_for_a_crate_win() {
+        let overrides = ["a=patch", "major", "minor", "b=major"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect::<Vec<BumpOverride>>();
+        assert_eq!(forced_bump(&overrides, "a"), Some(Bump::Patch));
+        assert_eq!(forced_bump(&overrides, "b"), Some(Bump::Major));
+        assert_eq!(forced_bump(&overrides, "c"), Some(Bump::Minor));
+        assert_eq!(forced_bump(&[], "c"), None);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! An isolated worktree of the repository of a workspace, so that `publish`
+//! makes its checkpoints, manifest edits and commits on a branch of their own
+//! and leaves the checkout, and the branch it's on, untouched.
+
+use crate::git::Git;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Where the worktree is created, in the git directory of the workspace, so
+/// that a failed run can be resumed in it.
+const WORKTREE_DIR: &str = "subpub/worktree";
+/// The branch the worktree is on, unless --worktree-branch says otherwise.
+pub const DEFAULT_BRANCH: &str = "subpub/release";
+
+/// A worktree of the repository of a workspace.
+pub struct Worktree {
+    /// The root of the worktree.
+    pub dir: PathBuf,
+    /// The root of the workspace in the worktree.
+    pub root: PathBuf,
+    /// The branch the worktree is on.
+    pub branch: String,
+    /// The root of the workspace in the checkout the worktree was made from.
+    checkout: PathBuf,
+}
+
+/// Run git in `dir`, failing with its error output if it fails.
+fn run_git(git: &Git, dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = git.command(dir)?.args(args).output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to run git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Create a worktree of the repository of the workspace at `root`, on a new
+/// `branch` starting at the commit the checkout is at. With `resume`, the
+/// worktree a failed run left behind is used instead.
+pub fn open(git: &Git, root: &Path, branch: &str, resume: bool) -> anyhow::Result<Worktree> {
+    let dir = git.path(root, WORKTREE_DIR)?;
+    let prefix = run_git(git, root, &["rev-parse", "--show-prefix"])?;
+
+    if dir.exists() {
+        if !resume {
+            anyhow::bail!(
+                "The worktree of a previous run is at {:?}; resume that run with --resume, or remove the worktree with git worktree remove",
+                dir.as_os_str()
+            );
+        }
+        let branch = run_git(git, &dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+        info!(
+            "Resuming in the worktree at {:?}, on {branch}",
+            dir.as_os_str()
+        );
+        return Ok(Worktree {
+            root: dir.join(&prefix),
+            dir,
+            branch,
+            checkout: root.to_owned(),
+        });
+    }
+    if resume {
+        anyhow::bail!(
+            "There is no worktree of a previous run at {:?} to resume",
+            dir.as_os_str()
+        );
+    }
+
+    if git
+        .command(root)?
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("refs/heads/{branch}"))
+        .output()?
+        .status
+        .success()
+    {
+        anyhow::bail!(
+            "The branch {branch} already exists; merge or delete it, or pick another one with --worktree-branch"
+        );
+    }
+    if !run_git(git, root, &["status", "--porcelain=v1"])?.is_empty() {
+        warn!("The uncommitted changes of the checkout are left out of the worktree");
+    }
+    let dir_arg = dir.to_string_lossy();
+    run_git(
+        git,
+        root,
+        &["worktree", "add", "--quiet", "-b", branch, &dir_arg, "HEAD"],
+    )?;
+    info!(
+        "Publishing from {branch}, in the worktree at {:?}",
+        dir.as_os_str()
+    );
+
+    Ok(Worktree {
+        root: dir.join(&prefix),
+        dir,
+        branch: branch.to_owned(),
+        checkout: root.to_owned(),
+    })
+}
+
+impl Worktree {
+    /// Remove the worktree, and its branch unless `keep_branch` is set.
+    pub fn remove(self, git: &Git, keep_branch: bool) -> anyhow::Result<()> {
+        let dir_arg = self.dir.to_string_lossy();
+        run_git(
+            git,
+            &self.checkout,
+            &["worktree", "remove", "--force", &dir_arg],
+        )?;
+        if !keep_branch {
+            run_git(
+                git,
+                &self.checkout,
+                &["branch", "--quiet", "-D", &self.branch],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Throw away `branch`, which a run created for publishing from a worktree of
+/// the repository of the workspace at `root`, along with the worktree if a
+/// failed run left it behind. Returns whether there was anything to throw
+/// away.
+pub fn discard(git: &Git, root: &Path, branch: &str) -> anyhow::Result<bool> {
+    let dir = git.path(root, WORKTREE_DIR)?;
+    let mut discarded = false;
+    if dir.exists() {
+        let dir_arg = dir.to_string_lossy();
+        run_git(git, root, &["worktree", "remove", "--force", &dir_arg])?;
+        discarded = true;
+    }
+    if git
+        .command(root)?
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("refs/heads/{branch}"))
+        .output()?
+        .status
+        .success()
+    {
+        run_git(git, root, &["branch", "--quiet", "-D", branch])?;
+        discarded = true;
+    }
+    Ok(discarded)
+}
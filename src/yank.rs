@@ -0,0 +1,160 @@
+// Copyright 2019-2022 Parity Technologies (UK) Ltd.
+// This file is part of subpub.
+//
+// subpub is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// subpub is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with subpub.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Roll back a bad release by yanking the versions the last publish run
+//! published.
+
+use crate::context::Context;
+use crate::journal;
+use crate::state::RunState;
+use semver::Version;
+use std::path::Path;
+use tracing::info;
+
+/// Yank the crates the last run published, the most recently published
+/// first, limited to `crates` and `version` if they're given. Crates given in
+/// `crates` which the last run didn't publish are yanked at `version`. With
+/// `from_journal`, what the last run published is read from its journal,
+/// along with the crates it was publishing when it stopped if the registry
+/// has them, rather than from its state. Returns the yanked crates and
+/// versions.
+pub fn yank<P: AsRef<Path>>(
+    ctx: &Context,
+    root: P,
+    crates: &[String],
+    version: Option<&Version>,
+    from_journal: bool,
+    dry_run: bool,
+) -> anyhow::Result<Vec<(String, Version)>> {
+    let root = root.as_ref();
+    let published = if from_journal {
+        journal_published(ctx, root)?
+    } else {
+        RunState::load_last(&ctx.git, root)?
+            .map(|run_state| {
+                run_state
+                    .published
+                    .into_iter()
+                    .map(|published| (published.krate, published.version))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut to_yank = published
+        .into_iter()
+        .rev()
+        .filter(|(krate, _)| crates.is_empty() || crates.contains(krate))
+        .filter(|(_, published)| version.is_none_or(|version| published == version))
+        .collect::<Vec<_>>();
+    for krate in crates {
+        if !to_yank.iter().any(|(yanked, _)| yanked == krate) {
+            match version {
+                Some(version) => to_yank.push((krate.clone(), version.clone())),
+                None => anyhow::bail!(
+                    "The last run did not publish {krate}, so the version to yank has to be given with --version"
+                ),
+            }
+        }
+    }
+    if to_yank.is_empty() {
+        anyhow::bail!(
+            "There is nothing to yank, since the last run did not publish any matching crate"
+        );
+    }
+
+    for (krate, version) in &to_yank {
+        if dry_run {
+            info!("Would yank {krate} {version}");
+        } else {
+            info!("Yanking {krate} {version}");
+            ctx.cargo.yank_crate(root, krate, version)?;
+        }
+    }
+
+    Ok(to_yank)
+}
+
+/// What the last run published according to its journal, in the order it
+/// was published, including the crates it was publishing when it stopped if
+/// the registry has them.
+pub fn journal_published(ctx: &Context, root: &Path) -> anyhow::Result<Vec<(String, Version)>> {
+    let steps = journal::load(&ctx.git, root)?;
+    let mut published = journal::published(&steps)
+        .into_iter()
+        .map(|(krate, version, _)| (krate, version))
+        .collect::<Vec<_>>();
+    for (krate, version, _) in journal::unconfirmed_publishes(&steps) {
+        ctx.registry.forget_crate_versions(&krate);
+        if ctx.registry.crate_versions(&krate)?.contains(&version) {
+            info!("{krate} {version} was published by the last run, though it stopped before recording it");
+            published.push((krate, version));
+        }
+    }
+    Ok(published)
+}
+
+/// Yank the pre-releases which the stable versions in `published` supersede,
+/// e.g. 1.2.0-rc.1 once 1.2.0 is published. When `kinds` isn't empty, only
+/// the pre-releases whose first identifier is one of them, e.g. `rc`, are
+/// yanked. Returns the yanked crates and versions.
+pub fn yank_superseded_prereleases<P: AsRef<Path>>(
+    ctx: &Context,
+    root: P,
+    published: &[(String, Version)],
+    kinds: &[String],
+    dry_run: bool,
+) -> anyhow::Result<Vec<(String, Version)>> {
+    let root = root.as_ref();
+    let mut yanked = vec![];
+    for (krate, version) in published
+        .iter()
+        .filter(|(_, version)| version.pre.is_empty())
+    {
+        let already_yanked = ctx.registry.yanked_crate_versions(krate)?;
+        let mut superseded = ctx
+            .registry
+            .crate_versions(krate)?
+            .into_iter()
+            .filter(|prerelease| {
+                !prerelease.pre.is_empty()
+                    && (prerelease.major, prerelease.minor, prerelease.patch)
+                        == (version.major, version.minor, version.patch)
+            })
+            .filter(|prerelease| {
+                let kind = prerelease
+                    .pre
+                    .as_str()
+                    .split('.')
+                    .next()
+                    .unwrap_or_default();
+                kinds.is_empty() || kinds.iter().any(|allowed| allowed == kind)
+            })
+            .filter(|prerelease| !already_yanked.contains(prerelease))
+            .collect::<Vec<_>>();
+        superseded.sort();
+        for prerelease in superseded {
+            if dry_run {
+                info!("Would yank {krate} {prerelease}, which {version} supersedes");
+            } else {
+                info!("Yanking {krate} {prerelease}, which {version} supersedes");
+                ctx.cargo.yank_crate(root, krate, &prerelease)?;
+            }
+            yanked.push((krate.clone(), prerelease));
+        }
+    }
+    Ok(yanked)
+}